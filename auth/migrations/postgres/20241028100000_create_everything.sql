@@ -0,0 +1,20 @@
+-- The schema the SQLite migrations add up to, as of 20241027100000_create_block
+CREATE TABLE IF NOT EXISTS "user" (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                username VARCHAR(255) NOT NULL,
+                email VARCHAR(255) NOT NULL,
+                new_email VARCHAR(255),
+                avatar TEXT NOT NULL,
+                servers TEXT NOT NULL,
+                login_code BIGINT,
+                tokens TEXT,
+                display_name VARCHAR(255) NOT NULL DEFAULT ''
+             );
+
+CREATE TABLE IF NOT EXISTS block (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                username VARCHAR(255) NOT NULL,
+                blocked_username VARCHAR(255) NOT NULL,
+                created TIMESTAMPTZ NOT NULL,
+                UNIQUE(username, blocked_username)
+            );
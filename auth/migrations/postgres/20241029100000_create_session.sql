@@ -0,0 +1,23 @@
+-- One row per signed in device, replacing the comma-joined "user".tokens
+CREATE TABLE IF NOT EXISTS session (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                username VARCHAR(255) NOT NULL,
+                token VARCHAR(255) NOT NULL UNIQUE,
+                device_name VARCHAR(255) NOT NULL,
+                client_version VARCHAR(255) NOT NULL,
+                ip VARCHAR(255) NOT NULL,
+                created TIMESTAMPTZ NOT NULL,
+                last_used TIMESTAMPTZ NOT NULL,
+                expires TIMESTAMPTZ NOT NULL
+            );
+
+CREATE INDEX IF NOT EXISTS session_username ON session (username);
+
+-- Tokens from before sessions become ones of an unknown device, as if signed in now
+INSERT INTO session (username, token, device_name, client_version, ip, created, last_used, expires)
+    SELECT username, token, 'Unknown device', '', '', now(), now(), now() + INTERVAL '90 days'
+    FROM "user", unnest(string_to_array(tokens, ',')) AS token
+    WHERE token != ''
+    ON CONFLICT DO NOTHING;
+
+ALTER TABLE "user" DROP COLUMN tokens;
@@ -0,0 +1,8 @@
+-- Sessions keep a hash of their token from now on. The tokens stored so far were also logged, so rather
+-- than hashing them every session is ended, and everyone signs in again once.
+-- Stokens are issued by the auth server from then on, keyed with a secret kept outside the database, which
+-- chat servers announce as protocol version 2. Clients from before it compute stokens themselves, get
+-- IncompatibleServer from upgraded chat servers, and need updating before signing in again.
+DELETE FROM session;
+
+ALTER TABLE session RENAME COLUMN token TO token_hash;
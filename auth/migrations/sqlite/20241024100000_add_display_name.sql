@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE user ADD COLUMN display_name VARCHAR(255) NOT NULL DEFAULT '';
+
+UPDATE user SET display_name = substr(username, 2, instr(username, ':') - 2);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS block (
+                id INTEGER PRIMARY KEY,
+                username VARCHAR(255) NOT NULL,
+                blocked_username VARCHAR(255) NOT NULL,
+                created DATETIME NOT NULL,
+                UNIQUE(username, blocked_username)
+            );
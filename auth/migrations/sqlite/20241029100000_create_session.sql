@@ -0,0 +1,27 @@
+-- One row per signed in device, replacing the comma-joined user.tokens
+CREATE TABLE IF NOT EXISTS session (
+                id INTEGER PRIMARY KEY,
+                username VARCHAR(255) NOT NULL,
+                token VARCHAR(255) NOT NULL UNIQUE,
+                device_name VARCHAR(255) NOT NULL,
+                client_version VARCHAR(255) NOT NULL,
+                ip VARCHAR(255) NOT NULL,
+                created DATETIME NOT NULL,
+                last_used DATETIME NOT NULL,
+                expires DATETIME NOT NULL
+            );
+
+CREATE INDEX IF NOT EXISTS session_username ON session (username);
+
+-- Tokens from before sessions become ones of an unknown device, as if signed in now
+WITH RECURSIVE split(username, token, rest) AS (
+    SELECT username, '', tokens || ',' FROM user WHERE tokens IS NOT NULL AND tokens != ''
+    UNION ALL
+    SELECT username, substr(rest, 1, instr(rest, ',') - 1), substr(rest, instr(rest, ',') + 1) FROM split WHERE rest != ''
+)
+INSERT OR IGNORE INTO session (username, token, device_name, client_version, ip, created, last_used, expires)
+    SELECT username, token, 'Unknown device', '', '',
+        strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'), strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'), strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now', '+90 days')
+    FROM split WHERE token != '';
+
+ALTER TABLE user DROP COLUMN tokens;
@@ -0,0 +1,308 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::time::Duration;
+use chrono::TimeDelta;
+use serde::Deserialize;
+use realm_shared::config::{env_override, load_file, ConfigError, DatabaseBackend};
+use realm_shared::net::{CodecConfig, TlsConfig};
+use crate::mailer::Templates;
+use crate::stoken::StokenKey;
+
+/// Everything `realm_auth` needs at startup, loaded once from `auth.toml` and env vars.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub server: ServerSection,
+    pub database: DatabaseSection,
+    pub limits: LimitsSection,
+    pub mail: MailSection,
+    pub paths: PathsSection,
+    pub sessions: SessionsSection,
+    pub shutdown: ShutdownSection,
+    pub metrics: MetricsSection,
+    pub tls: TlsConfig,
+    pub codec: CodecConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub domain: String,
+    pub bind_address: IpAddr,
+    pub port: u16,
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self {
+            domain: String::new(),
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 5052,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSection {
+    pub backend: DatabaseBackend,
+    pub url: String,
+}
+
+impl Default for DatabaseSection {
+    fn default() -> Self {
+        Self { backend: DatabaseBackend::Sqlite, url: "sqlite:auth.db".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    pub max_channels: usize,
+    pub max_channels_per_ip: u32, //NOTE: Kept above 1 so users behind one NAT can all sign in
+    pub max_in_flight_per_channel: usize,
+    pub max_frame_length: usize, //NOTE: Bytes
+    pub max_avatar_length: usize, //NOTE: Characters
+    pub max_device_name_length: usize, //NOTE: Characters
+}
+
+impl Default for LimitsSection {
+    fn default() -> Self {
+        Self {
+            max_channels: 1024,
+            max_channels_per_ip: 16,
+            max_in_flight_per_channel: 16,
+            max_frame_length: 1024 * 1024,
+            max_avatar_length: 2048,
+            max_device_name_length: 64,
+        }
+    }
+}
+
+/// `address`, `port`, `username` and `password` are the SMTP server's, `maildir` is only used by that backend.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailSection {
+    pub backend: MailBackend,
+    pub address: String,
+    pub port: u16,
+    pub name: String,
+    pub from_address: String,
+    pub username: String,
+    pub password: String,
+    pub maildir: String,
+    pub locale: String, //NOTE: Which folder under paths.templates emails come from
+}
+
+impl Default for MailSection {
+    fn default() -> Self {
+        Self {
+            backend: MailBackend::Smtp,
+            address: String::new(),
+            port: 0,
+            name: String::new(),
+            from_address: String::new(),
+            username: String::new(),
+            password: String::new(),
+            maildir: "./mail".to_string(),
+            locale: "en".to_string(),
+        }
+    }
+}
+
+/// Where emails go, `mail.backend` in config. Only `smtp` reaches anyone, the others are for development.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailBackend {
+    #[default]
+    Smtp,
+    Maildir,
+    Stdout,
+}
+
+impl FromStr for MailBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "smtp" => Ok(MailBackend::Smtp),
+            "maildir" => Ok(MailBackend::Maildir),
+            "stdout" => Ok(MailBackend::Stdout),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsSection {
+    pub templates: String, //NOTE: Holds a folder of email templates per locale
+}
+
+impl Default for PathsSection {
+    fn default() -> Self {
+        Self { templates: "./templates".to_string() }
+    }
+}
+
+/// Sessions end `lifetime` days after sign-in, whether or not they're still used.
+/// `stoken_key` is the file stokens are keyed with, generated on first start.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsSection {
+    pub lifetime: u32, //NOTE: Days
+    pub stoken_key: String,
+}
+
+impl Default for SessionsSection {
+    fn default() -> Self {
+        Self { lifetime: 90, stoken_key: "./stoken.key".to_string() }
+    }
+}
+
+impl SessionsSection {
+    pub fn lifetime(&self) -> TimeDelta { TimeDelta::days(self.lifetime.into()) }
+}
+
+/// On SIGTERM/SIGINT, in-flight requests get `drain_timeout` seconds to finish.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownSection {
+    pub drain_timeout: u64,
+}
+
+impl Default for ShutdownSection {
+    fn default() -> Self {
+        Self { drain_timeout: 30 }
+    }
+}
+
+impl ShutdownSection {
+    pub fn drain_timeout(&self) -> Duration { Duration::from_secs(self.drain_timeout) }
+}
+
+/// The Prometheus endpoint, `GET /metrics`. Only listens on loopback unless told otherwise.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSection {
+    pub enabled: bool,
+    pub bind_address: IpAddr,
+    pub port: u16,
+}
+
+impl Default for MetricsSection {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 9052,
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Loads `auth.toml` (or `CONFIG_PATH`), applies env overrides, then validates.
+    pub fn load() -> Result<AuthConfig, ConfigError> {
+        let mut config: AuthConfig = load_file("./auth.toml")?;
+
+        env_override(&mut config.server.domain, "DOMAIN", "server.domain")?;
+        env_override(&mut config.server.bind_address, "BIND_ADDRESS", "server.bind_address")?;
+        env_override(&mut config.server.port, "PORT", "server.port")?;
+        env_override(&mut config.database.backend, "DATABASE_BACKEND", "database.backend")?;
+        env_override(&mut config.database.url, "DATABASE_URL", "database.url")?;
+        env_override(&mut config.mail.backend, "SERVER_MAIL_BACKEND", "mail.backend")?;
+        env_override(&mut config.mail.address, "SERVER_MAIL_ADDRESS", "mail.address")?;
+        env_override(&mut config.mail.port, "SERVER_MAIL_PORT", "mail.port")?;
+        env_override(&mut config.mail.name, "SERVER_MAIL_NAME", "mail.name")?;
+        env_override(&mut config.mail.from_address, "SERVER_MAIL_FROM_ADDRESS", "mail.from_address")?;
+        env_override(&mut config.mail.username, "SERVER_MAIL_USERNAME", "mail.username")?;
+        env_override(&mut config.mail.password, "SERVER_MAIL_PASSWORD", "mail.password")?;
+        env_override(&mut config.mail.maildir, "SERVER_MAIL_MAILDIR", "mail.maildir")?;
+        env_override(&mut config.mail.locale, "SERVER_MAIL_LOCALE", "mail.locale")?;
+        env_override(&mut config.paths.templates, "EMAIL_TEMPLATES", "paths.templates")?;
+        env_override(&mut config.sessions.stoken_key, "STOKEN_KEY", "sessions.stoken_key")?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.domain.is_empty() {
+            return Err(ConfigError::invalid("server.domain", "must be set"));
+        }
+        if self.server.port == 0 {
+            return Err(ConfigError::invalid("server.port", "must not be 0"));
+        }
+        match (self.database.backend, DatabaseBackend::from_url(&self.database.url)) {
+            (DatabaseBackend::Sqlite, Some(DatabaseBackend::Sqlite)) | (DatabaseBackend::Postgres, Some(DatabaseBackend::Postgres)) => {}
+            (DatabaseBackend::Sqlite, _) => return Err(ConfigError::invalid("database.url", "must be a sqlite: url")),
+            (DatabaseBackend::Postgres, _) => return Err(ConfigError::invalid("database.url", "must be a postgres:// url")),
+        }
+        if self.limits.max_channels == 0 {
+            return Err(ConfigError::invalid("limits.max_channels", "must be at least 1"));
+        }
+        if self.limits.max_channels_per_ip == 0 {
+            return Err(ConfigError::invalid("limits.max_channels_per_ip", "must be at least 1"));
+        }
+        if self.limits.max_in_flight_per_channel == 0 {
+            return Err(ConfigError::invalid("limits.max_in_flight_per_channel", "must be at least 1"));
+        }
+        if self.limits.max_frame_length < 64 * 1024 {
+            return Err(ConfigError::invalid("limits.max_frame_length", "must be at least 65536"));
+        }
+        if self.limits.max_avatar_length == 0 {
+            return Err(ConfigError::invalid("limits.max_avatar_length", "must be at least 1"));
+        }
+        if self.limits.max_device_name_length == 0 {
+            return Err(ConfigError::invalid("limits.max_device_name_length", "must be at least 1"));
+        }
+        match self.mail.backend {
+            MailBackend::Smtp => {
+                if self.mail.address.is_empty() {
+                    return Err(ConfigError::invalid("mail.address", "must be set"));
+                }
+                if self.mail.port == 0 {
+                    return Err(ConfigError::invalid("mail.port", "must be set"));
+                }
+            }
+            MailBackend::Maildir => {
+                if self.mail.maildir.is_empty() {
+                    return Err(ConfigError::invalid("mail.maildir", "must be set"));
+                }
+            }
+            MailBackend::Stdout => {}
+        }
+        if self.mail.locale.is_empty() {
+            return Err(ConfigError::invalid("mail.locale", "must be set"));
+        }
+        if self.mail.from_address.parse::<lettre::Address>().is_err() {
+            return Err(ConfigError::invalid("mail.from_address", "must be an email address"));
+        }
+        if self.sessions.lifetime == 0 {
+            return Err(ConfigError::invalid("sessions.lifetime", "must be at least 1"));
+        }
+        if self.sessions.stoken_key.is_empty() {
+            return Err(ConfigError::invalid("sessions.stoken_key", "must be set"));
+        }
+        if self.shutdown.drain_timeout == 0 {
+            return Err(ConfigError::invalid("shutdown.drain_timeout", "must be at least 1"));
+        }
+        if self.metrics.enabled && self.metrics.port == 0 {
+            return Err(ConfigError::invalid("metrics.port", "must not be 0"));
+        }
+        self.tls.validate()?;
+        self.codec.validate()?;
+
+        Ok(())
+    }
+
+    /// Loads and checks the email templates for `mail.locale` from `paths.templates`.
+    pub fn load_email_templates(&self) -> Result<Templates, ConfigError> {
+        Templates::load(&self.paths.templates, &self.mail.locale)
+    }
+
+    /// Reads the key stokens are derived with from `sessions.stoken_key`, generating it on first start.
+    pub fn load_stoken_key(&self) -> Result<StokenKey, ConfigError> {
+        StokenKey::load_or_generate(&self.sessions.stoken_key).map_err(|e| ConfigError::invalid("sessions.stoken_key", e.to_string()))
+    }
+}
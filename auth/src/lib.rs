@@ -1,2 +1,7 @@
 pub mod server;
-pub mod types;
\ No newline at end of file
+pub mod types;
+pub mod config;
+pub mod metrics;
+pub mod store;
+pub mod mailer;
+pub mod stoken;
@@ -0,0 +1,49 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::message::Mailbox;
+use crate::mailer::{Mail, Mailer};
+
+/// Delivers into a maildir on disk instead of sending, so any mail client can read what would've gone out
+pub struct MaildirMailer {
+	from: Mailbox,
+	dir: PathBuf,
+}
+
+impl MaildirMailer {
+	/// Creates the maildir's `tmp`, `new` and `cur` folders if they're missing
+	pub fn open(dir: &str, from: Mailbox) -> io::Result<MaildirMailer> {
+		let dir = PathBuf::from(dir);
+		for folder in ["tmp", "new", "cur"] {
+			fs::create_dir_all(dir.join(folder))?;
+		}
+
+		Ok(MaildirMailer { from, dir })
+	}
+
+	/// Unique within this maildir, as `time.P<pid>Q<count>.host` per the maildir spec
+	fn file_name() -> String {
+		static DELIVERED: AtomicU64 = AtomicU64::new(0);
+		format!("{}.P{}Q{}.realm_auth", Utc::now().timestamp(), process::id(), DELIVERED.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+#[async_trait]
+impl Mailer for MaildirMailer {
+	async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+		let message = mail.to_message(&self.from)?.formatted();
+		let name = MaildirMailer::file_name();
+		let (tmp, new) = (self.dir.join("tmp").join(&name), self.dir.join("new").join(&name));
+
+		// Written to tmp first so readers never see half a message
+		tokio::task::spawn_blocking(move || {
+			fs::write(&tmp, message)?;
+			fs::rename(&tmp, &new)
+		}).await??;
+		Ok(())
+	}
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+use crate::config::{MailBackend, MailSection};
+
+pub mod smtp;
+pub mod maildir;
+pub mod stdout;
+pub mod templates;
+
+pub use self::maildir::MaildirMailer;
+pub use self::smtp::SmtpMailer;
+pub use self::stdout::StdoutMailer;
+pub use self::templates::{EmailKind, Rendered, Templates};
+
+/// An email to one user, sent from `mail.from_address`
+#[derive(Debug, Clone)]
+pub struct Mail {
+	pub to_name: String,
+	pub to_address: String,
+	pub subject: String,
+	pub text: String,
+	pub html: String,
+}
+
+/// Where the auth server's emails go, so the backend can be picked in config
+#[async_trait]
+pub trait Mailer: Send + Sync {
+	async fn send(&self, mail: Mail) -> anyhow::Result<()>; //NOTE: Ok once the mail is handed off, not once it's delivered
+}
+
+/// Sets up the mailer in config
+pub fn open(config: &MailSection) -> anyhow::Result<Arc<dyn Mailer>> {
+	let from = Mailbox::new(Some(config.name.clone()), config.from_address.parse()?);
+
+	Ok(match config.backend {
+		MailBackend::Smtp => Arc::new(SmtpMailer::new(config, from)?),
+		MailBackend::Maildir => Arc::new(MaildirMailer::open(&config.maildir, from)?),
+		MailBackend::Stdout => Arc::new(StdoutMailer::new(from)),
+	})
+}
+
+impl Mail {
+	pub fn new(to_name: &str, to_address: &str, rendered: Rendered) -> Mail {
+		Mail {
+			to_name: to_name.to_string(),
+			to_address: to_address.to_string(),
+			subject: rendered.subject,
+			text: rendered.text,
+			html: rendered.html,
+		}
+	}
+
+	/// The message as it goes out, with a blind copy to the sender
+	fn to_message(&self, from: &Mailbox) -> anyhow::Result<Message> {
+		Ok(Message::builder()
+			.from(from.clone())
+			.to(Mailbox::new(Some(self.to_name.clone()), self.to_address.parse()?))
+			.bcc(from.clone())
+			.subject(&self.subject)
+			.multipart(MultiPart::alternative_plain_html(self.text.clone(), self.html.clone()))?)
+	}
+}
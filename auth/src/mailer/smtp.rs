@@ -0,0 +1,40 @@
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{SmtpTransport, Transport};
+use crate::config::MailSection;
+use crate::mailer::{Mail, Mailer};
+
+/// Relays through the mail server in config, over TLS
+pub struct SmtpMailer {
+	from: Mailbox,
+	transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+	pub fn new(config: &MailSection, from: Mailbox) -> anyhow::Result<SmtpMailer> {
+		let builder = if config.port == 465 {
+			SmtpTransport::relay(&config.address)? //NOTE: Implicit TLS
+		} else {
+			SmtpTransport::starttls_relay(&config.address)?
+		};
+
+		let transport = builder
+			.port(config.port)
+			.credentials(Credentials::new(config.username.clone(), config.password.clone()))
+			.build();
+
+		Ok(SmtpMailer { from, transport })
+	}
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+	async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+		let message = mail.to_message(&self.from)?;
+		let transport = self.transport.clone(); //NOTE: Shares the connection pool
+
+		tokio::task::spawn_blocking(move || transport.send(&message)).await??;
+		Ok(())
+	}
+}
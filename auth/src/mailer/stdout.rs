@@ -0,0 +1,24 @@
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use crate::mailer::{Mail, Mailer};
+
+/// Prints emails instead of sending them, for running the auth server locally without a mail server
+pub struct StdoutMailer {
+	from: Mailbox,
+}
+
+impl StdoutMailer {
+	pub fn new(from: Mailbox) -> StdoutMailer {
+		StdoutMailer { from }
+	}
+}
+
+#[async_trait]
+impl Mailer for StdoutMailer {
+	async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+		mail.to_message(&self.from)?; //NOTE: Fails on the same bad addresses the other mailers would
+
+		println!("From: {}\nTo: {} <{}>\nSubject: {}\n\n{}\n", self.from, mail.to_name, mail.to_address, mail.subject, mail.text);
+		Ok(())
+	}
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use realm_shared::config::ConfigError;
+
+/// Every email the auth server sends. Each is a `<name>.txt`, whose first line is `Subject: ...`, and a
+/// `<name>.html` in the locale's folder, the HTML going into that folder's `layout.html` as `{{content}}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmailKind {
+	Welcome,
+	LoginCode,
+	EmailChange, //NOTE: To the new address, with the code confirming it
+	EmailChanged, //NOTE: To the old address, once the change is done
+	NewDevice,
+	AccountDeleted,
+}
+
+impl EmailKind {
+	pub const ALL: [EmailKind; 6] = [EmailKind::Welcome, EmailKind::LoginCode, EmailKind::EmailChange, EmailKind::EmailChanged, EmailKind::NewDevice, EmailKind::AccountDeleted];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			EmailKind::Welcome => "welcome",
+			EmailKind::LoginCode => "login_code",
+			EmailKind::EmailChange => "email_change",
+			EmailKind::EmailChanged => "email_changed",
+			EmailKind::NewDevice => "new_device",
+			EmailKind::AccountDeleted => "account_deleted",
+		}
+	}
+
+	/// What it's rendered with, besides the `COMMON_VARIABLES` every email gets
+	pub fn variables(self) -> &'static [&'static str] {
+		match self {
+			EmailKind::Welcome | EmailKind::LoginCode => &["login_code"],
+			EmailKind::EmailChange => &["login_code", "new_email"],
+			EmailKind::EmailChanged => &["old_email", "new_email"],
+			EmailKind::NewDevice => &["device", "ip", "time"],
+			EmailKind::AccountDeleted => &[],
+		}
+	}
+
+	/// What both parts have to show, the email being no use without it
+	fn required(self) -> &'static [&'static str] {
+		match self {
+			EmailKind::Welcome | EmailKind::LoginCode | EmailKind::EmailChange => &["login_code"],
+			_ => &[],
+		}
+	}
+}
+
+pub const COMMON_VARIABLES: [&str; 2] = ["username", "domain"];
+
+/// One email, ready to be addressed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+	pub subject: String,
+	pub text: String,
+	pub html: String,
+}
+
+enum Segment {
+	Text(String),
+	Variable(String),
+}
+
+/// Text with `{{name}}` variables in it
+struct Part(Vec<Segment>);
+
+impl Part {
+	fn parse(source: &str) -> Result<Part, String> {
+		let mut segments = Vec::new();
+		let mut rest = source;
+
+		while let Some(start) = rest.find("{{") {
+			let Some(end) = rest[start..].find("}}") else {
+				return Err(format!("unclosed {{{{ at `{}`", rest[start..].lines().next().unwrap_or_default()));
+			};
+
+			let name = rest[start + 2..start + end].trim();
+			if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+				return Err(format!("bad variable name `{}`", name));
+			}
+
+			segments.push(Segment::Text(rest[..start].to_string()));
+			segments.push(Segment::Variable(name.to_string()));
+			rest = &rest[start + end + 2..];
+		}
+		segments.push(Segment::Text(rest.to_string()));
+
+		Ok(Part(segments))
+	}
+
+	fn variables(&self) -> impl Iterator<Item = &str> {
+		self.0.iter().filter_map(|segment| match segment {
+			Segment::Variable(name) => Some(name.as_str()),
+			Segment::Text(_) => None,
+		})
+	}
+
+	/// Checks it only uses `allowed` variables, and all of `required`
+	fn check(&self, allowed: &[&str], required: &[&str]) -> Result<(), String> {
+		if let Some(unknown) = self.variables().find(|name| !allowed.contains(name)) {
+			return Err(format!("unknown variable {{{{{}}}}}", unknown));
+		}
+		if let Some(missing) = required.iter().find(|name| !self.variables().any(|used| used.eq(**name))) {
+			return Err(format!("missing variable {{{{{}}}}}", missing));
+		}
+		Ok(())
+	}
+
+	fn render(&self, variables: &[(&str, &str)], escape: fn(&str) -> String) -> String {
+		self.0.iter().map(|segment| match segment {
+			Segment::Text(text) => text.clone(),
+			Segment::Variable(name) => variables.iter().find(|(n, _)| n.eq(name)).map_or(String::new(), |(_, value)| escape(value)),
+		}).collect()
+	}
+}
+
+struct Template {
+	subject: Part,
+	text: Part,
+	html: Part,
+}
+
+/// The email templates for one locale, checked once at startup so a typo can't surface mid-flow
+pub struct Templates {
+	templates: HashMap<EmailKind, Template>,
+	layout: Part,
+}
+
+impl Templates {
+	/// Loads every template under `dir/locale`
+	pub fn load(dir: &str, locale: &str) -> Result<Templates, ConfigError> {
+		let folder = Path::new(dir).join(locale);
+		let invalid = |file: &str, reason: String| ConfigError::invalid("paths.templates", format!("{}: {}", folder.join(file).display(), reason));
+		let read = |file: &str| fs::read_to_string(folder.join(file)).map_err(|e| invalid(file, e.to_string()));
+
+		let layout = Part::parse(&read("layout.html")?).map_err(|e| invalid("layout.html", e))?;
+		let mut allowed = COMMON_VARIABLES.to_vec();
+		allowed.push("content");
+		layout.check(&allowed, &["content"]).map_err(|e| invalid("layout.html", e))?;
+
+		let mut templates = HashMap::new();
+		for kind in EmailKind::ALL {
+			let (text_file, html_file) = (format!("{}.txt", kind.name()), format!("{}.html", kind.name()));
+			let mut allowed = COMMON_VARIABLES.to_vec();
+			allowed.extend_from_slice(kind.variables());
+
+			let text = read(&text_file)?;
+			let Some((subject, text)) = text.split_once('\n').and_then(|(first, rest)| Some((first.strip_prefix("Subject:")?.trim(), rest.trim_start_matches(['\r', '\n'])))) else {
+				return Err(invalid(&text_file, "must start with a Subject: line".to_string()));
+			};
+
+			let subject = Part::parse(subject).map_err(|e| invalid(&text_file, e))?;
+			subject.check(&allowed, &[]).map_err(|e| invalid(&text_file, e))?;
+			let text = Part::parse(text).map_err(|e| invalid(&text_file, e))?;
+			text.check(&allowed, kind.required()).map_err(|e| invalid(&text_file, e))?;
+			let html = Part::parse(&read(&html_file)?).map_err(|e| invalid(&html_file, e))?;
+			html.check(&allowed, kind.required()).map_err(|e| invalid(&html_file, e))?;
+
+			templates.insert(kind, Template { subject, text, html });
+		}
+
+		Ok(Templates { templates, layout })
+	}
+
+	/// Fills in `variables`, escaping them in the HTML part. Any the caller left out render as nothing.
+	pub fn render(&self, kind: EmailKind, variables: &[(&str, &str)]) -> Rendered {
+		let template = &self.templates[&kind];
+		let content = template.html.render(variables, escape_html);
+
+		// Escaped up front, so the already escaped content goes in as it is
+		let mut escaped: Vec<(&str, String)> = variables.iter()
+			.filter(|(name, _)| COMMON_VARIABLES.contains(name))
+			.map(|(name, value)| (*name, escape_html(value)))
+			.collect();
+		escaped.push(("content", content));
+		let layout_variables: Vec<(&str, &str)> = escaped.iter().map(|(name, value)| (*name, value.as_str())).collect();
+		let html = self.layout.render(&layout_variables, str::to_string);
+
+		Rendered {
+			subject: template.subject.render(variables, str::to_string),
+			text: template.text.render(variables, str::to_string),
+			html,
+		}
+	}
+}
+
+fn escape_html(value: &str) -> String {
+	value.chars().map(|c| match c {
+		'&' => "&amp;".to_string(),
+		'<' => "&lt;".to_string(),
+		'>' => "&gt;".to_string(),
+		'"' => "&quot;".to_string(),
+		'\'' => "&#39;".to_string(),
+		c => c.to_string(),
+	}).collect()
+}
@@ -1,85 +1,112 @@
-use std::env;
-use std::future::Future;
-use std::net::{IpAddr, Ipv6Addr};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use dotenvy::dotenv;
-use futures::{future, StreamExt};
-use sqlx::{migrate, Sqlite, SqlitePool};
-use sqlx::migrate::MigrateDatabase;
+use futures::{FutureExt, StreamExt};
 use tarpc::server::{BaseChannel, Channel};
 use tarpc::server::incoming::Incoming;
-use tarpc::tokio_serde::formats::Json;
 use realm_auth::server::RealmAuthServer;
-use realm_auth::types::{AuthEmail, RealmAuth};
+use realm_auth::config::AuthConfig;
+use realm_auth::metrics::AuthServerMetrics;
+use realm_auth::types::RealmAuth;
+use realm_auth::store;
+use realm_auth::mailer;
+use realm_shared::metrics::DbMetricsLayer;
+use realm_shared::net;
+use realm_shared::redact;
+use realm_shared::shutdown::Shutdown;
+use tokio::time::timeout;
+use tokio_util::task::TaskTracker;
 use tracing::*;
+use tracing_subscriber::filter::{filter_fn, LevelFilter};
+use tracing_subscriber::prelude::*;
 
-async fn spawn(fut: impl Future<Output = ()> + Send + 'static) {
-    tokio::spawn(fut);
-}
+/// How long shutdown waits for database connections still held by abandoned requests
+const POOL_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
 
-    let subscriber = tracing_subscriber::fmt()
-        .compact()
-        .with_file(true)
-        .with_line_number(true)
-        .with_thread_ids(true)
-        .with_target(false)
-        .finish();
+    let metrics = Arc::new(AuthServerMetrics::default());
 
-    subscriber::set_global_default(subscriber).unwrap();
+    let subscriber = tracing_subscriber::registry()
+        .with(redact::layer().with_filter(LevelFilter::INFO))
+        .with(metrics.db.layer().with_filter(filter_fn(DbMetricsLayer::is_query)));
 
-    let auth_email = AuthEmail {
-        server_address: env::var("SERVER_MAIL_ADDRESS").expect("SERVER_MAIL_ADDRESS must be set"),
-        server_port: env::var("SERVER_MAIL_PORT").expect("SERVER_MAIL_PORT must be set").parse::<u16>().expect("SERVER_MAIL_ADDRESS must be a number"),
-        auth_name: env::var("SERVER_MAIL_NAME").expect("SERVER_MAIL_NAME must be set"),
-        auth_from_address: env::var("SERVER_MAIL_FROM_ADDRESS").expect("SERVER_MAIL_FROM_ADDRESS must be set"),
-        auth_username: env::var("SERVER_MAIL_USERNAME").expect("SERVER_MAIL_USERNAME must be set"),
-        auth_password: env::var("SERVER_MAIL_PASSWORD").expect("SERVER_MAIL_PASSWORD must be set"),
-    };
+    subscriber::set_global_default(subscriber).unwrap();
 
-    let database_url: &str = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let config = AuthConfig::load()?;
+    let templates = Arc::new(config.load_email_templates()?);
+    let stoken_key = config.load_stoken_key()?;
+    let store = store::open(&config.database).await?; // TODO: Do in Docker with Sqlx-cli
+    let mailer = mailer::open(&config.mail)?;
 
-    if !Sqlite::database_exists(database_url).await.unwrap_or(false) {
-        info!("Creating database {}", database_url);
-        match Sqlite::create_database(database_url).await {
-            Ok(_) => info!("Create db success"),
-            Err(error) => panic!("error: {}", error),
-        }
-    } else {
-        warn!("Database already exists");
-    } // TODO: Do in Docker with Sqlx-cli
-    
-    let db_pool = SqlitePool::connect(database_url).await.unwrap();
+    let server_addr = (config.server.bind_address, config.server.port);
 
-    info!("Running migrations...");
-    migrate!().run(&db_pool).await?; // TODO: Do in Docker with Sqlx-cli
-    info!("Migrations complete!");
+    let shutdown = Shutdown::on_signal();
+    // Every request handler, so draining can wait for them to finish and not just for their channels to close
+    let handlers = TaskTracker::new();
 
-    let server_addr = (IpAddr::V4("0.0.0.0".parse()?), env::var("PORT").expect("PORT must be set").parse::<u16>().unwrap());
+    if config.metrics.enabled {
+        let render_metrics = metrics.clone();
+        let render = move || {
+            let metrics = render_metrics.clone();
+            async move { realm_auth::metrics::render(&metrics) }
+        };
+        let metrics_addr = SocketAddr::from((config.metrics.bind_address, config.metrics.port));
+        tokio::spawn(async move {
+            if let Err(e) = realm_shared::metrics::serve_http(metrics_addr, render).await {
+                error!("Metrics endpoint stopped: {e}");
+            }
+        });
+    }
 
-    // JSON transport is provided by the json_transport tarpc module. It makes it easy
-    // to start up a serde-powered json serialization strategy over TCP.
-    let mut listener = tarpc::serde_transport::tcp::listen(&server_addr, Json::default).await?;
-    info!("Listening on port {}", listener.local_addr().port());
-    listener.config_mut().max_frame_length(usize::MAX);
-    listener
-        // Ignore accept errors.
-        .filter_map(|r| future::ready(r.ok()))
-        .map(BaseChannel::with_defaults)
-        // Limit channels to 1 per IP.
-        .max_channels_per_key(1, |t| t.transport().peer_addr().unwrap().ip())
+    let tls = config.tls.acceptor(&config.server.domain)?;
+    let (_, listener) = net::listen(server_addr, tls, config.codec.accept.clone(), config.limits.max_frame_length).await?;
+    // Stop accepting once shutting down, channels close once their in-flight requests are answered.
+    let serving = shutdown.until(listener)
+        .map(|transport| BaseChannel::with_defaults(shutdown.drain(transport)))
+        // Limit channels per IP.
+        .max_channels_per_key(config.limits.max_channels_per_ip, |t| t.transport().get_ref().get_ref().peer_addr().unwrap().ip())
         // serve is generated by the service attribute. It takes as input any type implementing
         // the generated World trait.
         .map(|channel| {
-            let server = RealmAuthServer::new(channel.transport().peer_addr().unwrap(), db_pool.clone(), auth_email.clone());
-            channel.execute(server.serve()).for_each(spawn)
+            let server = RealmAuthServer::new(&config, channel.transport().get_ref().get_ref().peer_addr().unwrap(), store.clone(), mailer.clone(), templates.clone(), stoken_key.clone(), metrics.clone());
+            let handlers = handlers.clone();
+            let rpc_metrics = metrics.rpc.clone();
+            rpc_metrics.active_channels.inc();
+            // Limit requests in flight per channel, further ones are answered with an error.
+            channel.max_concurrent_requests(config.limits.max_in_flight_per_channel).execute(rpc_metrics.measure(server.serve()))
+                .for_each(move |handler| {
+                    handlers.spawn(handler);
+                    futures::future::ready(())
+                })
+                .then(move |_| async move { rpc_metrics.active_channels.dec() })
         })
-        // Max 10 channels.
-        .buffer_unordered(10)
-        .for_each(|_| async {})
-        .await;
+        .buffer_unordered(config.limits.max_channels)
+        .for_each(|_| async {});
+    tokio::pin!(serving);
+
+    tokio::select! {
+        _ = &mut serving => {}
+        _ = shutdown.triggered() => {}
+    }
+
+    info!("Shutting down");
+    handlers.close();
+    let drained = async {
+        (&mut serving).await;
+        handlers.wait().await;
+    };
+    if timeout(config.shutdown.drain_timeout(), drained).await.is_err() {
+        warn!("Requests still in flight after {}s, dropping them", config.shutdown.drain_timeout);
+    }
+
+    if timeout(POOL_CLOSE_TIMEOUT, store.close()).await.is_err() {
+        warn!("Database connections still busy, closing anyway");
+    }
+    info!("Shutdown complete");
 
     Ok(())
 }
\ No newline at end of file
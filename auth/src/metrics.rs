@@ -0,0 +1,20 @@
+use std::sync::Arc;
+use realm_shared::metrics::{Counter, DbMetrics, Exposition, RpcMetrics};
+
+/// Everything the auth server exposes on its metrics endpoint.
+#[derive(Debug, Default)]
+pub struct AuthServerMetrics {
+    pub rpc: Arc<RpcMetrics>,
+    pub db: Arc<DbMetrics>,
+    pub emails_sent: Counter,
+    pub emails_failed: Counter,
+}
+
+pub fn render(metrics: &AuthServerMetrics) -> String {
+    let mut page = Exposition::default();
+    page.rpc(&metrics.rpc);
+    page.db(&metrics.db);
+    page.counter("realm_emails_sent_total", "Emails handed to the mail server", metrics.emails_sent.get());
+    page.counter("realm_emails_failed_total", "Emails the mail server refused or couldn't be reached for", metrics.emails_failed.get());
+    page.finish()
+}
@@ -1,39 +1,52 @@
-use std::env;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use chrono::Utc;
-use lettre::{Message, SmtpTransport, Transport};
-use lettre::message::header::ContentType;
-use lettre::message::Mailbox;
-use lettre::transport::smtp::authentication::Credentials;
+use chrono::{TimeDelta, Utc};
 use rand::Rng;
 use regex::Regex;
-use sha3::{Digest, Sha3_256};
-use sha3::digest::Update;
-use sqlx::{Pool, query, Sqlite};
 use tarpc::context::Context;
 use tracing::*;
-use crate::types::{AuthEmail, AuthUser, RealmAuth};
+use crate::config::AuthConfig;
+use crate::mailer::{EmailKind, Mail, Mailer, Templates};
+use crate::metrics::AuthServerMetrics;
+use crate::stoken::StokenKey;
+use crate::store::AuthStore;
+use crate::types::{AuthUser, RealmAuth, Session};
+use realm_shared::hash_token;
+use realm_shared::health::Health;
 use realm_shared::types::ErrorCode;
 use realm_shared::types::ErrorCode::*;
 
+/// How stale a session's `last_used` gets before a request updates it, sparing a write per request
+const SESSION_TOUCH_INTERVAL: TimeDelta = TimeDelta::seconds(60);
+
 #[derive(Clone)]
 pub struct RealmAuthServer {
 	pub socket: SocketAddr,
-	pub db_pool: Pool<Sqlite>,
-	pub auth_email: AuthEmail,
-	pub template_html: String,
+	pub store: Arc<dyn AuthStore>,
+	pub mailer: Arc<dyn Mailer>,
+	pub templates: Arc<Templates>,
 	pub domain: String,
+	pub max_avatar_length: usize,
+	pub max_device_name_length: usize,
+	pub session_lifetime: TimeDelta,
+	pub stoken_key: StokenKey,
+	pub metrics: Arc<AuthServerMetrics>,
 }
 
 impl RealmAuthServer {
-	pub fn new(socket: SocketAddr, db_pool: Pool<Sqlite>, auth_email: AuthEmail) -> RealmAuthServer {
+	pub fn new(config: &AuthConfig, socket: SocketAddr, store: Arc<dyn AuthStore>, mailer: Arc<dyn Mailer>, templates: Arc<Templates>, stoken_key: StokenKey, metrics: Arc<AuthServerMetrics>) -> RealmAuthServer {
 		RealmAuthServer {
 			socket,
-			db_pool,
-			auth_email,
-			template_html: std::fs::read_to_string("./login_email.html").expect("A login_email.html file is needed"),
-			domain: env::var("DOMAIN").expect("DOMAIN must be set"),
+			store,
+			mailer,
+			templates,
+			domain: config.server.domain.clone(),
+			max_avatar_length: config.limits.max_avatar_length,
+			max_device_name_length: config.limits.max_device_name_length,
+			session_lifetime: config.sessions.lifetime(),
+			stoken_key,
+			metrics,
 		}
 	}
 
@@ -47,91 +60,74 @@ impl RealmAuthServer {
 	}
 
 	async fn is_username_taken(&self, username: &str) -> Result<bool, ErrorCode> {
-		let result = query!("SELECT EXISTS (SELECT 1 FROM user WHERE username = ?) AS does_exist", username).fetch_one(&self.db_pool).await;
-
-		match result {
-			Ok(row) => Ok(row.does_exist != 0),
-			Err(_) => Err(InvalidUsername)
-		}
+		self.store.username_exists(username).await.map_err(|_| InvalidUsername)
 	}
 
 	async fn is_email_taken(&self, email: &str) -> Result<bool, ErrorCode> {
-		let result = query!("SELECT EXISTS (SELECT 1 FROM user WHERE email = ?) AS does_exist", email).fetch_one(&self.db_pool).await;
-
-		match result {
-			Ok(row) => Ok(row.does_exist != 0),
-			Err(_) => Err(InvalidUsername)
-		}
+		self.store.email_exists(email).await.map_err(|_| InvalidUsername)
 	}
 
 	async fn is_authorized(&self, username: &str, token: &str) -> Result<bool, ErrorCode> {
-		let result = query!("SELECT tokens FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
+		Ok(self.find_session(username, token).await?.is_some())
+	}
 
-		match result {
-			Ok(row) => {
-				let token_long: &str = &row.tokens.unwrap();
-				let tokens : Vec<&str> = {
-					if token_long.eq("") {
-						Vec::new()
-					} else {
-						token_long.split(',').collect::<Vec<&str>>()
-					}
-				};
+	/// The unexpired session `token` belongs to, noting that it was just used
+	async fn find_session(&self, username: &str, token: &str) -> Result<Option<Session>, ErrorCode> {
+		let now = Utc::now();
+		let Some(mut session) = self.store.find_session(username, &hash_token(token), now).await.map_err(|_| Error)? else {
+			return Ok(None)
+		};
+
+		let ip = self.socket.ip().to_string();
+		if now - session.last_used > SESSION_TOUCH_INTERVAL || !session.ip.eq(&ip) {
+			if let Err(e) = self.store.touch_session(session.id, now, &ip).await {
+				warn!("Could not update last use of session {}: {e:?}", session.id);
+			}
+			session.last_used = now;
+			session.ip = ip;
+		}
 
-				for i in 0..tokens.len() {
-					if tokens.get(i).unwrap() == &token {
-						return Ok(true);
-					}
-				}
+		Ok(Some(session))
+	}
+
+	/// Renders `kind` for the user and sends it, filling in `username` and `domain` along with `variables`
+	async fn send_email(&self, kind: EmailKind, username: &str, email: &str, variables: &[(&str, &str)]) -> Result<(), ErrorCode> {
+		let mut all = vec![("username", username), ("domain", self.domain.as_str())];
+		all.extend_from_slice(variables);
+		let mail = Mail::new(username, email, self.templates.render(kind, &all));
 
-				Ok(false)
+		match self.mailer.send(mail).await {
+			Ok(_) => {
+				self.metrics.emails_sent.inc();
+				info!("Email sent successfully! kind -> {}", kind.name());
+				Ok(())
 			}
-			Err(_) => Err(InvalidUsername),
-		}
-	}
-
-	fn send_login_message(&self, username: &str, email: &str, login_code: u32) { // -> Result<(), ErrorCode>
-		let auth_email = self.auth_email.clone();
-		let template_html = self.template_html.clone();
-		let username = username.to_string();
-		let email = email.to_string();
-
-		tokio::spawn(async move {
-			let email = Message::builder()
-				.from(Mailbox::new(Some(auth_email.auth_name.clone()), auth_email.auth_from_address.clone().parse().unwrap()))
-				.to(Mailbox::new(Some(username.clone()), email.clone().parse().unwrap()))
-				.bcc(Mailbox::new(Some(auth_email.auth_name.clone()), auth_email.auth_from_address.clone().parse().unwrap()))
-				.subject(format!("Realm confirmation code: {}", &login_code))
-				.header(ContentType::TEXT_HTML)
-				.body(template_html.replace("{$LOGIN_CODE}", &login_code.to_string()))
-				.unwrap();
-
-			let creds = Credentials::new(auth_email.auth_username, auth_email.auth_password);
-
-			// Open a remote connection to gmail
-			let mailer = SmtpTransport::relay(&auth_email.server_address)
-				.unwrap()
-				.credentials(creds)
-				.build();
-
-			// Send the email
-			match mailer.send(&email) {
-				Ok(_) => info!("Email sent successfully!"),
-				Err(e) => error!("Could not send email: {e:?}"),
+			Err(e) => {
+				self.metrics.emails_failed.inc();
+				error!("Could not send email: {e:?}");
+				Err(UnableToSendMail)
 			}
-		});
+		}
 	}
 
-	async fn is_login_code_valid(&self, username: &str, login_code: u32) -> Result<bool, ErrorCode> {
-		let result = query!("SELECT login_code FROM user WHERE username = ?;", username).fetch_one(&self.db_pool).await;
+	/// For emails telling the user about something already done, so a failure to send doesn't undo it
+	async fn send_notice(&self, kind: EmailKind, username: &str, email: &str, variables: &[(&str, &str)]) {
+		let _ = self.send_email(kind, username, email, variables).await; //NOTE: Logged and counted in send_email
+	}
 
-		match result {
-			Ok(row) => {
-				if row.login_code.unwrap() as u32 != login_code {
-					return Ok(false);
-				}
-				Ok(true)
-			}
+	async fn notify_new_device(&self, username: &str, device_name: &str) {
+		let Ok(user) = self.store.get_user(username).await else {
+			return
+		};
+
+		let time = Utc::now().format("%Y-%m-%d %H:%M UTC").to_string();
+		let ip = self.socket.ip().to_string();
+		self.send_notice(EmailKind::NewDevice, username, &user.email, &[("device", device_name), ("time", &time), ("ip", &ip)]).await;
+	}
+
+	async fn is_login_code_valid(&self, username: &str, login_code: u32) -> Result<bool, ErrorCode> {
+		match self.store.get_login_code(username).await {
+			Ok(stored) => Ok(stored == Some(login_code)),
 			Err(_) => Err(InvalidUsername)
 		}
 	}
@@ -156,13 +152,12 @@ impl RealmAuthServer {
 		true
 	}
 
-	async fn reset_login_code(&self, username: &str) -> Result<(), ErrorCode> {
-		let result = query!("UPDATE user SET login_code = NULL WHERE username = ?", username).execute(&self.db_pool).await;
+	async fn inner_get_blocked_users(&self, username: &str) -> Result<Vec<String>, ErrorCode> {
+		self.store.get_blocked(username).await.map_err(|_| Error)
+	}
 
-		match result {
-			Ok(_) => Ok(()),
-			Err(_) => Err(InvalidUsername)
-		}
+	async fn reset_login_code(&self, username: &str) -> Result<(), ErrorCode> {
+		self.store.set_login_code(username, None).await.map_err(|_| InvalidUsername)
 	}
 }
 
@@ -171,33 +166,29 @@ impl RealmAuth for RealmAuthServer {
 		format!("Hello {} auth!", name)
 	}
 
-	async fn server_token_validation(self, _: Context, server_token: String, username: String, server_id: String, domain: String, tarpc_port: u16) -> bool {
-		info!("API Request: server_token_validation( server_token -> {}, username -> {}, server_id -> {}, domain -> {}, tarpc_port -> {} )",
-            server_token, username, server_id, domain, tarpc_port);
+	async fn health(self, _: Context) -> Health {
+		self.store.health().await
+	}
 
-		let result = query!("SELECT tokens FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
+	async fn server_token_validation(self, _: Context, server_token: String, username: String, server_id: String, domain: String, tarpc_port: u16) -> bool {
+		info!("API Request: server_token_validation( username -> {}, server_id -> {}, domain -> {}, tarpc_port -> {} )",
+            username, server_id, domain, tarpc_port);
 
-		match result {
-			Ok(row) => {
-				let token_long: &str = &row.tokens.unwrap();
-				let tokens: Vec<&str> = {
-					if token_long.eq("") {
-						Vec::new()
-					} else {
-						token_long.split(',').collect::<Vec<&str>>()
-					}
-				};
+		match self.store.get_session_token_hashes(&username, Utc::now()).await {
+			Ok(hashes) => hashes.iter().any(|hash| self.stoken_key.stoken(hash, &server_id, &domain, tarpc_port) == server_token),
+			Err(_) => false,
+		}
+	}
 
-				for token in tokens {
-					if realm_shared::stoken(token, &server_id, &domain, tarpc_port) == server_token {
-						return true;
-					}
-				}
+	async fn server_get_blocked_users(self, context: Context, server_token: String, username: String, server_id: String, domain: String, tarpc_port: u16) -> Result<Vec<String>, ErrorCode> {
+		info!("API Request: server_get_blocked_users( username -> {}, server_id -> {}, domain -> {}, tarpc_port -> {} )",
+            username, server_id, domain, tarpc_port);
 
-				false
-			}
-			Err(_) => false,
+		if !self.clone().server_token_validation(context, server_token, username.clone(), server_id, domain, tarpc_port).await {
+			return Err(Unauthorized);
 		}
+
+		self.inner_get_blocked_users(&username).await
 	}
 
 	async fn create_account_flow(self, _: Context, username: String, email: String) -> Result<(), ErrorCode> {
@@ -216,10 +207,10 @@ impl RealmAuth for RealmAuthServer {
 		}
 
 		let code = self.gen_login_code();
-		self.send_login_message(&username, &email, code);
+		self.send_email(EmailKind::Welcome, &username, &email, &[("login_code", &code.to_string())]).await?;
 
-		let result = query!("INSERT INTO user (username, email, new_email, avatar, servers, login_code, tokens) VALUES (?, ?, '', '', '', ?, '')", username, email, code)
-			.execute(&self.db_pool).await;
+		let display_name = &username[1..username.find(':').unwrap()];
+		let result = self.store.create_user(&username, &email, display_name, code).await;
 
 		match result {
 			Ok(_) => Ok(()),
@@ -239,12 +230,11 @@ impl RealmAuth for RealmAuthServer {
 
 		if username.is_none() {
 			let tmp = email.clone().unwrap();
-			let result = query!("SELECT username FROM user WHERE email = ?;", tmp)
-				.fetch_one(&self.db_pool).await;
+			let result = self.store.get_username_by_email(&tmp).await;
 
 			match result {
-				Ok(row) => {
-					username = Some(row.username);
+				Ok(found) => {
+					username = Some(found);
 				}
 				Err(_) => return Err(InvalidEmail)
 			}
@@ -252,12 +242,11 @@ impl RealmAuth for RealmAuthServer {
 
 		if email.clone().is_none() {
 			let tmp = username.clone().unwrap();
-			let result = query!("SELECT email FROM user WHERE username = ?;", tmp)
-				.fetch_one(&self.db_pool).await;
+			let result = self.store.get_user(&tmp).await;
 
 			match result {
-				Ok(row) => {
-					email = Some(row.email);
+				Ok(user) => {
+					email = Some(user.email);
 				}
 				Err(_) => return Err(InvalidUsername)
 			}
@@ -265,60 +254,82 @@ impl RealmAuth for RealmAuthServer {
 
 		let code = self.gen_login_code();
 
-		let result = query!("UPDATE user SET login_code = ? WHERE username = ?;", code, username)
-			.execute(&self.db_pool).await;
+		let username = username.unwrap();
+		let result = self.store.set_login_code(&username, Some(code)).await;
 
 		match result {
-			Ok(_) => {
-				self.send_login_message(&username.unwrap(), &email.unwrap(), code);
-				Ok(())
-			}
+			Ok(_) => self.send_email(EmailKind::LoginCode, &username, &email.unwrap(), &[("login_code", &code.to_string())]).await,
 			Err(_) => Err(InvalidUsername)
 		}
 	}
 
-	async fn finish_login_flow(self, _: Context, username: String, login_code: u32) -> Result<String, ErrorCode> {
-		info!("API Request: finish_login_flow( username -> {}, login_code -> {} )", username, login_code);
+	async fn finish_login_flow(self, _: Context, username: String, login_code: u32, device_name: String, client_version: String) -> Result<String, ErrorCode> {
+		info!("API Request: finish_login_flow( username -> {}, device_name -> {}, client_version -> {} )", username, device_name, client_version);
+
+		if device_name.chars().count() > self.max_device_name_length || client_version.chars().count() > self.max_device_name_length {
+			return Err(InputTooLong);
+		}
 
 		if !self.is_login_code_valid(&username, login_code).await? {
-			error!("Unauthorized request made for finish_login_flow() (bad login code)! username -> {}, login_code -> {}", username, login_code);
+			error!("Unauthorized request made for finish_login_flow() (bad login code)! username -> {}", username);
 			return Err(InvalidLoginCode);
 		}
 
 		self.reset_login_code(&username).await?;
 
-		let _ = query!("UPDATE user SET login_code = NULL WHERE username = ?", username).execute(&self.db_pool).await;
+		let now = Utc::now();
+		if let Err(e) = self.store.delete_expired_sessions(now).await {
+			warn!("Could not clear expired sessions: {e:?}");
+		}
 
-		let hash = Sha3_256::new().chain(format!("{}{}{}", username, login_code, Utc::now().to_utc())).finalize();
-		let token = hex::encode(hash);
+		let token = hex::encode(rand::thread_rng().gen::<[u8; 32]>());
 
-		let result = query!("SELECT tokens FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
-		match result {
-			Ok(row) => {
-				let token_long: &str = &row.tokens.unwrap();
-				let mut tokens: Vec<&str> = {
-					if token_long.eq("") {
-						Vec::new()
-					} else {
-						token_long.split(',').collect::<Vec<&str>>()
-					}
-				};
-				tokens.push(&token);
+		let signed_in_elsewhere = !self.store.get_sessions(&username, now).await.map_err(|_| InvalidUsername)?.is_empty();
 
-				let mega_token = tokens.join(",");
-				let result = query!("UPDATE user SET tokens = ? WHERE username = ?", mega_token, username)
-					.execute(&self.db_pool).await;
-				match result {
-					Ok(_) => Ok(token),
-					Err(_) => Err(InvalidUsername)
+		let device_name = match device_name.trim() {
+			"" => "Unknown device".to_string(),
+			name => name.to_string(),
+		};
+		let session = Session {
+			id: 0,
+			device_name,
+			client_version,
+			ip: self.socket.ip().to_string(),
+			created: now,
+			last_used: now,
+			expires: now + self.session_lifetime,
+			current: true,
+		};
+
+		let result = self.store.create_session(&username, &hash_token(&token), &session).await;
+		match result {
+			Ok(_) => {
+				//NOTE: Not for a first sign-in, e.g. right after signing up
+				if signed_in_elsewhere {
+					self.notify_new_device(&username, &session.device_name).await;
 				}
+				Ok(token)
+			}
+			Err(e) => {
+				error!("Could not start a session for {}: {e:?}", username);
+				Err(Error)
 			}
-			Err(_) => Err(InvalidUsername)
 		}
 	}
 
+	async fn get_server_token(self, _: Context, username: String, token: String, server_id: String, domain: String, tarpc_port: u16) -> Result<String, ErrorCode> {
+		info!("API Request: get_server_token( username -> {}, server_id -> {}, domain -> {}, tarpc_port -> {} )",
+            username, server_id, domain, tarpc_port);
+
+		if !self.is_authorized(&username, &token).await? {
+			return Err(Unauthorized);
+		}
+
+		Ok(self.stoken_key.stoken(&hash_token(&token), &server_id, &domain, tarpc_port))
+	}
+
 	async fn change_email_flow(self, _: Context, username: String, new_email: String, token: String) -> Result<(), ErrorCode> {
-		info!("API Request: change_email_flow( username -> {}, new_email -> {}, token -> {} )", username, new_email, token);
+		info!("API Request: change_email_flow( username -> {}, new_email -> {} )", username, new_email);
 
 		if !self.is_authorized(&username, &token).await? {
 			return Err(Unauthorized);
@@ -328,27 +339,23 @@ impl RealmAuth for RealmAuthServer {
 			return Err(EmailTaken);
 		}
 
-		let _ = query!("UPDATE user SET new_email = ? WHERE username = ?", new_email, username).execute(&self.db_pool).await.unwrap();
+		self.store.set_new_email(&username, Some(&new_email)).await.map_err(|_| MalformedDBResponse)?;
 
 		let code = self.gen_login_code();
 
-		let result = query!("UPDATE user SET login_code = ? WHERE username = ?;", code, username)
-			.execute(&self.db_pool).await;
+		let result = self.store.set_login_code(&username, Some(code)).await;
 
 		match result {
-			Ok(_) => {
-				self.send_login_message(&username, &new_email, code);
-				Ok(())
-			}
+			Ok(_) => self.send_email(EmailKind::EmailChange, &username, &new_email, &[("login_code", &code.to_string()), ("new_email", &new_email)]).await,
 			Err(_) => Err(InvalidUsername)
 		}
 	}
 
 	async fn finish_change_email_flow(self, _: Context, username: String, new_email: String, token: String, login_code: u32) -> Result<(), ErrorCode> {
-		info!("API Request: finish_change_email_flow( username -> {}, new_email -> {}, token -> {}, login_code -> {} )", username, new_email, token, login_code);
+		info!("API Request: finish_change_email_flow( username -> {}, new_email -> {} )", username, new_email);
 
 		if !self.is_authorized(&username, &token).await? {
-			error!("Unauthorized request made for finish_change_email_flow() (bad token)! username -> {}, token -> {}", username, token);
+			error!("Unauthorized request made for finish_change_email_flow() (bad token)! username -> {}", username);
 			return Err(Unauthorized);
 		}
 
@@ -358,28 +365,54 @@ impl RealmAuth for RealmAuthServer {
 		}
 
 		if !self.is_login_code_valid(&username, login_code).await? {
-			error!("Unauthorized request made for finish_change_email_flow() (bad login code)! username -> {}, login_code -> {}", username, login_code);
+			error!("Unauthorized request made for finish_change_email_flow() (bad login code)! username -> {}", username);
 			return Err(InvalidLoginCode);
 		}
 
-		let _ = query!("UPDATE user SET new_email = NULL WHERE username = ?", username).execute(&self.db_pool).await;
+		let old_email = self.store.get_user(&username).await.map_err(|_| InvalidUsername)?.email;
 
-		let _ = query!("UPDATE user SET email = ? WHERE username = ?", new_email, username).execute(&self.db_pool).await;
+		self.store.set_new_email(&username, None).await.map_err(|_| MalformedDBResponse)?;
+
+		self.store.set_email(&username, &new_email).await.map_err(|_| MalformedDBResponse)?;
 
 		self.reset_login_code(&username).await?;
 
+		self.send_notice(EmailKind::EmailChanged, &username, &old_email, &[("old_email", &old_email), ("new_email", &new_email)]).await;
 		Ok(())
 	}
 
 	async fn change_avatar(self, _: Context, username: String, token: String, new_avatar: String) -> Result<(), ErrorCode> {
-		info!("API Request: change_avatar( username -> {}, token -> {}, new_avatar -> {} )", username, token, new_avatar);
+		info!("API Request: change_avatar( username -> {}, new_avatar -> {} )", username, new_avatar);
+
+		if !self.is_authorized(&username, &token).await? {
+			error!("Unauthorized request made for change_avatar()! username -> {}", username);
+			return Err(Unauthorized);
+		}
+
+		if new_avatar.chars().count() > self.max_avatar_length {
+			return Err(InputTooLong);
+		}
+
+		let result = self.store.set_avatar(&username, &new_avatar).await;
+		match result {
+			Ok(_) => Ok(()),
+			Err(_) => Err(Error)
+		}
+	}
+
+	async fn change_display_name(self, _: Context, username: String, token: String, new_display_name: String) -> Result<(), ErrorCode> {
+		info!("API Request: change_display_name( username -> {}, new_display_name -> {} )", username, new_display_name);
 
 		if !self.is_authorized(&username, &token).await? {
-			error!("Unauthorized request made for change_avatar()! username -> {}, token -> {}", username, token);
+			error!("Unauthorized request made for change_display_name()! username -> {}", username);
 			return Err(Unauthorized);
 		}
 
-		let result = query!("UPDATE user SET avatar = ? WHERE username = ?", new_avatar, username).execute(&self.db_pool).await;
+		if !realm_shared::is_display_name_valid(&new_display_name) {
+			return Err(InvalidDisplayName);
+		}
+
+		let result = self.store.set_display_name(&username, &new_display_name).await;
 		match result {
 			Ok(_) => Ok(()),
 			Err(_) => Err(Error)
@@ -387,26 +420,16 @@ impl RealmAuth for RealmAuthServer {
 	}
 
 	async fn get_all_data(self, _: Context, username: String, token: String) -> Result<AuthUser, ErrorCode> {
-		info!("API Request: get_all_data( username -> {}, token -> {} )", username, token);
+		info!("API Request: get_all_data( username -> {} )", username);
 
 		if !self.is_authorized(&username, &token).await? {
-			error!("Unauthorized request made for get_all_data()! username -> {}, token -> {}", username, token);
+			error!("Unauthorized request made for get_all_data()! username -> {}", username);
 			return Err(Unauthorized);
 		}
 
-		let result = query!(r"SELECT * FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
+		let result = self.store.get_user(&username).await;
 		match result {
-			Ok(row) => {
-				Ok(AuthUser {
-					id: row.id,
-					username: row.username,
-					email: row.email,
-					avatar: row.avatar,
-					servers: row.servers,
-					login_code: None,
-					bigtoken: row.tokens,
-				})
-			}
+			Ok(user) => Ok(user),
 			Err(_) => {
 				error!("Invalid username in request for get_all_data()! username -> {}", username);
 				Err(InvalidUsername)
@@ -415,61 +438,81 @@ impl RealmAuth for RealmAuthServer {
 	}
 
 	async fn sign_out(self, _: Context, username: String, token: String) -> Result<(), ErrorCode> {
-		info!("API Request: sign_out( username -> {}, token -> {} )", username, token);
+		info!("API Request: sign_out( username -> {} )", username);
 
-		let result = query!("SELECT tokens FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
+		let Some(session) = self.find_session(&username, &token).await? else {
+			error!("Unauthorized request made for sign_out()! username -> {}", username);
+			return Err(Unauthorized);
+		};
 
-		match result {
-			Ok(row) => {
-				let token_long: &str = &row.tokens.unwrap();
-				let mut tokens: Vec<&str> = {
-					if token_long.eq("") {
-						Vec::new()
-					} else {
-						token_long.split(',').collect::<Vec<&str>>()
-					}
-				};
+		match self.store.delete_session(&username, session.id).await {
+			Ok(_) => Ok(()),
+			Err(_) => {
+				error!("Unable to end session on sign_out()! username -> {}", username);
+				Err(Error)
+			}
+		}
+	}
 
-				for i in 0..tokens.len() {
-					if tokens.get(i).unwrap().eq(&token.as_str()) {
-						tokens.remove(i);
+	async fn list_sessions(self, _: Context, username: String, token: String) -> Result<Vec<Session>, ErrorCode> {
+		info!("API Request: list_sessions( username -> {} )", username);
 
-						let mega_token = tokens.join(",").to_string();
-						let result = query!("UPDATE user SET tokens = ? WHERE username = ?", mega_token, username)
-							.execute(&self.db_pool).await;
+		let Some(current) = self.find_session(&username, &token).await? else {
+			return Err(Unauthorized);
+		};
 
-						return match result {
-							Ok(_) => Ok(()),
-							Err(_) => {
-								error!("Unable to update tokens on sign_out()! \
-                                username -> {}, (previous) token_long -> {}, mega_token -> {}, token -> {}",
-                                    username, token_long, mega_token, token);
-								Err(Error)
-							}
-						};
-					}
-				}
+		let mut sessions = self.store.get_sessions(&username, Utc::now()).await.map_err(|_| Error)?;
+		for session in sessions.iter_mut().filter(|session| session.id == current.id) {
+			*session = current.clone(); //NOTE: With this request's use
+		}
+		Ok(sessions)
+	}
 
-				error!("Unauthorized request made for sign_out()! username -> {}, token -> {}", username, token);
-				Err(Unauthorized)
-			}
-			Err(_) => {
-				error!("Invalid username in request for get_avatar_for_user()! username -> {}", username);
-				Err(InvalidUsername)
+	async fn revoke_session(self, _: Context, username: String, token: String, session_id: i64) -> Result<(), ErrorCode> {
+		info!("API Request: revoke_session( username -> {}, session_id -> {} )", username, session_id);
+
+		if !self.is_authorized(&username, &token).await? {
+			return Err(Unauthorized);
+		}
+
+		match self.store.delete_session(&username, session_id).await {
+			Ok(true) => Ok(()),
+			Ok(false) => Err(SessionNotFound),
+			Err(_) => Err(Error)
+		}
+	}
+
+	async fn sign_out_everywhere(self, _: Context, username: String, token: String) -> Result<(), ErrorCode> {
+		info!("API Request: sign_out_everywhere( username -> {} )", username);
+
+		if !self.is_authorized(&username, &token).await? {
+			return Err(Unauthorized);
+		}
+
+		match self.store.delete_sessions(&username).await {
+			Ok(count) => {
+				info!("Signed {} out of {} sessions", username, count);
+				Ok(())
 			}
+			Err(_) => Err(Error)
 		}
 	}
 
 	async fn delete_account(self, _: Context, username: String, token: String) -> Result<(), ErrorCode> {
-		info!("API Request: delete_account_flow( username -> {}, token -> {} )", username, token);
+		info!("API Request: delete_account_flow( username -> {} )", username);
 
 		if !self.is_authorized(&username, &token).await? {
 			return Err(Unauthorized);
 		}
 
-		let result = query!("DELETE FROM user WHERE username = ?", username).execute(&self.db_pool).await;
+		let email = self.store.get_user(&username).await.map_err(|_| InvalidUsername)?.email;
+
+		let result = self.store.delete_user(&username).await;
 		match result {
-			Ok(_) => Ok(()),
+			Ok(_) => {
+				self.send_notice(EmailKind::AccountDeleted, &username, &email, &[]).await;
+				Ok(())
+			}
 			Err(_) => Err(InvalidUsername)
 		}
 	}
@@ -481,26 +524,18 @@ impl RealmAuth for RealmAuthServer {
 
 		let address = format!("{}:{}", domain, port);
 		
-		let result = query!("SELECT servers FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
+		let result = self.store.get_servers(&username).await;
 		match result {
-			Ok(row) => {
-				let mut vec_servers: Vec<&str> = {
-					if row.servers.eq("") {
-						Vec::new()
-					} else {
-						row.servers.split('|').collect::<Vec<&str>>()
-					}
-				};
+			Ok(mut vec_servers) => {
 				for server in &vec_servers {
 					if server.eq(&address) {
 						return Err(AlreadyJoinedServer);
 					}
 				}
 
-				vec_servers.push(&address);
-				let new_servers = vec_servers.join("|");
+				vec_servers.push(address);
 
-				let result = query!("UPDATE user SET servers = ? WHERE username = ?", new_servers, username).execute(&self.db_pool).await;
+				let result = self.store.set_servers(&username, &vec_servers).await;
 				match result {
 					Ok(_) => Ok(()),
 					Err(_) => Err(Error)
@@ -517,22 +552,14 @@ impl RealmAuth for RealmAuthServer {
 
 		let address = format!("{}:{}", domain, port);
 
-		let result = query!("SELECT servers FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
+		let result = self.store.get_servers(&username).await;
 		match result {
-			Ok(row) => {
-				let mut vec_servers: Vec<&str> = {
-					if row.servers.eq("") {
-						Vec::new()
-					} else {
-						row.servers.split('|').collect::<Vec<&str>>()
-					}
-				};
+			Ok(mut vec_servers) => {
 				for i in 0..vec_servers.len() {
 					if vec_servers.get(i).unwrap().eq(&address) {
 						vec_servers.remove(i);
 
-						let new_servers = vec_servers.join("|");
-						let result = query!("UPDATE user SET servers = ? WHERE username = ?", new_servers, username).fetch_one(&self.db_pool).await;
+						let result = self.store.set_servers(&username, &vec_servers).await;
 						return match result {
 							Ok(_) => Ok(()),
 							Err(_) => Err(Error)
@@ -551,38 +578,74 @@ impl RealmAuth for RealmAuthServer {
 			return Err(Unauthorized);
 		}
 
-		let result = query!("SELECT servers FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
+		self.store.get_servers(&username).await.map_err(|_| Error)
+	}
+
+	async fn block_user(self, _: Context, username: String, token: String, blocked_username: String) -> Result<(), ErrorCode> {
+		info!("API Request: block_user( username -> {}, blocked_username -> {} )", username, blocked_username);
+
+		if !self.is_authorized(&username, &token).await? {
+			return Err(Unauthorized);
+		}
+
+		if !blocked_username.starts_with('@') || !blocked_username.contains(':') || blocked_username.eq(&username) {
+			return Err(InvalidUsername);
+		}
+
+		let result = self.store.add_block(&username, &blocked_username, Utc::now()).await;
 		match result {
-			Ok(row) => {
-				let vec_servers: Vec<&str> = {
-					if row.servers.eq("") {
-						Vec::new()
-					} else {
-						row.servers.split('|').collect::<Vec<&str>>()
-					}
-				};
-				let mut servers = Vec::new();
-				for server in vec_servers {
-					servers.push(server.to_string())
-				}
+			Ok(_) => Ok(()),
+			Err(_) => Err(Error)
+		}
+	}
 
-				Ok(servers)
-			}
+	async fn unblock_user(self, _: Context, username: String, token: String, blocked_username: String) -> Result<(), ErrorCode> {
+		info!("API Request: unblock_user( username -> {}, blocked_username -> {} )", username, blocked_username);
+
+		if !self.is_authorized(&username, &token).await? {
+			return Err(Unauthorized);
+		}
+
+		let result = self.store.remove_block(&username, &blocked_username).await;
+		match result {
+			Ok(_) => Ok(()),
 			Err(_) => Err(Error)
 		}
 	}
 
+	async fn get_blocked_users(self, _: Context, username: String, token: String) -> Result<Vec<String>, ErrorCode> {
+		if !self.is_authorized(&username, &token).await? {
+			return Err(Unauthorized);
+		}
+
+		self.inner_get_blocked_users(&username).await
+	}
+
 	async fn get_avatar_for_user(self, _: Context, username: String) -> Result<String, ErrorCode> {
 		info!("API Request: get_avatar_for_user( username -> {} )", username);
 
-		let result = query!("SELECT avatar FROM user WHERE username = ?", username).fetch_one(&self.db_pool).await;
+		let result = self.store.get_user(&username).await;
 
 		match result {
-			Ok(row) => Ok(row.avatar),
+			Ok(user) => Ok(user.avatar),
 			Err(_) => {
 				error!("Invalid username in request for get_avatar_for_user()! username -> {}", username);
 				Err(InvalidUsername)
 			}
 		}
 	}
+
+	async fn get_display_name_for_user(self, _: Context, username: String) -> Result<String, ErrorCode> {
+		info!("API Request: get_display_name_for_user( username -> {} )", username);
+
+		let result = self.store.get_user(&username).await;
+
+		match result {
+			Ok(user) => Ok(user.display_name),
+			Err(_) => {
+				error!("Invalid username in request for get_display_name_for_user()! username -> {}", username);
+				Err(InvalidUsername)
+			}
+		}
+	}
 }
\ No newline at end of file
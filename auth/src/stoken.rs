@@ -0,0 +1,50 @@
+//! Server tokens (stokens): what a user hands one chat server in place of their session token.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use rand::RngCore;
+use sha3::digest::Update;
+use sha3::{Digest, Sha3_256};
+use tracing::warn;
+
+/// Keys stokens to this auth server. It's kept in its own file and never in the database, so the session
+/// hashes there aren't enough to mint stokens for anyone.
+#[derive(Clone)]
+pub struct StokenKey([u8; 32]);
+
+impl StokenKey {
+	pub fn generate() -> StokenKey {
+		let mut key = [0; 32];
+		rand::thread_rng().fill_bytes(&mut key);
+		StokenKey(key)
+	}
+
+	/// Reads the hex key at `path`, generating it on first start
+	pub fn load_or_generate(path: &str) -> io::Result<StokenKey> {
+		if !Path::new(path).exists() {
+			warn!("Generating a new stoken key at {}, sessions' stokens change with it", path);
+			let key = StokenKey::generate();
+			let mut file = fs::OpenOptions::new();
+			file.write(true).create_new(true);
+			#[cfg(unix)]
+			std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+			file.open(path)?.write_all(hex::encode(key.0).as_bytes())?;
+			return Ok(key);
+		}
+
+		let key = hex::decode(fs::read_to_string(path)?.trim()).ok()
+			.and_then(|key| <[u8; 32]>::try_from(key).ok())
+			.ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("{} doesn't hold a 64 digit hex key", path)))?;
+		Ok(StokenKey(key))
+	}
+
+	/// The stoken for the session whose token hashes to `token_hash`, on the chat server at `domain:port`
+	pub fn stoken(&self, token_hash: &str, server_id: &str, domain: &str, port: u16) -> String {
+		let hash = Sha3_256::new()
+			.chain(self.0)
+			.chain(format!("{}|{}|{}|{}", token_hash, server_id, domain, port))
+			.finalize();
+		hex::encode(hash)
+	}
+}
@@ -0,0 +1,250 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use realm_shared::health::{Health, MigrationState};
+use crate::store::AuthStore;
+use crate::types::{AuthUser, Session};
+
+/// Keeps everything in memory and loses it on drop, for tests and tools that embed the auth server
+#[derive(Default)]
+pub struct MemoryStore {
+	tables: Mutex<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+	users: Vec<StoredUser>,
+	blocks: Vec<Block>,
+	sessions: Vec<StoredSession>,
+}
+
+struct StoredUser {
+	id: i64,
+	username: String,
+	email: String,
+	#[allow(dead_code)] //NOTE: Written but never read back, as in the SQL stores
+	new_email: Option<String>,
+	display_name: String,
+	avatar: String,
+	servers: Vec<String>,
+	login_code: Option<u32>,
+}
+
+struct StoredSession {
+	username: String,
+	token_hash: String,
+	session: Session, //NOTE: current is always false here
+}
+
+struct Block {
+	id: i64,
+	username: String,
+	blocked_username: String,
+	#[allow(dead_code)] //NOTE: Written but never read back, as in the SQL stores
+	created: DateTime<Utc>,
+}
+
+impl MemoryStore {
+	pub fn new() -> MemoryStore {
+		MemoryStore::default()
+	}
+}
+
+impl Tables {
+	fn live_sessions<'a>(&'a self, username: &'a str, now: DateTime<Utc>) -> impl Iterator<Item = &'a StoredSession> {
+		self.sessions.iter().filter(move |stored| stored.username.eq(username) && stored.session.expires > now)
+	}
+
+	fn user(&self, username: &str) -> sqlx::Result<&StoredUser> {
+		self.users.iter().find(|user| user.username.eq(username)).ok_or(sqlx::Error::RowNotFound)
+	}
+
+	/// Updating a user that doesn't exist does nothing, as an `UPDATE` matching no rows would
+	fn update(&mut self, username: &str, f: impl FnOnce(&mut StoredUser)) {
+		if let Some(user) = self.users.iter_mut().find(|user| user.username.eq(username)) {
+			f(user);
+		}
+	}
+}
+
+#[async_trait]
+impl AuthStore for MemoryStore {
+	async fn health(&self) -> Health {
+		Health { database: true, migrations: MigrationState::Current { version: 0 } } //NOTE: Nothing to migrate
+	}
+
+	async fn close(&self) {}
+
+	async fn username_exists(&self, username: &str) -> sqlx::Result<bool> {
+		Ok(self.tables.lock().await.user(username).is_ok())
+	}
+
+	async fn email_exists(&self, email: &str) -> sqlx::Result<bool> {
+		Ok(self.tables.lock().await.users.iter().any(|user| user.email.eq(email)))
+	}
+
+	async fn create_user(&self, username: &str, email: &str, display_name: &str, login_code: u32) -> sqlx::Result<()> {
+		let mut tables = self.tables.lock().await;
+		let id = tables.users.iter().map(|user| user.id).max().unwrap_or(0) + 1;
+		tables.users.push(StoredUser {
+			id,
+			username: username.to_string(),
+			email: email.to_string(),
+			new_email: None,
+			display_name: display_name.to_string(),
+			avatar: String::new(),
+			servers: Vec::new(),
+			login_code: Some(login_code),
+		});
+		Ok(())
+	}
+
+	async fn get_user(&self, username: &str) -> sqlx::Result<AuthUser> {
+		let tables = self.tables.lock().await;
+		let user = tables.user(username)?;
+
+		Ok(AuthUser {
+			id: user.id,
+			username: user.username.clone(),
+			email: user.email.clone(),
+			display_name: user.display_name.clone(),
+			avatar: user.avatar.clone(),
+			servers: user.servers.join("|"),
+			login_code: None,
+		})
+	}
+
+	async fn get_username_by_email(&self, email: &str) -> sqlx::Result<String> {
+		let tables = self.tables.lock().await;
+		tables.users.iter().find(|user| user.email.eq(email)).map(|user| user.username.clone()).ok_or(sqlx::Error::RowNotFound)
+	}
+
+	async fn delete_user(&self, username: &str) -> sqlx::Result<()> {
+		let mut tables = self.tables.lock().await;
+		tables.blocks.retain(|block| !block.username.eq(username));
+		tables.sessions.retain(|stored| !stored.username.eq(username));
+		tables.users.retain(|user| !user.username.eq(username));
+		Ok(())
+	}
+
+	async fn get_login_code(&self, username: &str) -> sqlx::Result<Option<u32>> {
+		Ok(self.tables.lock().await.user(username)?.login_code)
+	}
+
+	async fn set_login_code(&self, username: &str, login_code: Option<u32>) -> sqlx::Result<()> {
+		self.tables.lock().await.update(username, |user| user.login_code = login_code);
+		Ok(())
+	}
+
+	async fn create_session(&self, username: &str, token_hash: &str, session: &Session) -> sqlx::Result<i64> {
+		let mut tables = self.tables.lock().await;
+		if tables.sessions.iter().any(|stored| stored.token_hash.eq(token_hash)) {
+			return Err(sqlx::Error::Protocol("token hash is already in use".to_string())) //NOTE: As the UNIQUE constraint would
+		}
+
+		let id = tables.sessions.iter().map(|stored| stored.session.id).max().unwrap_or(0) + 1;
+		tables.sessions.push(StoredSession {
+			username: username.to_string(),
+			token_hash: token_hash.to_string(),
+			session: Session { id, current: false, ..session.clone() },
+		});
+		Ok(id)
+	}
+
+	async fn find_session(&self, username: &str, token_hash: &str, now: DateTime<Utc>) -> sqlx::Result<Option<Session>> {
+		let tables = self.tables.lock().await;
+		let found = tables.live_sessions(username, now).find(|stored| stored.token_hash.eq(token_hash));
+		Ok(found.map(|stored| Session { current: true, ..stored.session.clone() }))
+	}
+
+	async fn get_sessions(&self, username: &str, now: DateTime<Utc>) -> sqlx::Result<Vec<Session>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.live_sessions(username, now).map(|stored| stored.session.clone()).collect())
+	}
+
+	async fn get_session_token_hashes(&self, username: &str, now: DateTime<Utc>) -> sqlx::Result<Vec<String>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.live_sessions(username, now).map(|stored| stored.token_hash.clone()).collect())
+	}
+
+	async fn touch_session(&self, id: i64, last_used: DateTime<Utc>, ip: &str) -> sqlx::Result<()> {
+		let mut tables = self.tables.lock().await;
+		if let Some(stored) = tables.sessions.iter_mut().find(|stored| stored.session.id == id) {
+			stored.session.last_used = last_used;
+			stored.session.ip = ip.to_string();
+		}
+		Ok(())
+	}
+
+	async fn delete_session(&self, username: &str, id: i64) -> sqlx::Result<bool> {
+		let mut tables = self.tables.lock().await;
+		let before = tables.sessions.len();
+		tables.sessions.retain(|stored| !(stored.username.eq(username) && stored.session.id == id));
+		Ok(tables.sessions.len() < before)
+	}
+
+	async fn delete_sessions(&self, username: &str) -> sqlx::Result<u64> {
+		let mut tables = self.tables.lock().await;
+		let before = tables.sessions.len();
+		tables.sessions.retain(|stored| !stored.username.eq(username));
+		Ok((before - tables.sessions.len()) as u64)
+	}
+
+	async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> sqlx::Result<u64> {
+		let mut tables = self.tables.lock().await;
+		let before = tables.sessions.len();
+		tables.sessions.retain(|stored| stored.session.expires > now);
+		Ok((before - tables.sessions.len()) as u64)
+	}
+
+	async fn set_new_email(&self, username: &str, new_email: Option<&str>) -> sqlx::Result<()> {
+		self.tables.lock().await.update(username, |user| user.new_email = new_email.map(str::to_string));
+		Ok(())
+	}
+
+	async fn set_email(&self, username: &str, email: &str) -> sqlx::Result<()> {
+		self.tables.lock().await.update(username, |user| user.email = email.to_string());
+		Ok(())
+	}
+
+	async fn set_avatar(&self, username: &str, avatar: &str) -> sqlx::Result<()> {
+		self.tables.lock().await.update(username, |user| user.avatar = avatar.to_string());
+		Ok(())
+	}
+
+	async fn set_display_name(&self, username: &str, display_name: &str) -> sqlx::Result<()> {
+		self.tables.lock().await.update(username, |user| user.display_name = display_name.to_string());
+		Ok(())
+	}
+
+	async fn get_servers(&self, username: &str) -> sqlx::Result<Vec<String>> {
+		Ok(self.tables.lock().await.user(username)?.servers.clone())
+	}
+
+	async fn set_servers(&self, username: &str, servers: &[String]) -> sqlx::Result<()> {
+		self.tables.lock().await.update(username, |user| user.servers = servers.to_vec());
+		Ok(())
+	}
+
+	async fn add_block(&self, username: &str, blocked_username: &str, created: DateTime<Utc>) -> sqlx::Result<()> {
+		let mut tables = self.tables.lock().await;
+		if tables.blocks.iter().any(|block| block.username.eq(username) && block.blocked_username.eq(blocked_username)) {
+			return Ok(())
+		}
+
+		let id = tables.blocks.iter().map(|block| block.id).max().unwrap_or(0) + 1;
+		tables.blocks.push(Block { id, username: username.to_string(), blocked_username: blocked_username.to_string(), created });
+		Ok(())
+	}
+
+	async fn remove_block(&self, username: &str, blocked_username: &str) -> sqlx::Result<()> {
+		let mut tables = self.tables.lock().await;
+		tables.blocks.retain(|block| !(block.username.eq(username) && block.blocked_username.eq(blocked_username)));
+		Ok(())
+	}
+
+	async fn get_blocked(&self, username: &str) -> sqlx::Result<Vec<String>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.blocks.iter().filter(|block| block.username.eq(username)).map(|block| block.blocked_username.clone()).collect())
+	}
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use realm_shared::config::DatabaseBackend;
+use realm_shared::health::Health;
+use crate::config::DatabaseSection;
+use crate::types::{AuthUser, Session};
+
+pub mod sqlite;
+pub mod postgres;
+pub mod memory;
+
+pub use self::memory::MemoryStore;
+pub use self::postgres::PostgresStore;
+pub use self::sqlite::SqliteStore;
+
+/// Everything the auth server keeps, so the backend can be picked in config, or swapped for `MemoryStore`
+/// in tests and tools that embed the auth server. Reads of sessions skip those expired by `now`.
+#[async_trait]
+pub trait AuthStore: Send + Sync {
+	async fn health(&self) -> Health;
+	async fn close(&self);
+
+	async fn username_exists(&self, username: &str) -> sqlx::Result<bool>;
+	async fn email_exists(&self, email: &str) -> sqlx::Result<bool>;
+	async fn create_user(&self, username: &str, email: &str, display_name: &str, login_code: u32) -> sqlx::Result<()>;
+	async fn get_user(&self, username: &str) -> sqlx::Result<AuthUser>; //NOTE: Without the login code
+	async fn get_username_by_email(&self, email: &str) -> sqlx::Result<String>;
+	async fn delete_user(&self, username: &str) -> sqlx::Result<()>; //NOTE: Along with their blocks and sessions
+
+	async fn get_login_code(&self, username: &str) -> sqlx::Result<Option<u32>>;
+	async fn set_login_code(&self, username: &str, login_code: Option<u32>) -> sqlx::Result<()>;
+
+	async fn create_session(&self, username: &str, token_hash: &str, session: &Session) -> sqlx::Result<i64>; //NOTE: Ignores session.current
+	async fn find_session(&self, username: &str, token_hash: &str, now: DateTime<Utc>) -> sqlx::Result<Option<Session>>;
+	async fn get_sessions(&self, username: &str, now: DateTime<Utc>) -> sqlx::Result<Vec<Session>>; //NOTE: Oldest first
+	async fn get_session_token_hashes(&self, username: &str, now: DateTime<Utc>) -> sqlx::Result<Vec<String>>;
+	async fn touch_session(&self, id: i64, last_used: DateTime<Utc>, ip: &str) -> sqlx::Result<()>;
+	async fn delete_session(&self, username: &str, id: i64) -> sqlx::Result<bool>; //NOTE: false if it wasn't theirs
+	async fn delete_sessions(&self, username: &str) -> sqlx::Result<u64>;
+	async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> sqlx::Result<u64>;
+
+	async fn set_new_email(&self, username: &str, new_email: Option<&str>) -> sqlx::Result<()>;
+	async fn set_email(&self, username: &str, email: &str) -> sqlx::Result<()>;
+	async fn set_avatar(&self, username: &str, avatar: &str) -> sqlx::Result<()>;
+	async fn set_display_name(&self, username: &str, display_name: &str) -> sqlx::Result<()>;
+	async fn get_servers(&self, username: &str) -> sqlx::Result<Vec<String>>;
+	async fn set_servers(&self, username: &str, servers: &[String]) -> sqlx::Result<()>;
+
+	async fn add_block(&self, username: &str, blocked_username: &str, created: DateTime<Utc>) -> sqlx::Result<()>; //NOTE: Blocking twice is a no-op
+	async fn remove_block(&self, username: &str, blocked_username: &str) -> sqlx::Result<()>;
+	async fn get_blocked(&self, username: &str) -> sqlx::Result<Vec<String>>;
+}
+
+/// Connects to the database in config, creating it and running migrations as needed
+pub async fn open(config: &DatabaseSection) -> anyhow::Result<Arc<dyn AuthStore>> {
+	Ok(match config.backend {
+		DatabaseBackend::Sqlite => Arc::new(SqliteStore::open(&config.url).await?),
+		DatabaseBackend::Postgres => Arc::new(PostgresStore::open(&config.url).await?),
+	})
+}
+
+//NOTE: Servers are kept as one delimited column
+fn split(joined: &str, delimiter: char) -> Vec<String> {
+	if joined.is_empty() {
+		Vec::new()
+	} else {
+		joined.split(delimiter).map(str::to_string).collect()
+	}
+}
@@ -0,0 +1,213 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::migrate::{MigrateDatabase, Migrator};
+use sqlx::postgres::PgRow;
+use sqlx::{query, query_scalar, PgPool, Pool, Postgres, Row};
+use tracing::info;
+use realm_shared::health::{self, Health};
+use crate::store::{split, AuthStore};
+use crate::types::{AuthUser, Session};
+
+/// The migrations under `migrations/postgres/`, run on startup and checked by the `health` RPC
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+
+//NOTE: `user` is reserved in PostgreSQL, so the table is quoted throughout
+pub struct PostgresStore {
+	db_pool: Pool<Postgres>,
+}
+
+impl PostgresStore {
+	pub fn new(db_pool: Pool<Postgres>) -> PostgresStore {
+		PostgresStore { db_pool }
+	}
+
+	/// Creates the database if it's missing, connects, and brings it up to date
+	pub async fn open(database_url: &str) -> anyhow::Result<PostgresStore> {
+		if !Postgres::database_exists(database_url).await? {
+			info!("Creating database {}", database_url);
+			Postgres::create_database(database_url).await?;
+		}
+
+		let store = PostgresStore::new(PgPool::connect(database_url).await?);
+		store.migrate().await?;
+		Ok(store)
+	}
+
+	pub async fn migrate(&self) -> anyhow::Result<()> {
+		info!("Running migrations...");
+		MIGRATOR.run(&self.db_pool).await?;
+		info!("Migrations complete!");
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl AuthStore for PostgresStore {
+	async fn health(&self) -> Health {
+		health::check(&self.db_pool, &MIGRATOR).await
+	}
+
+	async fn close(&self) {
+		self.db_pool.close().await
+	}
+
+	async fn username_exists(&self, username: &str) -> sqlx::Result<bool> {
+		query_scalar(r#"SELECT EXISTS (SELECT 1 FROM "user" WHERE username = $1)"#).bind(username).fetch_one(&self.db_pool).await
+	}
+
+	async fn email_exists(&self, email: &str) -> sqlx::Result<bool> {
+		query_scalar(r#"SELECT EXISTS (SELECT 1 FROM "user" WHERE email = $1)"#).bind(email).fetch_one(&self.db_pool).await
+	}
+
+	async fn create_user(&self, username: &str, email: &str, display_name: &str, login_code: u32) -> sqlx::Result<()> {
+		query(r#"INSERT INTO "user" (username, email, new_email, display_name, avatar, servers, login_code) VALUES ($1, $2, '', $3, '', '', $4)"#)
+			.bind(username).bind(email).bind(display_name).bind(login_code as i64)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_user(&self, username: &str) -> sqlx::Result<AuthUser> {
+		let row = query(r#"SELECT id, username, email, display_name, avatar, servers FROM "user" WHERE username = $1"#)
+			.bind(username)
+			.fetch_one(&self.db_pool).await?;
+
+		Ok(AuthUser {
+			id: row.try_get("id")?,
+			username: row.try_get("username")?,
+			email: row.try_get("email")?,
+			display_name: row.try_get("display_name")?,
+			avatar: row.try_get("avatar")?,
+			servers: row.try_get("servers")?,
+			login_code: None,
+		})
+	}
+
+	async fn get_username_by_email(&self, email: &str) -> sqlx::Result<String> {
+		query_scalar(r#"SELECT username FROM "user" WHERE email = $1"#).bind(email).fetch_one(&self.db_pool).await
+	}
+
+	async fn delete_user(&self, username: &str) -> sqlx::Result<()> {
+		query("DELETE FROM block WHERE username = $1").bind(username).execute(&self.db_pool).await?;
+		query("DELETE FROM session WHERE username = $1").bind(username).execute(&self.db_pool).await?;
+		query(r#"DELETE FROM "user" WHERE username = $1"#).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_login_code(&self, username: &str) -> sqlx::Result<Option<u32>> {
+		let login_code: Option<i64> = query_scalar(r#"SELECT login_code FROM "user" WHERE username = $1"#).bind(username).fetch_one(&self.db_pool).await?;
+		Ok(login_code.map(|code| code as u32))
+	}
+
+	async fn set_login_code(&self, username: &str, login_code: Option<u32>) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET login_code = $1 WHERE username = $2"#)
+			.bind(login_code.map(i64::from)).bind(username)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn create_session(&self, username: &str, token_hash: &str, session: &Session) -> sqlx::Result<i64> {
+		query_scalar("INSERT INTO session (username, token_hash, device_name, client_version, ip, created, last_used, expires) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id")
+			.bind(username).bind(token_hash).bind(&session.device_name).bind(&session.client_version).bind(&session.ip)
+			.bind(session.created).bind(session.last_used).bind(session.expires)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn find_session(&self, username: &str, token_hash: &str, now: DateTime<Utc>) -> sqlx::Result<Option<Session>> {
+		let row = query("SELECT id, device_name, client_version, ip, created, last_used, expires FROM session WHERE username = $1 AND token_hash = $2 AND expires > $3")
+			.bind(username).bind(token_hash).bind(now)
+			.fetch_optional(&self.db_pool).await?;
+		row.map(|row| session(&row, true)).transpose()
+	}
+
+	async fn get_sessions(&self, username: &str, now: DateTime<Utc>) -> sqlx::Result<Vec<Session>> {
+		let rows = query("SELECT id, device_name, client_version, ip, created, last_used, expires FROM session WHERE username = $1 AND expires > $2 ORDER BY id")
+			.bind(username).bind(now)
+			.fetch_all(&self.db_pool).await?;
+		rows.iter().map(|row| session(row, false)).collect()
+	}
+
+	async fn get_session_token_hashes(&self, username: &str, now: DateTime<Utc>) -> sqlx::Result<Vec<String>> {
+		query_scalar("SELECT token_hash FROM session WHERE username = $1 AND expires > $2 ORDER BY id").bind(username).bind(now).fetch_all(&self.db_pool).await
+	}
+
+	async fn touch_session(&self, id: i64, last_used: DateTime<Utc>, ip: &str) -> sqlx::Result<()> {
+		query("UPDATE session SET last_used = $1, ip = $2 WHERE id = $3").bind(last_used).bind(ip).bind(id).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn delete_session(&self, username: &str, id: i64) -> sqlx::Result<bool> {
+		let result = query("DELETE FROM session WHERE username = $1 AND id = $2").bind(username).bind(id).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn delete_sessions(&self, username: &str) -> sqlx::Result<u64> {
+		let result = query("DELETE FROM session WHERE username = $1").bind(username).execute(&self.db_pool).await?;
+		Ok(result.rows_affected())
+	}
+
+	async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> sqlx::Result<u64> {
+		let result = query("DELETE FROM session WHERE expires <= $1").bind(now).execute(&self.db_pool).await?;
+		Ok(result.rows_affected())
+	}
+
+	async fn set_new_email(&self, username: &str, new_email: Option<&str>) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET new_email = $1 WHERE username = $2"#).bind(new_email).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_email(&self, username: &str, email: &str) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET email = $1 WHERE username = $2"#).bind(email).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_avatar(&self, username: &str, avatar: &str) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET avatar = $1 WHERE username = $2"#).bind(avatar).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_display_name(&self, username: &str, display_name: &str) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET display_name = $1 WHERE username = $2"#).bind(display_name).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_servers(&self, username: &str) -> sqlx::Result<Vec<String>> {
+		let servers: String = query_scalar(r#"SELECT servers FROM "user" WHERE username = $1"#).bind(username).fetch_one(&self.db_pool).await?;
+		Ok(split(&servers, '|'))
+	}
+
+	async fn set_servers(&self, username: &str, servers: &[String]) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET servers = $1 WHERE username = $2"#).bind(servers.join("|")).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn add_block(&self, username: &str, blocked_username: &str, created: DateTime<Utc>) -> sqlx::Result<()> {
+		query("INSERT INTO block (username, blocked_username, created) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+			.bind(username).bind(blocked_username).bind(created)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn remove_block(&self, username: &str, blocked_username: &str) -> sqlx::Result<()> {
+		query("DELETE FROM block WHERE username = $1 AND blocked_username = $2")
+			.bind(username).bind(blocked_username)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_blocked(&self, username: &str) -> sqlx::Result<Vec<String>> {
+		query_scalar("SELECT blocked_username FROM block WHERE username = $1 ORDER BY id").bind(username).fetch_all(&self.db_pool).await
+	}
+}
+
+fn session(row: &PgRow, current: bool) -> sqlx::Result<Session> {
+	Ok(Session {
+		id: row.try_get("id")?,
+		device_name: row.try_get("device_name")?,
+		client_version: row.try_get("client_version")?,
+		ip: row.try_get("ip")?,
+		created: row.try_get("created")?,
+		last_used: row.try_get("last_used")?,
+		expires: row.try_get("expires")?,
+		current,
+	})
+}
@@ -0,0 +1,211 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::migrate::{MigrateDatabase, Migrator};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{query, query_scalar, Pool, Row, Sqlite, SqlitePool};
+use tracing::{info, warn};
+use realm_shared::health::{self, Health};
+use crate::store::{split, AuthStore};
+use crate::types::{AuthUser, Session};
+
+/// The migrations under `migrations/sqlite/`, run on startup and checked by the `health` RPC
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+pub struct SqliteStore {
+	db_pool: Pool<Sqlite>,
+}
+
+impl SqliteStore {
+	pub fn new(db_pool: Pool<Sqlite>) -> SqliteStore {
+		SqliteStore { db_pool }
+	}
+
+	/// Creates the database file if it's missing, connects, and brings it up to date
+	pub async fn open(database_url: &str) -> anyhow::Result<SqliteStore> {
+		if !Sqlite::database_exists(database_url).await.unwrap_or(false) {
+			info!("Creating database {}", database_url);
+			Sqlite::create_database(database_url).await?;
+		} else {
+			warn!("Database already exists");
+		} // TODO: Do in Docker with Sqlx-cli
+
+		let store = SqliteStore::new(SqlitePool::connect(database_url).await?);
+		store.migrate().await?;
+		Ok(store)
+	}
+
+	pub async fn migrate(&self) -> anyhow::Result<()> {
+		info!("Running migrations...");
+		MIGRATOR.run(&self.db_pool).await?; // TODO: Do in Docker with Sqlx-cli
+		info!("Migrations complete!");
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl AuthStore for SqliteStore {
+	async fn health(&self) -> Health {
+		health::check(&self.db_pool, &MIGRATOR).await
+	}
+
+	async fn close(&self) {
+		self.db_pool.close().await
+	}
+
+	async fn username_exists(&self, username: &str) -> sqlx::Result<bool> {
+		query_scalar("SELECT EXISTS (SELECT 1 FROM user WHERE username = ?)").bind(username).fetch_one(&self.db_pool).await
+	}
+
+	async fn email_exists(&self, email: &str) -> sqlx::Result<bool> {
+		query_scalar("SELECT EXISTS (SELECT 1 FROM user WHERE email = ?)").bind(email).fetch_one(&self.db_pool).await
+	}
+
+	async fn create_user(&self, username: &str, email: &str, display_name: &str, login_code: u32) -> sqlx::Result<()> {
+		query("INSERT INTO user (username, email, new_email, display_name, avatar, servers, login_code) VALUES (?, ?, '', ?, '', '', ?)")
+			.bind(username).bind(email).bind(display_name).bind(login_code)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_user(&self, username: &str) -> sqlx::Result<AuthUser> {
+		let row = query("SELECT id, username, email, display_name, avatar, servers FROM user WHERE username = ?")
+			.bind(username)
+			.fetch_one(&self.db_pool).await?;
+
+		Ok(AuthUser {
+			id: row.try_get("id")?,
+			username: row.try_get("username")?,
+			email: row.try_get("email")?,
+			display_name: row.try_get("display_name")?,
+			avatar: row.try_get("avatar")?,
+			servers: row.try_get("servers")?,
+			login_code: None,
+		})
+	}
+
+	async fn get_username_by_email(&self, email: &str) -> sqlx::Result<String> {
+		query_scalar("SELECT username FROM user WHERE email = ?").bind(email).fetch_one(&self.db_pool).await
+	}
+
+	async fn delete_user(&self, username: &str) -> sqlx::Result<()> {
+		query("DELETE FROM block WHERE username = ?").bind(username).execute(&self.db_pool).await?;
+		query("DELETE FROM session WHERE username = ?").bind(username).execute(&self.db_pool).await?;
+		query("DELETE FROM user WHERE username = ?").bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_login_code(&self, username: &str) -> sqlx::Result<Option<u32>> {
+		query_scalar("SELECT login_code FROM user WHERE username = ?").bind(username).fetch_one(&self.db_pool).await
+	}
+
+	async fn set_login_code(&self, username: &str, login_code: Option<u32>) -> sqlx::Result<()> {
+		query("UPDATE user SET login_code = ? WHERE username = ?").bind(login_code).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn create_session(&self, username: &str, token_hash: &str, session: &Session) -> sqlx::Result<i64> {
+		query_scalar("INSERT INTO session (username, token_hash, device_name, client_version, ip, created, last_used, expires) VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING id")
+			.bind(username).bind(token_hash).bind(&session.device_name).bind(&session.client_version).bind(&session.ip)
+			.bind(session.created).bind(session.last_used).bind(session.expires)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn find_session(&self, username: &str, token_hash: &str, now: DateTime<Utc>) -> sqlx::Result<Option<Session>> {
+		let row = query("SELECT id, device_name, client_version, ip, created, last_used, expires FROM session WHERE username = ? AND token_hash = ? AND expires > ?")
+			.bind(username).bind(token_hash).bind(now)
+			.fetch_optional(&self.db_pool).await?;
+		row.map(|row| session(&row, true)).transpose()
+	}
+
+	async fn get_sessions(&self, username: &str, now: DateTime<Utc>) -> sqlx::Result<Vec<Session>> {
+		let rows = query("SELECT id, device_name, client_version, ip, created, last_used, expires FROM session WHERE username = ? AND expires > ? ORDER BY id")
+			.bind(username).bind(now)
+			.fetch_all(&self.db_pool).await?;
+		rows.iter().map(|row| session(row, false)).collect()
+	}
+
+	async fn get_session_token_hashes(&self, username: &str, now: DateTime<Utc>) -> sqlx::Result<Vec<String>> {
+		query_scalar("SELECT token_hash FROM session WHERE username = ? AND expires > ? ORDER BY id").bind(username).bind(now).fetch_all(&self.db_pool).await
+	}
+
+	async fn touch_session(&self, id: i64, last_used: DateTime<Utc>, ip: &str) -> sqlx::Result<()> {
+		query("UPDATE session SET last_used = ?, ip = ? WHERE id = ?").bind(last_used).bind(ip).bind(id).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn delete_session(&self, username: &str, id: i64) -> sqlx::Result<bool> {
+		let result = query("DELETE FROM session WHERE username = ? AND id = ?").bind(username).bind(id).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn delete_sessions(&self, username: &str) -> sqlx::Result<u64> {
+		let result = query("DELETE FROM session WHERE username = ?").bind(username).execute(&self.db_pool).await?;
+		Ok(result.rows_affected())
+	}
+
+	async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> sqlx::Result<u64> {
+		let result = query("DELETE FROM session WHERE expires <= ?").bind(now).execute(&self.db_pool).await?;
+		Ok(result.rows_affected())
+	}
+
+	async fn set_new_email(&self, username: &str, new_email: Option<&str>) -> sqlx::Result<()> {
+		query("UPDATE user SET new_email = ? WHERE username = ?").bind(new_email).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_email(&self, username: &str, email: &str) -> sqlx::Result<()> {
+		query("UPDATE user SET email = ? WHERE username = ?").bind(email).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_avatar(&self, username: &str, avatar: &str) -> sqlx::Result<()> {
+		query("UPDATE user SET avatar = ? WHERE username = ?").bind(avatar).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_display_name(&self, username: &str, display_name: &str) -> sqlx::Result<()> {
+		query("UPDATE user SET display_name = ? WHERE username = ?").bind(display_name).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_servers(&self, username: &str) -> sqlx::Result<Vec<String>> {
+		let servers: String = query_scalar("SELECT servers FROM user WHERE username = ?").bind(username).fetch_one(&self.db_pool).await?;
+		Ok(split(&servers, '|'))
+	}
+
+	async fn set_servers(&self, username: &str, servers: &[String]) -> sqlx::Result<()> {
+		query("UPDATE user SET servers = ? WHERE username = ?").bind(servers.join("|")).bind(username).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn add_block(&self, username: &str, blocked_username: &str, created: DateTime<Utc>) -> sqlx::Result<()> {
+		query("INSERT OR IGNORE INTO block (username, blocked_username, created) VALUES (?, ?, ?)")
+			.bind(username).bind(blocked_username).bind(created)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn remove_block(&self, username: &str, blocked_username: &str) -> sqlx::Result<()> {
+		query("DELETE FROM block WHERE username = ? AND blocked_username = ?")
+			.bind(username).bind(blocked_username)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_blocked(&self, username: &str) -> sqlx::Result<Vec<String>> {
+		query_scalar("SELECT blocked_username FROM block WHERE username = ? ORDER BY id").bind(username).fetch_all(&self.db_pool).await
+	}
+}
+
+fn session(row: &SqliteRow, current: bool) -> sqlx::Result<Session> {
+	Ok(Session {
+		id: row.try_get("id")?,
+		device_name: row.try_get("device_name")?,
+		client_version: row.try_get("client_version")?,
+		ip: row.try_get("ip")?,
+		created: row.try_get("created")?,
+		last_used: row.try_get("last_used")?,
+		expires: row.try_get("expires")?,
+		current,
+	})
+}
@@ -1,28 +1,41 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use realm_shared::health::Health;
 use realm_shared::types::ErrorCode;
 
 #[tarpc::service]
 pub trait RealmAuth {
     async fn test(name: String) -> String;
+    async fn health() -> Health;
     async fn server_token_validation(server_token: String, username: String, server_id: String, domain: String, tarpc_port: u16) -> bool;
+    async fn server_get_blocked_users(server_token: String, username: String, server_id: String, domain: String, tarpc_port: u16) -> Result<Vec<String>, ErrorCode>;
     async fn create_account_flow(username: String, email: String) -> Result<(), ErrorCode>; //NOTE: Still require sign in flow
     async fn create_login_flow(username: Option<String>, email: Option<String>) -> Result<(), ErrorCode>;
-    async fn finish_login_flow(username: String, login_code: u32) -> Result<String, ErrorCode>;
+    async fn finish_login_flow(username: String, login_code: u32, device_name: String, client_version: String) -> Result<String, ErrorCode>; //NOTE: Starts a session
     
     //NOTE: Need to be the user
+    async fn get_server_token(username: String, token: String, server_id: String, domain: String, tarpc_port: u16) -> Result<String, ErrorCode>; //NOTE: The same for a session and server until the stoken key changes
     async fn change_email_flow(username: String, new_email: String, token: String) -> Result<(), ErrorCode>;
     async fn finish_change_email_flow(username: String, new_email: String, token: String, login_code: u32) -> Result<(), ErrorCode>;
     // async fn change_username(username: String, token: String, new_username: String) -> Result<(), ErrorCode>;
     async fn change_avatar(username: String, token: String, new_avatar: String) -> Result<(), ErrorCode>;
+    async fn change_display_name(username: String, token: String, new_display_name: String) -> Result<(), ErrorCode>;
     async fn get_all_data(username: String, token: String) -> Result<AuthUser, ErrorCode>;
     async fn sign_out(username: String, token: String) -> Result<(), ErrorCode>;
+    async fn list_sessions(username: String, token: String) -> Result<Vec<Session>, ErrorCode>;
+    async fn revoke_session(username: String, token: String, session_id: i64) -> Result<(), ErrorCode>;
+    async fn sign_out_everywhere(username: String, token: String) -> Result<(), ErrorCode>; //NOTE: This session included
     async fn delete_account(username: String, token: String) -> Result<(), ErrorCode>;
     async fn add_server(username: String, token: String, domain: String, port: u16) -> Result<(), ErrorCode>;
     async fn remove_server(username: String, token: String, domain: String, port: u16) -> Result<(), ErrorCode>;
     async fn get_joined_servers(username: String, token: String) -> Result<Vec<String>, ErrorCode>;
+    async fn block_user(username: String, token: String, blocked_username: String) -> Result<(), ErrorCode>;
+    async fn unblock_user(username: String, token: String, blocked_username: String) -> Result<(), ErrorCode>;
+    async fn get_blocked_users(username: String, token: String) -> Result<Vec<String>, ErrorCode>;
     
     //NOTE: Anyone can call
     async fn get_avatar_for_user(username: String) -> Result<String, ErrorCode>;
+    async fn get_display_name_for_user(username: String) -> Result<String, ErrorCode>;
     // TODO: OAuth login, check against email, store token, take avatar: Google, Apple, GitHub, Discord
 }
 
@@ -31,18 +44,21 @@ pub struct AuthUser {
     pub id: i64,
     pub username: String,
     pub email: String,
+    pub display_name: String,
     pub avatar: String,
     pub servers: String,
     pub login_code: Option<u32>,
-    pub bigtoken: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AuthEmail {
-    pub server_address: String,
-    pub server_port: u16,
-    pub auth_name: String,
-    pub auth_from_address: String,
-    pub auth_username: String,
-    pub auth_password: String,
-}
\ No newline at end of file
+/// A signed in device, as `list_sessions` shows it. Its token never leaves the auth server after sign-in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: i64,
+    pub device_name: String,
+    pub client_version: String,
+    pub ip: String, //NOTE: Last used from
+    pub created: DateTime<Utc>,
+    pub last_used: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    pub current: bool, //NOTE: Whether it's the one asking
+}
@@ -0,0 +1,101 @@
+//! The mailers that don't need a mail server, and the email templates.
+
+use std::fs;
+use std::path::PathBuf;
+use lettre::message::Mailbox;
+use realm_auth::mailer::{EmailKind, Mail, Mailer, MaildirMailer, StdoutMailer, Templates};
+
+const SHIPPED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/templates");
+
+fn from() -> Mailbox {
+	"Realm <realm@example.com>".parse().unwrap()
+}
+
+fn mail(to_address: &str) -> Mail {
+	Mail {
+		to_name: "@alice:localhost".to_string(),
+		to_address: to_address.to_string(),
+		subject: "Realm confirmation code: 123456".to_string(),
+		text: "123456".to_string(),
+		html: "<p>123456</p>".to_string(),
+	}
+}
+
+#[tokio::test]
+async fn maildir_delivers_into_new() {
+	let dir = std::env::temp_dir().join(format!("realm_auth_maildir_{}", std::process::id()));
+	let mailer = MaildirMailer::open(dir.to_str().unwrap(), from()).unwrap();
+
+	mailer.send(mail("alice@example.com")).await.unwrap();
+	mailer.send(mail("alice@example.com")).await.unwrap();
+
+	let delivered = fs::read_dir(dir.join("new")).unwrap().map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+	assert_eq!(delivered.len(), 2);
+	assert_eq!(fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+
+	let message = fs::read_to_string(&delivered[0]).unwrap();
+	assert!(message.lines().any(|line| line.starts_with("To: ") && line.ends_with("<alice@example.com>")));
+	assert!(message.contains("Subject: Realm confirmation code: 123456"));
+	assert!(!message.contains("Bcc:"));
+
+	fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn bad_addresses_fail() {
+	let dir = std::env::temp_dir().join(format!("realm_auth_maildir_bad_{}", std::process::id()));
+	let maildir = MaildirMailer::open(dir.to_str().unwrap(), from()).unwrap();
+
+	assert!(maildir.send(mail("not an address")).await.is_err());
+	assert!(StdoutMailer::new(from()).send(mail("not an address")).await.is_err());
+	assert_eq!(fs::read_dir(dir.join("new")).unwrap().count(), 0);
+
+	fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn shipped_templates_render() {
+	let templates = Templates::load(SHIPPED, "en").unwrap();
+
+	for kind in EmailKind::ALL {
+		let rendered = templates.render(kind, &[("username", "@alice:localhost"), ("domain", "localhost"), ("login_code", "123456")]);
+		assert!(!rendered.subject.is_empty() && !rendered.text.is_empty());
+		assert!(rendered.html.contains("Realm, Inc."), "{} isn't in the layout", kind.name());
+		assert!(!rendered.html.contains("{{"));
+	}
+
+	let rendered = templates.render(EmailKind::LoginCode, &[("username", "<b>@alice:localhost</b>"), ("login_code", "123456")]);
+	assert_eq!(rendered.subject, "Realm confirmation code: 123456");
+	assert!(rendered.text.contains("<b>@alice:localhost</b>"));
+	assert!(rendered.html.contains("&lt;b&gt;@alice:localhost&lt;/b&gt;"));
+}
+
+/// A copy of the shipped templates with `file` replaced, loaded for the error it gives
+fn load_with(file: &str, contents: Option<&str>) -> String {
+	let dir = std::env::temp_dir().join(format!("realm_auth_templates_{}_{}", std::process::id(), file));
+	let locale = dir.join("en");
+	fs::create_dir_all(&locale).unwrap();
+	for entry in fs::read_dir(PathBuf::from(SHIPPED).join("en")).unwrap() {
+		let path = entry.unwrap().path();
+		fs::copy(&path, locale.join(path.file_name().unwrap())).unwrap();
+	}
+	match contents {
+		Some(contents) => fs::write(locale.join(file), contents).unwrap(),
+		None => fs::remove_file(locale.join(file)).unwrap(),
+	}
+
+	let error = Templates::load(dir.to_str().unwrap(), "en").err().map(|e| e.to_string()).unwrap_or_default();
+	fs::remove_dir_all(dir).unwrap();
+	error
+}
+
+#[test]
+fn templates_are_checked() {
+	assert!(load_with("welcome.txt", Some("Subject: Hi\n\nYour code is {{login_code}} {{password}}")).contains("unknown variable {{password}}"));
+	assert!(load_with("login_code.html", Some("<p>Hi {{username}}</p>")).contains("missing variable {{login_code}}"));
+	assert!(load_with("new_device.txt", Some("Hi {{username}}")).contains("must start with a Subject: line"));
+	assert!(load_with("email_changed.html", Some("<p>{{old_email</p>")).contains("unclosed {{"));
+	assert!(load_with("layout.html", Some("<p>Realm</p>")).contains("missing variable {{content}}"));
+	assert!(load_with("account_deleted.html", None).contains("account_deleted.html"));
+	assert!(Templates::load(SHIPPED, "xx").is_err());
+}
@@ -0,0 +1,268 @@
+//! RPC logic run against `MemoryStore`, without a database or a mail server.
+//! Mail goes to an `Outbox` the tests can read login codes back from.
+
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use chrono::{TimeDelta, Utc};
+use sha3::digest::Update;
+use sha3::{Digest, Sha3_256};
+use tarpc::context;
+use realm_auth::config::AuthConfig;
+use realm_auth::mailer::{Mail, Mailer, Templates};
+use realm_auth::metrics::AuthServerMetrics;
+use realm_auth::server::RealmAuthServer;
+use realm_auth::stoken::StokenKey;
+use realm_auth::store::{AuthStore, MemoryStore};
+use realm_auth::types::RealmAuth;
+use realm_shared::types::ErrorCode;
+
+const ALICE: &str = "@alice:localhost";
+const LOGIN_CODE: u32 = 123456;
+
+/// Keeps what's sent, or refuses everything once `broken`
+#[derive(Default)]
+struct Outbox {
+	sent: Mutex<Vec<Mail>>,
+	broken: bool,
+}
+
+#[async_trait]
+impl Mailer for Outbox {
+	async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+		anyhow::ensure!(!self.broken, "mail server unreachable");
+		self.sent.lock().unwrap().push(mail);
+		Ok(())
+	}
+}
+
+impl Outbox {
+	fn subjects(&self) -> Vec<String> {
+		self.sent.lock().unwrap().iter().map(|mail| mail.subject.clone()).collect()
+	}
+
+	//NOTE: The shipped subjects end with the code
+	fn last_login_code(&self) -> u32 {
+		let sent = self.sent.lock().unwrap();
+		sent.last().unwrap().subject.rsplit(' ').next().unwrap().parse().unwrap()
+	}
+}
+
+fn server_with(store: Arc<MemoryStore>, outbox: Arc<Outbox>) -> RealmAuthServer {
+	let mut config = AuthConfig::default();
+	config.server.domain = "localhost".to_string();
+
+	let templates = Templates::load(concat!(env!("CARGO_MANIFEST_DIR"), "/templates"), "en").unwrap();
+	RealmAuthServer::new(&config, "127.0.0.1:5000".parse().unwrap(), store, outbox, Arc::new(templates), StokenKey::generate(), Arc::new(AuthServerMetrics::default()))
+}
+
+async fn server() -> (RealmAuthServer, Arc<MemoryStore>) {
+	let store = Arc::new(MemoryStore::new());
+	store.create_user(ALICE, "alice@example.com", "alice", LOGIN_CODE).await.unwrap();
+
+	(server_with(store.clone(), Arc::new(Outbox::default())), store)
+}
+
+async fn sign_in(server: &RealmAuthServer) -> String {
+	sign_in_from(server, "laptop").await
+}
+
+async fn sign_in_from(server: &RealmAuthServer, device_name: &str) -> String {
+	finish_login(server, ALICE, LOGIN_CODE, device_name).await.unwrap()
+}
+
+async fn finish_login(server: &RealmAuthServer, username: &str, login_code: u32, device_name: &str) -> Result<String, ErrorCode> {
+	server.clone().finish_login_flow(context::current(), username.to_string(), login_code, device_name.to_string(), "0.1.0".to_string()).await
+}
+
+#[tokio::test]
+async fn create_account() {
+	let (store, outbox) = (Arc::new(MemoryStore::new()), Arc::new(Outbox::default()));
+	let server = server_with(store.clone(), outbox.clone());
+
+	server.clone().create_account_flow(context::current(), "@bob:localhost".to_string(), "bob@example.com".to_string()).await.unwrap();
+	let mail = outbox.sent.lock().unwrap()[0].clone();
+	assert_eq!((mail.to_name.as_str(), mail.to_address.as_str()), ("@bob:localhost", "bob@example.com"));
+	assert!(mail.subject.starts_with("Welcome to Realm!"));
+	assert!(mail.text.contains(&outbox.last_login_code().to_string()));
+	assert!(mail.html.contains(&outbox.last_login_code().to_string()));
+
+	let result = server.clone().create_account_flow(context::current(), "@bob:localhost".to_string(), "robert@example.com".to_string()).await;
+	assert_eq!(result, Err(ErrorCode::UsernameTaken));
+	let result = server.clone().create_account_flow(context::current(), "@robert:localhost".to_string(), "bob@example.com".to_string()).await;
+	assert_eq!(result, Err(ErrorCode::EmailTaken));
+
+	finish_login(&server, "@bob:localhost", outbox.last_login_code(), "laptop").await.unwrap();
+	assert_eq!(store.get_user("@bob:localhost").await.unwrap().display_name, "bob");
+}
+
+#[tokio::test]
+async fn mail_failures_are_reported() {
+	let store = Arc::new(MemoryStore::new());
+	let server = server_with(store.clone(), Arc::new(Outbox { broken: true, ..Outbox::default() }));
+
+	let result = server.clone().create_account_flow(context::current(), "@bob:localhost".to_string(), "bob@example.com".to_string()).await;
+	assert_eq!(result, Err(ErrorCode::UnableToSendMail));
+	assert!(!store.username_exists("@bob:localhost").await.unwrap());
+}
+
+#[tokio::test]
+async fn login_codes_are_single_use() {
+	let (server, store) = server().await;
+
+	assert_eq!(finish_login(&server, ALICE, 654321, "laptop").await, Err(ErrorCode::InvalidLoginCode));
+
+	let token = sign_in(&server).await;
+	assert_eq!(store.get_session_token_hashes(ALICE, Utc::now()).await.unwrap(), [realm_shared::hash_token(&token)]);
+	assert_eq!(store.get_login_code(ALICE).await.unwrap(), None);
+
+	assert_eq!(finish_login(&server, ALICE, LOGIN_CODE, "laptop").await, Err(ErrorCode::InvalidLoginCode));
+}
+
+#[tokio::test]
+async fn sign_out_revokes_only_that_token() {
+	let (server, store) = server().await;
+	let first = sign_in(&server).await;
+	store.set_login_code(ALICE, Some(LOGIN_CODE)).await.unwrap();
+	let second = sign_in(&server).await;
+
+	server.clone().sign_out(context::current(), ALICE.to_string(), first.clone()).await.unwrap();
+	assert_eq!(server.clone().get_joined_servers(context::current(), ALICE.to_string(), first.clone()).await, Err(ErrorCode::Unauthorized));
+	assert!(server.clone().get_joined_servers(context::current(), ALICE.to_string(), second).await.is_ok());
+	assert_eq!(server.clone().sign_out(context::current(), ALICE.to_string(), first).await, Err(ErrorCode::Unauthorized));
+}
+
+#[tokio::test]
+async fn sessions() {
+	let (server, store) = server().await;
+	let laptop = sign_in_from(&server, "laptop").await;
+	store.set_login_code(ALICE, Some(LOGIN_CODE)).await.unwrap();
+	let phone = sign_in_from(&server, "  ").await;
+	let list = |token: &str| server.clone().list_sessions(context::current(), ALICE.to_string(), token.to_string());
+
+	let sessions = list(&phone).await.unwrap();
+	let shown: Vec<_> = sessions.iter().map(|s| (s.device_name.as_str(), s.client_version.as_str(), s.ip.as_str(), s.current)).collect();
+	assert_eq!(shown, [("laptop", "0.1.0", "127.0.0.1", false), ("Unknown device", "0.1.0", "127.0.0.1", true)]);
+	assert!(sessions.iter().all(|s| s.expires == s.created + TimeDelta::days(90)));
+
+	let result = server.clone().revoke_session(context::current(), ALICE.to_string(), phone.clone(), sessions[0].id + 100).await;
+	assert_eq!(result, Err(ErrorCode::SessionNotFound));
+	server.clone().revoke_session(context::current(), ALICE.to_string(), phone.clone(), sessions[0].id).await.unwrap();
+	assert_eq!(list(&laptop).await, Err(ErrorCode::Unauthorized));
+	assert_eq!(list(&phone).await.unwrap().len(), 1);
+
+	store.set_login_code(ALICE, Some(LOGIN_CODE)).await.unwrap();
+	let desktop = sign_in_from(&server, "desktop").await;
+	server.clone().sign_out_everywhere(context::current(), ALICE.to_string(), desktop.clone()).await.unwrap();
+	assert_eq!(list(&phone).await, Err(ErrorCode::Unauthorized));
+	assert_eq!(list(&desktop).await, Err(ErrorCode::Unauthorized));
+
+	store.set_login_code(ALICE, Some(LOGIN_CODE)).await.unwrap();
+	assert_eq!(finish_login(&server, ALICE, LOGIN_CODE, &"x".repeat(65)).await, Err(ErrorCode::InputTooLong));
+}
+
+#[tokio::test]
+async fn server_tokens() {
+	let (server, _) = server().await;
+	let token = sign_in(&server).await;
+	let issue = |token: &str, port: u16| server.clone().get_server_token(context::current(), ALICE.to_string(), token.to_string(), "realm".to_string(), "localhost".to_string(), port);
+	let validate = |stoken: String, port: u16| server.clone().server_token_validation(context::current(), stoken, ALICE.to_string(), "realm".to_string(), "localhost".to_string(), port);
+
+	let stoken = issue(&token, 5051).await.unwrap();
+	assert_eq!(issue(&token, 5051).await.unwrap(), stoken);
+	assert_eq!(issue("not-a-token", 5051).await, Err(ErrorCode::Unauthorized));
+	assert!(validate(stoken.clone(), 5051).await);
+	assert!(!validate(stoken.clone(), 5052).await);
+	assert!(!validate(token.clone(), 5051).await);
+
+	server.clone().sign_out(context::current(), ALICE.to_string(), token.clone()).await.unwrap();
+	assert!(!validate(stoken, 5051).await);
+}
+
+#[tokio::test]
+async fn leaked_token_hashes_dont_make_stokens() {
+	let (server, store) = server().await;
+	sign_in(&server).await;
+	let validate = |stoken: String| server.clone().server_token_validation(context::current(), stoken, ALICE.to_string(), "realm".to_string(), "localhost".to_string(), 5051);
+
+	let hash = store.get_session_token_hashes(ALICE, Utc::now()).await.unwrap().remove(0);
+	let unkeyed = hex::encode(Sha3_256::new().chain(format!("{}realmlocalhost5051", hash)).finalize());
+	assert!(!validate(hash.clone()).await);
+	assert!(!validate(unkeyed).await);
+	assert!(!validate(StokenKey::generate().stoken(&hash, "realm", "localhost", 5051)).await);
+	assert!(validate(server.stoken_key.stoken(&hash, "realm", "localhost", 5051)).await);
+}
+
+#[test]
+fn stoken_keys_outlive_restarts() {
+	let path = std::env::temp_dir().join(format!("realm-stoken-{}.key", std::process::id()));
+	let path = path.to_str().unwrap();
+
+	let generated = StokenKey::load_or_generate(path).unwrap();
+	let loaded = StokenKey::load_or_generate(path).unwrap();
+	assert_eq!(generated.stoken("hash", "realm", "localhost", 5051), loaded.stoken("hash", "realm", "localhost", 5051));
+
+	std::fs::write(path, "not hex").unwrap();
+	assert!(StokenKey::load_or_generate(path).is_err());
+	std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn server_list() {
+	let (server, _) = server().await;
+	let token = sign_in(&server).await;
+	let add = |domain: &str| server.clone().add_server(context::current(), ALICE.to_string(), token.clone(), domain.to_string(), 5051);
+	let remove = |domain: &str| server.clone().remove_server(context::current(), ALICE.to_string(), token.clone(), domain.to_string(), 5051);
+	let joined = || server.clone().get_joined_servers(context::current(), ALICE.to_string(), token.clone());
+
+	add("localhost").await.unwrap();
+	add("chat.example.com").await.unwrap();
+	assert_eq!(add("localhost").await, Err(ErrorCode::AlreadyJoinedServer));
+	assert_eq!(joined().await.unwrap(), ["localhost:5051", "chat.example.com:5051"]);
+
+	remove("localhost").await.unwrap();
+	assert_eq!(remove("localhost").await, Err(ErrorCode::NotInServer));
+	assert_eq!(joined().await.unwrap(), ["chat.example.com:5051"]);
+}
+
+#[tokio::test]
+async fn blocks() {
+	let (server, _) = server().await;
+	let token = sign_in(&server).await;
+
+	let result = server.clone().block_user(context::current(), ALICE.to_string(), token.clone(), ALICE.to_string()).await;
+	assert_eq!(result, Err(ErrorCode::InvalidUsername));
+	let result = server.clone().block_user(context::current(), ALICE.to_string(), token.clone(), "bob".to_string()).await;
+	assert_eq!(result, Err(ErrorCode::InvalidUsername));
+
+	server.clone().block_user(context::current(), ALICE.to_string(), token.clone(), "@bob:localhost".to_string()).await.unwrap();
+	assert_eq!(server.clone().get_blocked_users(context::current(), ALICE.to_string(), token.clone()).await.unwrap(), ["@bob:localhost"]);
+
+	server.clone().unblock_user(context::current(), ALICE.to_string(), token.clone(), "@bob:localhost".to_string()).await.unwrap();
+	assert!(server.clone().get_blocked_users(context::current(), ALICE.to_string(), token).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn notices() {
+	let (store, outbox) = (Arc::new(MemoryStore::new()), Arc::new(Outbox::default()));
+	store.create_user(ALICE, "alice@example.com", "alice", LOGIN_CODE).await.unwrap();
+	let server = server_with(store.clone(), outbox.clone());
+
+	let token = sign_in(&server).await;
+	assert!(outbox.subjects().is_empty());
+	store.set_login_code(ALICE, Some(LOGIN_CODE)).await.unwrap();
+	sign_in_from(&server, "phone").await;
+	assert_eq!(outbox.subjects(), ["New sign-in to your Realm account"]);
+	assert!(outbox.sent.lock().unwrap()[0].text.contains("Device: phone\n"));
+	assert!(outbox.sent.lock().unwrap()[0].text.contains("IP address: 127.0.0.1"));
+
+	server.clone().change_email_flow(context::current(), ALICE.to_string(), "alice@example.org".to_string(), token.clone()).await.unwrap();
+	let code = outbox.last_login_code();
+	server.clone().finish_change_email_flow(context::current(), ALICE.to_string(), "alice@example.org".to_string(), token.clone(), code).await.unwrap();
+	let sent = outbox.sent.lock().unwrap().clone();
+	assert_eq!((sent[1].to_address.as_str(), sent[2].to_address.as_str()), ("alice@example.org", "alice@example.com"));
+	assert_eq!(sent[2].subject, "Your Realm email address was changed");
+
+	server.clone().delete_account(context::current(), ALICE.to_string(), token).await.unwrap();
+	let sent = outbox.sent.lock().unwrap().clone();
+	assert_eq!((sent[3].to_address.as_str(), sent[3].subject.as_str()), ("alice@example.org", "Your Realm account was deleted"));
+}
@@ -0,0 +1,207 @@
+//! The same checks run against every `AuthStore` backend.
+//!
+//! `MemoryStore` and SQLite run in memory. The Postgres tests need a local server and are ignored by default,
+//! run them with `cargo test --test store -- --ignored`. Each test creates, and then drops, its
+//! own database on the server at `REALM_TEST_POSTGRES_URL` (default `postgres://postgres@localhost/postgres`).
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use chrono::{TimeDelta, Timelike, Utc};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{query, PgPool};
+use realm_auth::store::{AuthStore, MemoryStore, PostgresStore, SqliteStore};
+use realm_auth::types::Session;
+
+async fn sqlite() -> SqliteStore {
+	// One connection, as every connection to `sqlite::memory:` is its own database
+	let db_pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+	let store = SqliteStore::new(db_pool);
+	store.migrate().await.unwrap();
+	store
+}
+
+/// A fresh database on the local Postgres server, dropped by `drop_postgres`
+async fn postgres() -> (PostgresStore, String) {
+	static NEXT: AtomicU32 = AtomicU32::new(0);
+	let name = format!("realm_auth_test_{}_{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+
+	let admin = PgPool::connect(&postgres_url()).await.unwrap();
+	query(&format!("DROP DATABASE IF EXISTS {name} WITH (FORCE)")).execute(&admin).await.unwrap();
+	query(&format!("CREATE DATABASE {name}")).execute(&admin).await.unwrap();
+	admin.close().await;
+
+	let mut url = postgres_url();
+	url.truncate(url.rfind('/').unwrap() + 1);
+	url.push_str(&name);
+
+	let store = PostgresStore::new(PgPoolOptions::new().connect(&url).await.unwrap());
+	store.migrate().await.unwrap();
+	(store, name)
+}
+
+async fn drop_postgres(store: PostgresStore, name: String) {
+	store.close().await;
+	let admin = PgPool::connect(&postgres_url()).await.unwrap();
+	query(&format!("DROP DATABASE IF EXISTS {name} WITH (FORCE)")).execute(&admin).await.unwrap();
+}
+
+fn postgres_url() -> String {
+	std::env::var("REALM_TEST_POSTGRES_URL").unwrap_or("postgres://postgres@localhost/postgres".to_string())
+}
+
+/// Runs each check once per backend
+macro_rules! store_tests {
+	($($check:ident),* $(,)?) => {
+		mod memory {
+			$(
+				#[tokio::test]
+				async fn $check() {
+					super::$check(&super::MemoryStore::new()).await;
+				}
+			)*
+		}
+
+		mod sqlite {
+			$(
+				#[tokio::test]
+				async fn $check() {
+					super::$check(&super::sqlite().await).await;
+				}
+			)*
+		}
+
+		mod postgres {
+			$(
+				#[tokio::test]
+				#[ignore = "needs a local PostgreSQL server"]
+				async fn $check() {
+					let (store, name) = super::postgres().await;
+					super::$check(&store).await;
+					super::drop_postgres(store, name).await;
+				}
+			)*
+		}
+	};
+}
+
+store_tests!(health, accounts, login_codes, sessions, profile, servers, blocks);
+
+async fn health(store: &dyn AuthStore) {
+	assert!(store.health().await.is_ready());
+}
+
+async fn accounts(store: &dyn AuthStore) {
+	assert!(!store.username_exists("@alice:localhost").await.unwrap());
+	store.create_user("@alice:localhost", "alice@example.com", "alice", 123456).await.unwrap();
+
+	assert!(store.username_exists("@alice:localhost").await.unwrap());
+	assert!(store.email_exists("alice@example.com").await.unwrap());
+	assert!(!store.email_exists("bob@example.com").await.unwrap());
+	assert_eq!(store.get_username_by_email("alice@example.com").await.unwrap(), "@alice:localhost");
+	assert!(matches!(store.get_username_by_email("bob@example.com").await, Err(sqlx::Error::RowNotFound)));
+
+	let user = store.get_user("@alice:localhost").await.unwrap();
+	assert_eq!(user.email, "alice@example.com");
+	assert_eq!(user.display_name, "alice");
+	assert_eq!(user.avatar, "");
+	assert_eq!(user.login_code, None);
+
+	store.add_block("@alice:localhost", "@bob:localhost", Utc::now()).await.unwrap();
+	store.create_session("@alice:localhost", "token", &session("laptop", Utc::now())).await.unwrap();
+	store.delete_user("@alice:localhost").await.unwrap();
+	assert!(!store.username_exists("@alice:localhost").await.unwrap());
+	assert!(store.get_blocked("@alice:localhost").await.unwrap().is_empty());
+	assert!(store.get_sessions("@alice:localhost", Utc::now()).await.unwrap().is_empty());
+}
+
+async fn login_codes(store: &dyn AuthStore) {
+	store.create_user("@alice:localhost", "alice@example.com", "alice", 987654).await.unwrap();
+	assert_eq!(store.get_login_code("@alice:localhost").await.unwrap(), Some(987654));
+
+	store.set_login_code("@alice:localhost", None).await.unwrap();
+	assert_eq!(store.get_login_code("@alice:localhost").await.unwrap(), None);
+	store.set_login_code("@alice:localhost", Some(100000)).await.unwrap();
+	assert_eq!(store.get_login_code("@alice:localhost").await.unwrap(), Some(100000));
+}
+
+/// A session made at `created`, lasting a day. Timestamps are whole seconds, as every backend keeps those.
+fn session(device_name: &str, created: chrono::DateTime<Utc>) -> Session {
+	Session {
+		id: 0,
+		device_name: device_name.to_string(),
+		client_version: "0.1.0".to_string(),
+		ip: "127.0.0.1".to_string(),
+		created,
+		last_used: created,
+		expires: created + TimeDelta::days(1),
+		current: false,
+	}
+}
+
+async fn sessions(store: &dyn AuthStore) {
+	let now = Utc::now().with_nanosecond(0).unwrap();
+	let laptop = store.create_session("@alice:localhost", "first", &session("laptop", now)).await.unwrap();
+	let phone = store.create_session("@alice:localhost", "second", &session("phone", now)).await.unwrap();
+	store.create_session("@alice:localhost", "old", &session("tablet", now - TimeDelta::days(2))).await.unwrap();
+	store.create_session("@bob:localhost", "third", &session("desktop", now)).await.unwrap();
+	assert!(store.create_session("@bob:localhost", "third", &session("desktop", now)).await.is_err());
+
+	let found = store.find_session("@alice:localhost", "first", now).await.unwrap().unwrap();
+	assert_eq!(found, Session { id: laptop, current: true, ..session("laptop", now) });
+	assert_eq!(store.find_session("@alice:localhost", "third", now).await.unwrap(), None);
+	assert_eq!(store.find_session("@alice:localhost", "old", now).await.unwrap(), None);
+
+	let listed = store.get_sessions("@alice:localhost", now).await.unwrap();
+	assert_eq!(listed.iter().map(|s| (s.id, s.device_name.as_str(), s.current)).collect::<Vec<_>>(), [(laptop, "laptop", false), (phone, "phone", false)]);
+	assert_eq!(store.get_session_token_hashes("@alice:localhost", now).await.unwrap(), ["first", "second"]);
+
+	store.touch_session(phone, now + TimeDelta::hours(1), "10.0.0.2").await.unwrap();
+	let touched = store.find_session("@alice:localhost", "second", now).await.unwrap().unwrap();
+	assert_eq!((touched.last_used, touched.ip.as_str()), (now + TimeDelta::hours(1), "10.0.0.2"));
+
+	assert!(!store.delete_session("@bob:localhost", laptop).await.unwrap());
+	assert!(store.delete_session("@alice:localhost", laptop).await.unwrap());
+	assert_eq!(store.get_session_token_hashes("@alice:localhost", now).await.unwrap(), ["second"]);
+
+	assert_eq!(store.delete_expired_sessions(now).await.unwrap(), 1);
+	assert_eq!(store.delete_sessions("@alice:localhost").await.unwrap(), 1);
+	assert!(store.get_sessions("@alice:localhost", now).await.unwrap().is_empty());
+	assert_eq!(store.get_session_token_hashes("@bob:localhost", now).await.unwrap(), ["third"]);
+}
+
+async fn profile(store: &dyn AuthStore) {
+	store.create_user("@alice:localhost", "alice@example.com", "alice", 123456).await.unwrap();
+
+	store.set_new_email("@alice:localhost", Some("alice@example.org")).await.unwrap();
+	store.set_email("@alice:localhost", "alice@example.org").await.unwrap();
+	store.set_new_email("@alice:localhost", None).await.unwrap();
+	store.set_avatar("@alice:localhost", "https://example.com/alice.png").await.unwrap();
+	store.set_display_name("@alice:localhost", "Alice").await.unwrap();
+
+	let user = store.get_user("@alice:localhost").await.unwrap();
+	assert_eq!(user.email, "alice@example.org");
+	assert_eq!(user.avatar, "https://example.com/alice.png");
+	assert_eq!(user.display_name, "Alice");
+	assert!(!store.email_exists("alice@example.com").await.unwrap());
+}
+
+async fn servers(store: &dyn AuthStore) {
+	store.create_user("@alice:localhost", "alice@example.com", "alice", 123456).await.unwrap();
+	assert!(store.get_servers("@alice:localhost").await.unwrap().is_empty());
+
+	let servers = vec!["localhost:5051".to_string(), "chat.example.com:5051".to_string()];
+	store.set_servers("@alice:localhost", &servers).await.unwrap();
+	assert_eq!(store.get_servers("@alice:localhost").await.unwrap(), servers);
+	assert_eq!(store.get_user("@alice:localhost").await.unwrap().servers, "localhost:5051|chat.example.com:5051");
+}
+
+async fn blocks(store: &dyn AuthStore) {
+	store.add_block("@alice:localhost", "@bob:localhost", Utc::now()).await.unwrap();
+	store.add_block("@alice:localhost", "@carol:localhost", Utc::now()).await.unwrap();
+	store.add_block("@alice:localhost", "@bob:localhost", Utc::now()).await.unwrap();
+	assert_eq!(store.get_blocked("@alice:localhost").await.unwrap(), ["@bob:localhost", "@carol:localhost"]);
+	assert!(store.get_blocked("@bob:localhost").await.unwrap().is_empty());
+
+	store.remove_block("@alice:localhost", "@bob:localhost").await.unwrap();
+	assert_eq!(store.get_blocked("@alice:localhost").await.unwrap(), ["@carol:localhost"]);
+}
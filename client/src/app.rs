@@ -1,20 +1,25 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tarpc::context;
-use tarpc::tokio_serde::formats::Json;
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::{Receiver, Sender};
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tracing::log::*;
-use realm_auth::types::RealmAuthClient;
+use realm_auth::types::{RealmAuthClient, Session};
 use realm_server::events::Event;
-use realm_server::types::{RealmChatClient, Room};
-use realm_shared::stoken;
+use realm_server::types::{AuditLogEntry, AuditLogFilter, PresenceStatus, RealmChatClient, Room, ServerInfo, FEATURES, PROTOCOL_VERSION};
 use realm_shared::types::ErrorCode::*;
 use realm_shared::types::ErrorCode;
+use crate::net;
 use crate::types::{CServer, CUser};
 use crate::ui::gui;
 
+/// Both ends of a broadcast channel, as `broadcast::channel` hands them out
+pub type Channel<T> = (Sender<T>, Receiver<T>);
+
+/// A page of the audit log, and whether it extends the one already shown
+pub type AuditLogPage = Result<(bool, Vec<AuditLogEntry>), ErrorCode>;
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
@@ -74,6 +79,25 @@ pub struct RealmApp {
 	#[serde(skip)]
 	pub info_window_open: bool,
 
+	#[serde(skip)]
+	pub presence_status: PresenceStatus,
+	#[serde(skip)]
+	pub presence_custom_status: String,
+
+	#[serde(skip)]
+	pub audit_window_open: bool,
+	#[serde(skip)]
+	pub audit_window_filter: AuditLogFilter,
+	#[serde(skip)]
+	pub audit_window_entries: Vec<AuditLogEntry>,
+	#[serde(skip)]
+	pub audit_window_has_more: bool,
+
+	#[serde(skip)]
+	pub settings_window_open: bool,
+	#[serde(skip)]
+	pub settings_window_sessions: Vec<Session>,
+
 	#[serde(skip)]
 	pub login_start_channel: (Sender<Result<(), ErrorCode>>, Receiver<Result<(), ErrorCode>>),
 	#[serde(skip)]
@@ -101,6 +125,11 @@ pub struct RealmApp {
 	#[serde(skip)]
 	pub room_changes_channel: (Sender<Result<(CServer, Vec<Room>), ErrorCode>>, Receiver<Result<(CServer, Vec<Room>), ErrorCode>>),
 
+	#[serde(skip)]
+	pub audit_log_channel: Channel<AuditLogPage>,
+	#[serde(skip)]
+	pub sessions_channel: Channel<Result<Vec<Session>, ErrorCode>>,
+
 	#[serde(skip)]
 	pub event_channel: (Sender<(String, (i64, Event))>, Receiver<(String, (i64, Event))>),
 	#[serde(skip)]
@@ -142,6 +171,17 @@ impl Default for RealmApp {
 
 			info_window_open: false,
 
+			presence_status: PresenceStatus::Online,
+			presence_custom_status: String::new(),
+
+			audit_window_open: false,
+			audit_window_filter: AuditLogFilter::default(),
+			audit_window_entries: Vec::new(),
+			audit_window_has_more: false,
+
+			settings_window_open: false,
+			settings_window_sessions: Vec::new(),
+
 			fetching_user_data_channel: broadcast::channel(256),
 			add_server_channel: broadcast::channel(256),
 			remove_server_channel: broadcast::channel(256),
@@ -151,6 +191,8 @@ impl Default for RealmApp {
 			add_room_channel: broadcast::channel(256),
 			delete_room_channel: broadcast::channel(256),
 			room_changes_channel: broadcast::channel(256),
+			audit_log_channel: broadcast::channel(256),
+			sessions_channel: broadcast::channel(256),
 			event_channel: broadcast::channel(256),
 			polling_threads: Vec::new(),
 		}
@@ -174,12 +216,37 @@ impl RealmApp {
 
 		Default::default()
 	}
+
+	/// Forgets who's signed in, after signing out or once the session is gone
+	pub fn clear_user(&mut self) {
+		self.current_user = None;
+		self.saved_username = None;
+		self.saved_token = None;
+		self.saved_auth_address = None;
+
+		self.active_servers = None;
+		self.selected_roomid.clear();
+		self.selected_serverid.clear();
+
+		self.settings_window_open = false;
+		self.settings_window_sessions.clear();
+	}
+}
+
+/// What the auth server lists this sign-in as, the machine's name where the OS gives it out
+pub fn device_name() -> String {
+	let os = std::env::consts::OS;
+	match std::env::var("HOSTNAME").or_else(|_| std::env::var("COMPUTERNAME")) {
+		Ok(host) if !host.is_empty() => format!("{} ({})", host, os),
+		_ => os.to_string(),
+	}
 }
 
+pub const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 pub fn fetch_user_data(send_channel: Sender<Result<CUser, ErrorCode>>, server_address: String, username: String, token: String) {
 	let _handle = tokio::spawn(async move {
-		let mut transport = tarpc::serde_transport::tcp::connect(&server_address, Json::default);
-		transport.config_mut().max_frame_length(usize::MAX);
+		let transport = net::connect(&server_address);
 
 		let result = transport.await;
 		let auth_connection = match result {
@@ -191,7 +258,9 @@ pub fn fetch_user_data(send_channel: Sender<Result<CUser, ErrorCode>>, server_ad
 		};
 
 		let client = RealmAuthClient::new(tarpc::client::Config::default(), auth_connection).spawn();
-		let result = client.get_all_data(context::current(), username, token.clone()).await;
+		let result = client.get_all_data(context::current(), username.clone(), token.clone()).await;
+		let blocked_users = client.get_blocked_users(context::current(), username, token.clone()).await
+			.ok().and_then(|r| r.ok()).unwrap_or_default();
 
 		match result {
 			Ok(r) => {
@@ -207,6 +276,7 @@ pub fn fetch_user_data(send_channel: Sender<Result<CUser, ErrorCode>>, server_ad
 						//avatar: auth_user.avatar,
 						server_addresses: auth_user.servers.split('|').map(|s| s.to_string()).collect(),
 						token,
+						blocked_users,
 					})).unwrap();
 				}
 			}
@@ -217,15 +287,47 @@ pub fn fetch_user_data(send_channel: Sender<Result<CUser, ErrorCode>>, server_ad
 	});
 }
 
-pub fn fetch_server_data(channel: Sender<Result<CServer, ErrorCode>>, addresses: Vec<String>, token: String, username: String){
+/// Asks a freshly connected server what it runs, warning about (and refusing) one that speaks another protocol
+pub async fn check_server(client: &RealmChatClient, address: &str) -> Result<ServerInfo, ErrorCode> {
+	let info = match client.get_info(context::current()).await {
+		Ok(info) => info,
+		Err(e) => {
+			warn!("{} didn't answer get_info in a form this client understands, it's likely running an incompatible version: {}", address, e);
+			return Err(IncompatibleServer);
+		}
+	};
+
+	if info.protocol_version != PROTOCOL_VERSION {
+		warn!("{} runs realm_server {} with protocol version {}, but this client speaks protocol version {}",
+			address, info.version, info.protocol_version, PROTOCOL_VERSION);
+		return Err(IncompatibleServer);
+	}
+
+	let missing = FEATURES.iter().filter(|f| !info.supports(f)).collect::<Vec<_>>();
+	if !missing.is_empty() {
+		warn!("{} (realm_server {}) doesn't support {:?}, those parts of the client won't work there", address, info.version, missing);
+	}
+
+	Ok(info)
+}
+
+/// Asks the user's auth server for their stoken on a chat server, which the client can't derive itself
+pub async fn server_token(auth_address: &str, userid: &str, token: &str, server_id: &str, domain: &str, port: u16) -> Result<String, ErrorCode> {
+	let connection = net::connect(auth_address).await.map_err(|_| UnableToConnectToServer)?;
+	let client = RealmAuthClient::new(tarpc::client::Config::default(), connection).spawn();
+	client.get_server_token(context::current(), userid.to_string(), token.to_string(), server_id.to_string(), domain.to_string(), port).await
+		.map_err(|_| RPCError)?
+}
+
+pub fn fetch_server_data(channel: Sender<Result<CServer, ErrorCode>>, addresses: Vec<String>, auth_address: String, token: String, username: String){
 	for server_address in addresses {
 		let send_channel = channel.clone();
+		let auth_address = auth_address.clone();
 		let token = token.clone();
 		let userid = username.clone();
 
 		let _handle = tokio::spawn(async move {
-			let mut transport = tarpc::serde_transport::tcp::connect(&server_address, Json::default);
-			transport.config_mut().max_frame_length(usize::MAX);
+			let transport = net::connect(&server_address);
 
 			let result = transport.await;
 			let connection = match result {
@@ -237,16 +339,34 @@ pub fn fetch_server_data(channel: Sender<Result<CServer, ErrorCode>>, addresses:
 			};
 
 			let client = RealmChatClient::new(tarpc::client::Config::default(), connection).spawn();
-			let info = client.get_info(context::current()).await.unwrap();
+			let info = match check_server(&client, &server_address).await {
+				Ok(info) => info,
+				Err(e) => {
+					send_channel.send(Err(e)).unwrap();
+					return;
+				}
+			};
 			let domain = server_address.split(':').collect::<Vec<&str>>()[0].to_string();
 			let port = server_address.split(':').collect::<Vec<&str>>()[1].to_string().parse::<u16>().unwrap();
-			let stoken = stoken(&token, &info.server_id, &domain, port);
+			let stoken = match server_token(&auth_address, &userid, &token, &info.server_id, &domain, port).await {
+				Ok(stoken) => stoken,
+				Err(e) => {
+					send_channel.send(Err(e)).unwrap();
+					return;
+				}
+			};
 			let is_admin = client.is_user_admin(context::current(), userid.clone()).await.unwrap();
 			let is_owner = client.is_user_owner(context::current(), userid.clone()).await.unwrap();
 			let rooms = client.get_rooms(context::current(), stoken.clone(), userid.clone()).await.unwrap().unwrap();
+			let timeout = client.get_timeout(context::current(), stoken.clone(), userid.clone()).await.unwrap().unwrap_or(None);
+			let members = client.get_users(context::current()).await.unwrap().unwrap_or_default();
+			let presences = client.get_presences(context::current(), stoken.clone(), userid.clone()).await.unwrap().unwrap_or_default();
 			send_channel.send(Ok(CServer {
 				tarpc_conn: client,
 				server_id: info.server_id,
+				stoken,
+				version: info.version,
+				features: info.features,
 				domain,
 				port,
 				is_admin,
@@ -254,16 +374,19 @@ pub fn fetch_server_data(channel: Sender<Result<CServer, ErrorCode>>, addresses:
 				last_event_index: 0,
 				messages: Vec::new(),
 				rooms,
+				timeout,
+				members,
+				presences: presences.into_iter().map(|p| (p.userid.clone(), p)).collect(),
 			})).unwrap();
 		});
 	}
 }
 
-pub fn fetch_rooms_data(send_channel: Sender<Result<(CServer, Vec<Room>), ErrorCode>>, server: CServer, token: String, userid: String) {
+pub fn fetch_rooms_data(send_channel: Sender<Result<(CServer, Vec<Room>), ErrorCode>>, server: CServer, userid: String) {
 	let _handle = tokio::spawn(async move {
 		let result = server.tarpc_conn.get_rooms(
 			context::current(),
-			stoken(&token, &server.server_id, &server.domain, server.port),
+			server.stoken.clone(),
 			userid
 		).await;
 		
@@ -280,6 +403,66 @@ pub fn fetch_rooms_data(send_channel: Sender<Result<(CServer, Vec<Room>), ErrorC
 	});
 }
 
+pub const AUDIT_LOG_PAGE_SIZE: u32 = 50;
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+pub const RECONNECT_BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+pub const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Fetches a page of the audit log, `append` tells the receiver whether to extend or replace what it has.
+pub fn fetch_audit_log(send_channel: Sender<Result<(bool, Vec<AuditLogEntry>), ErrorCode>>, server: CServer, userid: String, filter: AuditLogFilter, before_id: Option<i64>) {
+	let _handle = tokio::spawn(async move {
+		let result = server.tarpc_conn.get_audit_log(
+			context::current(),
+			server.stoken.clone(),
+			userid,
+			filter,
+			before_id,
+			AUDIT_LOG_PAGE_SIZE
+		).await;
+
+		match result {
+			Ok(r) => {
+				match r {
+					Ok(entries) => send_channel.send(Ok((before_id.is_some(), entries))).unwrap(),
+					Err(e) => send_channel.send(Err(e)).unwrap(),
+				};
+			}
+			Err(_) => { send_channel.send(Err(RPCError)).unwrap(); }
+		}
+	});
+}
+
+/// Fetches the user's sessions, first revoking the one with id `revoke` if given.
+pub fn fetch_sessions(send_channel: Sender<Result<Vec<Session>, ErrorCode>>, user: CUser, revoke: Option<i64>) {
+	let _handle = tokio::spawn(async move {
+		let connection = match net::connect(&user.auth_address).await {
+			Ok(connection) => connection,
+			Err(_) => {
+				send_channel.send(Err(UnableToConnectToServer)).unwrap();
+				return;
+			}
+		};
+
+		let client = RealmAuthClient::new(tarpc::client::Config::default(), connection).spawn();
+
+		if let Some(session_id) = revoke {
+			match client.revoke_session(context::current(), user.username.clone(), user.token.clone(), session_id).await {
+				Ok(Ok(_)) => info!("Revoked session {}", session_id),
+				Ok(Err(e)) => error!("Error revoking session: {:?}", e),
+				Err(_) => {
+					send_channel.send(Err(RPCError)).unwrap();
+					return;
+				}
+			}
+		}
+
+		match client.list_sessions(context::current(), user.username, user.token).await {
+			Ok(r) => send_channel.send(r).unwrap(),
+			Err(_) => send_channel.send(Err(RPCError)).unwrap(),
+		};
+	});
+}
+
 impl eframe::App for RealmApp {
 	/// Called each time the UI needs repainting, which may be many times per second.
 	fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
@@ -305,6 +488,7 @@ impl eframe::App for RealmApp {
 			fetch_server_data(
 				self.fetching_servers_channel.0.clone(),
 				self.current_user.as_ref().unwrap().server_addresses.clone(), 
+				self.current_user.as_ref().unwrap().auth_address.clone(),
 				self.current_user.as_ref().unwrap().token.clone(),
 				self.current_user.as_ref().unwrap().username.clone()
 			);
@@ -322,7 +506,7 @@ impl eframe::App for RealmApp {
 		while let Ok(result) = self.login_ending_channel.1.try_recv() {
 			match result {
 				Ok(token) => {
-					info!("Login successful! Token: {token}");
+					info!("Login successful!");
 					self.login_ready_for_code_input = false;
 					self.login_window_open = false;
 					self.signup_window_open = false;
@@ -367,11 +551,11 @@ impl eframe::App for RealmApp {
 					let username = self.saved_username.clone().unwrap();
 					let token = self.saved_token.clone().unwrap();
 					
+					let thread_auth_address = auth_address.clone();
 					let thread_username = username.clone();
 					let thread_token = token.clone();
 					let _handle = tokio::spawn(async move {
-						let mut transport = tarpc::serde_transport::tcp::connect(&address, Json::default);
-						transport.config_mut().max_frame_length(usize::MAX);
+						let transport = net::connect(&address);
 
 						let result = transport.await;
 						let connection = match result {
@@ -387,9 +571,22 @@ impl eframe::App for RealmApp {
 						let domain = address.split(':').collect::<Vec<&str>>()[0].to_string();
 						let port = address.split(':').collect::<Vec<&str>>()[1].to_string().parse::<u16>().unwrap();
 						
-						let info = client.get_info(context::current()).await.unwrap();
+						let info = match check_server(&client, &address).await {
+							Ok(info) => info,
+							Err(e) => {
+								send_channel.send(Err(e)).unwrap();
+								return;
+							}
+						};
 						
-						let result = client.join_server(context::current(), stoken(&thread_token, &info.server_id, &domain, port), thread_username).await;
+						let stoken = match server_token(&thread_auth_address, &thread_username, &thread_token, &info.server_id, &domain, port).await {
+							Ok(stoken) => stoken,
+							Err(e) => {
+								send_channel.send(Err(e)).unwrap();
+								return;
+							}
+						};
+						let result = client.join_server(context::current(), stoken, thread_username).await;
 						
 						match result {
 							Ok(r) => {
@@ -420,6 +617,7 @@ impl eframe::App for RealmApp {
 					fetch_server_data(
 						self.fetching_servers_channel.0.clone(),
 						self.current_user.as_ref().unwrap().server_addresses.clone(), 
+						self.current_user.as_ref().unwrap().auth_address.clone(),
 						self.current_user.as_ref().unwrap().token.clone(),
 						self.current_user.as_ref().unwrap().username.clone()
 					);
@@ -443,8 +641,7 @@ impl eframe::App for RealmApp {
 					let username = self.current_user.clone().unwrap().username;
 					let token = self.current_user.clone().unwrap().token;
 					let _handle = tokio::spawn(async move {
-						let mut transport = tarpc::serde_transport::tcp::connect(&auth_address, Json::default);
-						transport.config_mut().max_frame_length(usize::MAX);
+						let transport = net::connect(&auth_address);
 
 						let result = transport.await;
 						let connection = match result {
@@ -508,7 +705,6 @@ impl eframe::App for RealmApp {
 					fetch_rooms_data(
 						self.room_changes_channel.0.clone(), 
 						server, 
-						self.current_user.as_ref().unwrap().token.clone(),
 						self.current_user.as_ref().unwrap().username.clone()
 					);
 					self.room_window_open = false;
@@ -526,7 +722,6 @@ impl eframe::App for RealmApp {
 					fetch_rooms_data(
 						self.room_changes_channel.0.clone(),
 						server,
-						self.current_user.as_ref().unwrap().token.clone(),
 						self.current_user.as_ref().unwrap().username.clone()
 					);
 				}
@@ -551,6 +746,32 @@ impl eframe::App for RealmApp {
 			}
 		}
 
+		// Fetching the audit log
+		while let Ok(result) = self.audit_log_channel.1.try_recv() {
+			match result {
+				Ok((append, entries)) => {
+					self.audit_window_has_more = entries.len() as u32 == AUDIT_LOG_PAGE_SIZE;
+					if !append {
+						self.audit_window_entries.clear();
+					}
+					self.audit_window_entries.extend(entries);
+				}
+				Err(e) => error!("Error fetching audit log: {:?}", e),
+			}
+		}
+
+		// Fetching sessions
+		while let Ok(result) = self.sessions_channel.1.try_recv() {
+			match result {
+				Ok(sessions) => self.settings_window_sessions = sessions,
+				Err(Unauthorized) => {
+					warn!("Session ended elsewhere, signing out");
+					self.clear_user();
+				}
+				Err(e) => error!("Error fetching sessions: {:?}", e),
+			}
+		}
+
 		// Polling events
 		while let Ok((serverid, (index, event))) = self.event_channel.1.try_recv() {
 			if let Some(active_servers) = &mut self.active_servers {
@@ -569,6 +790,42 @@ impl eframe::App for RealmApp {
 									self.selected_roomid.clear();
 								}
 							},
+							Event::TimedOutUser(timeout) => {
+								if let Some(user) = &self.current_user {
+									if user.username.eq(&timeout.userid) {
+										server.timeout = Some(timeout);
+									}
+								}
+							},
+							Event::RenamedUser(user) => {
+								for message in server.messages.iter_mut().filter(|m| m.user.userid.eq(&user.userid)) {
+									message.user = user.clone();
+								}
+								for member in server.members.iter_mut().filter(|m| m.userid.eq(&user.userid)) {
+									*member = user.clone();
+								}
+							},
+							Event::ChangedOwnership(user) => {
+								if let Some(current_user) = &self.current_user {
+									if current_user.username.eq(&user.userid) {
+										server.is_owner = user.owner;
+										server.is_admin = user.admin;
+									}
+								}
+								for member in server.members.iter_mut().filter(|m| m.userid.eq(&user.userid)) {
+									*member = user.clone();
+								}
+							},
+							Event::PresenceChanged(presence) => {
+								server.presences.insert(presence.userid.clone(), presence);
+							},
+							Event::RemovedTimeout(userid) => {
+								if let Some(user) = &self.current_user {
+									if user.username.eq(&userid) {
+										server.timeout = None;
+									}
+								}
+							},
 							_ => {  }
 						}
 						if index > server.last_event_index {
@@ -587,44 +844,96 @@ impl eframe::App for RealmApp {
 				for server in missing_servers {
 					let send_channel = self.event_channel.0.clone();
 					let serverid = server.server_id.clone();
-					let token = self.current_user.as_ref().unwrap().token.clone();
 					let userid = self.current_user.as_ref().unwrap().username.clone();
 					let handle = tokio::spawn(async move {
-						let mut transport = tarpc::serde_transport::tcp::connect(format!("{}:{}", server.domain, server.port), Json::default);
-						transport.config_mut().max_frame_length(usize::MAX);
-						let result = transport.await;
-						let connection = match result {
-							Ok(connection) => connection,
-							Err(_) => {
-								return;
+						let mut last_message_index = 0;
+						let mut last_event_index;
+						let mut backoff = RECONNECT_BACKOFF_INITIAL;
+
+						// Reconnects whenever the server goes away, e.g. on a restart during a rolling deploy
+						'connection: loop {
+							let transport = net::connect(format!("{}:{}", server.domain, server.port));
+							let connection = match transport.await {
+								Ok(connection) => connection,
+								Err(e) => {
+									warn!("Unable to connect to {}, retrying in {:?}: {:?}", serverid, backoff, e);
+									sleep(backoff).await;
+									backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+									continue;
+								}
+							};
+							let client = RealmChatClient::new(tarpc::client::Config::default(), connection).spawn();
+							//NOTE: The server may have been upgraded while we were away
+							if check_server(&client, &serverid).await.is_err() {
+								sleep(backoff).await;
+								backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+								continue;
 							}
-						};
-						let client = RealmChatClient::new(tarpc::client::Config::default(), connection).spawn();
-						let mut last_event_index = 0;
-						
-						loop {
-							let result = client.get_messages_since(
-								context::current(),
-								stoken(&token, &serverid, &server.domain, server.port),
-								userid.clone(),
-								last_event_index
-							).await;
-
-							match result {
-								Ok(messages) => {
-									if let Ok(messages) = messages {
-										if let Some(last) = messages.last() {
-											last_event_index = last.id;
+							backoff = RECONNECT_BACKOFF_INITIAL;
+							//NOTE: We can't tell a dropped connection from a restart, and a new instance starts its event log over
+							last_event_index = 0;
+							let mut last_heartbeat: Option<Instant> = None;
+
+							loop {
+								if last_heartbeat.is_none_or(|t| t.elapsed() >= HEARTBEAT_INTERVAL) {
+									let result = client.heartbeat(
+										context::current(),
+										server.stoken.clone(),
+										userid.clone()
+									).await;
+									if let Ok(Err(e)) = result {
+										error!("Error sending heartbeat: {:?}", e);
+									}
+									last_heartbeat = Some(Instant::now());
+								}
+
+								let result = client.get_messages_since(
+									context::current(),
+									server.stoken.clone(),
+									userid.clone(),
+									last_message_index
+								).await;
+
+								match result {
+									Ok(messages) => {
+										if let Ok(messages) = messages {
+											if let Some(last) = messages.last() {
+												last_message_index = last.id;
+											}
+											for message in messages {
+												send_channel.send((serverid.clone(), (message.id, Event::NewMessage(message)))).unwrap();
+											}
 										}
-										for message in messages {
-											send_channel.send((serverid.clone(), (message.id, Event::NewMessage(message)))).unwrap();
+									}
+									Err(_) => break,
+								}
+
+								let result = client.poll_events_since(
+									context::current(),
+									server.stoken.clone(),
+									userid.clone(),
+									last_event_index
+								).await;
+
+								match result {
+									Ok(events) => {
+										if let Ok(events) = events {
+											for (index, event) in events {
+												last_event_index = index;
+												match event {
+													Event::NewMessage(_) => continue, // Already fetched through get_messages_since
+													Event::ShuttingDown => continue 'connection,
+													_ => {}
+												}
+												send_channel.send((serverid.clone(), (index as i64, event))).unwrap();
+											}
 										}
 									}
+									Err(_) => break,
 								}
-								Err(_) => break,
-							}
 
-							sleep(Duration::from_millis(1000)).await;
+								sleep(Duration::from_millis(1000)).await;
+							}
 						}
 					});
 					self.polling_threads.push((server.server_id.clone(), handle));
@@ -639,6 +948,8 @@ impl eframe::App for RealmApp {
 
 		gui::rooms(self, ctx);
 
+		gui::members(self, ctx);
+
 		gui::messages(self, ctx);
 
 		gui::modals(self, ctx)
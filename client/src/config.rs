@@ -0,0 +1,61 @@
+use std::env;
+use std::path::Path;
+use serde::Deserialize;
+use realm_shared::config::{env_override, load_file, ConfigError};
+use realm_shared::net::Codec;
+
+/// How the client connects, loaded once from `client.toml` and env vars.
+/// Accounts and servers aren't here, they're app state that eframe persists.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+	pub net: NetSection,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetSection {
+	pub tls: bool,
+	pub tls_ca: String, //NOTE: Extra PEM CA to trust, e.g. a dev server's self-signed certificate
+	pub codec: Codec,
+	pub max_frame_length: usize, //NOTE: Bytes, the most a server may send in one response
+}
+
+impl Default for NetSection {
+	fn default() -> Self {
+		Self {
+			tls: false,
+			tls_ca: String::new(),
+			codec: Codec::Bincode,
+			max_frame_length: 16 * 1024 * 1024,
+		}
+	}
+}
+
+impl ClientConfig {
+	/// Loads `client.toml` (or `CONFIG_PATH`), applies env overrides, then validates.
+	pub fn load() -> Result<ClientConfig, ConfigError> {
+		let mut config: ClientConfig = load_file("./client.toml")?;
+
+		if let Ok(tls) = env::var("REALM_TLS") {
+			config.net.tls = tls.eq("1");
+		}
+		env_override(&mut config.net.tls_ca, "REALM_TLS_CA", "net.tls_ca")?;
+		env_override(&mut config.net.codec, "REALM_CODEC", "net.codec")?;
+
+		config.validate()?;
+		Ok(config)
+	}
+
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		if !self.net.tls_ca.is_empty() && !Path::new(&self.net.tls_ca).exists() {
+			return Err(ConfigError::invalid("net.tls_ca", format!("{} does not exist", self.net.tls_ca)));
+		}
+
+		if self.net.max_frame_length < 64 * 1024 {
+			return Err(ConfigError::invalid("net.max_frame_length", "must be at least 65536"));
+		}
+
+		Ok(())
+	}
+}
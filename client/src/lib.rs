@@ -1,3 +1,5 @@
 pub mod types;
 pub mod app;
-pub mod ui;
\ No newline at end of file
+pub mod ui;
+pub mod net;
+pub mod config;
@@ -1,3 +1,4 @@
+use realm_shared::redact::Redacting;
 use tracing::*;
 
 #[tokio::main]
@@ -8,10 +9,14 @@ async fn main() -> eframe::Result {
         .with_line_number(true)
         .with_thread_ids(true)
         .with_target(false)
+        .with_writer(Redacting(std::io::stdout))
         .finish();
 
     subscriber::set_global_default(subscriber).unwrap();
 
+    let config = realm_client::config::ClientConfig::load().unwrap_or_else(|e| panic!("Unable to load client config, {}", e));
+    realm_client::net::init(&config.net).expect("Unable to set up networking, check net.tls_ca");
+
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([720.0, 500.0])
@@ -0,0 +1,37 @@
+use std::io;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use realm_shared::net::{self, Codec, NetTransport, TlsConnector};
+use crate::config::NetSection;
+
+static TLS: OnceLock<Option<TlsConnector>> = OnceLock::new();
+static CODEC: OnceLock<Codec> = OnceLock::new();
+static MAX_FRAME_LENGTH: OnceLock<usize> = OnceLock::new();
+
+/// Sets up TLS and the wire codec from the `[net]` config section. Call once at startup.
+pub fn init(config: &NetSection) -> io::Result<()> {
+	let _ = CODEC.set(config.codec);
+	let _ = MAX_FRAME_LENGTH.set(config.max_frame_length);
+
+	let connector = match config.tls {
+		true => Some(net::client_connector(Some(&config.tls_ca).filter(|ca| !ca.is_empty()).map(String::as_str))?),
+		false => None,
+	};
+
+	let _ = TLS.set(connector);
+	Ok(())
+}
+
+/// Connects to a `host:port` address, over TLS if enabled
+pub async fn connect<Item, SinkItem>(address: impl AsRef<str>) -> io::Result<NetTransport<Item, SinkItem>>
+where
+	Item: for<'de> Deserialize<'de>,
+	SinkItem: Serialize,
+{
+	let address = address.as_ref();
+	let (host, port) = address.rsplit_once(':')
+		.ok_or(io::Error::new(io::ErrorKind::InvalidInput, format!("expected host:port, found {}", address)))?;
+	let port = port.parse::<u16>().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+	net::connect(host, port, TLS.get().and_then(Option::as_ref), CODEC.get().copied().unwrap_or(Codec::Bincode), MAX_FRAME_LENGTH.get().copied().unwrap_or(16 * 1024 * 1024)).await
+}
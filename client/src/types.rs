@@ -1,4 +1,5 @@
-use realm_server::types::{Message, RealmChatClient, Room};
+use std::collections::HashMap;
+use realm_server::types::{Message, Presence, RealmChatClient, Room, Timeout, User};
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct CUser {
@@ -9,12 +10,16 @@ pub struct CUser {
 	//pub avatar: String,
 	pub server_addresses: Vec<String>,
 	pub token: String,
+	pub blocked_users: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
 pub struct CServer {
 	pub tarpc_conn: RealmChatClient,
 	pub server_id: String,
+	pub stoken: String, //NOTE: From the user's auth server, see app::server_token
+	pub version: String, //NOTE: realm_server version, from get_info
+	pub features: Vec<String>,
 	pub domain: String,
 	pub port: u16,
 	pub is_admin: bool,
@@ -22,4 +27,7 @@ pub struct CServer {
 	pub rooms: Vec<Room>,
 	pub last_event_index: i64,
 	pub messages: Vec<Message>,
+	pub timeout: Option<Timeout>,
+	pub members: Vec<User>,
+	pub presences: HashMap<String, Presence>, //NOTE: Keyed by user.userid
 }
\ No newline at end of file
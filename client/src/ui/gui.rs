@@ -1,14 +1,14 @@
+use std::time::Duration;
 use chrono::Utc;
 use egui::{Context, SelectableLabel};
 use tarpc::context;
-use tarpc::tokio_serde::formats::Json;
 use realm_auth::types::RealmAuthClient;
 use realm_shared::types::ErrorCode::RPCError;
 use regex::Regex;
 use tracing::log::*;
-use realm_server::types::{Message, MessageData, Room, User};
-use realm_shared::stoken;
-use crate::app::RealmApp;
+use realm_server::types::{AuditAction, Message, MessageData, PresenceStatus, Room, User};
+use crate::app::{device_name, fetch_audit_log, fetch_sessions, RealmApp, CLIENT_VERSION};
+use crate::net;
 use crate::types::CServer;
 
 pub fn top_panel(app: &mut RealmApp, ctx: &Context) {
@@ -28,8 +28,7 @@ pub fn top_panel(app: &mut RealmApp, ctx: &Context) {
 				let token = app.current_user.clone().unwrap().token;
 
 				let _handle = tokio::spawn(async move {
-					let mut transport = tarpc::serde_transport::tcp::connect(&address, Json::default);
-					transport.config_mut().max_frame_length(usize::MAX);
+					let transport = net::connect(&address);
 
 					let result = transport.await;
 					let connection = match result {
@@ -49,14 +48,7 @@ pub fn top_panel(app: &mut RealmApp, ctx: &Context) {
 					}
 				});
 
-				app.current_user = None;
-				app.saved_username = None;
-				app.saved_token = None;
-				app.saved_auth_address = None;
-				
-				app.active_servers = None;
-				app.selected_roomid.clear();
-				app.selected_serverid.clear();
+				app.clear_user();
 			}
 
 			if ui.button("Quit").clicked() {
@@ -70,14 +62,18 @@ pub fn top_panel(app: &mut RealmApp, ctx: &Context) {
 				if ui.button("ℹ").clicked() {
 					app.info_window_open = true;
 				}
+
+				if app.current_user.is_some() && ui.button("⚙").on_hover_text("Settings").clicked() {
+					app.settings_window_open = true;
+					fetch_sessions(app.sessions_channel.0.clone(), app.current_user.clone().unwrap(), None);
+				}
 				
 				if app.current_user.is_some() && ui.button("Delete Account").clicked() {
 					let address = app.current_user.clone().unwrap().auth_address;
 					let username = app.current_user.clone().unwrap().username;
 					let token = app.current_user.clone().unwrap().token;
 					let _handle = tokio::spawn(async move {
-						let mut transport = tarpc::serde_transport::tcp::connect(address, Json::default);
-						transport.config_mut().max_frame_length(usize::MAX);
+						let transport = net::connect(address);
 
 						let result = transport.await;
 						let connection = match result {
@@ -97,14 +93,7 @@ pub fn top_panel(app: &mut RealmApp, ctx: &Context) {
 						}
 					});
 					
-					app.current_user = None;
-					app.saved_username = None;
-					app.saved_token = None;
-					app.saved_auth_address = None;
-
-					app.active_servers = None;
-					app.selected_roomid.clear();
-					app.selected_serverid.clear();
+					app.clear_user();
 				}
 			});
 			
@@ -121,13 +110,12 @@ pub fn servers(app: &mut RealmApp, ctx: &Context) {
 			}
 			if !app.selected_serverid.is_empty() && ui.button("-").clicked() {
 				let server = app.active_servers.clone().unwrap().into_iter().find(|s| s.server_id.eq(&app.selected_serverid)).unwrap();
-				let token = app.current_user.as_ref().unwrap().token.clone();
 				let userid = app.current_user.as_ref().unwrap().username.clone();
 				let send_channel = app.leave_server_channel.0.clone();
 				let _handle = tokio::spawn(async move {
 					let result = server.tarpc_conn.leave_server(
 						context::current(),
-						stoken(&token, &server.server_id, &server.domain, server.port),
+						server.stoken.clone(),
 						userid
 					).await;
 
@@ -177,15 +165,25 @@ pub fn rooms(app: &mut RealmApp, ctx: &Context) {
 				if server.is_admin && ui.button("+").clicked() {
 					app.room_window_open = true;
 				}
+				if server.is_admin && ui.button("📜").on_hover_text("Audit log").clicked() {
+					app.audit_window_open = true;
+					app.audit_window_entries.clear();
+					fetch_audit_log(
+						app.audit_log_channel.0.clone(),
+						server.clone(),
+						app.current_user.as_ref().unwrap().username.clone(),
+						app.audit_window_filter.clone(),
+						None
+					);
+				}
 				if server.is_admin && !app.selected_roomid.is_empty() && ui.button("-").clicked() {
-					let token = app.current_user.as_ref().unwrap().token.clone();
 					let roomid = app.selected_roomid.clone();
 					let userid = app.current_user.as_ref().unwrap().username.clone();
 					let send_channel = app.delete_room_channel.0.clone();
 					let _handle = tokio::spawn(async move {
 						let result = server.tarpc_conn.delete_room(
 							context::current(),
-							stoken(&token, &server.server_id, &server.domain, server.port),
+							server.stoken.clone(),
 							userid,
 							roomid
 						).await;
@@ -220,22 +218,178 @@ pub fn rooms(app: &mut RealmApp, ctx: &Context) {
 	});
 }
 
+pub fn members(app: &mut RealmApp, ctx: &Context) {
+	let Some(server) = app.active_servers.clone()
+		.and_then(|servers| servers.into_iter().find(|s| s.server_id.eq(&app.selected_serverid))) else {
+		return;
+	};
+
+	egui::SidePanel::right("members").show(ctx, |ui| {
+		ui.heading("Members");
+		ui.separator();
+
+		ui.horizontal(|ui| {
+			egui::ComboBox::from_id_salt("presence_status")
+				.selected_text(presence_label(app.presence_status))
+				.show_ui(ui, |ui| {
+					for status in [PresenceStatus::Online, PresenceStatus::Idle, PresenceStatus::DoNotDisturb, PresenceStatus::Offline] {
+						ui.selectable_value(&mut app.presence_status, status, presence_label(status));
+					}
+				});
+
+			if ui.button("Set").clicked() {
+				let userid = app.current_user.as_ref().unwrap().username.clone();
+				let status = app.presence_status;
+				let custom_status = Some(app.presence_custom_status.clone()).filter(|s| !s.is_empty());
+				let server = server.clone();
+				let _handle = tokio::spawn(async move {
+					let result = server.tarpc_conn.set_presence(
+						context::current(),
+						server.stoken.clone(),
+						userid,
+						status,
+						custom_status
+					).await;
+
+					match result {
+						Ok(Ok(_)) => {}
+						Ok(Err(e)) => error!("Error setting presence: {:?}", e),
+						Err(_) => error!("Error setting presence: {:?}", RPCError),
+					}
+				});
+			}
+		});
+
+		ui.add(egui::TextEdit::singleline(&mut app.presence_custom_status).hint_text("Custom status"));
+		ui.separator();
+
+		egui::ScrollArea::vertical().show(ui, |ui| {
+			for status in [PresenceStatus::Online, PresenceStatus::Idle, PresenceStatus::DoNotDisturb, PresenceStatus::Offline] {
+				let mut in_group = server.members.iter()
+					.filter(|m| server.presences.get(&m.userid).map_or(PresenceStatus::Offline, |p| p.status) == status)
+					.collect::<Vec<&User>>();
+
+				if in_group.is_empty() {
+					continue;
+				}
+
+				in_group.sort_by_key(|m| m.display_name().to_lowercase());
+
+				ui.label(egui::RichText::new(format!("{} — {}", presence_label(status), in_group.len())).strong());
+				for member in in_group {
+					let presence = server.presences.get(&member.userid);
+					let label = ui.label(member.display_name());
+
+					let mut hover = member.userid.clone();
+					if let Some(custom_status) = presence.and_then(|p| p.custom_status.as_ref()) {
+						hover = format!("{}\n{}", hover, custom_status);
+					}
+					if status == PresenceStatus::Offline {
+						if let Some(last_seen) = presence.and_then(|p| p.last_seen) {
+							hover = format!("{}\nLast seen {}", hover, last_seen.format("%Y-%m-%d %H:%M"));
+						}
+					}
+					let label = label.on_hover_text(hover);
+
+					let Some(current_user) = app.current_user.as_mut() else { continue };
+					if member.userid.eq(&current_user.username) {
+						continue;
+					}
+					let is_blocked = current_user.blocked_users.contains(&member.userid);
+					label.context_menu(|ui| {
+						if ui.button(if is_blocked { "Unblock" } else { "Block" }).clicked() {
+							if is_blocked {
+								current_user.blocked_users.retain(|b| !b.eq(&member.userid));
+							} else {
+								current_user.blocked_users.push(member.userid.clone());
+							}
+							set_blocked(current_user.auth_address.clone(), current_user.username.clone(), current_user.token.clone(), server.clone(), member.userid.clone(), !is_blocked);
+							ui.close_menu();
+						}
+					});
+				}
+				ui.add_space(4.0);
+			}
+		});
+	});
+}
+
+fn set_blocked(auth_address: String, userid: String, token: String, server: CServer, target: String, blocked: bool) {
+	let _handle = tokio::spawn(async move {
+		let transport = net::connect(&auth_address);
+
+		let Ok(auth_connection) = transport.await else {
+			error!("Error connecting to auth server to update blocks");
+			return;
+		};
+
+		let client = RealmAuthClient::new(tarpc::client::Config::default(), auth_connection).spawn();
+		let result = if blocked {
+			client.block_user(context::current(), userid.clone(), token.clone(), target).await
+		} else {
+			client.unblock_user(context::current(), userid.clone(), token.clone(), target).await
+		};
+
+		match result {
+			Ok(Ok(_)) => {}
+			Ok(Err(e)) => { error!("Error updating blocks: {:?}", e); return; }
+			Err(_) => { error!("Error updating blocks: {:?}", RPCError); return; }
+		}
+
+		// Servers cache block lists, so let this one know right away
+		let result = server.tarpc_conn.sync_block_list(
+			context::current(),
+			server.stoken.clone(),
+			userid
+		).await;
+
+		match result {
+			Ok(Ok(_)) => {}
+			Ok(Err(e)) => error!("Error syncing block list: {:?}", e),
+			Err(_) => error!("Error syncing block list: {:?}", RPCError),
+		}
+	});
+}
+
+fn presence_label(status: PresenceStatus) -> &'static str {
+	match status {
+		PresenceStatus::Online => "Online",
+		PresenceStatus::Idle => "Idle",
+		PresenceStatus::DoNotDisturb => "Do Not Disturb",
+		PresenceStatus::Offline => "Offline",
+	}
+}
+
 pub fn messages(app: &mut RealmApp, ctx: &Context) {
+	let timed_out_for = app.active_servers.as_ref()
+		.and_then(|servers| servers.iter().find(|s| s.server_id.eq(&app.selected_serverid)))
+		.and_then(|server| server.timeout.as_ref())
+		.map(|timeout| timeout.expires - Utc::now())
+		.filter(|remaining| remaining.num_seconds() > 0);
+
+	if timed_out_for.is_some() {
+		ctx.request_repaint_after(Duration::from_secs(1));
+	}
+
 	egui::CentralPanel::default().show(ctx, |ui| {
 		ui.with_layout(egui::Layout::bottom_up(egui::Align::TOP), |ui| {
+			if let Some(remaining) = timed_out_for {
+				ui.label(format!("You are timed out for {:02}:{:02}:{:02}",
+								 remaining.num_hours(), remaining.num_minutes() % 60, remaining.num_seconds() % 60));
+			}
+			
 			ui.with_layout(egui::Layout::right_to_left(egui::Align::Max), |ui| {
-				if ui.button("✉").on_hover_text("Send a message").clicked() {
+				if ui.add_enabled(timed_out_for.is_none(), egui::Button::new("✉")).on_hover_text("Send a message").clicked() {
 					if let Some(active_servers) = &app.active_servers {
 						for server in active_servers.clone() {
 							if server.server_id.eq(&app.selected_serverid) {
 								let username = app.current_user.as_ref().unwrap().username.clone();
-								let token = app.current_user.as_ref().unwrap().token.clone();
 								let room = server.rooms.iter().find(|r| r.roomid.eq(&app.selected_roomid)).unwrap().clone();
 								let text_message = app.text_message_input.clone();
 								let _handle = tokio::spawn(async move {
 									let result = server.tarpc_conn.send_message(
 										context::current(),
-										stoken(&token, &server.server_id, &server.domain, server.port),
+										server.stoken.clone(),
 										Message {
 											id: 0,
 											timestamp: Utc::now(),
@@ -252,7 +406,8 @@ pub fn messages(app: &mut RealmApp, ctx: &Context) {
 					}
 				}
 				
-				ui.add(
+				ui.add_enabled(
+					timed_out_for.is_none(),
 					egui::TextEdit::multiline(&mut app.text_message_input)
 						.desired_rows(1)
 						.desired_width(ui.available_width())
@@ -269,11 +424,22 @@ pub fn messages(app: &mut RealmApp, ctx: &Context) {
 								.collect::<Vec<&Message>>();
 							
 							for message in messages_to_display {
+								let is_blocked = app.current_user.as_ref()
+									.is_some_and(|u| u.blocked_users.contains(&message.user.userid));
+								if is_blocked {
+									ui.collapsing(format!("{} - Message from blocked user", message.timestamp.format("%Y-%m-%d %H:%M:%S")), |ui| {
+										if let MessageData::Text(text) = &message.data {
+											ui.label(format!("{}: {}", message.user.display_name(), text));
+										}
+									});
+									continue;
+								}
+
 								match message.clone().data {
 									MessageData::Text(text) => {
 										ui.label(format!("{} - {}: {}",
 														 message.timestamp.format("%Y-%m-%d %H:%M:%S"),
-														 message.user.userid.split(':').collect::<Vec<&str>>()[0], 
+														 message.user.display_name(),
 														 text));
 									}
 									MessageData::Attachment(_) => {}
@@ -347,8 +513,7 @@ pub fn modals(app: &mut RealmApp, ctx: &Context) {
 				let send_channel = app.login_start_channel.0.clone();
 
 				let _handle = tokio::spawn(async move {
-					let mut transport = tarpc::serde_transport::tcp::connect(login_window_server_address, Json::default);
-					transport.config_mut().max_frame_length(usize::MAX);
+					let transport = net::connect(login_window_server_address);
 
 					let result = transport.await;
 					let connection = match result {
@@ -397,8 +562,7 @@ pub fn modals(app: &mut RealmApp, ctx: &Context) {
 				let send_channel = app.login_start_channel.0.clone();
 
 				let _handle = tokio::spawn(async move {
-					let mut transport = tarpc::serde_transport::tcp::connect(login_window_server_address, Json::default);
-					transport.config_mut().max_frame_length(usize::MAX);
+					let transport = net::connect(login_window_server_address);
 
 					let result = transport.await;
 					let connection = match result {
@@ -441,8 +605,7 @@ pub fn modals(app: &mut RealmApp, ctx: &Context) {
 				let send_channel = app.login_ending_channel.0.clone();
 
 				let _handle = tokio::spawn(async move {
-					let mut transport = tarpc::serde_transport::tcp::connect(login_window_server_address, Json::default);
-					transport.config_mut().max_frame_length(usize::MAX);
+					let transport = net::connect(login_window_server_address);
 
 					let result = transport.await;
 					let connection = match result {
@@ -454,7 +617,7 @@ pub fn modals(app: &mut RealmApp, ctx: &Context) {
 					};
 
 					let client = RealmAuthClient::new(tarpc::client::Config::default(), connection).spawn();
-					let result = client.finish_login_flow(context::current(), login_window_username, login_window_code.parse::<u32>().unwrap()).await;
+					let result = client.finish_login_flow(context::current(), login_window_username, login_window_code.parse::<u32>().unwrap(), device_name(), CLIENT_VERSION.to_string()).await;
 
 					match result {
 						Ok(r) => {
@@ -497,8 +660,7 @@ pub fn modals(app: &mut RealmApp, ctx: &Context) {
 				let send_channel = app.add_server_channel.0.clone();
 
 				let _handle = tokio::spawn(async move {
-					let mut transport = tarpc::serde_transport::tcp::connect(auth_address, Json::default);
-					transport.config_mut().max_frame_length(usize::MAX);
+					let transport = net::connect(auth_address);
 
 					let result = transport.await;
 					let connection = match result {
@@ -544,7 +706,6 @@ pub fn modals(app: &mut RealmApp, ctx: &Context) {
 			if ui.button("Add Room").clicked() {
 				for server in app.active_servers.clone().unwrap() {
 					if server.server_id.eq(&app.selected_serverid) {
-						let token = app.current_user.as_ref().unwrap().token.clone();
 						let roomid = app.room_window_name.clone();
 						let admin_only_send = app.room_window_admin_only_send;
 						let admin_only_view = app.room_window_admin_only_view;
@@ -553,7 +714,7 @@ pub fn modals(app: &mut RealmApp, ctx: &Context) {
 						let _handle = tokio::spawn(async move {
 							let result = server.tarpc_conn.create_room(
 								context::current(), 
-								stoken(&token, &server.server_id, &server.domain, server.port),
+								server.stoken.clone(),
 								userid,
 								Room {
 									id: 0,
@@ -579,4 +740,162 @@ pub fn modals(app: &mut RealmApp, ctx: &Context) {
 				}
 			}
 		});
-}
\ No newline at end of file
+
+	let selected_server = app.active_servers.clone()
+		.and_then(|servers| servers.into_iter().find(|s| s.server_id.eq(&app.selected_serverid)));
+
+	egui::Window::new("Audit Log")
+		.open(&mut app.audit_window_open)
+		.min_size((500.0, 300.0))
+		.show(ctx, |ui| {
+			let Some(server) = selected_server else {
+				ui.label("Select a server to view its audit log");
+				return;
+			};
+
+			let mut actor = app.audit_window_filter.actor.clone().unwrap_or_default();
+			let mut target = app.audit_window_filter.target.clone().unwrap_or_default();
+
+			ui.horizontal(|ui| {
+				ui.label("Actor: ");
+				ui.text_edit_singleline(&mut actor);
+				ui.label("Target: ");
+				ui.text_edit_singleline(&mut target);
+			});
+
+			app.audit_window_filter.actor = Some(actor).filter(|s| !s.is_empty());
+			app.audit_window_filter.target = Some(target).filter(|s| !s.is_empty());
+
+			let mut search = false;
+			ui.horizontal(|ui| {
+				ui.label("Action: ");
+				egui::ComboBox::from_id_salt("audit_action")
+					.selected_text(app.audit_window_filter.action.map(|a| format!("{:?}", a)).unwrap_or("Any".to_string()))
+					.show_ui(ui, |ui| {
+						ui.selectable_value(&mut app.audit_window_filter.action, None, "Any");
+						for action in [
+							AuditAction::PromoteUser, AuditAction::DemoteUser, AuditAction::KickUser,
+							AuditAction::BanUser, AuditAction::PardonUser, AuditAction::TimeoutUser,
+							AuditAction::RemoveTimeout, AuditAction::CreateRoom, AuditAction::DeleteRoom,
+							AuditAction::DeleteMessage, AuditAction::ResolveReport, AuditAction::ClaimReport,
+							AuditAction::TransferOwnership, AuditAction::AcceptOwnership, AuditAction::StepDownAsOwner,
+							AuditAction::RecoverOwnership, AuditAction::CompactDatabase,
+						] {
+							ui.selectable_value(&mut app.audit_window_filter.action, Some(action), format!("{:?}", action));
+						}
+					});
+
+				search = ui.button("Search").clicked();
+			});
+
+			ui.separator();
+
+			let mut load_more = false;
+			egui::ScrollArea::vertical().show(ui, |ui| {
+				egui::Grid::new("audit_log_entries").striped(true).show(ui, |ui| {
+					for entry in &app.audit_window_entries {
+						ui.label(entry.timestamp.format("%Y-%m-%d %H:%M:%S").to_string());
+						ui.label(&entry.actor);
+						ui.label(format!("{:?}", entry.action));
+						ui.label(&entry.target);
+						ui.label(&entry.reason);
+						ui.end_row();
+					}
+				});
+
+				if app.audit_window_has_more {
+					load_more = ui.button("Load more").clicked();
+				}
+			});
+
+			if search || load_more {
+				let before_id = if load_more {
+					app.audit_window_entries.last().map(|e| e.id)
+				} else {
+					None
+				};
+
+				fetch_audit_log(
+					app.audit_log_channel.0.clone(),
+					server,
+					app.current_user.as_ref().unwrap().username.clone(),
+					app.audit_window_filter.clone(),
+					before_id
+				);
+			}
+		});
+	let mut sign_out_everywhere = false;
+	egui::Window::new("Settings")
+		.open(&mut app.settings_window_open)
+		.min_size((500.0, 300.0))
+		.show(ctx, |ui| {
+			let Some(user) = app.current_user.clone() else {
+				return;
+			};
+
+			ui.horizontal(|ui| {
+				ui.heading("Active sessions");
+				if ui.button("⟳").on_hover_text("Refresh").clicked() {
+					fetch_sessions(app.sessions_channel.0.clone(), user.clone(), None);
+				}
+			});
+
+			let mut revoke = None;
+			egui::ScrollArea::vertical().show(ui, |ui| {
+				egui::Grid::new("sessions").striped(true).show(ui, |ui| {
+					ui.strong("Device");
+					ui.strong("Version");
+					ui.strong("IP address");
+					ui.strong("Signed in");
+					ui.strong("Last used");
+					ui.strong("Expires");
+					ui.end_row();
+
+					for session in &app.settings_window_sessions {
+						ui.label(&session.device_name);
+						ui.label(&session.client_version);
+						ui.label(&session.ip);
+						ui.label(session.created.format("%Y-%m-%d %H:%M").to_string());
+						ui.label(session.last_used.format("%Y-%m-%d %H:%M").to_string());
+						ui.label(session.expires.format("%Y-%m-%d").to_string());
+						if session.current {
+							ui.label("This device");
+						} else if ui.button("Revoke").clicked() {
+							revoke = Some(session.id);
+						}
+						ui.end_row();
+					}
+				});
+			});
+
+			if revoke.is_some() {
+				fetch_sessions(app.sessions_channel.0.clone(), user.clone(), revoke);
+			}
+
+			ui.separator();
+
+			if ui.button("Sign out everywhere").on_hover_text("Ends every session, including this one").clicked() {
+				let _handle = tokio::spawn(async move {
+					let connection = match net::connect(&user.auth_address).await {
+						Ok(connection) => connection,
+						Err(e) => {
+							tracing::error!("Failed to connect to server: {}", e);
+							return;
+						}
+					};
+
+					let client = RealmAuthClient::new(tarpc::client::Config::default(), connection).spawn();
+					match client.sign_out_everywhere(context::current(), user.username, user.token).await {
+						Ok(Ok(_)) => info!("Signed out everywhere!"),
+						Ok(Err(e)) => error!("Error signing out everywhere: {:?}", e),
+						Err(e) => error!("Error signing out everywhere: {:?}", e),
+					}
+				});
+				sign_out_everywhere = true;
+			}
+		});
+
+	if sign_out_everywhere {
+		app.clear_user();
+	}
+}
@@ -0,0 +1,57 @@
+//! Compares wire sizes and encode/decode times of `get_messages_since` responses per codec.
+//! Run with `cargo bench --bench message_codecs`.
+
+use std::time::Instant;
+use chrono::Utc;
+use realm_server::types::{Message, MessageData, RealmChatResponse, Reply, Room, User};
+use realm_shared::net::Codec;
+
+fn messages(count: usize) -> Vec<Message> {
+	let room = Room { id: 1, roomid: "general".to_string(), admin_only_send: false, admin_only_view: false };
+	let users = (0..20).map(|i| User {
+		id: i,
+		userid: format!("@user{}:realm.example.com", i),
+		name: format!("User {}", i),
+		owner: i == 0,
+		admin: i < 3,
+		nickname: (i % 4 == 0).then(|| format!("nick{}", i)),
+	}).collect::<Vec<User>>();
+
+	(0..count).map(|i| Message {
+		id: i as i64,
+		timestamp: Utc::now(),
+		user: users[i % users.len()].clone(),
+		room: room.clone(),
+		data: match i % 5 {
+			0 => MessageData::Reply(Reply { referencing_id: i as i64 - 1, text: "Agreed, let's ship it on Friday.".to_string() }),
+			_ => MessageData::Text(format!("Message number {} with a bit of typical chat text in it, nothing fancy.", i)),
+		},
+	}).collect()
+}
+
+fn main() {
+	const ROUNDS: u32 = 10;
+	println!("{:>8} {:>8} {:>12} {:>12} {:>12}", "messages", "codec", "bytes", "encode", "decode");
+
+	for count in [100, 1_000, 10_000] {
+		let response = RealmChatResponse::GetMessagesSince(Ok(messages(count)));
+
+		for codec in [Codec::Json, Codec::Bincode] {
+			let encoded = codec.encode(&response).unwrap();
+
+			let started = Instant::now();
+			for _ in 0..ROUNDS {
+				codec.encode(&response).unwrap();
+			}
+			let encode = started.elapsed() / ROUNDS;
+
+			let started = Instant::now();
+			for _ in 0..ROUNDS {
+				codec.decode::<RealmChatResponse>(&encoded).unwrap();
+			}
+			let decode = started.elapsed() / ROUNDS;
+
+			println!("{:>8} {:>8} {:>12} {:>12?} {:>12?}", count, format!("{:?}", codec), encoded.len(), encode, decode);
+		}
+	}
+}
@@ -0,0 +1,87 @@
+-- The schema the SQLite migrations add up to, as of 20241026130000_create_ownership_transfer
+CREATE TABLE IF NOT EXISTS room (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                roomid VARCHAR(255) NOT NULL,
+                admin_only_send BOOL NOT NULL,
+                admin_only_view BOOL NOT NULL
+            );
+
+CREATE TABLE IF NOT EXISTS "user" (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                userid VARCHAR(255) NOT NULL,
+                name VARCHAR(255) NOT NULL,
+                owner BOOL NOT NULL,
+                admin BOOL NOT NULL,
+                nickname VARCHAR(255),
+                last_seen TIMESTAMPTZ,
+                custom_status TEXT
+            );
+
+CREATE TABLE IF NOT EXISTS message (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                timestamp TIMESTAMPTZ NOT NULL,
+                "user" BIGINT NOT NULL,
+                room BIGINT NOT NULL,
+                msg_type VARCHAR CHECK( msg_type IN ('text', 'attachment', 'reply', 'edit', 'reaction', 'redaction')) NOT NULL,
+                msg_text TEXT,
+                referencing_id BIGINT,
+                emoji TEXT
+            );
+
+CREATE TABLE IF NOT EXISTS banned (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                userid VARCHAR(255) NOT NULL,
+                reason TEXT NOT NULL,
+                banned_by VARCHAR(255) NOT NULL,
+                created TIMESTAMPTZ NOT NULL,
+                expires TIMESTAMPTZ
+            );
+
+CREATE TABLE IF NOT EXISTS timeout (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                userid VARCHAR(255) NOT NULL,
+                reason TEXT NOT NULL,
+                issued_by VARCHAR(255) NOT NULL,
+                created TIMESTAMPTZ NOT NULL,
+                expires TIMESTAMPTZ NOT NULL
+            );
+
+CREATE TABLE IF NOT EXISTS audit_log (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                timestamp TIMESTAMPTZ NOT NULL,
+                actor VARCHAR(255) NOT NULL,
+                action TEXT NOT NULL, -- An AuditAction, validated in Rust so new actions need no migration
+                target VARCHAR(255) NOT NULL,
+                reason TEXT NOT NULL
+            );
+
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+CREATE TABLE IF NOT EXISTS report (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                created TIMESTAMPTZ NOT NULL,
+                reporter VARCHAR(255) NOT NULL,
+                target_userid VARCHAR(255) NOT NULL,
+                message_id BIGINT,
+                category TEXT CHECK( category IN ('spam', 'harassment', 'hate_speech', 'nsfw', 'impersonation', 'other')) NOT NULL,
+                note TEXT NOT NULL,
+                status TEXT CHECK( status IN ('open', 'claimed', 'resolved')) NOT NULL,
+                claimed_by VARCHAR(255),
+                resolution TEXT CHECK( resolution IN ('dismissed', 'deleted_message', 'timed_out', 'kicked', 'banned')),
+                resolved_at TIMESTAMPTZ
+            );
+
+CREATE TABLE IF NOT EXISTS ownership_transfer (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                from_userid VARCHAR(255) NOT NULL,
+                to_userid VARCHAR(255) NOT NULL,
+                keep_ownership BOOL NOT NULL,
+                created TIMESTAMPTZ NOT NULL
+            );
@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS banned_new (
+                id INTEGER PRIMARY KEY,
+                userid VARCHAR(255) NOT NULL,
+                reason TEXT NOT NULL,
+                banned_by VARCHAR(255) NOT NULL,
+                created DATETIME NOT NULL,
+                expires DATETIME
+            );
+
+INSERT INTO banned_new (id, userid, reason, banned_by, created, expires)
+    SELECT id, userid, '', '', CURRENT_TIMESTAMP, NULL FROM banned;
+
+DROP TABLE banned;
+
+ALTER TABLE banned_new RENAME TO banned;
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS timeout (
+                id INTEGER PRIMARY KEY,
+                userid VARCHAR(255) NOT NULL,
+                reason TEXT NOT NULL,
+                issued_by VARCHAR(255) NOT NULL,
+                created DATETIME NOT NULL,
+                expires DATETIME NOT NULL
+            );
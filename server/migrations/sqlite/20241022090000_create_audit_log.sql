@@ -0,0 +1,19 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY,
+                timestamp DATETIME NOT NULL,
+                actor VARCHAR(255) NOT NULL,
+                action VARCHAR CHECK( action IN ('promote_user', 'demote_user', 'kick_user', 'ban_user', 'pardon_user', 'timeout_user', 'remove_timeout', 'create_room', 'delete_room')) NOT NULL,
+                target VARCHAR(255) NOT NULL,
+                reason TEXT NOT NULL
+            );
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
@@ -0,0 +1,43 @@
+CREATE TABLE IF NOT EXISTS report (
+                id INTEGER PRIMARY KEY,
+                created DATETIME NOT NULL,
+                reporter VARCHAR(255) NOT NULL,
+                target_userid VARCHAR(255) NOT NULL,
+                message_id INTEGER,
+                category VARCHAR CHECK( category IN ('spam', 'harassment', 'hate_speech', 'nsfw', 'impersonation', 'other')) NOT NULL,
+                note TEXT NOT NULL,
+                status VARCHAR CHECK( status IN ('open', 'claimed', 'resolved')) NOT NULL,
+                claimed_by VARCHAR(255),
+                resolution VARCHAR CHECK( resolution IN ('dismissed', 'deleted_message', 'timed_out', 'kicked', 'banned')),
+                resolved_at DATETIME
+            );
+
+-- Rebuild audit_log once without the CHECK on action. AuditAction is validated in Rust when rows are
+-- read back, so later actions don't need a migration.
+DROP TRIGGER IF EXISTS audit_log_no_update;
+DROP TRIGGER IF EXISTS audit_log_no_delete;
+
+CREATE TABLE IF NOT EXISTS audit_log_new (
+                id INTEGER PRIMARY KEY,
+                timestamp DATETIME NOT NULL,
+                actor VARCHAR(255) NOT NULL,
+                action VARCHAR NOT NULL,
+                target VARCHAR(255) NOT NULL,
+                reason TEXT NOT NULL
+            );
+
+INSERT INTO audit_log_new SELECT * FROM audit_log;
+
+DROP TABLE audit_log;
+
+ALTER TABLE audit_log_new RENAME TO audit_log;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
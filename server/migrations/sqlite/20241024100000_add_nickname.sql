@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE user ADD COLUMN nickname VARCHAR(255);
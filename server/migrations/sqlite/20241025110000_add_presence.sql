@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE user ADD COLUMN last_seen DATETIME;
+
+ALTER TABLE user ADD COLUMN custom_status TEXT;
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS ownership_transfer (
+                id INTEGER PRIMARY KEY,
+                from_userid VARCHAR(255) NOT NULL,
+                to_userid VARCHAR(255) NOT NULL,
+                keep_ownership BOOL NOT NULL,
+                created DATETIME NOT NULL
+            );
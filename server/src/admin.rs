@@ -0,0 +1,71 @@
+use chrono::Utc;
+use crate::store::ChatStore;
+use crate::types::{AuditAction, AuditLogEntry, Ban, Room, User};
+
+/// Local recovery for an orphaned server, makes `userid` an owner without needing anyone's confirmation.
+/// Only reachable by whoever can run the server binary against its database.
+pub async fn recover_ownership(store: &dyn ChatStore, userid: &str) -> anyhow::Result<()> {
+	if !store.set_owner(userid, true).await? {
+		anyhow::bail!("{} has not joined this server", userid);
+	}
+	store.set_admin(userid, true).await?;
+
+	audit(store, AuditAction::RecoverOwnership, userid).await
+}
+
+// The functions below back `realm-admin` when it's pointed at a database instead of a running server.
+
+pub async fn list_users(store: &dyn ChatStore) -> anyhow::Result<Vec<User>> {
+	Ok(store.get_users().await?)
+}
+
+pub async fn list_rooms(store: &dyn ChatStore) -> anyhow::Result<Vec<Room>> {
+	Ok(store.get_rooms().await?)
+}
+
+pub async fn list_bans(store: &dyn ChatStore) -> anyhow::Result<Vec<Ban>> {
+	Ok(store.get_bans(Utc::now()).await?)
+}
+
+pub async fn promote_user(store: &dyn ChatStore, userid: &str) -> anyhow::Result<()> {
+	if !store.set_admin(userid, true).await? {
+		anyhow::bail!("{} has not joined this server", userid);
+	}
+
+	audit(store, AuditAction::PromoteUser, userid).await
+}
+
+pub async fn pardon_user(store: &dyn ChatStore, userid: &str) -> anyhow::Result<()> {
+	if !store.delete_ban(userid).await? {
+		anyhow::bail!("{} is not banned", userid);
+	}
+
+	audit(store, AuditAction::PardonUser, userid).await
+}
+
+/// Rebuilds the database to give back space left by deleted rows, returns the bytes freed
+pub async fn compact(store: &dyn ChatStore) -> anyhow::Result<i64> {
+	let before = store.database_bytes().await?;
+	store.compact().await?;
+	Ok(before - store.database_bytes().await?)
+}
+
+/// `compact`, recorded in the audit log
+pub async fn compact_database(store: &dyn ChatStore) -> anyhow::Result<i64> {
+	let freed = compact(store).await?;
+	audit(store, AuditAction::CompactDatabase, "").await?;
+	Ok(freed)
+}
+
+async fn audit(store: &dyn ChatStore, action: AuditAction, target: &str) -> anyhow::Result<()> {
+	store.add_audit(&AuditLogEntry {
+		id: 0,
+		timestamp: Utc::now(),
+		actor: "local-admin".to_string(),
+		action,
+		target: target.to_string(),
+		reason: String::new(),
+	}).await?;
+
+	Ok(())
+}
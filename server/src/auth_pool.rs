@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tarpc::client::RpcError;
+use tarpc::context::Context;
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use tracing::{error, info, warn};
+use realm_auth::types::RealmAuthClient;
+use realm_shared::net::{self, Codec, TlsConnector};
+use crate::config::AuthSection;
+use crate::discovery::AuthDiscovery;
+use crate::metrics::AuthMetrics;
+
+#[derive(Default)]
+struct PooledAuth {
+	client: Option<RealmAuthClient>,
+	failures: u32,
+	retry_at: Option<Instant>,
+}
+
+/// One multiplexed connection per auth domain, shared by every chat connection.
+/// Connecting is serialized per domain, so a burst of cache misses only dials once.
+#[derive(Clone)]
+pub struct AuthPool {
+	discovery: AuthDiscovery,
+	entries: Arc<Mutex<HashMap<String, Arc<Mutex<PooledAuth>>>>>,
+	call_timeout: Duration,
+	backoff_initial: Duration,
+	backoff_max: Duration,
+	tls: Option<TlsConnector>,
+	codec: Codec,
+	max_frame_length: usize,
+	pub metrics: Arc<AuthMetrics>,
+}
+
+impl AuthPool {
+	/// Auth domains are named by users, so responses are held to `max_frame_length` like anything else read off the wire
+	pub fn new(discovery: AuthDiscovery, config: &AuthSection, tls: Option<TlsConnector>, codec: Codec, max_frame_length: usize) -> AuthPool {
+		AuthPool {
+			discovery,
+			entries: Arc::new(Mutex::new(HashMap::new())),
+			call_timeout: config.call_timeout(),
+			backoff_initial: config.backoff_initial(),
+			backoff_max: config.backoff_max(),
+			tls,
+			codec,
+			max_frame_length,
+			metrics: Arc::new(AuthMetrics::default()),
+		}
+	}
+
+	/// A context whose deadline is `auth.call_timeout` from now
+	pub fn context(&self) -> Context {
+		let mut context = tarpc::context::current();
+		context.deadline = SystemTime::now() + self.call_timeout;
+		context
+	}
+
+	async fn entry(&self, domain: &str) -> Arc<Mutex<PooledAuth>> {
+		self.entries.lock().await
+			.entry(domain.to_string())
+			.or_default()
+			.clone()
+	}
+
+	/// Returns the pooled client for `domain`, connecting if needed. `None` while backing off after failures.
+	pub async fn get(&self, domain: &str) -> Option<RealmAuthClient> {
+		let entry = self.entry(domain).await;
+		let mut entry = entry.lock().await;
+
+		if let Some(client) = &entry.client {
+			return Some(client.clone());
+		}
+
+		if entry.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+			return None;
+		}
+
+		let endpoint = self.discovery.resolve(domain).await;
+		let auth_transport = net::connect(&endpoint.host, endpoint.port, self.tls.as_ref(), self.codec, self.max_frame_length);
+
+		self.metrics.connects.inc();
+		match tokio::time::timeout(self.call_timeout, auth_transport).await {
+			Ok(Ok(connected)) => {
+				let client = RealmAuthClient::new(tarpc::client::Config::default(), connected).spawn();
+				entry.client = Some(client.clone());
+				entry.failures = 0;
+				entry.retry_at = None;
+				Some(client)
+			}
+			_ => {
+				self.metrics.connect_failures.inc();
+				entry.failures += 1;
+				let backoff = self.backoff(entry.failures);
+				entry.retry_at = Some(Instant::now() + backoff);
+				error!("Unable to reach auth server for {} at {}, retrying in {:?}", domain, endpoint, backoff);
+				self.discovery.forget(domain).await;
+				None
+			}
+		}
+	}
+
+	/// How long to wait before dialing again after `failures` failed connects in a row, doubling up to `auth.backoff_max`
+	pub fn backoff(&self, failures: u32) -> Duration {
+		self.backoff_initial
+			.saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+			.min(self.backoff_max)
+	}
+
+	/// Drops the pooled client after a transport-level failure so the next call reconnects
+	pub async fn report_error(&self, domain: &str, rpc_error: &RpcError) {
+		match rpc_error {
+			RpcError::Shutdown | RpcError::Send(_) | RpcError::Receive(_) => {
+				warn!("Connection to auth server for {} failed: {}", domain, rpc_error);
+				self.entry(domain).await.lock().await.client = None;
+			}
+			RpcError::DeadlineExceeded | RpcError::Server(_) => {}
+		}
+	}
+
+	/// Pings every pooled connection, dropping the ones that don't answer, and logs the validation metrics
+	pub async fn health_check(self, period: Duration) {
+		let mut ticker = interval(period);
+
+		loop {
+			ticker.tick().await;
+
+			let entries = self.entries.lock().await.iter()
+				.map(|(domain, entry)| (domain.clone(), entry.clone()))
+				.collect::<Vec<_>>();
+
+			for (domain, entry) in entries {
+				let Some(client) = entry.lock().await.client.clone() else {
+					continue;
+				};
+
+				if let Err(e) = client.test(self.context(), "ping".to_string()).await {
+					self.report_error(&domain, &e).await;
+					if let RpcError::DeadlineExceeded = e {
+						warn!("Auth server for {} did not answer a health check in time", domain);
+						entry.lock().await.client = None;
+					}
+				}
+			}
+
+			let metrics = &self.metrics;
+			let validations = metrics.token_validation_latency.count();
+			if validations > 0 {
+				info!("Token validations: {} ({} failed), mean latency {:?}, auth connects: {} ({} failed)",
+					metrics.token_validations.get(), metrics.token_validation_failures.get(),
+					metrics.token_validation_latency.sum() / validations as u32,
+					metrics.connects.get(), metrics.connect_failures.get());
+			}
+		}
+	}
+}
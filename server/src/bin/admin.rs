@@ -0,0 +1,268 @@
+use std::env;
+use std::io;
+use std::sync::Arc;
+use anyhow::{anyhow, bail};
+use tarpc::context;
+use realm_auth::types::RealmAuthClient;
+use realm_server::admin;
+use realm_server::config::ServerConfig;
+use realm_server::stats;
+use realm_server::store::{self, ChatStore};
+use realm_server::types::{Ban, RealmChatClient, Room, ServerStats, User};
+use realm_shared::config::DatabaseBackend;
+use realm_shared::net::{self, Codec};
+use realm_shared::types::ErrorCode;
+
+const USAGE: &str = "Usage: realm-admin [--db <url> | --connect <host:port> --auth <host:port> --user <@name:domain>] <command>
+
+Commands:
+  stats               Users online, messages per minute, storage, CPU and RAM
+  users               List users
+  rooms               List rooms
+  bans                List active bans
+  promote <userid>    Make a user an admin
+  pardon <userid>     Lift a user's ban
+  compact             Give back space left by deleted rows, locks the database while it runs
+
+Without --connect the database is opened directly, from --db or database.url in server.toml
+(or CONFIG_PATH); use this while the server is stopped. With --connect, commands go through the
+running server as --user, an admin (an owner for promote and compact), whose bearer token is read
+from REALM_TOKEN. --auth is that user's auth server, which issues the stoken for the chat server.
+REALM_TLS, REALM_TLS_CA and REALM_CODEC work as they do for the client.";
+
+/// Where commands are carried out
+enum Target {
+	Offline(Arc<dyn ChatStore>),
+	Online {
+		client: RealmChatClient,
+		stoken: String,
+		userid: String,
+	},
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+	let mut args = env::args().skip(1);
+	let mut db = None;
+	let mut connect = None;
+	let mut auth = None;
+	let mut user = None;
+	let mut command = Vec::new();
+
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--db" => db = Some(args.next().ok_or(anyhow!("--db needs a database url"))?),
+			"--connect" => connect = Some(args.next().ok_or(anyhow!("--connect needs host:port"))?),
+			"--auth" => auth = Some(args.next().ok_or(anyhow!("--auth needs host:port"))?),
+			"--user" => user = Some(args.next().ok_or(anyhow!("--user needs a userid"))?),
+			"-h" | "--help" => {
+				println!("{}", USAGE);
+				return Ok(());
+			}
+			_ => command.push(arg),
+		}
+	}
+
+	let target = match connect {
+		Some(address) => {
+			let userid = user.ok_or(anyhow!("--connect needs --user"))?;
+			let auth = auth.ok_or(anyhow!("--connect needs --auth"))?;
+			connect_online(&address, &auth, userid).await?
+		}
+		None => {
+			let (backend, url) = match db {
+				Some(url) => (DatabaseBackend::from_url(&url).ok_or(anyhow!("--db needs a sqlite: or postgres:// url"))?, url),
+				None => {
+					let database = ServerConfig::load()?.database;
+					(database.backend, database.url)
+				}
+			};
+			Target::Offline(store::connect(backend, &url).await?)
+		}
+	};
+
+	let command: Vec<&str> = command.iter().map(String::as_str).collect();
+	match command.as_slice() {
+		["stats"] => print_stats(&target.stats().await?),
+		["users"] => print_users(&target.users().await?),
+		["rooms"] => print_rooms(&target.rooms().await?),
+		["bans"] => print_bans(&target.bans().await?),
+		["promote", userid] => {
+			target.promote(userid).await?;
+			println!("{} is now an admin", userid);
+		}
+		["pardon", userid] => {
+			target.pardon(userid).await?;
+			println!("Lifted the ban on {}", userid);
+		}
+		["compact"] => target.compact().await?,
+		_ => bail!("{}", USAGE),
+	}
+
+	Ok(())
+}
+
+async fn connect_online(address: &str, auth_address: &str, userid: String) -> anyhow::Result<Target> {
+	let token = env::var("REALM_TOKEN").map_err(|_| anyhow!("REALM_TOKEN must hold {}'s bearer token", userid))?;
+	let (host, port) = address.rsplit_once(':').ok_or(anyhow!("expected host:port, found {}", address))?;
+	let port: u16 = port.parse()?;
+	let (auth_host, auth_port) = auth_address.rsplit_once(':').ok_or(anyhow!("expected host:port, found {}", auth_address))?;
+	let auth_port: u16 = auth_port.parse()?;
+
+	let codec = match env::var("REALM_CODEC").as_deref() {
+		Ok("json") => Codec::Json,
+		Ok("bincode") | Err(_) => Codec::Bincode,
+		Ok(other) => bail!("unknown REALM_CODEC {}", other),
+	};
+	let tls = match env::var("REALM_TLS").is_ok_and(|v| v.eq("1")) {
+		true => Some(net::client_connector(env::var("REALM_TLS_CA").ok().as_deref())?),
+		false => None,
+	};
+
+	let transport = net::connect(host, port, tls.as_ref(), codec, usize::MAX).await?;
+	let client = RealmChatClient::new(tarpc::client::Config::default(), transport).spawn();
+	let info = client.get_info(context::current()).await?;
+
+	let transport = net::connect(auth_host, auth_port, tls.as_ref(), codec, usize::MAX).await?;
+	let auth_client = RealmAuthClient::new(tarpc::client::Config::default(), transport).spawn();
+	let stoken = rpc(auth_client.get_server_token(context::current(), userid.clone(), token, info.server_id, host.to_string(), port).await)?;
+
+	Ok(Target::Online { client, stoken, userid })
+}
+
+/// Turns a server's answer into an error naming its ErrorCode
+fn rpc<T>(result: Result<Result<T, ErrorCode>, tarpc::client::RpcError>) -> anyhow::Result<T> {
+	match result {
+		Ok(Ok(value)) => Ok(value),
+		Ok(Err(code)) => Err(anyhow!("the server refused: {:?}", code)),
+		Err(e) => Err(io::Error::other(e).into()),
+	}
+}
+
+impl Target {
+	async fn stats(&self) -> anyhow::Result<ServerStats> {
+		match self {
+			Target::Offline(store) => Ok(stats::collect(store.as_ref()).await?),
+			Target::Online { client, stoken, userid } => rpc(client.get_stats(context::current(), stoken.clone(), userid.clone()).await),
+		}
+	}
+
+	async fn users(&self) -> anyhow::Result<Vec<User>> {
+		match self {
+			Target::Offline(store) => admin::list_users(store.as_ref()).await,
+			Target::Online { client, .. } => rpc(client.get_users(context::current()).await),
+		}
+	}
+
+	async fn rooms(&self) -> anyhow::Result<Vec<Room>> {
+		match self {
+			Target::Offline(store) => admin::list_rooms(store.as_ref()).await,
+			Target::Online { client, stoken, userid } => rpc(client.get_rooms(context::current(), stoken.clone(), userid.clone()).await),
+		}
+	}
+
+	async fn bans(&self) -> anyhow::Result<Vec<Ban>> {
+		match self {
+			Target::Offline(store) => admin::list_bans(store.as_ref()).await,
+			Target::Online { client, stoken, userid } => rpc(client.list_bans(context::current(), stoken.clone(), userid.clone()).await),
+		}
+	}
+
+	async fn promote(&self, target: &str) -> anyhow::Result<()> {
+		match self {
+			Target::Offline(store) => admin::promote_user(store.as_ref(), target).await,
+			Target::Online { client, stoken, userid } => rpc(client.promote_user(context::current(), stoken.clone(), userid.clone(), target.to_string()).await),
+		}
+	}
+
+	async fn pardon(&self, target: &str) -> anyhow::Result<()> {
+		match self {
+			Target::Offline(store) => admin::pardon_user(store.as_ref(), target).await,
+			Target::Online { client, stoken, userid } => rpc(client.pardon_user(context::current(), stoken.clone(), userid.clone(), target.to_string()).await),
+		}
+	}
+
+	async fn compact(&self) -> anyhow::Result<()> {
+		match self {
+			Target::Offline(store) => {
+				let freed = admin::compact_database(store.as_ref()).await?;
+				println!("Compacted the database, freed {}", human_bytes(freed));
+			}
+			Target::Online { client, stoken, userid } => {
+				// VACUUM can take a while on a big database
+				let mut context = context::current();
+				context.deadline = std::time::SystemTime::now() + std::time::Duration::from_secs(10*60);
+				rpc(client.compact_database(context, stoken.clone(), userid.clone()).await)?;
+				println!("Compacted the database");
+			}
+		}
+		Ok(())
+	}
+}
+
+fn print_stats(stats: &ServerStats) {
+	match stats.users_online {
+		Some(online) => println!("Users online:        {}", online),
+		None => println!("Users online:        unknown (offline database)"),
+	}
+	println!("Users:               {}", stats.users_total);
+	println!("Rooms:               {}", stats.rooms_total);
+	println!("Messages:            {}", stats.messages_total);
+	println!("Messages per minute: {:.2}", stats.messages_per_minute);
+	println!("Database size:       {}", human_bytes(stats.storage.database_bytes));
+
+	println!("Storage by message type:");
+	for (msg_type, bytes) in &stats.storage.by_message_type {
+		println!("  {:<18} {}", msg_type, human_bytes(*bytes));
+	}
+	println!("Storage by room:");
+	for (roomid, bytes) in &stats.storage.by_room {
+		println!("  {:<18} {}", roomid, human_bytes(*bytes));
+	}
+
+	match &stats.process {
+		Some(process) => {
+			println!("Uptime:              {}s", process.uptime.as_secs());
+			println!("CPU:                 {:.1}%", process.cpu_percent);
+			println!("RAM:                 {}", human_bytes(process.memory_bytes as i64));
+		}
+		None => println!("CPU and RAM:         unknown"),
+	}
+}
+
+fn print_users(users: &[User]) {
+	for user in users {
+		let role = if user.owner { "owner" } else if user.admin { "admin" } else { "" };
+		println!("{:<32} {:<24} {}", user.userid, user.display_name(), role);
+	}
+}
+
+fn print_rooms(rooms: &[Room]) {
+	for room in rooms {
+		let mut flags = Vec::new();
+		if room.admin_only_send { flags.push("admin-only send"); }
+		if room.admin_only_view { flags.push("admin-only view"); }
+		println!("{:<32} {}", room.roomid, flags.join(", "));
+	}
+}
+
+fn print_bans(bans: &[Ban]) {
+	for ban in bans {
+		let expires = ban.expires.map_or("never".to_string(), |e| e.to_rfc3339());
+		println!("{:<32} by {:<24} expires {:<26} {}", ban.userid, ban.banned_by, expires, ban.reason);
+	}
+}
+
+fn human_bytes(bytes: i64) -> String {
+	const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+	let mut size = bytes as f64;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < UNITS.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	match unit {
+		0 => format!("{} B", bytes),
+		_ => format!("{:.1} {}", size, UNITS[unit]),
+	}
+}
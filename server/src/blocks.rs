@@ -0,0 +1,67 @@
+use std::future::Future;
+use std::sync::Arc;
+use moka::future::Cache;
+use realm_shared::types::ErrorCode;
+use crate::config::CacheSection;
+
+/// Block lists pulled from each member's auth server, which is the source of truth for them, shared by all
+/// channels of a server. Concurrent misses on the same user share one fetch, and a failed fetch is remembered
+/// for `cache.block_list_failure_ttl` so an unreachable auth server isn't asked again on every request.
+#[derive(Clone)]
+pub struct BlockLists {
+	lists: Cache<String, Arc<Vec<String>>>, //NOTE: user.userid -> userids they blocked
+	failures: Cache<String, ErrorCode>,
+	stokens: Cache<String, String>, //NOTE: user.userid -> their last validated stoken, what their auth server wants to see
+}
+
+impl BlockLists {
+	pub fn new(config: &CacheSection) -> BlockLists {
+		BlockLists {
+			lists: Cache::builder()
+				.max_capacity(config.block_list_capacity)
+				.time_to_live(config.block_list_ttl())
+				.build(),
+			failures: Cache::builder()
+				.max_capacity(config.block_list_capacity)
+				.time_to_live(config.block_list_failure_ttl())
+				.build(),
+			stokens: Cache::builder()
+				.max_capacity(config.block_list_capacity)
+				.build(),
+		}
+	}
+
+	pub async fn remember_stoken(&self, userid: &str, stoken: &str) {
+		self.stokens.insert(userid.to_string(), stoken.to_string()).await;
+	}
+
+	pub async fn stoken(&self, userid: &str) -> Option<String> {
+		self.stokens.get(userid).await
+	}
+
+	/// Drops a stoken the user's auth server refused, e.g. after they signed out
+	pub async fn forget_stoken(&self, userid: &str) {
+		self.stokens.invalidate(userid).await;
+	}
+
+	/// The cached list for `userid`, otherwise the result of `fetch`
+	pub async fn get_or_fetch(&self, userid: &str, fetch: impl Future<Output = Result<Vec<String>, ErrorCode>>) -> Result<Arc<Vec<String>>, ErrorCode> {
+		if let Some(failed) = self.failures.get(userid).await {
+			return Err(failed);
+		}
+
+		match self.lists.try_get_with(userid.to_string(), async { fetch.await.map(Arc::new) }).await {
+			Ok(blocked) => Ok(blocked),
+			Err(failed) => {
+				self.failures.insert(userid.to_string(), (*failed).clone()).await;
+				Err((*failed).clone())
+			}
+		}
+	}
+
+	/// Replaces the cached list with a freshly fetched one
+	pub async fn insert(&self, userid: &str, blocked: Vec<String>) {
+		self.failures.invalidate(userid).await;
+		self.lists.insert(userid.to_string(), Arc::new(blocked)).await;
+	}
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+use serde::Deserialize;
+use crate::discovery::AuthEndpoint;
+use realm_shared::config::{env_override, load_file, ConfigError, DatabaseBackend};
+use realm_shared::net::{CodecConfig, TlsConfig};
+
+/// Everything `realm_server` needs at startup, loaded once from `server.toml` and env vars.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+	pub server: ServerSection,
+	pub database: DatabaseSection,
+	pub limits: LimitsSection,
+	pub cache: CacheSection,
+	pub presence: PresenceSection,
+	pub moderation: ModerationSection,
+	pub shutdown: ShutdownSection,
+	pub metrics: MetricsSection,
+	pub auth: AuthSection,
+	pub tls: TlsConfig,
+	pub codec: CodecConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+	pub id: String,
+	pub domain: String,
+	pub bind_address: IpAddr,
+	pub port: u16,
+}
+
+impl Default for ServerSection {
+	fn default() -> Self {
+		Self {
+			id: String::new(),
+			domain: String::new(),
+			bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+			port: 5051,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSection {
+	pub backend: DatabaseBackend,
+	pub url: String,
+}
+
+impl Default for DatabaseSection {
+	fn default() -> Self {
+		Self { backend: DatabaseBackend::Sqlite, url: "sqlite:server.db".to_string() }
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+	pub max_channels: usize,
+	pub max_channels_per_ip: u32,
+	pub max_channels_per_user: usize,
+	pub max_in_flight_per_channel: usize,
+	pub max_frame_length: usize, //NOTE: Bytes
+	pub max_message_length: usize, //NOTE: Characters of message text
+	pub max_input_length: usize, //NOTE: Characters of any other free text, e.g. reasons, notes and room names
+	pub max_messages_per_request: u32,
+}
+
+impl Default for LimitsSection {
+	fn default() -> Self {
+		Self {
+			max_channels: 10240,
+			max_channels_per_ip: 1024,
+			max_channels_per_user: 16,
+			max_in_flight_per_channel: 64,
+			max_frame_length: 16 * 1024 * 1024,
+			max_message_length: 4000,
+			max_input_length: 512,
+			max_messages_per_request: 500,
+		}
+	}
+}
+
+/// All durations are in seconds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSection {
+	pub stoken_capacity: u64,
+	pub stoken_idle: u64,
+	pub stoken_ttl: u64,
+	pub block_list_capacity: u64,
+	pub block_list_ttl: u64,
+	pub block_list_failure_ttl: u64,
+}
+
+impl Default for CacheSection {
+	fn default() -> Self {
+		Self {
+			stoken_capacity: 10_000,
+			stoken_idle: 5*60,
+			stoken_ttl: 60*60,
+			block_list_capacity: 10_000,
+			block_list_ttl: 10*60,
+			block_list_failure_ttl: 30,
+		}
+	}
+}
+
+/// All durations are in seconds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PresenceSection {
+	pub sweep_interval: u64,
+	pub idle_after: u64,
+	pub offline_after: u64,
+}
+
+impl Default for PresenceSection {
+	fn default() -> Self {
+		Self {
+			sweep_interval: 15,
+			idle_after: 5*60,
+			offline_after: 15*60,
+		}
+	}
+}
+
+/// All durations are in seconds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationSection {
+	pub ban_sweep_interval: u64,
+}
+
+impl Default for ModerationSection {
+	fn default() -> Self {
+		Self {
+			ban_sweep_interval: 60,
+		}
+	}
+}
+
+/// On SIGTERM/SIGINT clients are told to reconnect, get `notice` seconds to hear it,
+/// then in-flight requests get `drain_timeout` seconds to finish.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownSection {
+	pub notice: u64,
+	pub drain_timeout: u64,
+}
+
+impl Default for ShutdownSection {
+	fn default() -> Self {
+		Self {
+			notice: 3,
+			drain_timeout: 30,
+		}
+	}
+}
+
+/// The Prometheus endpoint, `GET /metrics`. Only listens on loopback unless told otherwise.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSection {
+	pub enabled: bool,
+	pub bind_address: IpAddr,
+	pub port: u16,
+}
+
+impl Default for MetricsSection {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+			port: 9051,
+		}
+	}
+}
+
+/// How a user's domain is mapped to their auth server. `endpoints` always wins over the resolver.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+	pub port: u16, //NOTE: Used on the user's own domain when nothing else is published
+	pub resolver: ResolverKind,
+	pub discovery_ttl: u64,
+	pub endpoints: HashMap<String, String>, //NOTE: domain -> "host:port"
+	pub call_timeout: u64,
+	pub health_check_interval: u64,
+	pub backoff_initial: u64,
+	pub backoff_max: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolverKind {
+	Srv,
+	Static,
+}
+
+impl Default for AuthSection {
+	fn default() -> Self {
+		Self {
+			port: 5052,
+			resolver: ResolverKind::Srv,
+			discovery_ttl: 10*60,
+			endpoints: HashMap::new(),
+			call_timeout: 5,
+			health_check_interval: 30,
+			backoff_initial: 1,
+			backoff_max: 60,
+		}
+	}
+}
+
+impl ServerConfig {
+	/// Loads `server.toml` (or `CONFIG_PATH`), applies env overrides, then validates.
+	pub fn load() -> Result<ServerConfig, ConfigError> {
+		let mut config: ServerConfig = load_file("./server.toml")?;
+
+		env_override(&mut config.server.id, "SERVER_ID", "server.id")?;
+		env_override(&mut config.server.domain, "DOMAIN", "server.domain")?;
+		env_override(&mut config.server.bind_address, "BIND_ADDRESS", "server.bind_address")?;
+		env_override(&mut config.server.port, "PORT", "server.port")?;
+		env_override(&mut config.database.backend, "DATABASE_BACKEND", "database.backend")?;
+		env_override(&mut config.database.url, "DATABASE_URL", "database.url")?;
+		env_override(&mut config.auth.port, "AUTH_PORT", "auth.port")?;
+
+		config.validate()?;
+		Ok(config)
+	}
+
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		if self.server.id.is_empty() {
+			return Err(ConfigError::invalid("server.id", "must be set"));
+		}
+		if self.server.domain.is_empty() {
+			return Err(ConfigError::invalid("server.domain", "must be set"));
+		}
+		if self.server.port == 0 {
+			return Err(ConfigError::invalid("server.port", "must not be 0"));
+		}
+		match (self.database.backend, DatabaseBackend::from_url(&self.database.url)) {
+			(DatabaseBackend::Sqlite, Some(DatabaseBackend::Sqlite)) | (DatabaseBackend::Postgres, Some(DatabaseBackend::Postgres)) => {}
+			(DatabaseBackend::Sqlite, _) => return Err(ConfigError::invalid("database.url", "must be a sqlite: url")),
+			(DatabaseBackend::Postgres, _) => return Err(ConfigError::invalid("database.url", "must be a postgres:// url")),
+		}
+		if self.limits.max_channels == 0 {
+			return Err(ConfigError::invalid("limits.max_channels", "must be at least 1"));
+		}
+		if self.limits.max_channels_per_ip == 0 {
+			return Err(ConfigError::invalid("limits.max_channels_per_ip", "must be at least 1"));
+		}
+		if self.limits.max_channels_per_user == 0 {
+			return Err(ConfigError::invalid("limits.max_channels_per_user", "must be at least 1"));
+		}
+		if self.limits.max_in_flight_per_channel == 0 {
+			return Err(ConfigError::invalid("limits.max_in_flight_per_channel", "must be at least 1"));
+		}
+		if self.limits.max_frame_length < 64 * 1024 {
+			return Err(ConfigError::invalid("limits.max_frame_length", "must be at least 65536"));
+		}
+		if self.limits.max_message_length == 0 {
+			return Err(ConfigError::invalid("limits.max_message_length", "must be at least 1"));
+		}
+		if self.limits.max_input_length == 0 {
+			return Err(ConfigError::invalid("limits.max_input_length", "must be at least 1"));
+		}
+		if self.limits.max_messages_per_request == 0 {
+			return Err(ConfigError::invalid("limits.max_messages_per_request", "must be at least 1"));
+		}
+		if self.cache.stoken_ttl == 0 {
+			return Err(ConfigError::invalid("cache.stoken_ttl", "must be at least 1"));
+		}
+		if self.cache.stoken_idle == 0 {
+			return Err(ConfigError::invalid("cache.stoken_idle", "must be at least 1"));
+		}
+		if self.cache.block_list_ttl == 0 {
+			return Err(ConfigError::invalid("cache.block_list_ttl", "must be at least 1"));
+		}
+		if self.cache.block_list_failure_ttl == 0 {
+			return Err(ConfigError::invalid("cache.block_list_failure_ttl", "must be at least 1"));
+		}
+		if self.presence.sweep_interval == 0 {
+			return Err(ConfigError::invalid("presence.sweep_interval", "must be at least 1"));
+		}
+		if self.presence.offline_after <= self.presence.idle_after {
+			return Err(ConfigError::invalid("presence.offline_after", "must be greater than presence.idle_after"));
+		}
+		if self.moderation.ban_sweep_interval == 0 {
+			return Err(ConfigError::invalid("moderation.ban_sweep_interval", "must be at least 1"));
+		}
+		if self.shutdown.drain_timeout == 0 {
+			return Err(ConfigError::invalid("shutdown.drain_timeout", "must be at least 1"));
+		}
+		if self.metrics.enabled && self.metrics.port == 0 {
+			return Err(ConfigError::invalid("metrics.port", "must not be 0"));
+		}
+		if self.auth.port == 0 {
+			return Err(ConfigError::invalid("auth.port", "must not be 0"));
+		}
+		if self.auth.discovery_ttl == 0 {
+			return Err(ConfigError::invalid("auth.discovery_ttl", "must be at least 1"));
+		}
+		if self.auth.call_timeout == 0 {
+			return Err(ConfigError::invalid("auth.call_timeout", "must be at least 1"));
+		}
+		if self.auth.health_check_interval == 0 {
+			return Err(ConfigError::invalid("auth.health_check_interval", "must be at least 1"));
+		}
+		if self.auth.backoff_initial == 0 {
+			return Err(ConfigError::invalid("auth.backoff_initial", "must be at least 1"));
+		}
+		if self.auth.backoff_max < self.auth.backoff_initial {
+			return Err(ConfigError::invalid("auth.backoff_max", "must not be less than auth.backoff_initial"));
+		}
+		self.auth.endpoints()?;
+		self.tls.validate()?;
+		self.codec.validate()?;
+
+		Ok(())
+	}
+}
+
+impl CacheSection {
+	pub fn stoken_idle(&self) -> Duration { Duration::from_secs(self.stoken_idle) }
+	pub fn stoken_ttl(&self) -> Duration { Duration::from_secs(self.stoken_ttl) }
+	pub fn block_list_ttl(&self) -> Duration { Duration::from_secs(self.block_list_ttl) }
+	pub fn block_list_failure_ttl(&self) -> Duration { Duration::from_secs(self.block_list_failure_ttl) }
+}
+
+impl AuthSection {
+	pub fn discovery_ttl(&self) -> Duration { Duration::from_secs(self.discovery_ttl) }
+	pub fn call_timeout(&self) -> Duration { Duration::from_secs(self.call_timeout) }
+	pub fn health_check_interval(&self) -> Duration { Duration::from_secs(self.health_check_interval) }
+	pub fn backoff_initial(&self) -> Duration { Duration::from_secs(self.backoff_initial) }
+	pub fn backoff_max(&self) -> Duration { Duration::from_secs(self.backoff_max) }
+
+	pub fn endpoints(&self) -> Result<HashMap<String, AuthEndpoint>, ConfigError> {
+		self.endpoints.iter()
+			.map(|(domain, endpoint)| endpoint.parse::<AuthEndpoint>()
+				.map(|endpoint| (domain.clone(), endpoint))
+				.map_err(|reason| ConfigError::invalid(&format!("auth.endpoints.{}", domain), reason)))
+			.collect()
+	}
+}
+
+impl PresenceSection {
+	pub fn sweep_interval(&self) -> Duration { Duration::from_secs(self.sweep_interval) }
+	pub fn idle_after(&self) -> Duration { Duration::from_secs(self.idle_after) }
+	pub fn offline_after(&self) -> Duration { Duration::from_secs(self.offline_after) }
+}
+
+impl ModerationSection {
+	pub fn ban_sweep_interval(&self) -> Duration { Duration::from_secs(self.ban_sweep_interval) }
+}
+
+impl ShutdownSection {
+	pub fn notice(&self) -> Duration { Duration::from_secs(self.notice) }
+	pub fn drain_timeout(&self) -> Duration { Duration::from_secs(self.drain_timeout) }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hickory_resolver::TokioAsyncResolver;
+use moka::future::Cache;
+use tracing::warn;
+use crate::config::{AuthSection, ResolverKind};
+
+/// Where a domain's auth server can be reached.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuthEndpoint {
+	pub host: String,
+	pub port: u16,
+}
+
+impl Display for AuthEndpoint {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}:{}", self.host, self.port)
+	}
+}
+
+impl FromStr for AuthEndpoint {
+	type Err = String;
+
+	/// Parses `host:port`
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (host, port) = s.rsplit_once(':').ok_or(format!("expected host:port, found {}", s))?;
+		let port = port.parse::<u16>().map_err(|_| format!("invalid port in {}", s))?;
+		if host.is_empty() {
+			return Err(format!("missing host in {}", s));
+		}
+
+		Ok(AuthEndpoint { host: host.to_string(), port })
+	}
+}
+
+/// Maps a user's domain to their auth server. `None` means the domain publishes nothing,
+/// in which case `AuthDiscovery` falls back to the domain itself on the default port.
+pub trait AuthResolver: Send + Sync {
+	fn resolve<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Option<AuthEndpoint>>;
+}
+
+/// Resolves from a fixed table, handing anything it doesn't know to `next`.
+/// Used for `auth.endpoints` overrides and as a local stub for testing.
+pub struct StaticResolver {
+	pub endpoints: HashMap<String, AuthEndpoint>,
+	pub next: Option<Arc<dyn AuthResolver>>,
+}
+
+impl AuthResolver for StaticResolver {
+	fn resolve<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Option<AuthEndpoint>> {
+		async move {
+			if let Some(endpoint) = self.endpoints.get(domain) {
+				return Some(endpoint.clone());
+			}
+
+			match &self.next {
+				Some(next) => next.resolve(domain).await,
+				None => None,
+			}
+		}.boxed()
+	}
+}
+
+/// Looks up the `_realm-auth._tcp.<domain>` SRV record, preferring the lowest priority then highest weight.
+pub struct SrvResolver {
+	resolver: TokioAsyncResolver,
+}
+
+impl SrvResolver {
+	pub fn from_system_conf() -> anyhow::Result<SrvResolver> {
+		Ok(SrvResolver { resolver: TokioAsyncResolver::tokio_from_system_conf()? })
+	}
+}
+
+impl AuthResolver for SrvResolver {
+	fn resolve<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Option<AuthEndpoint>> {
+		async move {
+			let lookup = match self.resolver.srv_lookup(format!("_realm-auth._tcp.{}.", domain)).await {
+				Ok(lookup) => lookup,
+				Err(e) => {
+					warn!("No auth SRV record for {}: {}", domain, e);
+					return None;
+				}
+			};
+
+			lookup.iter()
+				.min_by_key(|srv| (srv.priority(), u16::MAX - srv.weight()))
+				.map(|srv| AuthEndpoint {
+					host: srv.target().to_utf8().trim_end_matches('.').to_string(),
+					port: srv.port(),
+				})
+		}.boxed()
+	}
+}
+
+/// Caches resolved auth endpoints per domain; shared by every connection.
+#[derive(Clone)]
+pub struct AuthDiscovery {
+	resolver: Arc<dyn AuthResolver>,
+	default_port: u16,
+	cache: Cache<String, AuthEndpoint>,
+}
+
+impl AuthDiscovery {
+	pub fn new(resolver: Arc<dyn AuthResolver>, default_port: u16, ttl: Duration) -> AuthDiscovery {
+		AuthDiscovery {
+			resolver,
+			default_port,
+			cache: Cache::builder()
+				.max_capacity(10_000)
+				.time_to_live(ttl)
+				.build(),
+		}
+	}
+
+	pub fn from_config(config: &AuthSection) -> anyhow::Result<AuthDiscovery> {
+		let next: Option<Arc<dyn AuthResolver>> = match config.resolver {
+			ResolverKind::Srv => Some(Arc::new(SrvResolver::from_system_conf()?)),
+			ResolverKind::Static => None,
+		};
+
+		let resolver = StaticResolver { endpoints: config.endpoints()?, next };
+		Ok(AuthDiscovery::new(Arc::new(resolver), config.port, config.discovery_ttl()))
+	}
+
+	pub async fn resolve(&self, domain: &str) -> AuthEndpoint {
+		if let Some(endpoint) = self.cache.get(domain).await {
+			return endpoint;
+		}
+
+		let endpoint = self.resolver.resolve(domain).await.unwrap_or(AuthEndpoint {
+			host: domain.to_string(),
+			port: self.default_port,
+		});
+
+		self.cache.insert(domain.to_string(), endpoint.clone()).await;
+		endpoint
+	}
+
+	/// Drops a cached endpoint, e.g. after failing to connect to it
+	pub async fn forget(&self, domain: &str) {
+		self.cache.invalidate(domain).await;
+	}
+}
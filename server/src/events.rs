@@ -1,4 +1,6 @@
-use crate::types::{Message, Room, User};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use crate::types::{Message, OwnershipTransfer, Presence, Room, Timeout, User};
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum Event {
@@ -8,8 +10,52 @@ pub enum Event {
 	NewMessage(Message),
 	NewRoom(Room),
 	DeleteRoom(String),
+	StartedTyping(String, String), //NOTE: user.userid, room.roomid
+	StoppedTyping(String, String), //NOTE: user.userid, room.roomid
+	TimedOutUser(Timeout),
+	RemovedTimeout(String),
+	RenamedUser(User),
+	PresenceChanged(Presence),
+	OfferedOwnership(OwnershipTransfer),
+	ChangedOwnership(User),
+	ShuttingDown, //NOTE: The server is going away, reconnect (possibly to another instance) and poll from 0
 	// KickedUser(KickedUser),
 	// BannedUser(BannedUser),
 	// PromotedUser(PromotedUser),
 	// DemotedUser(DemotedUser),
-}
\ No newline at end of file
+}
+
+impl Event {
+	/// Moderation events are only sent to the affected user and to admins.
+	pub fn is_visible_to(&self, userid: &str, is_admin: bool) -> bool {
+		match self {
+			Event::TimedOutUser(timeout) => is_admin || timeout.userid.eq(userid),
+			Event::RemovedTimeout(target) => is_admin || target.eq(userid),
+			Event::OfferedOwnership(transfer) => transfer.from_userid.eq(userid) || transfer.to_userid.eq(userid),
+			_ => true,
+		}
+	}
+}
+
+/// Events held in memory for polling clients, numbered from 1. They don't outlive the process,
+/// which is why clients poll from 0 again after `ShuttingDown`.
+#[derive(Clone, Default)]
+pub struct EventLog(Arc<Mutex<Vec<(u32, Event)>>>);
+
+impl EventLog {
+	pub async fn push(&self, event: Event) -> u32 {
+		let mut events = self.0.lock().await;
+		let index = events.len() as u32 + 1;
+		events.push((index, event));
+		index
+	}
+
+	pub async fn since(&self, index: u32) -> Vec<(u32, Event)> {
+		let events = self.0.lock().await;
+		events.iter().skip(index as usize).cloned().collect()
+	}
+
+	pub async fn count(&self) -> usize {
+		self.0.lock().await.len()
+	}
+}
@@ -1,3 +1,14 @@
 pub mod server;
 pub mod types;
-pub mod events;
\ No newline at end of file
+pub mod events;
+pub mod tasks;
+pub mod presence;
+pub mod admin;
+pub mod config;
+pub mod discovery;
+pub mod auth_pool;
+pub mod metrics;
+pub mod limits;
+pub mod blocks;
+pub mod stats;
+pub mod store;
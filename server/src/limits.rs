@@ -0,0 +1,36 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Open channels per user, shared by all channels of a server, so one account can't hog connections.
+#[derive(Clone, Default)]
+pub struct ConnectionLimiter {
+	users: Arc<Mutex<HashMap<String, HashSet<SocketAddr>>>>,
+}
+
+impl ConnectionLimiter {
+	/// Records `socket` as one of the user's channels, refusing it if they already have `max` others
+	pub async fn admit(&self, userid: &str, socket: SocketAddr, max: usize) -> bool {
+		let mut users = self.users.lock().await;
+		let sockets = users.entry(userid.to_string()).or_default();
+
+		if sockets.contains(&socket) {
+			return true;
+		}
+		if sockets.len() >= max {
+			return false;
+		}
+
+		sockets.insert(socket);
+		true
+	}
+
+	pub async fn release(&self, socket: SocketAddr) {
+		let mut users = self.users.lock().await;
+		users.retain(|_, sockets| {
+			sockets.remove(&socket);
+			!sockets.is_empty()
+		});
+	}
+}
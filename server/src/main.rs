@@ -1,63 +1,100 @@
 use std::env;
-use std::future::Future;
-use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::net::SocketAddr;
 use std::sync::{Arc};
+use std::time::Duration;
 use dotenvy::dotenv;
-use futures::future::{self};
-use futures::StreamExt;
-use sqlx::migrate::MigrateDatabase;
-use sqlx::{migrate, Sqlite, SqlitePool};
-use tarpc::{
-	server::{Channel},
-	tokio_serde::formats::Json,
-};
+use futures::{FutureExt, StreamExt};
+use tarpc::server::Channel;
 use tarpc::server::incoming::Incoming;
 use tarpc::server::BaseChannel;
-use tokio::sync::Mutex;
-use tracing::{info, subscriber, warn};
-use tracing::instrument::WithSubscriber;
+use tokio::time::timeout;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, subscriber, warn};
+use tracing_subscriber::filter::{filter_fn, LevelFilter};
+use tracing_subscriber::prelude::*;
 use realm_server::events::*;
-use realm_server::server::RealmChatServer;
+use realm_server::admin;
+use realm_server::config::ServerConfig;
+use realm_server::auth_pool::AuthPool;
+use realm_server::blocks::BlockLists;
+use realm_server::discovery::AuthDiscovery;
+use realm_server::limits::ConnectionLimiter;
+use realm_server::metrics::ChatMetrics;
+use realm_server::presence::{self, PresenceTracker};
+use realm_server::server::{RealmChatServer, SharedState};
+use realm_server::tasks;
+use realm_server::store;
 use realm_server::types::{RealmChat};
+use realm_shared::metrics::{self, DbMetricsLayer};
+use realm_shared::net;
+use realm_shared::redact;
+use realm_shared::shutdown::Shutdown;
 
-async fn spawn(fut: impl Future<Output = ()> + Send + 'static) {
-	tokio::spawn(fut);
-}
+/// How long shutdown waits for database connections still held by abandoned requests
+const POOL_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
 	dotenv().ok();
 
-	let subscriber = tracing_subscriber::fmt()
-		.compact()
-		.with_file(true)
-		.with_line_number(true)
-		.with_thread_ids(true)
-		.with_target(false)
-		.finish();
+	let metrics = Arc::new(ChatMetrics::default());
+
+	let subscriber = tracing_subscriber::registry()
+		.with(redact::layer().with_filter(LevelFilter::INFO))
+		.with(metrics.db.layer().with_filter(filter_fn(DbMetricsLayer::is_query)));
 
 	subscriber::set_global_default(subscriber)?;
 
-	let database_url: &str = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+	let config = ServerConfig::load()?;
+	let store = store::open(&config.database).await?; // TODO: Do in Docker with Sqlx-cli
+
+	// Recovery path for servers left without an owner: `realm_server recover-owner @user:domain`
+	let args: Vec<String> = env::args().collect();
+	if args.get(1).is_some_and(|a| a.eq("recover-owner")) {
+		let userid = args.get(2).expect("Usage: realm_server recover-owner <userid>");
+		admin::recover_ownership(store.as_ref(), userid).await?;
+		info!("{} is now an owner", userid);
+		return Ok(());
+	}
+
+	tokio::spawn(tasks::lift_expired_bans(store.clone(), config.moderation.ban_sweep_interval()));
+
+	let presence = PresenceTracker::default();
+	let auth_pool = AuthPool::new(AuthDiscovery::from_config(&config.auth)?, &config.auth, config.tls.connector()?, config.codec.connect, config.limits.max_frame_length);
+	tokio::spawn(auth_pool.clone().health_check(config.auth.health_check_interval()));
+
+	let block_lists = BlockLists::new(&config.cache);
+
+	tokio::spawn(tasks::sweep_presence(
+		store.clone(), presence.clone(),
+		config.presence.sweep_interval(), config.presence.idle_after(), config.presence.offline_after()));
 
-	if !Sqlite::database_exists(database_url).await.unwrap_or(false) {
-		info!("Creating database {}", database_url);
-		match Sqlite::create_database(database_url).await {
-			Ok(_) => info!("Create db success"),
-			Err(error) => panic!("error: {}", error),
-		}
-	} else {
-		warn!("Database already exists");
-	} // TODO: Do in Docker with Sqlx-cli
+	let connections = ConnectionLimiter::default();
 
-	let db_pool = SqlitePool::connect(database_url).await?;
+	let shared = SharedState {
+		store: store.clone(),
+		presence: presence.clone(),
+		block_lists,
+		auth_pool: auth_pool.clone(),
+		connections,
+		metrics: metrics.clone(),
+	};
 
-	info!("Running migrations...");
-	migrate!().run(&db_pool).await?; // TODO: Do in Docker with Sqlx-cli
-	info!("Migrations complete!");
+	if config.metrics.enabled {
+		let (metrics, auth_metrics, store) = (metrics.clone(), auth_pool.metrics.clone(), store.clone());
+		let render = move || {
+			let (metrics, auth_metrics, store) = (metrics.clone(), auth_metrics.clone(), store.clone());
+			async move { realm_server::metrics::render(&metrics, &auth_metrics, store.as_ref()).await }
+		};
+		let metrics_addr = SocketAddr::from((config.metrics.bind_address, config.metrics.port));
+		tokio::spawn(async move {
+			if let Err(e) = metrics::serve_http(metrics_addr, render).await {
+				error!("Metrics endpoint stopped: {e}");
+			}
+		});
+	}
 
-	let port = env::var("PORT").expect("PORT must be set").parse::<u16>()?;
-	let server_addr = (IpAddr::V4("0.0.0.0".parse()?), port);
+	let server_addr = (config.server.bind_address, config.server.port);
 
 	// let (handler, listener) = node::split::<()>();
 	// handler.network().listen(Transport::FramedTcp, "0.0.0.0:"+(port-1))?;
@@ -84,27 +121,64 @@ async fn main() -> anyhow::Result<()> {
 	// 	listener,
 	// }));
 
-	// JSON transport is provided by the json_transport tarpc module. It makes it easy
-	// to start up a serde-powered json serialization strategy over TCP.
-	let mut listener = tarpc::serde_transport::tcp::listen(&server_addr, Json::default).await?;
-	info!("Listening on port {}", listener.local_addr().port());
-	listener.config_mut().max_frame_length(usize::MAX);
-	listener
-		// Ignore accept errors.
-		.filter_map(|r| future::ready(r.ok()))
-		.map(BaseChannel::with_defaults)
-		// Limit channels to 1 per IP.
-		.max_channels_per_key(1024, |t| t.transport().peer_addr().unwrap().ip())
+	let shutdown = Shutdown::on_signal();
+	let drain = Shutdown::default();
+	// Every request handler, so draining can wait for them to finish and not just for their channels to close
+	let handlers = TaskTracker::new();
+
+	let tls = config.tls.acceptor(&config.server.domain)?;
+	let (_, listener) = net::listen(server_addr, tls, config.codec.accept.clone(), config.limits.max_frame_length).await?;
+	// Stop accepting once shutting down.
+	let serving = shutdown.until(listener)
+		.map(|transport| BaseChannel::with_defaults(drain.drain(transport)))
+		// Limit channels per IP.
+		.max_channels_per_key(config.limits.max_channels_per_ip, |t| t.transport().get_ref().get_ref().peer_addr().unwrap().ip())
 		// serve is generated by the service attribute. It takes as input any type implementing
 		// the generated World trait.
 		.map(|channel| {
-			let server = RealmChatServer::new(env::var("SERVER_ID").expect("SERVER_ID must be set"), channel.transport().peer_addr().unwrap(), db_pool.clone());
-			channel.execute(server.serve()).for_each(spawn)
+			let server = RealmChatServer::new(&config, channel.transport().get_ref().get_ref().peer_addr().unwrap(), shared.clone());
+			let closed = server.clone();
+			let handlers = handlers.clone();
+			let rpc_metrics = metrics.rpc.clone();
+			rpc_metrics.active_channels.inc();
+			// Limit requests in flight per channel, further ones are answered with an error.
+			channel.max_concurrent_requests(config.limits.max_in_flight_per_channel).execute(rpc_metrics.measure(server.serve())).for_each(move |handler| {
+				handlers.spawn(handler);
+				futures::future::ready(())
+			}).then(move |_| async move {
+				rpc_metrics.active_channels.dec();
+				closed.channel_closed().await;
+			})
 		})
-		// Max 10 channels.
-		.buffer_unordered(10240)
-		.for_each(|_| async {})
-		.await;
+		.buffer_unordered(config.limits.max_channels)
+		.for_each(|_| async {});
+	tokio::pin!(serving);
+
+	tokio::select! {
+		_ = &mut serving => {}
+		_ = shutdown.triggered() => {}
+	}
+
+	info!("Shutting down, telling clients to reconnect");
+	store.push_event(Event::ShuttingDown).await;
+	let _ = timeout(config.shutdown.notice(), &mut serving).await;
+
+	// Channels stop reading new requests, and close once their in-flight ones are answered
+	drain.trigger();
+	handlers.close();
+	let drained = async {
+		(&mut serving).await;
+		handlers.wait().await;
+	};
+	if timeout(config.shutdown.drain_timeout(), drained).await.is_err() {
+		warn!("Requests still in flight after {}s, dropping them", config.shutdown.drain_timeout);
+	}
+
+	presence::publish(store.as_ref(), presence.disconnect_all().await).await;
+	if timeout(POOL_CLOSE_TIMEOUT, store.close()).await.is_err() {
+		warn!("Database connections still busy, closing anyway");
+	}
+	info!("Shutdown complete");
 
 	Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use realm_shared::metrics::{Counter, DbMetrics, Exposition, Histogram, RpcMetrics};
+use crate::store::ChatStore;
+
+/// Chat server -> auth server traffic.
+#[derive(Debug, Default)]
+pub struct AuthMetrics {
+	pub token_validations: Counter,
+	pub token_validation_failures: Counter, //NOTE: Auth server unreachable or erroring, not merely an invalid token
+	pub token_validation_latency: Histogram,
+	pub connects: Counter,
+	pub connect_failures: Counter,
+}
+
+/// Everything the chat server exposes on its metrics endpoint, besides `AuthMetrics`.
+#[derive(Debug, Default)]
+pub struct ChatMetrics {
+	pub rpc: Arc<RpcMetrics>,
+	pub db: Arc<DbMetrics>,
+	pub stoken_hits: Counter,
+	pub stoken_misses: Counter,
+}
+
+pub async fn render(metrics: &ChatMetrics, auth: &AuthMetrics, store: &dyn ChatStore) -> String {
+	let mut page = Exposition::default();
+	page.rpc(&metrics.rpc);
+	page.db(&metrics.db);
+
+	page.counter("realm_stoken_cache_hits_total", "Server tokens found in the cache", metrics.stoken_hits.get());
+	page.counter("realm_stoken_cache_misses_total", "Server tokens that had to be checked with an auth server", metrics.stoken_misses.get());
+
+	page.counter("realm_auth_token_validation_failures_total", "Token validations that failed to reach an auth server", auth.token_validation_failures.get());
+	page.histogram("realm_auth_token_validation_duration_seconds", "Time spent validating tokens with auth servers", &auth.token_validation_latency);
+	page.counter("realm_auth_connects_total", "Connections opened to auth servers", auth.connects.get());
+	page.counter("realm_auth_connect_failures_total", "Failed connections to auth servers", auth.connect_failures.get());
+
+	page.gauge("realm_event_log_size", "Events held in memory for polling clients", store.count_events().await);
+	page.finish()
+}
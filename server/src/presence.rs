@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use tracing::error;
+use crate::events::Event;
+use crate::store::ChatStore;
+use crate::types::{Presence, PresenceStatus};
+
+struct Tracked {
+	status: PresenceStatus,
+	chosen: PresenceStatus, //NOTE: What the user asked for, restored when they come back from idle
+	custom_status: Option<String>,
+	last_seen: DateTime<Utc>,
+	sockets: HashSet<SocketAddr>,
+}
+
+impl Tracked {
+	fn presence(&self, userid: &str) -> Presence {
+		Presence {
+			userid: userid.to_string(),
+			status: self.status,
+			custom_status: self.custom_status.clone(),
+			last_seen: Some(self.last_seen),
+		}
+	}
+}
+
+/// Live presence for every connected user, shared by all channels of a server.
+/// Anything returned from the mutating methods is a presence that changed and should be sent out as an event.
+#[derive(Clone, Default)]
+pub struct PresenceTracker {
+	users: Arc<Mutex<HashMap<String, Tracked>>>,
+}
+
+impl PresenceTracker {
+	pub async fn heartbeat(&self, userid: &str, socket: SocketAddr) -> Option<Presence> {
+		let mut users = self.users.lock().await;
+		let now = Utc::now();
+
+		match users.get_mut(userid) {
+			Some(tracked) => {
+				tracked.last_seen = now;
+				tracked.sockets.insert(socket);
+
+				if tracked.status != tracked.chosen {
+					tracked.status = tracked.chosen;
+					return Some(tracked.presence(userid));
+				}
+
+				None
+			}
+			None => {
+				let tracked = Tracked {
+					status: PresenceStatus::Online,
+					chosen: PresenceStatus::Online,
+					custom_status: None,
+					last_seen: now,
+					sockets: HashSet::from([socket]),
+				};
+				let presence = tracked.presence(userid);
+				users.insert(userid.to_string(), tracked);
+				Some(presence)
+			}
+		}
+	}
+
+	pub async fn set_status(&self, userid: &str, socket: SocketAddr, status: PresenceStatus, custom_status: Option<String>) -> Presence {
+		let mut users = self.users.lock().await;
+		let tracked = users.entry(userid.to_string()).or_insert_with(|| Tracked {
+			status,
+			chosen: status,
+			custom_status: None,
+			last_seen: Utc::now(),
+			sockets: HashSet::new(),
+		});
+
+		tracked.status = status;
+		tracked.chosen = status;
+		tracked.custom_status = custom_status;
+		tracked.last_seen = Utc::now();
+		tracked.sockets.insert(socket);
+
+		tracked.presence(userid)
+	}
+
+	/// Drops a closed channel, users with no channels left go offline
+	pub async fn disconnect(&self, socket: SocketAddr) -> Vec<Presence> {
+		let mut users = self.users.lock().await;
+		let mut changed = Vec::new();
+
+		users.retain(|userid, tracked| {
+			if !tracked.sockets.remove(&socket) || !tracked.sockets.is_empty() {
+				return true;
+			}
+
+			tracked.status = PresenceStatus::Offline;
+			changed.push(tracked.presence(userid));
+			false
+		});
+
+		changed
+	}
+
+	/// Takes everyone offline, for when the server shuts down
+	pub async fn disconnect_all(&self) -> Vec<Presence> {
+		let mut users = self.users.lock().await;
+
+		users.drain()
+			.map(|(userid, mut tracked)| {
+				tracked.status = PresenceStatus::Offline;
+				tracked.presence(&userid)
+			})
+			.collect()
+	}
+
+	/// Marks users idle, then offline, when their heartbeats stop
+	pub async fn sweep(&self, idle_after: Duration, offline_after: Duration) -> Vec<Presence> {
+		let mut users = self.users.lock().await;
+		let now = Utc::now();
+		let mut changed = Vec::new();
+
+		users.retain(|userid, tracked| {
+			let silent_for = (now - tracked.last_seen).to_std().unwrap_or_default();
+
+			if silent_for >= offline_after {
+				tracked.status = PresenceStatus::Offline;
+				changed.push(tracked.presence(userid));
+				return false;
+			}
+
+			if silent_for >= idle_after && tracked.status == PresenceStatus::Online {
+				tracked.status = PresenceStatus::Idle;
+				changed.push(tracked.presence(userid));
+			}
+
+			true
+		});
+
+		changed
+	}
+
+	/// Users with at least one open channel or a recent heartbeat
+	pub async fn online(&self) -> usize {
+		self.users.lock().await.len()
+	}
+
+	pub async fn get(&self, userid: &str) -> Option<Presence> {
+		self.users.lock().await.get(userid).map(|tracked| tracked.presence(userid))
+	}
+}
+
+/// Sends out changed presences and remembers when users were last seen
+pub async fn publish(store: &dyn ChatStore, changed: Vec<Presence>) {
+	for presence in changed {
+		if let Err(e) = store.set_last_seen(&presence.userid, presence.last_seen).await {
+			error!("Error updating last_seen for user, {}: {e:?}", presence.userid);
+		}
+
+		store.push_event(Event::PresenceChanged(presence)).await;
+	}
+}
@@ -1,132 +1,294 @@
-use std::env;
 use std::net::SocketAddr;
 use std::sync::{Arc};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
 use moka::future::Cache;
-use sqlx::{FromRow, Pool, query_as, Sqlite};
-use sqlx::query;
 use tarpc::context::Context;
-use tarpc::tokio_serde::formats::Json;
-use tokio::sync::Mutex;
 use tracing::error;
-use realm_auth::types::RealmAuthClient;
+use realm_shared::health::Health;
 use realm_shared::types::ErrorCode::*;
 use realm_shared::types::ErrorCode;
+use crate::config::{LimitsSection, ServerConfig};
+use crate::limits::ConnectionLimiter;
+use crate::metrics::ChatMetrics;
+use crate::admin;
+use crate::stats;
+use crate::auth_pool::AuthPool;
+use crate::blocks::BlockLists;
 use crate::events::*;
-use crate::types::{Attachment, Edit, FromRows, Message, MessageData, Reaction, RealmChat, Redaction, Reply, ReplyChain, Room, ServerInfo, User};
+use crate::presence::{self, PresenceTracker};
+use crate::store::ChatStore;
+use crate::types::{AuditAction, AuditLogEntry, AuditLogFilter, Ban, Message, MessageData, OwnershipTransfer, RealmChat, Redaction, Presence, PresenceStatus, ReplyChain, Report, ReportAction, ReportCategory, ReportResolution, ReportStatus, Room, ServerInfo, ServerStats, Timeout, User, FEATURES, PROTOCOL_VERSION};
 
 #[derive(Clone)]
 pub struct RealmChatServer {
 	pub server_id: String,
 	pub domain: String,
 	pub port: u16,
+	pub auth_pool: AuthPool,
 	pub socket: SocketAddr, 
-	pub db_pool: Pool<Sqlite>,
+	pub store: Arc<dyn ChatStore>,
 	pub typing_users: Vec<(String, String)>, //NOTE: user.userid, room.roomid
 	pub cache: Cache<String, String>,
-	//pub events: Arc<Mutex<Vec<(u32, Event)>>>,
+	pub presence: PresenceTracker,
+	pub block_lists: BlockLists,
+	pub connections: ConnectionLimiter,
+	pub limits: LimitsSection,
+	pub metrics: Arc<ChatMetrics>,
 }
 
-const FETCH_MESSAGE: &str = "SELECT message.*,
-        room.id AS 'room_id', room.roomid AS 'room_roomid', room.admin_only_send AS 'room_admin_only_send', room.admin_only_view AS 'room_admin_only_view',
-        user.id AS 'user_id', user.userid AS 'user_userid', user.name AS 'user_name', user.owner AS 'user_owner', user.admin AS 'user_admin'
-	    FROM message INNER JOIN room ON message.room = room.id INNER JOIN user ON message.user = user.id";
+/// What all channels of a server share, each `RealmChatServer` gets a clone
+#[derive(Clone)]
+pub struct SharedState {
+	pub store: Arc<dyn ChatStore>,
+	pub presence: PresenceTracker,
+	pub block_lists: BlockLists,
+	pub auth_pool: AuthPool,
+	pub connections: ConnectionLimiter,
+	pub metrics: Arc<ChatMetrics>,
+}
+
+const MAX_AUDIT_LOG_PAGE: u32 = 100;
+
+/// Userids mentioned in a message, e.g. `@name:domain`
+fn mentions(text: &str) -> impl Iterator<Item = &str> {
+	text.split_whitespace()
+		.map(|word| word.trim_end_matches(|c: char| !c.is_alphanumeric()))
+		.filter(|word| word.starts_with('@') && word.contains(':'))
+}
+
+/// `text` with the `@` taken off its mentions of `userid`, so it reads the same but doesn't notify them
+fn without_mention(text: &str, userid: &str) -> String {
+	text.split_inclusive(char::is_whitespace)
+		.map(|word| match word.trim_end_matches(|c: char| !c.is_alphanumeric()).eq(userid) {
+			true => &word[1..],
+			false => word,
+		})
+		.collect()
+}
 
 impl RealmChatServer {
-	pub fn new(server_id: String, socket: SocketAddr, db_pool: Pool<Sqlite>) -> RealmChatServer {
+	pub fn new(config: &ServerConfig, socket: SocketAddr, shared: SharedState) -> RealmChatServer {
+		let SharedState { store, presence, block_lists, auth_pool, connections, metrics } = shared;
 		RealmChatServer {
-			server_id,
-			port: env::var("PORT").unwrap().parse::<u16>().unwrap(),
-			domain: env::var("DOMAIN").expect("DOMAIN must be set"),
+			server_id: config.server.id.clone(),
+			port: config.server.port,
+			domain: config.server.domain.clone(),
 			socket,
-			db_pool,
+			store,
 			typing_users: Vec::new(),
 			cache: Cache::builder()
-				.max_capacity(10_000)
-				.time_to_idle(Duration::from_secs(5*60))
-				.time_to_live(Duration::from_secs(60*60))
+				.max_capacity(config.cache.stoken_capacity)
+				.time_to_idle(config.cache.stoken_idle())
+				.time_to_live(config.cache.stoken_ttl())
 				.build(),
-			//events: Arc::new(Mutex::new(Vec::new())),
+			presence,
+			block_lists,
+			auth_pool,
+			connections,
+			limits: config.limits.clone(),
+			metrics,
 		}
 	}
 	
 	async fn is_stoken_valid(&self, userid: &str, stoken: &str) -> bool {
-		match self.cache.get(stoken).await {
-		    None => {
-				// if !self.is_user_in_server(userid).await {
-				// 	return false;
-				// }
-				
-				let user_domain = &userid[userid.find(':').unwrap()+1..];
-
-				let mut auth_transport = tarpc::serde_transport::tcp::connect((user_domain, 5052), Json::default);
-				auth_transport.config_mut().max_frame_length(usize::MAX);
-				let connected = match auth_transport.await {
-					Ok(out) => Some(out),
-					Err(_) => None
-				};
-				if connected.is_none() {
-					return false;
-				}
-				let auth_client = RealmAuthClient::new(tarpc::client::Config::default(), connected.unwrap()).spawn();
-				
-				let result = auth_client.server_token_validation(
-					tarpc::context::current(), stoken.to_string(), userid.to_string(), self.server_id.clone(), self.domain.clone(), self.port)
-					.await;
-				
-				match result {
-					Ok(valid) => {
-						if valid {
-							self.cache.insert(stoken.to_string(), userid.to_string()).await;
-							return true
-						}
-						false
-					}
-					Err(_) => {
-						error!("Error validating server token for user, {}, with stoken {}", userid, stoken);
-						false
-					}
+		// Concurrent misses on the same stoken share a single validation
+		let mut missed = false;
+		let validated = self.cache.optionally_get_with(stoken.to_string(), async {
+			missed = true;
+			// if !self.is_user_in_server(userid).await {
+			// 	return None;
+			// }
+
+			let user_domain = &userid[userid.find(':').unwrap()+1..];
+			let metrics = &self.auth_pool.metrics;
+			metrics.token_validations.inc();
+
+			let Some(auth_client) = self.auth_pool.get(user_domain).await else {
+				metrics.token_validation_failures.inc();
+				return None;
+			};
+
+			let started = Instant::now();
+			let result = auth_client.server_token_validation(
+				self.auth_pool.context(), stoken.to_string(), userid.to_string(), self.server_id.clone(), self.domain.clone(), self.port)
+				.await;
+			metrics.token_validation_latency.observe(started.elapsed());
+
+			match result {
+				Ok(valid) => valid.then(|| userid.to_string()),
+				Err(e) => {
+					metrics.token_validation_failures.inc();
+					self.auth_pool.report_error(user_domain, &e).await;
+					error!("Error validating server token for user, {}", userid);
+					None
 				}
 			}
-			Some(cached_username) => cached_username.eq(userid),
+		}).await;
+
+		if missed { self.metrics.stoken_misses.inc() } else { self.metrics.stoken_hits.inc() }
+		validated.is_some_and(|cached_userid| cached_userid.eq(userid))
+	}
+
+	/// Asks the user's auth server for their display name, falling back to the local part of their userid
+	async fn fetch_display_name(&self, userid: &str) -> String {
+		let local_part = userid.trim_start_matches('@').split(':').next().unwrap_or(userid).to_string();
+		let user_domain = &userid[userid.find(':').unwrap()+1..];
+
+		let Some(auth_client) = self.auth_pool.get(user_domain).await else {
+			return local_part;
+		};
+
+		match auth_client.get_display_name_for_user(self.auth_pool.context(), userid.to_string()).await {
+			Ok(Ok(display_name)) => display_name,
+			Err(e) => {
+				self.auth_pool.report_error(user_domain, &e).await;
+				error!("Error fetching display name for user, {}", userid);
+				local_part
+			}
+			_ => {
+				error!("Error fetching display name for user, {}", userid);
+				local_part
+			}
 		}
 	}
 
-	pub async fn internal_is_user_admin(&self, userid: &str) -> bool {
-		let result = query!("SELECT admin FROM user WHERE userid = ?", userid).fetch_one(&self.db_pool).await;
+	async fn authorize(&self, userid: &str, stoken: &str) -> Result<(), ErrorCode> {
+		if !self.is_stoken_valid(userid, stoken).await {
+			return Err(Unauthorized)
+		}
+
+		if self.is_user_banned(userid).await? {
+			return Err(Banned)
+		}
+
+		if !self.connections.admit(userid, self.socket, self.limits.max_channels_per_user).await {
+			return Err(TooManyConnections)
+		}
+
+		self.block_lists.remember_stoken(userid, stoken).await;
+		Ok(())
+	}
+
+	/// Pulls the user's block list from their auth server, which is the source of truth for it
+	async fn fetch_block_list(&self, userid: &str, stoken: &str) -> Result<Vec<String>, ErrorCode> {
+		let user_domain = &userid[userid.find(':').unwrap()+1..];
+		let Some(auth_client) = self.auth_pool.get(user_domain).await else {
+			return Err(UnableToConnectToServer)
+		};
+
+		let result = auth_client.server_get_blocked_users(
+			self.auth_pool.context(), stoken.to_string(), userid.to_string(), self.server_id.clone(), self.domain.clone(), self.port)
+			.await;
+
 		match result {
-			Ok(record) => record.admin,
-			Err(_) => false
+			Ok(Ok(blocked)) => Ok(blocked),
+			Ok(Err(Unauthorized)) => {
+				self.block_lists.forget_stoken(userid).await;
+				Err(Unauthorized)
+			}
+			Ok(Err(e)) => {
+				error!("Error fetching block list for user, {}: {e:?}", userid);
+				Err(e)
+			}
+			Err(e) => {
+				self.auth_pool.report_error(user_domain, &e).await;
+				error!("Error fetching block list for user, {}", userid);
+				Err(UnableToConnectToServer)
+			}
+		}
+	}
+
+	/// Whether `blocker` may have blocked `userid`, asking the blocker's auth server if their list isn't cached.
+	/// Whatever can't be found out counts as blocked, so a block is never skipped because the blocker hasn't
+	/// signed in since this server started (there's no stoken to ask their auth server with) or it was unreachable.
+	async fn is_blocked_by(&self, userid: &str, blocker: &str) -> bool {
+		if !self.is_user_in_server(blocker).await {
+			return false
+		}
+
+		let Some(stoken) = self.block_lists.stoken(blocker).await else {
+			return true
+		};
+
+		match self.block_lists.get_or_fetch(blocker, self.fetch_block_list(blocker, &stoken)).await {
+			Ok(blocked) => blocked.iter().any(|b| b.eq(userid)),
+			Err(_) => true,
+		}
+	}
+
+	async fn is_user_banned(&self, userid: &str) -> Result<bool, ErrorCode> {
+		self.store.is_banned(userid, Utc::now()).await.map_err(|e| {
+			error!("Error checking bans for user, {}: {e:?}", userid);
+			MalformedDBResponse
+		})
+	}
+
+	async fn is_user_timed_out(&self, userid: &str) -> Result<bool, ErrorCode> {
+		Ok(self.inner_get_timeout(userid).await?.is_some())
+	}
+
+	async fn inner_get_timeout(&self, userid: &str) -> Result<Option<Timeout>, ErrorCode> {
+		self.store.get_timeout(userid, Utc::now()).await.map_err(|e| {
+			error!("Error fetching timeout for user, {}: {e:?}", userid);
+			MalformedDBResponse
+		})
+	}
+
+	async fn record_audit(&self, actor: &str, action: AuditAction, target: &str, reason: &str) {
+		let result = self.store.add_audit(&AuditLogEntry {
+			id: 0,
+			timestamp: Utc::now(),
+			actor: actor.to_string(),
+			action,
+			target: target.to_string(),
+			reason: reason.to_string(),
+		}).await;
+
+		if let Err(e) = result {
+			error!("Error writing audit log entry! actor -> {}, action -> {:?}, target -> {}: {e:?}", actor, action, target);
 		}
 	}
+
+	async fn push_event(&self, event: Event) {
+		self.store.push_event(event).await;
+	}
+
+	/// Rejects free text (reasons, notes, names) longer than the configured limit
+	fn check_input(&self, text: &str) -> Result<(), ErrorCode> {
+		if text.chars().count() > self.limits.max_input_length {
+			return Err(InputTooLong)
+		}
+		Ok(())
+	}
+
+	/// Called once the channel this server instance belongs to has closed
+	pub async fn channel_closed(&self) {
+		self.connections.release(self.socket).await;
+		let changed = self.presence.disconnect(self.socket).await;
+		presence::publish(self.store.as_ref(), changed).await;
+	}
+
+	pub async fn internal_is_user_admin(&self, userid: &str) -> bool {
+		self.store.get_user(userid).await.is_ok_and(|user| user.admin)
+	}
 	
 	pub async fn internal_is_user_owner(&self, userid: &str) -> bool {
-		let result = query!("SELECT owner FROM user WHERE userid = ?", userid).fetch_one(&self.db_pool).await;
-		match result {
-			Ok(record) => record.owner,
-			Err(_) => false
-		}
+		self.store.get_user(userid).await.is_ok_and(|user| user.owner)
 	}
 	
+	async fn count_owners(&self) -> Result<i64, ErrorCode> {
+		self.store.count_owners().await.map_err(|_| MalformedDBResponse)
+	}
+
 	async fn is_user_in_server(&self, userid: &str) -> bool {
-		let result = query!("SELECT EXISTS (SELECT 1 FROM user WHERE userid = ?) AS does_exist", userid).fetch_one(&self.db_pool).await;
-		
-		match result {
-			Ok(record) => record.does_exist != 0,
-			Err(_) => false
-		}
+		self.store.get_user(userid).await.is_ok()
 	}
 
 	async fn inner_get_all_direct_replies(&self, userid: &str, head: i64) -> Result<Vec<Message>, ErrorCode> {
 		let is_admin = self.internal_is_user_admin(userid).await;
-		let result = sqlx::query(&format!("{}{}", FETCH_MESSAGE, "AND message.referencing_id = ?"))
-			.bind(is_admin)
-			.bind(head)
-			.fetch_all(&self.db_pool).await;
-
-		match result {
-			Ok(rows) => Ok(Message::from_rows(rows).unwrap()),
+		match self.store.get_direct_replies(head).await {
+			Ok(replies) => Ok(replies.into_iter().filter(|reply| is_admin || !reply.room.admin_only_view).collect()),
 			Err(_) => Err(MessageNotFound),
 		}
 	}
@@ -158,46 +320,190 @@ impl RealmChatServer {
 	}
 
 	async fn inner_get_room(&self, userid: &str, roomid: &str) -> Result<Room, ErrorCode> {
-		let is_admin = self.internal_is_user_admin(&userid).await;
-		let result = query_as!(
-			Room, "SELECT * FROM room WHERE roomid = ? AND admin_only_view = ? OR false", is_admin, roomid).fetch_one(&self.db_pool).await;
+		let is_admin = self.internal_is_user_admin(userid).await;
+		match self.store.get_room(roomid).await {
+			Ok(room) if is_admin || !room.admin_only_view => Ok(room),
+			_ => Err(RoomNotFound),
+		}
+	}
+
+	async fn inner_get_user(&self, userid: &str) -> Result<User, ErrorCode> {
+		self.store.get_user(userid).await.map_err(|_| UserNotFound)
+	}
+	
+	async fn inner_get_all_users(&self) -> Result<Vec<User>, ErrorCode> {
+		self.store.get_users().await.map_err(|_| Error)
+	}
+
+	async fn inner_get_message(&self, userid: &str, id: i64) -> Result<Message, ErrorCode> {
+		let is_admin = self.internal_is_user_admin(userid).await;
+		match self.store.get_message(id).await {
+			Ok(message) => {
+				if message.room.admin_only_view && !is_admin {
+					return Err(MessageNotFound)
+				}
+				Ok(message)
+			}
+			Err(sqlx::Error::RowNotFound) => Err(MessageNotFound),
+			Err(_) => Err(MalformedDBResponse),
+		}
+	}
+
+	async fn inner_kick_user(&self, admin_userid: &str, userid: &str, reason: &str) -> Result<(), ErrorCode> {
+		if self.internal_is_user_owner(userid).await {
+			return Err(Unauthorized)
+		}
+
+		let result = self.store.delete_user(userid).await;
 
 		match result {
-			Ok(room) => Ok(room),
-			Err(_) => Err(RoomNotFound),
+			Ok(_) => {
+				self.record_audit(admin_userid, AuditAction::KickUser, userid, reason).await;
+
+				// let result = self.packet_manager.lock().await.broadcast(KickedUserEvent {
+				// 	userid,
+				// });
+				// 
+				// if result.is_err() {
+				// 	error!("Error broadcasting KickedUserEvent!");
+				// }
+				
+				Ok(())
+			}
+			Err(_) => Err(MalformedDBResponse)
 		}
 	}
 
-	async fn inner_get_user(&self, userid: &str) -> Result<User, ErrorCode> {
-		let result = query_as!(User, "SELECT * FROM user WHERE userid = ?", userid).fetch_one(&self.db_pool).await;
+	async fn inner_ban_user(&self, admin_userid: &str, userid: &str, reason: &str, expires: Option<DateTime<Utc>>) -> Result<(), ErrorCode> {
+		if self.internal_is_user_owner(userid).await {
+			return Err(Unauthorized)
+		}
+
+		if self.is_user_banned(userid).await? {
+			return Err(Banned)
+		}
+
+		if self.store.delete_user(userid).await.is_err() {
+			return Err(MalformedDBResponse)
+		}
+
+		let result = self.store.add_ban(&Ban {
+			id: 0,
+			userid: userid.to_string(),
+			reason: reason.to_string(),
+			banned_by: admin_userid.to_string(),
+			created: Utc::now(),
+			expires,
+		}).await;
 
 		match result {
-			Ok(user) => Ok(user),
-			Err(_) => Err(UserNotFound),
+			Ok(_) => {
+				self.record_audit(admin_userid, AuditAction::BanUser, userid, reason).await;
+
+				// let result = self.packet_manager.lock().await.broadcast(BannedUserEvent {
+				// 	userid,
+				// });
+				// 
+				// if result.is_err() {
+				// 	error!("Error broadcasting BannedUserEvent!");
+				// }
+				
+				Ok(())
+			}
+			Err(_) => Err(MalformedDBResponse)
 		}
 	}
-	
-	async fn inner_get_all_users(&self) -> Result<Vec<User>, ErrorCode> {
-		let result = query_as!(User, "SELECT * FROM user").fetch_all(&self.db_pool).await;
-		
+
+	async fn inner_timeout_user(&self, admin_userid: &str, userid: &str, duration: Duration, reason: &str) -> Result<Timeout, ErrorCode> {
+		if self.internal_is_user_owner(userid).await {
+			return Err(Unauthorized)
+		}
+
+		if !self.is_user_in_server(userid).await {
+			return Err(UserNotFound)
+		}
+
+		let created = Utc::now();
+		let expires = chrono::Duration::from_std(duration).ok()
+			.and_then(|duration| created.checked_add_signed(duration))
+			.ok_or(InvalidDuration)?;
+
+		if self.store.delete_timeout(userid).await.is_err() {
+			return Err(MalformedDBResponse)
+		}
+
+		let mut timeout = Timeout {
+			id: 0,
+			userid: userid.to_string(),
+			reason: reason.to_string(),
+			issued_by: admin_userid.to_string(),
+			created,
+			expires,
+		};
+
+		match self.store.add_timeout(&timeout).await {
+			Ok(id) => {
+				timeout.id = id;
+
+				self.record_audit(admin_userid, AuditAction::TimeoutUser, userid, reason).await;
+				self.push_event(Event::TimedOutUser(timeout.clone())).await;
+				Ok(timeout)
+			}
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+	async fn inner_delete_message(&self, admin_userid: &str, id: i64, reason: &str) -> Result<(), ErrorCode> {
+		let admin = self.inner_get_user(admin_userid).await?;
+		let message = self.inner_get_message(admin_userid, id).await?;
+
+		let referencing_id = message.id;
+		let result = self.store.add_message(&Message {
+			id: 0,
+			timestamp: Utc::now(),
+			user: admin,
+			room: message.room,
+			data: MessageData::Redaction(Redaction { referencing_id }),
+		}).await;
+
 		match result {
-			Ok(users) => Ok(users),
-			Err(_) => Err(Error),
+			Ok(_) => {
+				self.record_audit(admin_userid, AuditAction::DeleteMessage, &message.user.userid, reason).await;
+				Ok(())
+			}
+			Err(_) => Err(MalformedDBResponse)
 		}
 	}
 
-	async fn inner_get_message(&self, userid: &str, id: i64) -> Result<Message, ErrorCode> {
-		let is_admin = self.internal_is_user_admin(&userid).await;
-		let result = sqlx::query(&format!("{}{}", FETCH_MESSAGE, "AND message.id = ?"))
-			.bind(is_admin)
-			.bind(id)
-			.fetch_one(&self.db_pool).await;
+	async fn inner_get_report(&self, id: i64) -> Result<Report, ErrorCode> {
+		self.store.get_report(id).await.map_err(|_| ReportNotFound)
+	}
+
+	async fn inner_create_report(&self, reporter: &str, target_userid: &str, message_id: Option<i64>, category: ReportCategory, note: &str) -> Result<Report, ErrorCode> {
+		self.check_input(note)?;
+
+		let result = self.store.add_report(&Report {
+			id: 0,
+			created: Utc::now(),
+			reporter: reporter.to_string(),
+			target_userid: target_userid.to_string(),
+			message_id,
+			category,
+			note: note.to_string(),
+			status: ReportStatus::Open,
+			claimed_by: None,
+			resolution: None,
+			resolved_at: None,
+		}).await;
 
 		match result {
-			Ok(row) => Ok(Message::from_row(&row).unwrap()),
-			Err(_) =>Err(MessageNotFound),
+			Ok(id) => self.inner_get_report(id).await,
+			Err(_) => Err(MalformedDBResponse)
 		}
 	}
+
+	async fn inner_get_ownership_transfer(&self, id: i64) -> Result<OwnershipTransfer, ErrorCode> {
+		self.store.get_transfer(id).await.map_err(|_| TransferNotFound)
+	}
 }
 
 impl RealmChat for RealmChatServer {
@@ -208,9 +514,16 @@ impl RealmChat for RealmChatServer {
 	async fn get_info(self, _: Context) -> ServerInfo {
 		ServerInfo {
 			server_id: self.server_id.clone(),
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			protocol_version: PROTOCOL_VERSION,
+			features: FEATURES.iter().map(|f| f.to_string()).collect(),
 		}
 	}
 
+	async fn health(self, _: Context) -> Health {
+		self.store.health().await
+	}
+
 	async fn is_user_admin(self, _: Context, userid: String) -> bool {
 		self.internal_is_user_admin(&userid).await
 	}
@@ -219,25 +532,19 @@ impl RealmChat for RealmChatServer {
 		self.internal_is_user_owner(&userid).await
 	}
 
-	async fn poll_events_since(self, _: Context, index: u32) -> Vec<(u32, Event)> {
-		//self.events.lock().await.iter().filter(|(i, _)| i > &index).map(|(i, e)| (*i, e.clone())).collect()
-		
-		// let mut events_to_send = Vec::new();
-		// 
-		// for (i, event) in self.events.lock().await.clone() {
-		// 	if i > index {
-		// 		events_to_send.push((i, event));
-		// 	}
-		// }
-		// 
-		// events_to_send
-		Vec::new()
+	async fn poll_events_since(self, _: Context, stoken: String, userid: String, index: u32) -> Result<Vec<(u32, Event)>, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		let is_admin = self.internal_is_user_admin(&userid).await;
+		let events = self.store.get_events_since(index).await;
+
+		Ok(events.into_iter()
+			.filter(|(_, e)| e.is_visible_to(&userid, is_admin))
+			.collect())
 	}
 
 	async fn join_server(self, _: Context, stoken: String, userid: String) -> Result<User, ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 
 		if self.is_user_in_server(&userid).await {
 			return Err(AlreadyJoinedServer)
@@ -248,8 +555,8 @@ impl RealmChat for RealmChatServer {
 			all_users.is_empty()
 		};
 		
-		//TOOD: name support
-		let result = query!("INSERT INTO user (userid, name, owner, admin) VALUES (?,?,?,?)", userid, "userid", is_owner, is_owner).execute(&self.db_pool).await;
+		let name = self.fetch_display_name(&userid).await;
+		let result = self.store.add_user(&userid, &name, is_owner, is_owner).await;
 		
 
 		match result {
@@ -270,9 +577,7 @@ impl RealmChat for RealmChatServer {
 	}
 
 	async fn leave_server(self, _: Context, stoken: String, userid: String) -> Result<(), ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 
 		if !self.is_user_in_server(&userid).await {
 			return Err(NotInServer)
@@ -280,7 +585,11 @@ impl RealmChat for RealmChatServer {
 		
 		let user = self.inner_get_user(&userid).await?;
 
-		let result = query!("DELETE FROM user WHERE userid = ?", userid).execute(&self.db_pool).await;
+		if user.owner && self.count_owners().await? <= 1 {
+			return Err(LastOwner) // Hand ownership over with transfer_ownership first
+		}
+
+		let result = self.store.delete_user(&userid).await;
 		
 		match result {
 			Ok(_) => {
@@ -298,11 +607,139 @@ impl RealmChat for RealmChatServer {
 		}	
 	}
 
+	async fn heartbeat(self, _: Context, stoken: String, userid: String) -> Result<(), ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		if !self.is_user_in_server(&userid).await {
+			return Err(NotInServer)
+		}
+
+		if let Some(changed) = self.presence.heartbeat(&userid, self.socket).await {
+			presence::publish(self.store.as_ref(), vec![changed]).await;
+		}
+
+		Ok(())
+	}
+
+	async fn set_presence(self, _: Context, stoken: String, userid: String, status: PresenceStatus, custom_status: Option<String>) -> Result<Presence, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		if !self.is_user_in_server(&userid).await {
+			return Err(NotInServer)
+		}
+
+		if let Some(custom_status) = &custom_status {
+			if custom_status.chars().count() > 128 {
+				return Err(InvalidCustomStatus)
+			}
+		}
+
+		let result = self.store.set_custom_status(&userid, custom_status.as_deref()).await;
+		if result.is_err() {
+			return Err(MalformedDBResponse)
+		}
+
+		let presence = self.presence.set_status(&userid, self.socket, status, custom_status).await;
+		presence::publish(self.store.as_ref(), vec![presence.clone()]).await;
+
+		Ok(presence)
+	}
+
+	async fn get_presences(self, _: Context, stoken: String, userid: String) -> Result<Vec<Presence>, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		match self.store.get_presences().await {
+			Ok(stored) => {
+				let mut presences = Vec::new();
+				for offline in stored {
+					let presence = match self.presence.get(&offline.userid).await {
+						Some(presence) => presence,
+						None => offline,
+					};
+					presences.push(presence);
+				}
+				Ok(presences)
+			}
+			Err(_) => Err(MalformedDBResponse),
+		}
+	}
+
+	async fn sync_block_list(self, _: Context, stoken: String, userid: String) -> Result<Vec<String>, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		let blocked = self.fetch_block_list(&userid, &stoken).await?;
+		self.block_lists.insert(&userid, blocked.clone()).await;
+		Ok(blocked)
+	}
+
+	async fn set_nickname(self, _: Context, stoken: String, userid: String, nickname: Option<String>) -> Result<User, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		if let Some(nickname) = &nickname {
+			if !realm_shared::is_display_name_valid(nickname) {
+				return Err(InvalidDisplayName)
+			}
+		}
+
+		let result = self.store.set_nickname(&userid, nickname.as_deref()).await;
+
+		match result {
+			Ok(_) => {
+				let user = self.inner_get_user(&userid).await?;
+				self.push_event(Event::RenamedUser(user.clone())).await;
+				Ok(user)
+			}
+			Err(_) => Err(MalformedDBResponse),
+		}
+	}
+
+	async fn refresh_display_name(self, _: Context, stoken: String, userid: String) -> Result<User, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		let user = self.inner_get_user(&userid).await?;
+		let name = self.fetch_display_name(&userid).await;
+		if user.name.eq(&name) {
+			return Ok(user)
+		}
+
+		let result = self.store.set_name(&userid, &name).await;
+
+		match result {
+			Ok(_) => {
+				let user = self.inner_get_user(&userid).await?;
+				self.push_event(Event::RenamedUser(user.clone())).await;
+				Ok(user)
+			}
+			Err(_) => Err(MalformedDBResponse),
+		}
+	}
+
 	async fn send_message(self, _: Context, stoken: String, mut message: Message) -> Result<Message, ErrorCode> {
-		// if !self.is_stoken_valid(&message.user.userid, &stoken).await { // Check sender userid
-		// 	return Err(Unauthorized)
-		// }
-		// 
+		self.authorize(&message.user.userid, &stoken).await?; // Check sender userid
+
+		if self.is_user_timed_out(&message.user.userid).await? { // Covers replies, edits and reactions too
+			return Err(TimedOut)
+		}
+
+		let text = match &mut message.data {
+			MessageData::Text(text) => Some(text),
+			MessageData::Reply(reply) => Some(&mut reply.text),
+			MessageData::Edit(edit) => Some(&mut edit.text),
+			_ => None,
+		};
+		if let Some(text) = text {
+			if text.chars().count() > self.limits.max_message_length {
+				return Err(MessageTooLong)
+			}
+			// The message still goes out, so the sender can't tell they're blocked; it just doesn't notify the blocker
+			let mentioned: Vec<String> = mentions(text).map(str::to_string).collect();
+			for blocker in mentioned {
+				if self.is_blocked_by(&message.user.userid, &blocker).await {
+					*text = without_mention(text, &blocker);
+				}
+			}
+		}
+		
 		// // Assert all the data in message is correct
 		// message.user = self.inner_get_user(&message.user.userid).await?;
 
@@ -325,7 +762,7 @@ impl RealmChat for RealmChatServer {
 		// let is_admin = self.internal_is_user_admin(&message.user.userid).await;
 		// let admin_only_send = query!(
 		// 	"SELECT admin_only_send FROM room WHERE roomid = ?",
-		// 	message.room.roomid).fetch_one(&self.db_pool).await;
+		// 	message.room.roomid).fetch_one(&self.store).await;
 		// if let Ok(record) = admin_only_send {
 		// 	if record.admin_only_send && !is_admin {
 		// 		return Err(Unauthorized)
@@ -336,34 +773,11 @@ impl RealmChat for RealmChatServer {
 		// 
 		// message.room = self.inner_get_room(&message.user.userid, &message.room.roomid).await?;
 
-		let result = match &message.data {
-			MessageData::Text(text) => {
-				query!("INSERT INTO message (timestamp, user, room, msg_type, msg_text) VALUES (?, ?, ?, 'text', ?)", 
-					message.timestamp, message.user.id, message.room.id, text)
-					.execute(&self.db_pool).await
-			}
-			MessageData::Attachment(attachment) => { todo!() }
-			MessageData::Reply(reply) => {
-				query!("INSERT INTO message (timestamp, user, room, msg_type, msg_text, referencing_id) VALUES (?, ?, ?, 'reply', ?, ?)",
-					message.timestamp, message.user.id, message.room.id, reply.text, reply.referencing_id)
-					.execute(&self.db_pool).await
-			}
-			MessageData::Edit(edit) => {
-				query!("INSERT INTO message (timestamp, user, room, msg_type, msg_text, referencing_id) VALUES (?, ?, ?, 'edit', ?, ?)",
-					message.timestamp, message.user.id, message.room.id, edit.text, edit.referencing_id)
-					.execute(&self.db_pool).await
-			}
-			MessageData::Reaction(reaction) => {
-				query!("INSERT INTO message (timestamp, user, room, msg_type, emoji, referencing_id) VALUES (?, ?, ?, 'reaction', ?, ?)",
-					message.timestamp, message.user.id, message.room.id, reaction.emoji, reaction.referencing_id)
-					.execute(&self.db_pool).await
-			}
-			MessageData::Redaction(redaction) => {
-				query!("INSERT INTO message (timestamp, user, room, msg_type, referencing_id) VALUES (?, ?, ?, 'redaction', ?)",
-					message.timestamp, message.user.id, message.room.id, redaction.referencing_id)
-					.execute(&self.db_pool).await
-			}
-		};
+		if let MessageData::Attachment(_) = &message.data {
+			todo!()
+		}
+
+		let result = self.store.add_message(&message).await;
 
 		match result {
 			Ok(_) => {
@@ -384,91 +798,88 @@ impl RealmChat for RealmChatServer {
 		}
 	}
 
-	async fn start_typing(self, _: Context, stoken: String, userid: String, roomid: String) -> ErrorCode {
-		todo!()
+	async fn start_typing(self, _: Context, stoken: String, userid: String, roomid: String) -> Result<(), ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		if self.is_user_timed_out(&userid).await? {
+			return Err(TimedOut)
+		}
+
+		self.inner_get_room(&userid, &roomid).await?;
+		self.push_event(Event::StartedTyping(userid, roomid)).await;
+		Ok(())
 	}
 
-	async fn stop_typing(self, _: Context, stoken: String, userid: String, roomid: String) -> ErrorCode {
-		todo!()
+	async fn stop_typing(self, _: Context, stoken: String, userid: String, roomid: String) -> Result<(), ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		self.inner_get_room(&userid, &roomid).await?;
+		self.push_event(Event::StoppedTyping(userid, roomid)).await;
+		Ok(())
 	}
 
-	async fn keep_typing(self, _: Context, stoken: String, userid: String, roomid: String) -> ErrorCode {
-		todo!()
+	async fn keep_typing(self, _: Context, stoken: String, userid: String, roomid: String) -> Result<(), ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		if self.is_user_timed_out(&userid).await? {
+			return Err(TimedOut)
+		}
+
+		self.inner_get_room(&userid, &roomid).await?;
+		self.push_event(Event::StartedTyping(userid, roomid)).await;
+		Ok(())
+	}
+
+	async fn get_timeout(self, _: Context, stoken: String, userid: String) -> Result<Option<Timeout>, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		self.inner_get_timeout(&userid).await
 	}
 
 	async fn get_message(self, _: Context, stoken: String, userid: String, id: i64) -> Result<Message, ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 		
-		let is_admin = self.internal_is_user_admin(&userid).await;
-		let result = sqlx::query(&format!("{}{}", FETCH_MESSAGE, "AND message.id = ?"))
-			.bind(is_admin)
-			.bind(id)
-			.fetch_one(&self.db_pool).await;
-
-		match result {
-			Ok(row) => {
-				Ok(Message::from_row(&row).unwrap())
-			},
-			Err(_) => {
-				Err(MessageNotFound)
-			},
-		}
+		self.inner_get_message(&userid, id).await
 	}
 
 	async fn get_messages_since(self, _: Context, stoken: String, userid: String, id: i64) -> Result<Vec<Message>, ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 		
 		let is_admin = self.internal_is_user_admin(&userid).await;
-		let result = sqlx::query(&format!("{}{}", FETCH_MESSAGE, " AND message.id > ?"))
-			//.bind(is_admin)
-			.bind(id)
-			.fetch_all(&self.db_pool).await;
+		// Capped so a client far behind catches up over several polls instead of one huge response.
+		// Admin-only rooms are skipped in the query, so a page of them doesn't come back empty and stall the client
+		let result = self.store.get_messages_since(id, self.limits.max_messages_per_request, is_admin).await;
 
 		match result {
-			Ok(rows) => Ok(Message::from_rows(rows).unwrap()),
+			Ok(messages) => Ok(messages),
 			Err(_) => Err(MalformedDBResponse)
 		}
 	}
 
 	async fn get_all_direct_replies(self, _: Context, stoken: String, userid: String, head: i64) -> Result<Vec<Message>, ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 		
 		self.inner_get_all_direct_replies(&userid, head).await
 	}
 
 	async fn get_reply_chain(self, _: Context, stoken: String, userid: String, head: Message, depth: u8) -> Result<ReplyChain, ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 		
 		self.inner_get_reply_chain(&stoken, head, depth).await
 	}
 
 	async fn get_rooms(self, _: Context, stoken: String, userid: String) -> Result<Vec<Room>, ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 		
 		let is_admin = self.internal_is_user_admin(&userid).await;
-		let result = query_as!(
-			Room, "SELECT * FROM room WHERE admin_only_view LIKE false or ?", is_admin).fetch_all(&self.db_pool).await;
-
-		match result {
-			Ok(rooms) => Ok(rooms),
+		match self.store.get_rooms().await {
+			Ok(rooms) => Ok(rooms.into_iter().filter(|room| is_admin || !room.admin_only_view).collect()),
 			Err(_) => Err(Error),
 		}
 	}
 
 	async fn get_room(self, _: Context, stoken: String, userid: String, roomid: String) -> Result<Room, ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 		
 		self.inner_get_room(&userid, &roomid).await
 	}
@@ -482,20 +893,19 @@ impl RealmChat for RealmChatServer {
 	}
 
 	async fn create_room(self, _: Context, stoken: String, userid: String, room: Room) -> Result<Room, ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 		
 		if !self.internal_is_user_admin(&userid).await {
 			return Err(Unauthorized)
 		}
 
-		let result = query!("INSERT INTO room (roomid, admin_only_send, admin_only_view) VALUES (?,?,?)",
-			room.roomid, room.admin_only_send, room.admin_only_view)
-			.execute(&self.db_pool).await;
+		self.check_input(&room.roomid)?;
+		let result = self.store.add_room(&room).await;
 
 		match result {
 			Ok(_) => {
+				self.record_audit(&userid, AuditAction::CreateRoom, &room.roomid, "").await;
+
 				// let result = self.packet_manager.lock().await.broadcast(NewRoomEvent {
 				// 	room: room.clone(),
 				// });
@@ -515,18 +925,18 @@ impl RealmChat for RealmChatServer {
 	}
 
 	async fn delete_room(self, _: Context, stoken: String, userid: String, roomid: String) -> Result<(), ErrorCode> {
-		if !self.is_stoken_valid(&userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&userid, &stoken).await?;
 		
 		if !self.internal_is_user_admin(&userid).await {
 			return Err(Unauthorized)
 		}
 
-		let result = query!("DELETE FROM room WHERE roomid = ?", roomid).execute(&self.db_pool).await;
+		let result = self.store.delete_room(&roomid).await;
 
 		match result {
 			Ok(_) => {
+				self.record_audit(&userid, AuditAction::DeleteRoom, &roomid, "").await;
+
 				// let result = self.packet_manager.lock().await.broadcast(DeleteRoomEvent {
 				// 	roomid,
 				// });
@@ -546,18 +956,18 @@ impl RealmChat for RealmChatServer {
 	}
 	
 	async fn promote_user(self, _: Context, stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode> {
-		if !self.is_stoken_valid(&admin_userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&admin_userid, &stoken).await?;
 		
 		if !self.internal_is_user_owner(&admin_userid).await {
 			return Err(Unauthorized)
 		}
 		
-		let result = query!("UPDATE user SET admin = true WHERE userid = ?", userid).execute(&self.db_pool).await;
+		let result = self.store.set_admin(&userid, true).await;
 		
 		match result {
 			Ok(_) => {
+				self.record_audit(&admin_userid, AuditAction::PromoteUser, &userid, "").await;
+
 				// let result = self.packet_manager.lock().await.broadcast(PromotedUserEvent {
 				// 	userid,
 				// });
@@ -573,18 +983,18 @@ impl RealmChat for RealmChatServer {
 	}
 	
 	async fn demote_user(self, _: Context, stoken: String, owner_userid: String, userid: String) -> Result<(), ErrorCode> {
-		if !self.is_stoken_valid(&owner_userid, &stoken).await {
-			return Err(Unauthorized)
-		}
+		self.authorize(&owner_userid, &stoken).await?;
 		
 		if !self.internal_is_user_owner(&owner_userid).await {
 			return Err(Unauthorized)
 		}
 		
-		let result = query!("UPDATE user SET admin = false WHERE userid = ?", userid).execute(&self.db_pool).await;
+		let result = self.store.set_admin(&userid, false).await;
 		
 		match result {
 			Ok(_) => {
+				self.record_audit(&owner_userid, AuditAction::DemoteUser, &userid, "").await;
+
 				// let result = self.packet_manager.lock().await.broadcast(DemotedUserEvent {
 				// 	userid,
 				// });
@@ -600,69 +1010,368 @@ impl RealmChat for RealmChatServer {
 	}
 
 	async fn kick_user(self, _: Context, stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode> {
-		if !self.is_stoken_valid(&admin_userid, &stoken).await {
+		self.authorize(&admin_userid, &stoken).await?;
+		
+		if !self.internal_is_user_admin(&admin_userid).await {
 			return Err(Unauthorized)
 		}
+
+		self.inner_kick_user(&admin_userid, &userid, "").await
+	}
+
+	async fn ban_user(self, _: Context, stoken: String, admin_userid: String, userid: String, reason: String, expires: Option<DateTime<Utc>>) -> Result<(), ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
 		
 		if !self.internal_is_user_admin(&admin_userid).await {
 			return Err(Unauthorized)
 		}
 
-		let result = query!("DELETE FROM user WHERE userid = ?", userid).execute(&self.db_pool).await;
+		self.check_input(&reason)?;
+		self.inner_ban_user(&admin_userid, &userid, &reason, expires).await
+	}
+
+	async fn pardon_user(self, _: Context, stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
+		if !self.internal_is_user_admin(&admin_userid).await {
+			return Err(Unauthorized)
+		}
+
+		let result = self.store.delete_ban(&userid).await;
 
 		match result {
 			Ok(_) => {
-				// let result = self.packet_manager.lock().await.broadcast(KickedUserEvent {
-				// 	userid,
-				// });
-				// 
-				// if result.is_err() {
-				// 	error!("Error broadcasting KickedUserEvent!");
-				// }
-				
+				self.record_audit(&admin_userid, AuditAction::PardonUser, &userid, "").await;
 				Ok(())
 			}
 			Err(_) => Err(MalformedDBResponse)
 		}
 	}
 
-	async fn ban_user(self, _: Context, stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode> {
-		if !self.is_stoken_valid(&admin_userid, &stoken).await {
+	async fn transfer_ownership(self, _: Context, stoken: String, owner_userid: String, userid: String, keep_ownership: bool) -> Result<OwnershipTransfer, ErrorCode> {
+		self.authorize(&owner_userid, &stoken).await?;
+
+		if !self.internal_is_user_owner(&owner_userid).await {
 			return Err(Unauthorized)
 		}
-		
+
+		let target = self.inner_get_user(&userid).await?;
+		if target.owner {
+			return Err(AlreadyOwner)
+		}
+
+		if self.store.delete_transfers_between(&owner_userid, &userid).await.is_err() {
+			return Err(MalformedDBResponse)
+		}
+
+		let result = self.store.add_transfer(&OwnershipTransfer {
+			id: 0,
+			from_userid: owner_userid.clone(),
+			to_userid: userid.clone(),
+			keep_ownership,
+			created: Utc::now(),
+		}).await;
+
+		match result {
+			Ok(id) => {
+				let transfer = self.inner_get_ownership_transfer(id).await?;
+				let reason = if keep_ownership { "co-owner" } else { "transfer" };
+				self.record_audit(&owner_userid, AuditAction::TransferOwnership, &userid, reason).await;
+				self.push_event(Event::OfferedOwnership(transfer.clone())).await;
+				Ok(transfer)
+			}
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn get_ownership_transfers(self, _: Context, stoken: String, userid: String) -> Result<Vec<OwnershipTransfer>, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		let result = self.store.get_transfers(&userid).await;
+
+		match result {
+			Ok(transfers) => Ok(transfers),
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn accept_ownership_transfer(self, _: Context, stoken: String, userid: String, transfer_id: i64) -> Result<User, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		let transfer = self.inner_get_ownership_transfer(transfer_id).await?;
+		if !transfer.to_userid.eq(&userid) {
+			return Err(TransferNotFound)
+		}
+
+		if self.store.delete_transfer(transfer_id).await.is_err() {
+			return Err(MalformedDBResponse)
+		}
+
+		// The offer is void if whoever made it isn't an owner anymore
+		if !self.internal_is_user_owner(&transfer.from_userid).await {
+			return Err(TransferNotFound)
+		}
+
+		if self.store.set_owner(&userid, true).await.is_err() || self.store.set_admin(&userid, true).await.is_err() {
+			return Err(MalformedDBResponse)
+		}
+
+		if !transfer.keep_ownership {
+			if self.store.set_owner(&transfer.from_userid, false).await.is_err() {
+				return Err(MalformedDBResponse)
+			}
+			self.push_event(Event::ChangedOwnership(self.inner_get_user(&transfer.from_userid).await?)).await;
+		}
+
+		let new_owner = self.inner_get_user(&userid).await?;
+		self.record_audit(&userid, AuditAction::AcceptOwnership, &transfer.from_userid, "").await;
+		self.push_event(Event::ChangedOwnership(new_owner.clone())).await;
+
+		Ok(new_owner)
+	}
+
+	async fn decline_ownership_transfer(self, _: Context, stoken: String, userid: String, transfer_id: i64) -> Result<(), ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		let transfer = self.inner_get_ownership_transfer(transfer_id).await?;
+		if !transfer.to_userid.eq(&userid) {
+			return Err(TransferNotFound)
+		}
+
+		match self.store.delete_transfer(transfer_id).await {
+			Ok(_) => Ok(()),
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn step_down_as_owner(self, _: Context, stoken: String, owner_userid: String) -> Result<(), ErrorCode> {
+		self.authorize(&owner_userid, &stoken).await?;
+
+		if !self.internal_is_user_owner(&owner_userid).await {
+			return Err(Unauthorized)
+		}
+
+		if self.count_owners().await? <= 1 {
+			return Err(LastOwner)
+		}
+
+		let result = self.store.set_owner(&owner_userid, false).await;
+
+		match result {
+			Ok(_) => {
+				self.record_audit(&owner_userid, AuditAction::StepDownAsOwner, &owner_userid, "").await;
+				self.push_event(Event::ChangedOwnership(self.inner_get_user(&owner_userid).await?)).await;
+				Ok(())
+			}
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn list_bans(self, _: Context, stoken: String, admin_userid: String) -> Result<Vec<Ban>, ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
+		if !self.internal_is_user_admin(&admin_userid).await {
+			return Err(Unauthorized)
+		}
+
+		let result = self.store.get_bans(Utc::now()).await;
+
+		match result {
+			Ok(bans) => Ok(bans),
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn timeout_user(self, _: Context, stoken: String, admin_userid: String, userid: String, duration: Duration, reason: String) -> Result<Timeout, ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
 		if !self.internal_is_user_admin(&admin_userid).await {
 			return Err(Unauthorized)
 		}
 
-		query!("DELETE FROM user WHERE userid = ?", userid).execute(&self.db_pool).await.unwrap();
-		let result = query!("INSERT INTO banned (userid) VALUES (?)", userid).execute(&self.db_pool).await;
+		self.check_input(&reason)?;
+		self.inner_timeout_user(&admin_userid, &userid, duration, &reason).await
+	}
+
+	async fn remove_timeout(self, _: Context, stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
+		if !self.internal_is_user_admin(&admin_userid).await {
+			return Err(Unauthorized)
+		}
+
+		let result = self.store.delete_timeout(&userid).await;
 
 		match result {
 			Ok(_) => {
-				// let result = self.packet_manager.lock().await.broadcast(BannedUserEvent {
-				// 	userid,
-				// });
-				// 
-				// if result.is_err() {
-				// 	error!("Error broadcasting BannedUserEvent!");
-				// }
-				
+				self.record_audit(&admin_userid, AuditAction::RemoveTimeout, &userid, "").await;
+
+				self.push_event(Event::RemovedTimeout(userid)).await;
 				Ok(())
 			}
 			Err(_) => Err(MalformedDBResponse)
 		}
 	}
 
-	async fn pardon_user(self, _: Context, stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode> {
+	async fn report_message(self, _: Context, stoken: String, userid: String, message_id: i64, category: ReportCategory, note: String) -> Result<Report, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		let message = self.inner_get_message(&userid, message_id).await?;
+		self.inner_create_report(&userid, &message.user.userid, Some(message.id), category, &note).await
+	}
+
+	async fn report_user(self, _: Context, stoken: String, userid: String, target_userid: String, category: ReportCategory, note: String) -> Result<Report, ErrorCode> {
+		self.authorize(&userid, &stoken).await?;
+
+		let target = self.inner_get_user(&target_userid).await?;
+		self.inner_create_report(&userid, &target.userid, None, category, &note).await
+	}
+
+	async fn list_reports(self, _: Context, stoken: String, admin_userid: String, status: Option<ReportStatus>) -> Result<Vec<Report>, ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
 		if !self.internal_is_user_admin(&admin_userid).await {
 			return Err(Unauthorized)
 		}
 
-		let result = query!("DELETE FROM banned WHERE userid = ?", userid).execute(&self.db_pool).await;
+		let result = self.store.get_reports(status).await;
 
 		match result {
-			Ok(_) => Ok(()),
+			Ok(reports) => Ok(reports),
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn claim_report(self, _: Context, stoken: String, admin_userid: String, report_id: i64) -> Result<Report, ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
+		if !self.internal_is_user_admin(&admin_userid).await {
+			return Err(Unauthorized)
+		}
+
+		let report = self.inner_get_report(report_id).await?;
+		if report.status == ReportStatus::Resolved {
+			return Err(ReportAlreadyResolved)
+		}
+
+		if report.claimed_by.as_ref().is_some_and(|claimed_by| !claimed_by.eq(&admin_userid)) {
+			return Err(ReportAlreadyClaimed)
+		}
+
+		let result = self.store.claim_report(report_id, &admin_userid).await;
+
+		match result {
+			Ok(false) => Err(ReportAlreadyClaimed), //NOTE: Lost a race with another admin
+			Ok(true) => {
+				let audit_reason = format!("Report #{}", report_id);
+				self.record_audit(&admin_userid, AuditAction::ClaimReport, &report.target_userid, &audit_reason).await;
+				self.inner_get_report(report_id).await
+			}
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn resolve_report(self, _: Context, stoken: String, admin_userid: String, report_id: i64, action: ReportAction, reason: String) -> Result<Report, ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
+		if !self.internal_is_user_admin(&admin_userid).await {
+			return Err(Unauthorized)
+		}
+
+		self.check_input(&reason)?;
+
+		let report = self.inner_get_report(report_id).await?;
+		if report.status == ReportStatus::Resolved {
+			return Err(ReportAlreadyResolved)
+		}
+
+		if let Some(claimed_by) = &report.claimed_by {
+			if !claimed_by.eq(&admin_userid) {
+				return Err(Unauthorized)
+			}
+		}
+
+		let resolution = match action {
+			ReportAction::Dismiss => ReportResolution::Dismissed,
+			ReportAction::DeleteMessage => {
+				let Some(message_id) = report.message_id else {
+					return Err(MessageNotFound)
+				};
+				self.inner_delete_message(&admin_userid, message_id, &reason).await?;
+				ReportResolution::DeletedMessage
+			}
+			ReportAction::Timeout(duration) => {
+				self.inner_timeout_user(&admin_userid, &report.target_userid, duration, &reason).await?;
+				ReportResolution::TimedOut
+			}
+			ReportAction::Kick => {
+				self.inner_kick_user(&admin_userid, &report.target_userid, &reason).await?;
+				ReportResolution::Kicked
+			}
+			ReportAction::Ban(expires) => {
+				self.inner_ban_user(&admin_userid, &report.target_userid, &reason, expires).await?;
+				ReportResolution::Banned
+			}
+		};
+
+		let result = self.store.resolve_report(report_id, &admin_userid, resolution, Utc::now()).await;
+
+		match result {
+			Ok(_) => {
+				let audit_reason = format!("Report #{} {:?}: {}", report_id, resolution, reason);
+				self.record_audit(&admin_userid, AuditAction::ResolveReport, &report.target_userid, &audit_reason).await;
+				self.inner_get_report(report_id).await
+			}
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn get_audit_log(self, _: Context, stoken: String, admin_userid: String, filter: AuditLogFilter, before_id: Option<i64>, limit: u32) -> Result<Vec<AuditLogEntry>, ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
+		if !self.internal_is_user_admin(&admin_userid).await {
+			return Err(Unauthorized)
+		}
+
+		let limit = limit.min(MAX_AUDIT_LOG_PAGE);
+		let before_id = before_id.unwrap_or(i64::MAX);
+		let result = self.store.get_audit_log(&filter, before_id, limit).await;
+
+		match result {
+			Ok(entries) => Ok(entries),
+			Err(_) => Err(MalformedDBResponse)
+		}
+	}
+
+	async fn get_stats(self, _: Context, stoken: String, admin_userid: String) -> Result<ServerStats, ErrorCode> {
+		self.authorize(&admin_userid, &stoken).await?;
+
+		if !self.internal_is_user_admin(&admin_userid).await {
+			return Err(Unauthorized)
+		}
+
+		let mut result = match stats::collect(self.store.as_ref()).await {
+			Ok(result) => result,
+			Err(_) => return Err(MalformedDBResponse)
+		};
+		result.users_online = Some(self.presence.online().await as u32);
+		result.process = stats::process().await;
+
+		Ok(result)
+	}
+
+	async fn compact_database(self, _: Context, stoken: String, owner_userid: String) -> Result<(), ErrorCode> {
+		self.authorize(&owner_userid, &stoken).await?;
+
+		if !self.internal_is_user_owner(&owner_userid).await {
+			return Err(Unauthorized)
+		}
+
+		match admin::compact(self.store.as_ref()).await {
+			Ok(freed) => {
+				self.record_audit(&owner_userid, AuditAction::CompactDatabase, &self.server_id, &format!("Freed {} bytes", freed)).await;
+				Ok(())
+			}
 			Err(_) => Err(MalformedDBResponse)
 		}
 	}
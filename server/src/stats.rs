@@ -0,0 +1,69 @@
+use std::fs;
+use std::time::Duration;
+use chrono::Utc;
+use tokio::time::sleep;
+use crate::store::ChatStore;
+use crate::types::{ProcessStats, ServerStats, StorageStats};
+
+/// Window `messages_per_minute` is averaged over
+const MESSAGE_RATE_WINDOW: Duration = Duration::from_secs(15*60);
+/// How long CPU time is sampled for
+const CPU_SAMPLE: Duration = Duration::from_millis(500);
+/// Kernel clock ticks per second, which is 100 on every Linux architecture we run on
+const CLOCK_TICKS: f64 = 100.0;
+
+/// Usage figures read from the database. `users_online` and `process` are only known to the
+/// running server, which fills them in.
+pub async fn collect(store: &dyn ChatStore) -> sqlx::Result<ServerStats> {
+	let since = Utc::now() - MESSAGE_RATE_WINDOW;
+	let recent = store.count_messages(Some(since)).await?;
+
+	Ok(ServerStats {
+		users_online: None,
+		users_total: store.count_users().await?,
+		rooms_total: store.count_rooms().await?,
+		messages_total: store.count_messages(None).await?,
+		messages_per_minute: recent as f64 / (MESSAGE_RATE_WINDOW.as_secs() as f64 / 60.0),
+		storage: StorageStats {
+			database_bytes: store.database_bytes().await?,
+			by_message_type: store.message_bytes_by_type().await?,
+			by_room: store.message_bytes_by_room().await?,
+		},
+		process: None,
+	})
+}
+
+/// CPU and memory use of this process, from `/proc`
+pub async fn process() -> Option<ProcessStats> {
+	let (start_cpu, started) = cpu_ticks()?;
+	sleep(CPU_SAMPLE).await;
+	let (end_cpu, _) = cpu_ticks()?;
+
+	let uptime: f64 = fs::read_to_string("/proc/uptime").ok()?
+		.split_whitespace().next()?
+		.parse().ok()?;
+
+	let memory_kb: u64 = fs::read_to_string("/proc/self/status").ok()?
+		.lines()
+		.find_map(|line| line.strip_prefix("VmRSS:"))?
+		.trim().trim_end_matches("kB").trim()
+		.parse().ok()?;
+
+	Some(ProcessStats {
+		uptime: Duration::from_secs_f64((uptime - started as f64 / CLOCK_TICKS).max(0.0)),
+		cpu_percent: (end_cpu - start_cpu) as f64 / CLOCK_TICKS / CPU_SAMPLE.as_secs_f64() * 100.0,
+		memory_bytes: memory_kb * 1024,
+	})
+}
+
+/// User plus system CPU time, and the start time, of this process in clock ticks
+fn cpu_ticks() -> Option<(u64, u64)> {
+	let stat = fs::read_to_string("/proc/self/stat").ok()?;
+	// Fields after the parenthesised command name, which may itself contain spaces
+	let fields: Vec<&str> = stat[stat.rfind(')')? + 2..].split_whitespace().collect();
+
+	let utime: u64 = fields.get(11)?.parse().ok()?;
+	let stime: u64 = fields.get(12)?.parse().ok()?;
+	let starttime: u64 = fields.get(19)?.parse().ok()?;
+	Some((utime + stime, starttime))
+}
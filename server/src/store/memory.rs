@@ -0,0 +1,445 @@
+use std::cmp::Reverse;
+use std::mem::size_of;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use realm_shared::health::{Health, MigrationState};
+use crate::events::{Event, EventLog};
+use crate::store::{message_columns, ChatStore};
+use crate::types::{AuditLogEntry, AuditLogFilter, Ban, Message, MessageData, OwnershipTransfer, Presence, PresenceStatus, Report, ReportResolution, ReportStatus, Room, Timeout, User};
+
+/// Keeps everything in memory and loses it on drop. Behaves like the SQL stores, down to
+/// messages only being found while their user and room still exist.
+#[derive(Default)]
+pub struct MemoryStore {
+	tables: Mutex<Tables>,
+	events: EventLog,
+}
+
+#[derive(Default)]
+struct Tables {
+	users: Vec<StoredUser>,
+	rooms: Vec<Room>,
+	messages: Vec<StoredMessage>,
+	bans: Vec<Ban>,
+	timeouts: Vec<Timeout>,
+	audit_log: Vec<AuditLogEntry>,
+	reports: Vec<Report>,
+	transfers: Vec<OwnershipTransfer>,
+}
+
+struct StoredUser {
+	user: User,
+	last_seen: Option<DateTime<Utc>>,
+	custom_status: Option<String>,
+}
+
+struct StoredMessage {
+	id: i64,
+	timestamp: DateTime<Utc>,
+	user: i64, //NOTE: user.id
+	room: i64, //NOTE: room.id
+	data: MessageData,
+}
+
+impl MemoryStore {
+	pub fn new() -> MemoryStore {
+		MemoryStore::default()
+	}
+}
+
+impl Tables {
+	fn user_mut(&mut self, userid: &str) -> Option<&mut StoredUser> {
+		self.users.iter_mut().find(|stored| stored.user.userid.eq(userid))
+	}
+
+	/// The message with its user and room, as the SQL stores join them
+	fn message(&self, stored: &StoredMessage) -> Option<Message> {
+		Some(Message {
+			id: stored.id,
+			timestamp: stored.timestamp,
+			user: self.users.iter().find(|u| u.user.id == stored.user)?.user.clone(),
+			room: self.rooms.iter().find(|r| r.id == stored.room)?.clone(),
+			data: stored.data.clone(),
+		})
+	}
+}
+
+/// Ids count up from 1 in each table
+fn next_id(ids: impl Iterator<Item = i64>) -> i64 {
+	ids.max().unwrap_or(0) + 1
+}
+
+fn text_bytes(data: &MessageData) -> i64 {
+	let (_, msg_text, _, _) = message_columns(data);
+	msg_text.map_or(0, |text| text.len() as i64)
+}
+
+#[async_trait]
+impl ChatStore for MemoryStore {
+	async fn health(&self) -> Health {
+		Health { database: true, migrations: MigrationState::Current { version: 0 } } //NOTE: Nothing to migrate
+	}
+
+	async fn close(&self) {}
+
+	async fn push_event(&self, event: Event) -> u32 {
+		self.events.push(event).await
+	}
+
+	async fn get_events_since(&self, index: u32) -> Vec<(u32, Event)> {
+		self.events.since(index).await
+	}
+
+	async fn count_events(&self) -> usize {
+		self.events.count().await
+	}
+
+	async fn get_user(&self, userid: &str) -> sqlx::Result<User> {
+		let tables = self.tables.lock().await;
+		tables.users.iter().find(|stored| stored.user.userid.eq(userid)).map(|stored| stored.user.clone()).ok_or(sqlx::Error::RowNotFound)
+	}
+
+	async fn get_users(&self) -> sqlx::Result<Vec<User>> {
+		let tables = self.tables.lock().await;
+		let mut users: Vec<User> = tables.users.iter().map(|stored| stored.user.clone()).collect();
+		users.sort_by(|a, b| a.userid.cmp(&b.userid));
+		Ok(users)
+	}
+
+	async fn add_user(&self, userid: &str, name: &str, owner: bool, admin: bool) -> sqlx::Result<()> {
+		let mut tables = self.tables.lock().await;
+		let id = next_id(tables.users.iter().map(|stored| stored.user.id));
+		tables.users.push(StoredUser {
+			user: User { id, userid: userid.to_string(), name: name.to_string(), owner, admin, nickname: None },
+			last_seen: None,
+			custom_status: None,
+		});
+		Ok(())
+	}
+
+	async fn delete_user(&self, userid: &str) -> sqlx::Result<bool> {
+		let mut tables = self.tables.lock().await;
+		let before = tables.users.len();
+		tables.users.retain(|stored| !stored.user.userid.eq(userid));
+		Ok(tables.users.len() < before)
+	}
+
+	async fn count_owners(&self) -> sqlx::Result<i64> {
+		let tables = self.tables.lock().await;
+		Ok(tables.users.iter().filter(|stored| stored.user.owner).count() as i64)
+	}
+
+	async fn set_name(&self, userid: &str, name: &str) -> sqlx::Result<()> {
+		if let Some(stored) = self.tables.lock().await.user_mut(userid) {
+			stored.user.name = name.to_string();
+		}
+		Ok(())
+	}
+
+	async fn set_nickname(&self, userid: &str, nickname: Option<&str>) -> sqlx::Result<()> {
+		if let Some(stored) = self.tables.lock().await.user_mut(userid) {
+			stored.user.nickname = nickname.map(str::to_string);
+		}
+		Ok(())
+	}
+
+	async fn set_admin(&self, userid: &str, admin: bool) -> sqlx::Result<bool> {
+		let mut tables = self.tables.lock().await;
+		let Some(stored) = tables.user_mut(userid) else {
+			return Ok(false)
+		};
+		stored.user.admin = admin;
+		Ok(true)
+	}
+
+	async fn set_owner(&self, userid: &str, owner: bool) -> sqlx::Result<bool> {
+		let mut tables = self.tables.lock().await;
+		let Some(stored) = tables.user_mut(userid) else {
+			return Ok(false)
+		};
+		stored.user.owner = owner;
+		Ok(true)
+	}
+
+	async fn set_custom_status(&self, userid: &str, custom_status: Option<&str>) -> sqlx::Result<()> {
+		if let Some(stored) = self.tables.lock().await.user_mut(userid) {
+			stored.custom_status = custom_status.map(str::to_string);
+		}
+		Ok(())
+	}
+
+	async fn set_last_seen(&self, userid: &str, last_seen: Option<DateTime<Utc>>) -> sqlx::Result<()> {
+		if let Some(stored) = self.tables.lock().await.user_mut(userid) {
+			stored.last_seen = last_seen;
+		}
+		Ok(())
+	}
+
+	async fn get_presences(&self) -> sqlx::Result<Vec<Presence>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.users.iter().map(|stored| Presence {
+			userid: stored.user.userid.clone(),
+			status: PresenceStatus::Offline,
+			custom_status: stored.custom_status.clone(),
+			last_seen: stored.last_seen,
+		}).collect())
+	}
+
+	async fn get_room(&self, roomid: &str) -> sqlx::Result<Room> {
+		let tables = self.tables.lock().await;
+		tables.rooms.iter().find(|room| room.roomid.eq(roomid)).cloned().ok_or(sqlx::Error::RowNotFound)
+	}
+
+	async fn get_rooms(&self) -> sqlx::Result<Vec<Room>> {
+		let mut rooms = self.tables.lock().await.rooms.clone();
+		rooms.sort_by(|a, b| a.roomid.cmp(&b.roomid));
+		Ok(rooms)
+	}
+
+	async fn add_room(&self, room: &Room) -> sqlx::Result<i64> {
+		let mut tables = self.tables.lock().await;
+		let id = next_id(tables.rooms.iter().map(|room| room.id));
+		tables.rooms.push(Room { id, ..room.clone() });
+		Ok(id)
+	}
+
+	async fn delete_room(&self, roomid: &str) -> sqlx::Result<()> {
+		self.tables.lock().await.rooms.retain(|room| !room.roomid.eq(roomid));
+		Ok(())
+	}
+
+	async fn add_message(&self, message: &Message) -> sqlx::Result<i64> {
+		let mut tables = self.tables.lock().await;
+		let id = next_id(tables.messages.iter().map(|message| message.id));
+		tables.messages.push(StoredMessage {
+			id,
+			timestamp: message.timestamp,
+			user: message.user.id,
+			room: message.room.id,
+			data: message.data.clone(),
+		});
+		Ok(id)
+	}
+
+	async fn get_message(&self, id: i64) -> sqlx::Result<Message> {
+		let tables = self.tables.lock().await;
+		tables.messages.iter()
+			.find(|stored| stored.id == id)
+			.and_then(|stored| tables.message(stored))
+			.ok_or(sqlx::Error::RowNotFound)
+	}
+
+	async fn get_messages_since(&self, id: i64, limit: u32, admin_only_view: bool) -> sqlx::Result<Vec<Message>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.messages.iter()
+			.filter(|stored| stored.id > id)
+			.filter_map(|stored| tables.message(stored))
+			.filter(|message| admin_only_view || !message.room.admin_only_view)
+			.take(limit as usize)
+			.collect())
+	}
+
+	async fn get_direct_replies(&self, head: i64) -> sqlx::Result<Vec<Message>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.messages.iter()
+			.filter(|stored| message_columns(&stored.data).3 == Some(head))
+			.filter_map(|stored| tables.message(stored))
+			.collect())
+	}
+
+	async fn is_banned(&self, userid: &str, now: DateTime<Utc>) -> sqlx::Result<bool> {
+		let tables = self.tables.lock().await;
+		Ok(tables.bans.iter().any(|ban| ban.userid.eq(userid) && ban.expires.is_none_or(|expires| expires > now)))
+	}
+
+	async fn get_bans(&self, now: DateTime<Utc>) -> sqlx::Result<Vec<Ban>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.bans.iter().filter(|ban| ban.expires.is_none_or(|expires| expires > now)).cloned().collect())
+	}
+
+	async fn add_ban(&self, ban: &Ban) -> sqlx::Result<i64> {
+		let mut tables = self.tables.lock().await;
+		let id = next_id(tables.bans.iter().map(|ban| ban.id));
+		tables.bans.push(Ban { id, ..ban.clone() });
+		Ok(id)
+	}
+
+	async fn delete_ban(&self, userid: &str) -> sqlx::Result<bool> {
+		let mut tables = self.tables.lock().await;
+		let before = tables.bans.len();
+		tables.bans.retain(|ban| !ban.userid.eq(userid));
+		Ok(tables.bans.len() < before)
+	}
+
+	async fn delete_expired_bans(&self, now: DateTime<Utc>) -> sqlx::Result<u64> {
+		let mut tables = self.tables.lock().await;
+		let before = tables.bans.len();
+		tables.bans.retain(|ban| ban.expires.is_none_or(|expires| expires > now));
+		Ok((before - tables.bans.len()) as u64)
+	}
+
+	async fn get_timeout(&self, userid: &str, now: DateTime<Utc>) -> sqlx::Result<Option<Timeout>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.timeouts.iter()
+			.filter(|timeout| timeout.userid.eq(userid) && timeout.expires > now)
+			.max_by_key(|timeout| timeout.expires)
+			.cloned())
+	}
+
+	async fn add_timeout(&self, timeout: &Timeout) -> sqlx::Result<i64> {
+		let mut tables = self.tables.lock().await;
+		let id = next_id(tables.timeouts.iter().map(|timeout| timeout.id));
+		tables.timeouts.push(Timeout { id, ..timeout.clone() });
+		Ok(id)
+	}
+
+	async fn delete_timeout(&self, userid: &str) -> sqlx::Result<()> {
+		self.tables.lock().await.timeouts.retain(|timeout| !timeout.userid.eq(userid));
+		Ok(())
+	}
+
+	async fn add_audit(&self, entry: &AuditLogEntry) -> sqlx::Result<i64> {
+		let mut tables = self.tables.lock().await;
+		let id = next_id(tables.audit_log.iter().map(|entry| entry.id));
+		tables.audit_log.push(AuditLogEntry { id, ..entry.clone() });
+		Ok(id)
+	}
+
+	async fn get_audit_log(&self, filter: &AuditLogFilter, before_id: i64, limit: u32) -> sqlx::Result<Vec<AuditLogEntry>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.audit_log.iter().rev()
+			.filter(|entry| entry.id < before_id)
+			.filter(|entry| filter.actor.as_ref().is_none_or(|actor| entry.actor.eq(actor)))
+			.filter(|entry| filter.target.as_ref().is_none_or(|target| entry.target.eq(target)))
+			.filter(|entry| filter.action.is_none_or(|action| entry.action == action))
+			.take(limit as usize)
+			.cloned()
+			.collect())
+	}
+
+	async fn add_report(&self, report: &Report) -> sqlx::Result<i64> {
+		let mut tables = self.tables.lock().await;
+		let id = next_id(tables.reports.iter().map(|report| report.id));
+		tables.reports.push(Report { id, ..report.clone() });
+		Ok(id)
+	}
+
+	async fn get_report(&self, id: i64) -> sqlx::Result<Report> {
+		let tables = self.tables.lock().await;
+		tables.reports.iter().find(|report| report.id == id).cloned().ok_or(sqlx::Error::RowNotFound)
+	}
+
+	async fn get_reports(&self, status: Option<ReportStatus>) -> sqlx::Result<Vec<Report>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.reports.iter().filter(|report| status.is_none_or(|status| report.status == status)).cloned().collect())
+	}
+
+	async fn claim_report(&self, id: i64, claimed_by: &str) -> sqlx::Result<bool> {
+		let mut tables = self.tables.lock().await;
+		let claimable = |report: &&mut Report| report.id == id && report.status != ReportStatus::Resolved
+			&& report.claimed_by.as_deref().is_none_or(|by| by.eq(claimed_by));
+		match tables.reports.iter_mut().find(claimable) {
+			Some(report) => {
+				report.status = ReportStatus::Claimed;
+				report.claimed_by = Some(claimed_by.to_string());
+				Ok(true)
+			}
+			None => Ok(false),
+		}
+	}
+
+	async fn resolve_report(&self, id: i64, resolved_by: &str, resolution: ReportResolution, resolved_at: DateTime<Utc>) -> sqlx::Result<()> {
+		let mut tables = self.tables.lock().await;
+		if let Some(report) = tables.reports.iter_mut().find(|report| report.id == id) {
+			report.status = ReportStatus::Resolved;
+			report.claimed_by = Some(resolved_by.to_string());
+			report.resolution = Some(resolution);
+			report.resolved_at = Some(resolved_at);
+		}
+		Ok(())
+	}
+
+	async fn add_transfer(&self, transfer: &OwnershipTransfer) -> sqlx::Result<i64> {
+		let mut tables = self.tables.lock().await;
+		let id = next_id(tables.transfers.iter().map(|transfer| transfer.id));
+		tables.transfers.push(OwnershipTransfer { id, ..transfer.clone() });
+		Ok(id)
+	}
+
+	async fn get_transfer(&self, id: i64) -> sqlx::Result<OwnershipTransfer> {
+		let tables = self.tables.lock().await;
+		tables.transfers.iter().find(|transfer| transfer.id == id).cloned().ok_or(sqlx::Error::RowNotFound)
+	}
+
+	async fn get_transfers(&self, userid: &str) -> sqlx::Result<Vec<OwnershipTransfer>> {
+		let tables = self.tables.lock().await;
+		Ok(tables.transfers.iter().filter(|transfer| transfer.from_userid.eq(userid) || transfer.to_userid.eq(userid)).cloned().collect())
+	}
+
+	async fn delete_transfer(&self, id: i64) -> sqlx::Result<()> {
+		self.tables.lock().await.transfers.retain(|transfer| transfer.id != id);
+		Ok(())
+	}
+
+	async fn delete_transfers_between(&self, from_userid: &str, to_userid: &str) -> sqlx::Result<()> {
+		self.tables.lock().await.transfers.retain(|transfer| !(transfer.from_userid.eq(from_userid) && transfer.to_userid.eq(to_userid)));
+		Ok(())
+	}
+
+	async fn count_users(&self) -> sqlx::Result<i64> {
+		Ok(self.tables.lock().await.users.len() as i64)
+	}
+
+	async fn count_rooms(&self) -> sqlx::Result<i64> {
+		Ok(self.tables.lock().await.rooms.len() as i64)
+	}
+
+	async fn count_messages(&self, since: Option<DateTime<Utc>>) -> sqlx::Result<i64> {
+		let tables = self.tables.lock().await;
+		Ok(tables.messages.iter().filter(|message| since.is_none_or(|since| message.timestamp >= since)).count() as i64)
+	}
+
+	async fn message_bytes_by_type(&self) -> sqlx::Result<Vec<(String, i64)>> {
+		let tables = self.tables.lock().await;
+		let mut by_type: Vec<(String, i64)> = Vec::new();
+		for message in &tables.messages {
+			let msg_type = message_columns(&message.data).0;
+			match by_type.iter_mut().find(|(t, _)| t.eq(msg_type)) {
+				Some((_, bytes)) => *bytes += text_bytes(&message.data),
+				None => by_type.push((msg_type.to_string(), text_bytes(&message.data))),
+			}
+		}
+		by_type.sort();
+		Ok(by_type)
+	}
+
+	async fn message_bytes_by_room(&self) -> sqlx::Result<Vec<(String, i64)>> {
+		let tables = self.tables.lock().await;
+		let mut by_room: Vec<(String, i64)> = tables.rooms.iter().map(|room| {
+			let bytes = tables.messages.iter().filter(|message| message.room == room.id).map(|message| text_bytes(&message.data)).sum();
+			(room.roomid.clone(), bytes)
+		}).collect();
+		by_room.sort_by_key(|(_, bytes)| Reverse(*bytes));
+		Ok(by_room)
+	}
+
+	/// A rough count of what the tables hold, there being no file to measure
+	async fn database_bytes(&self) -> sqlx::Result<i64> {
+		let tables = self.tables.lock().await;
+		let rows = tables.users.len() * size_of::<StoredUser>()
+			+ tables.rooms.len() * size_of::<Room>()
+			+ tables.messages.len() * size_of::<StoredMessage>()
+			+ tables.bans.len() * size_of::<Ban>()
+			+ tables.timeouts.len() * size_of::<Timeout>()
+			+ tables.audit_log.len() * size_of::<AuditLogEntry>()
+			+ tables.reports.len() * size_of::<Report>()
+			+ tables.transfers.len() * size_of::<OwnershipTransfer>();
+		let text: i64 = tables.messages.iter().map(|message| text_bytes(&message.data)).sum();
+		Ok(rows as i64 + text)
+	}
+
+	async fn compact(&self) -> sqlx::Result<()> {
+		Ok(()) //NOTE: Deleted rows are already gone
+	}
+}
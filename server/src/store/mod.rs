@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, SqlitePool};
+use realm_shared::config::DatabaseBackend;
+use realm_shared::health::Health;
+use crate::config::DatabaseSection;
+use crate::events::Event;
+use crate::types::{AuditLogEntry, AuditLogFilter, Ban, Message, MessageData, OwnershipTransfer, Presence, Report, ReportResolution, ReportStatus, Room, Timeout, User};
+
+pub mod sqlite;
+pub mod postgres;
+pub mod memory;
+
+pub use self::memory::MemoryStore;
+pub use self::postgres::PostgresStore;
+pub use self::sqlite::SqliteStore;
+
+/// Everything the chat server keeps, so the backend can be picked in config, or swapped for `MemoryStore`
+/// in tests and tools that embed the server. Events are only ever held in memory.
+/// Inserts take the row to write and ignore its `id`, returning the one the database gave it.
+#[async_trait]
+pub trait ChatStore: Send + Sync {
+	async fn health(&self) -> Health;
+	async fn close(&self);
+
+	async fn push_event(&self, event: Event) -> u32;
+	async fn get_events_since(&self, index: u32) -> Vec<(u32, Event)>;
+	async fn count_events(&self) -> usize;
+
+	async fn get_user(&self, userid: &str) -> sqlx::Result<User>;
+	async fn get_users(&self) -> sqlx::Result<Vec<User>>;
+	async fn add_user(&self, userid: &str, name: &str, owner: bool, admin: bool) -> sqlx::Result<()>;
+	async fn delete_user(&self, userid: &str) -> sqlx::Result<bool>; //NOTE: false if they weren't in the server
+	async fn count_owners(&self) -> sqlx::Result<i64>;
+	async fn set_name(&self, userid: &str, name: &str) -> sqlx::Result<()>;
+	async fn set_nickname(&self, userid: &str, nickname: Option<&str>) -> sqlx::Result<()>;
+	async fn set_admin(&self, userid: &str, admin: bool) -> sqlx::Result<bool>;
+	async fn set_owner(&self, userid: &str, owner: bool) -> sqlx::Result<bool>;
+	async fn set_custom_status(&self, userid: &str, custom_status: Option<&str>) -> sqlx::Result<()>;
+	async fn set_last_seen(&self, userid: &str, last_seen: Option<DateTime<Utc>>) -> sqlx::Result<()>;
+	async fn get_presences(&self) -> sqlx::Result<Vec<Presence>>; //NOTE: Everyone as Offline, with what's stored of them
+
+	async fn get_room(&self, roomid: &str) -> sqlx::Result<Room>;
+	async fn get_rooms(&self) -> sqlx::Result<Vec<Room>>;
+	async fn add_room(&self, room: &Room) -> sqlx::Result<i64>;
+	async fn delete_room(&self, roomid: &str) -> sqlx::Result<()>;
+
+	async fn add_message(&self, message: &Message) -> sqlx::Result<i64>;
+	async fn get_message(&self, id: i64) -> sqlx::Result<Message>;
+	/// Messages after `id`, skipping those in admin-only rooms unless `admin_only_view` is set
+	async fn get_messages_since(&self, id: i64, limit: u32, admin_only_view: bool) -> sqlx::Result<Vec<Message>>;
+	async fn get_direct_replies(&self, head: i64) -> sqlx::Result<Vec<Message>>;
+
+	async fn is_banned(&self, userid: &str, now: DateTime<Utc>) -> sqlx::Result<bool>;
+	async fn get_bans(&self, now: DateTime<Utc>) -> sqlx::Result<Vec<Ban>>;
+	async fn add_ban(&self, ban: &Ban) -> sqlx::Result<i64>;
+	async fn delete_ban(&self, userid: &str) -> sqlx::Result<bool>; //NOTE: false if they weren't banned
+	async fn delete_expired_bans(&self, now: DateTime<Utc>) -> sqlx::Result<u64>;
+	async fn get_timeout(&self, userid: &str, now: DateTime<Utc>) -> sqlx::Result<Option<Timeout>>;
+	async fn add_timeout(&self, timeout: &Timeout) -> sqlx::Result<i64>;
+	async fn delete_timeout(&self, userid: &str) -> sqlx::Result<()>;
+
+	async fn add_audit(&self, entry: &AuditLogEntry) -> sqlx::Result<i64>;
+	async fn get_audit_log(&self, filter: &AuditLogFilter, before_id: i64, limit: u32) -> sqlx::Result<Vec<AuditLogEntry>>; //NOTE: Newest first
+
+	async fn add_report(&self, report: &Report) -> sqlx::Result<i64>;
+	async fn get_report(&self, id: i64) -> sqlx::Result<Report>;
+	async fn get_reports(&self, status: Option<ReportStatus>) -> sqlx::Result<Vec<Report>>;
+	async fn claim_report(&self, id: i64, claimed_by: &str) -> sqlx::Result<bool>; //NOTE: False if someone else claimed it first, or it was resolved
+	async fn resolve_report(&self, id: i64, resolved_by: &str, resolution: ReportResolution, resolved_at: DateTime<Utc>) -> sqlx::Result<()>;
+
+	async fn add_transfer(&self, transfer: &OwnershipTransfer) -> sqlx::Result<i64>;
+	async fn get_transfer(&self, id: i64) -> sqlx::Result<OwnershipTransfer>;
+	async fn get_transfers(&self, userid: &str) -> sqlx::Result<Vec<OwnershipTransfer>>; //NOTE: Made by or to userid
+	async fn delete_transfer(&self, id: i64) -> sqlx::Result<()>;
+	async fn delete_transfers_between(&self, from_userid: &str, to_userid: &str) -> sqlx::Result<()>;
+
+	async fn count_users(&self) -> sqlx::Result<i64>;
+	async fn count_rooms(&self) -> sqlx::Result<i64>;
+	async fn count_messages(&self, since: Option<DateTime<Utc>>) -> sqlx::Result<i64>;
+	async fn message_bytes_by_type(&self) -> sqlx::Result<Vec<(String, i64)>>;
+	async fn message_bytes_by_room(&self) -> sqlx::Result<Vec<(String, i64)>>; //NOTE: Largest first
+	async fn database_bytes(&self) -> sqlx::Result<i64>;
+	async fn compact(&self) -> sqlx::Result<()>; //NOTE: Locks the database while it runs
+}
+
+/// Connects to the database in config, creating it and running migrations as needed
+pub async fn open(config: &DatabaseSection) -> anyhow::Result<Arc<dyn ChatStore>> {
+	open_url(config.backend, &config.url).await
+}
+
+pub async fn open_url(backend: DatabaseBackend, url: &str) -> anyhow::Result<Arc<dyn ChatStore>> {
+	Ok(match backend {
+		DatabaseBackend::Sqlite => Arc::new(SqliteStore::open(url).await?),
+		DatabaseBackend::Postgres => Arc::new(PostgresStore::open(url).await?),
+	})
+}
+
+/// Connects to a database that's already set up, without creating or migrating it
+pub async fn connect(backend: DatabaseBackend, url: &str) -> anyhow::Result<Arc<dyn ChatStore>> {
+	Ok(match backend {
+		DatabaseBackend::Sqlite => Arc::new(SqliteStore::new(SqlitePool::connect(url).await?)),
+		DatabaseBackend::Postgres => Arc::new(PostgresStore::new(PgPool::connect(url).await?)),
+	})
+}
+
+/// The `msg_type`, `msg_text`, `emoji` and `referencing_id` columns a message is stored as
+fn message_columns(data: &MessageData) -> (&'static str, Option<&str>, Option<&str>, Option<i64>) {
+	match data {
+		MessageData::Text(text) => ("text", Some(text), None, None),
+		MessageData::Attachment(_) => ("attachment", None, None, None),
+		MessageData::Reply(reply) => ("reply", Some(&reply.text), None, Some(reply.referencing_id)),
+		MessageData::Edit(edit) => ("edit", Some(&edit.text), None, Some(edit.referencing_id)),
+		MessageData::Reaction(reaction) => ("reaction", None, Some(&reaction.emoji), Some(reaction.referencing_id)),
+		MessageData::Redaction(redaction) => ("redaction", None, None, Some(redaction.referencing_id)),
+	}
+}
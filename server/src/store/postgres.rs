@@ -0,0 +1,351 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::migrate::{MigrateDatabase, Migrator};
+use sqlx::{query, query_as, query_scalar, FromRow, PgPool, Pool, Postgres, Row};
+use tracing::info;
+use realm_shared::health::{self, Health};
+use crate::events::{Event, EventLog};
+use crate::store::{message_columns, ChatStore};
+use crate::types::{AuditLogEntry, AuditLogFilter, Ban, FromRows, Message, OwnershipTransfer, Presence, PresenceStatus, Report, ReportResolution, ReportStatus, Room, Timeout, User};
+
+/// The migrations under `migrations/postgres/`, run on startup and checked by the `health` RPC
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+
+//NOTE: `user` is reserved in PostgreSQL, so the table and message's column are quoted throughout
+const FETCH_MESSAGE: &str = r#"SELECT message.*,
+        room.id AS room_id, room.roomid AS room_roomid, room.admin_only_send AS room_admin_only_send, room.admin_only_view AS room_admin_only_view,
+        "user".id AS user_id, "user".userid AS user_userid, "user".name AS user_name, "user".owner AS user_owner, "user".admin AS user_admin, "user".nickname AS user_nickname
+	    FROM message INNER JOIN room ON message.room = room.id INNER JOIN "user" ON message."user" = "user".id"#;
+
+const FETCH_REPORT: &str = "SELECT id, created, reporter, target_userid, message_id, category, note, status, claimed_by, resolution, resolved_at FROM report";
+
+const FETCH_TRANSFER: &str = "SELECT id, from_userid, to_userid, keep_ownership, created FROM ownership_transfer";
+
+pub struct PostgresStore {
+	db_pool: Pool<Postgres>,
+	events: EventLog,
+}
+
+impl PostgresStore {
+	pub fn new(db_pool: Pool<Postgres>) -> PostgresStore {
+		PostgresStore { db_pool, events: EventLog::default() }
+	}
+
+	/// Creates the database if it's missing, connects, and brings it up to date
+	pub async fn open(database_url: &str) -> anyhow::Result<PostgresStore> {
+		if !Postgres::database_exists(database_url).await? {
+			info!("Creating database {}", database_url);
+			Postgres::create_database(database_url).await?;
+		}
+
+		let store = PostgresStore::new(PgPool::connect(database_url).await?);
+		store.migrate().await?;
+		Ok(store)
+	}
+
+	pub async fn migrate(&self) -> anyhow::Result<()> {
+		info!("Running migrations...");
+		MIGRATOR.run(&self.db_pool).await?;
+		info!("Migrations complete!");
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl ChatStore for PostgresStore {
+	async fn health(&self) -> Health {
+		health::check(&self.db_pool, &MIGRATOR).await
+	}
+
+	async fn close(&self) {
+		self.db_pool.close().await
+	}
+
+	async fn push_event(&self, event: Event) -> u32 {
+		self.events.push(event).await
+	}
+
+	async fn get_events_since(&self, index: u32) -> Vec<(u32, Event)> {
+		self.events.since(index).await
+	}
+
+	async fn count_events(&self) -> usize {
+		self.events.count().await
+	}
+
+	async fn get_user(&self, userid: &str) -> sqlx::Result<User> {
+		query_as(r#"SELECT id, userid, name, owner, admin, nickname FROM "user" WHERE userid = $1"#)
+			.bind(userid)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn get_users(&self) -> sqlx::Result<Vec<User>> {
+		query_as(r#"SELECT id, userid, name, owner, admin, nickname FROM "user" ORDER BY userid"#)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn add_user(&self, userid: &str, name: &str, owner: bool, admin: bool) -> sqlx::Result<()> {
+		query(r#"INSERT INTO "user" (userid, name, owner, admin) VALUES ($1, $2, $3, $4)"#)
+			.bind(userid).bind(name).bind(owner).bind(admin)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn delete_user(&self, userid: &str) -> sqlx::Result<bool> {
+		let result = query(r#"DELETE FROM "user" WHERE userid = $1"#).bind(userid).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn count_owners(&self) -> sqlx::Result<i64> {
+		query_scalar(r#"SELECT COUNT(*) FROM "user" WHERE owner = true"#).fetch_one(&self.db_pool).await
+	}
+
+	async fn set_name(&self, userid: &str, name: &str) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET name = $1 WHERE userid = $2"#).bind(name).bind(userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_nickname(&self, userid: &str, nickname: Option<&str>) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET nickname = $1 WHERE userid = $2"#).bind(nickname).bind(userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_admin(&self, userid: &str, admin: bool) -> sqlx::Result<bool> {
+		let result = query(r#"UPDATE "user" SET admin = $1 WHERE userid = $2"#).bind(admin).bind(userid).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn set_owner(&self, userid: &str, owner: bool) -> sqlx::Result<bool> {
+		let result = query(r#"UPDATE "user" SET owner = $1 WHERE userid = $2"#).bind(owner).bind(userid).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn set_custom_status(&self, userid: &str, custom_status: Option<&str>) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET custom_status = $1 WHERE userid = $2"#).bind(custom_status).bind(userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_last_seen(&self, userid: &str, last_seen: Option<DateTime<Utc>>) -> sqlx::Result<()> {
+		query(r#"UPDATE "user" SET last_seen = $1 WHERE userid = $2"#).bind(last_seen).bind(userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_presences(&self) -> sqlx::Result<Vec<Presence>> {
+		let rows = query(r#"SELECT userid, last_seen, custom_status FROM "user""#).fetch_all(&self.db_pool).await?;
+
+		let mut presences = Vec::new();
+		for row in rows {
+			presences.push(Presence {
+				userid: row.try_get("userid")?,
+				status: PresenceStatus::Offline,
+				custom_status: row.try_get("custom_status")?,
+				last_seen: row.try_get("last_seen")?,
+			});
+		}
+		Ok(presences)
+	}
+
+	async fn get_room(&self, roomid: &str) -> sqlx::Result<Room> {
+		query_as("SELECT * FROM room WHERE roomid = $1").bind(roomid).fetch_one(&self.db_pool).await
+	}
+
+	async fn get_rooms(&self) -> sqlx::Result<Vec<Room>> {
+		query_as("SELECT * FROM room ORDER BY roomid").fetch_all(&self.db_pool).await
+	}
+
+	async fn add_room(&self, room: &Room) -> sqlx::Result<i64> {
+		query_scalar("INSERT INTO room (roomid, admin_only_send, admin_only_view) VALUES ($1, $2, $3) RETURNING id")
+			.bind(&room.roomid).bind(room.admin_only_send).bind(room.admin_only_view)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn delete_room(&self, roomid: &str) -> sqlx::Result<()> {
+		query("DELETE FROM room WHERE roomid = $1").bind(roomid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn add_message(&self, message: &Message) -> sqlx::Result<i64> {
+		let (msg_type, msg_text, emoji, referencing_id) = message_columns(&message.data);
+		query_scalar(r#"INSERT INTO message (timestamp, "user", room, msg_type, msg_text, emoji, referencing_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id"#)
+			.bind(message.timestamp).bind(message.user.id).bind(message.room.id)
+			.bind(msg_type).bind(msg_text).bind(emoji).bind(referencing_id)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn get_message(&self, id: i64) -> sqlx::Result<Message> {
+		let row = query(&format!("{}{}", FETCH_MESSAGE, " WHERE message.id = $1"))
+			.bind(id)
+			.fetch_one(&self.db_pool).await?;
+		Message::from_row(&row)
+	}
+
+	async fn get_messages_since(&self, id: i64, limit: u32, admin_only_view: bool) -> sqlx::Result<Vec<Message>> {
+		let rows = query(&format!("{}{}", FETCH_MESSAGE, " WHERE message.id > $1 AND ($2 OR NOT room.admin_only_view) ORDER BY message.id LIMIT $3"))
+			.bind(id)
+			.bind(admin_only_view)
+			.bind(limit as i64)
+			.fetch_all(&self.db_pool).await?;
+		Message::from_rows(rows)
+	}
+
+	async fn get_direct_replies(&self, head: i64) -> sqlx::Result<Vec<Message>> {
+		let rows = query(&format!("{}{}", FETCH_MESSAGE, " WHERE message.referencing_id = $1 ORDER BY message.id"))
+			.bind(head)
+			.fetch_all(&self.db_pool).await?;
+		Message::from_rows(rows)
+	}
+
+	async fn is_banned(&self, userid: &str, now: DateTime<Utc>) -> sqlx::Result<bool> {
+		query_scalar("SELECT EXISTS (SELECT 1 FROM banned WHERE userid = $1 AND (expires IS NULL OR expires > $2))")
+			.bind(userid).bind(now)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn get_bans(&self, now: DateTime<Utc>) -> sqlx::Result<Vec<Ban>> {
+		query_as("SELECT id, userid, reason, banned_by, created, expires FROM banned WHERE expires IS NULL OR expires > $1 ORDER BY id")
+			.bind(now)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn add_ban(&self, ban: &Ban) -> sqlx::Result<i64> {
+		query_scalar("INSERT INTO banned (userid, reason, banned_by, created, expires) VALUES ($1, $2, $3, $4, $5) RETURNING id")
+			.bind(&ban.userid).bind(&ban.reason).bind(&ban.banned_by).bind(ban.created).bind(ban.expires)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn delete_ban(&self, userid: &str) -> sqlx::Result<bool> {
+		let result = query("DELETE FROM banned WHERE userid = $1").bind(userid).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn delete_expired_bans(&self, now: DateTime<Utc>) -> sqlx::Result<u64> {
+		let result = query("DELETE FROM banned WHERE expires IS NOT NULL AND expires <= $1").bind(now).execute(&self.db_pool).await?;
+		Ok(result.rows_affected())
+	}
+
+	async fn get_timeout(&self, userid: &str, now: DateTime<Utc>) -> sqlx::Result<Option<Timeout>> {
+		query_as("SELECT id, userid, reason, issued_by, created, expires FROM timeout WHERE userid = $1 AND expires > $2 ORDER BY expires DESC")
+			.bind(userid).bind(now)
+			.fetch_optional(&self.db_pool).await
+	}
+
+	async fn add_timeout(&self, timeout: &Timeout) -> sqlx::Result<i64> {
+		query_scalar("INSERT INTO timeout (userid, reason, issued_by, created, expires) VALUES ($1, $2, $3, $4, $5) RETURNING id")
+			.bind(&timeout.userid).bind(&timeout.reason).bind(&timeout.issued_by).bind(timeout.created).bind(timeout.expires)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn delete_timeout(&self, userid: &str) -> sqlx::Result<()> {
+		query("DELETE FROM timeout WHERE userid = $1").bind(userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn add_audit(&self, entry: &AuditLogEntry) -> sqlx::Result<i64> {
+		query_scalar("INSERT INTO audit_log (timestamp, actor, action, target, reason) VALUES ($1, $2, $3, $4, $5) RETURNING id")
+			.bind(entry.timestamp).bind(&entry.actor).bind(entry.action).bind(&entry.target).bind(&entry.reason)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn get_audit_log(&self, filter: &AuditLogFilter, before_id: i64, limit: u32) -> sqlx::Result<Vec<AuditLogEntry>> {
+		query_as("SELECT id, timestamp, actor, action, target, reason
+			FROM audit_log
+			WHERE id < $1 AND ($2::text IS NULL OR actor = $2) AND ($3::text IS NULL OR target = $3) AND ($4::text IS NULL OR action = $4)
+			ORDER BY id DESC LIMIT $5")
+			.bind(before_id).bind(&filter.actor).bind(&filter.target).bind(filter.action).bind(limit as i64)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn add_report(&self, report: &Report) -> sqlx::Result<i64> {
+		query_scalar("INSERT INTO report (created, reporter, target_userid, message_id, category, note, status, claimed_by, resolution, resolved_at)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id")
+			.bind(report.created).bind(&report.reporter).bind(&report.target_userid).bind(report.message_id).bind(report.category)
+			.bind(&report.note).bind(report.status).bind(&report.claimed_by).bind(report.resolution).bind(report.resolved_at)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn get_report(&self, id: i64) -> sqlx::Result<Report> {
+		query_as(&format!("{}{}", FETCH_REPORT, " WHERE id = $1")).bind(id).fetch_one(&self.db_pool).await
+	}
+
+	async fn get_reports(&self, status: Option<ReportStatus>) -> sqlx::Result<Vec<Report>> {
+		query_as(&format!("{}{}", FETCH_REPORT, " WHERE ($1::text IS NULL OR status = $1) ORDER BY id"))
+			.bind(status)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn claim_report(&self, id: i64, claimed_by: &str) -> sqlx::Result<bool> {
+		let result = query("UPDATE report SET status = 'claimed', claimed_by = $1 WHERE id = $2 AND status <> 'resolved' AND (claimed_by IS NULL OR claimed_by = $1)")
+			.bind(claimed_by).bind(id)
+			.execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn resolve_report(&self, id: i64, resolved_by: &str, resolution: ReportResolution, resolved_at: DateTime<Utc>) -> sqlx::Result<()> {
+		query("UPDATE report SET status = 'resolved', claimed_by = $1, resolution = $2, resolved_at = $3 WHERE id = $4")
+			.bind(resolved_by).bind(resolution).bind(resolved_at).bind(id)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn add_transfer(&self, transfer: &OwnershipTransfer) -> sqlx::Result<i64> {
+		query_scalar("INSERT INTO ownership_transfer (from_userid, to_userid, keep_ownership, created) VALUES ($1, $2, $3, $4) RETURNING id")
+			.bind(&transfer.from_userid).bind(&transfer.to_userid).bind(transfer.keep_ownership).bind(transfer.created)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn get_transfer(&self, id: i64) -> sqlx::Result<OwnershipTransfer> {
+		query_as(&format!("{}{}", FETCH_TRANSFER, " WHERE id = $1")).bind(id).fetch_one(&self.db_pool).await
+	}
+
+	async fn get_transfers(&self, userid: &str) -> sqlx::Result<Vec<OwnershipTransfer>> {
+		query_as(&format!("{}{}", FETCH_TRANSFER, " WHERE from_userid = $1 OR to_userid = $1 ORDER BY id"))
+			.bind(userid)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn delete_transfer(&self, id: i64) -> sqlx::Result<()> {
+		query("DELETE FROM ownership_transfer WHERE id = $1").bind(id).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn delete_transfers_between(&self, from_userid: &str, to_userid: &str) -> sqlx::Result<()> {
+		query("DELETE FROM ownership_transfer WHERE from_userid = $1 AND to_userid = $2")
+			.bind(from_userid).bind(to_userid)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn count_users(&self) -> sqlx::Result<i64> {
+		query_scalar(r#"SELECT COUNT(*) FROM "user""#).fetch_one(&self.db_pool).await
+	}
+
+	async fn count_rooms(&self) -> sqlx::Result<i64> {
+		query_scalar("SELECT COUNT(*) FROM room").fetch_one(&self.db_pool).await
+	}
+
+	async fn count_messages(&self, since: Option<DateTime<Utc>>) -> sqlx::Result<i64> {
+		query_scalar("SELECT COUNT(*) FROM message WHERE $1::timestamptz IS NULL OR timestamp >= $1")
+			.bind(since)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn message_bytes_by_type(&self) -> sqlx::Result<Vec<(String, i64)>> {
+		query_as("SELECT msg_type, COALESCE(SUM(OCTET_LENGTH(msg_text)), 0)::BIGINT FROM message GROUP BY msg_type ORDER BY msg_type")
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn message_bytes_by_room(&self) -> sqlx::Result<Vec<(String, i64)>> {
+		query_as("SELECT room.roomid, COALESCE(SUM(OCTET_LENGTH(message.msg_text)), 0)::BIGINT
+			FROM room LEFT JOIN message ON message.room = room.id GROUP BY room.id ORDER BY 2 DESC")
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn database_bytes(&self) -> sqlx::Result<i64> {
+		query_scalar("SELECT pg_database_size(current_database())").fetch_one(&self.db_pool).await
+	}
+
+	async fn compact(&self) -> sqlx::Result<()> {
+		query("VACUUM FULL").execute(&self.db_pool).await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,369 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::migrate::{MigrateDatabase, Migrator};
+use sqlx::{query, query_as, query_scalar, FromRow, Pool, Sqlite, SqlitePool};
+use tracing::{info, warn};
+use realm_shared::health::{self, Health};
+use crate::events::{Event, EventLog};
+use crate::store::{message_columns, ChatStore};
+use crate::types::{AuditAction, AuditLogEntry, AuditLogFilter, Ban, FromRows, Message, OwnershipTransfer, Presence, PresenceStatus, Report, ReportCategory, ReportResolution, ReportStatus, Room, Timeout, User};
+
+/// The migrations under `migrations/sqlite/`, run on startup and checked by the `health` RPC
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+const FETCH_MESSAGE: &str = "SELECT message.*,
+        room.id AS 'room_id', room.roomid AS 'room_roomid', room.admin_only_send AS 'room_admin_only_send', room.admin_only_view AS 'room_admin_only_view',
+        user.id AS 'user_id', user.userid AS 'user_userid', user.name AS 'user_name', user.owner AS 'user_owner', user.admin AS 'user_admin', user.nickname AS 'user_nickname'
+	    FROM message INNER JOIN room ON message.room = room.id INNER JOIN user ON message.user = user.id";
+
+pub struct SqliteStore {
+	db_pool: Pool<Sqlite>,
+	events: EventLog,
+}
+
+impl SqliteStore {
+	pub fn new(db_pool: Pool<Sqlite>) -> SqliteStore {
+		SqliteStore { db_pool, events: EventLog::default() }
+	}
+
+	/// Creates the database file if it's missing, connects, and brings it up to date
+	pub async fn open(database_url: &str) -> anyhow::Result<SqliteStore> {
+		if !Sqlite::database_exists(database_url).await.unwrap_or(false) {
+			info!("Creating database {}", database_url);
+			Sqlite::create_database(database_url).await?;
+		} else {
+			warn!("Database already exists");
+		} // TODO: Do in Docker with Sqlx-cli
+
+		let store = SqliteStore::new(SqlitePool::connect(database_url).await?);
+		store.migrate().await?;
+		Ok(store)
+	}
+
+	pub async fn migrate(&self) -> anyhow::Result<()> {
+		info!("Running migrations...");
+		MIGRATOR.run(&self.db_pool).await?; // TODO: Do in Docker with Sqlx-cli
+		info!("Migrations complete!");
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl ChatStore for SqliteStore {
+	async fn health(&self) -> Health {
+		health::check(&self.db_pool, &MIGRATOR).await
+	}
+
+	async fn close(&self) {
+		self.db_pool.close().await
+	}
+
+	async fn push_event(&self, event: Event) -> u32 {
+		self.events.push(event).await
+	}
+
+	async fn get_events_since(&self, index: u32) -> Vec<(u32, Event)> {
+		self.events.since(index).await
+	}
+
+	async fn count_events(&self) -> usize {
+		self.events.count().await
+	}
+
+	async fn get_user(&self, userid: &str) -> sqlx::Result<User> {
+		query_as!(User, "SELECT id, userid, name, owner, admin, nickname FROM user WHERE userid = ?", userid).fetch_one(&self.db_pool).await
+	}
+
+	async fn get_users(&self) -> sqlx::Result<Vec<User>> {
+		query_as!(User, "SELECT id, userid, name, owner, admin, nickname FROM user ORDER BY userid").fetch_all(&self.db_pool).await
+	}
+
+	async fn add_user(&self, userid: &str, name: &str, owner: bool, admin: bool) -> sqlx::Result<()> {
+		query!("INSERT INTO user (userid, name, owner, admin) VALUES (?,?,?,?)", userid, name, owner, admin).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn delete_user(&self, userid: &str) -> sqlx::Result<bool> {
+		let result = query!("DELETE FROM user WHERE userid = ?", userid).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn count_owners(&self) -> sqlx::Result<i64> {
+		Ok(query!("SELECT COUNT(*) AS owners FROM user WHERE owner = true").fetch_one(&self.db_pool).await?.owners)
+	}
+
+	async fn set_name(&self, userid: &str, name: &str) -> sqlx::Result<()> {
+		query!("UPDATE user SET name = ? WHERE userid = ?", name, userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_nickname(&self, userid: &str, nickname: Option<&str>) -> sqlx::Result<()> {
+		query!("UPDATE user SET nickname = ? WHERE userid = ?", nickname, userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_admin(&self, userid: &str, admin: bool) -> sqlx::Result<bool> {
+		let result = query!("UPDATE user SET admin = ? WHERE userid = ?", admin, userid).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn set_owner(&self, userid: &str, owner: bool) -> sqlx::Result<bool> {
+		let result = query!("UPDATE user SET owner = ? WHERE userid = ?", owner, userid).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn set_custom_status(&self, userid: &str, custom_status: Option<&str>) -> sqlx::Result<()> {
+		query!("UPDATE user SET custom_status = ? WHERE userid = ?", custom_status, userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn set_last_seen(&self, userid: &str, last_seen: Option<DateTime<Utc>>) -> sqlx::Result<()> {
+		query!("UPDATE user SET last_seen = ? WHERE userid = ?", last_seen, userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn get_presences(&self) -> sqlx::Result<Vec<Presence>> {
+		let rows = query!(r#"SELECT userid, last_seen AS "last_seen: DateTime<Utc>", custom_status FROM user"#)
+			.fetch_all(&self.db_pool).await?;
+
+		Ok(rows.into_iter().map(|row| Presence {
+			userid: row.userid,
+			status: PresenceStatus::Offline,
+			custom_status: row.custom_status,
+			last_seen: row.last_seen,
+		}).collect())
+	}
+
+	async fn get_room(&self, roomid: &str) -> sqlx::Result<Room> {
+		query_as!(Room, "SELECT * FROM room WHERE roomid = ?", roomid).fetch_one(&self.db_pool).await
+	}
+
+	async fn get_rooms(&self) -> sqlx::Result<Vec<Room>> {
+		query_as!(Room, "SELECT * FROM room ORDER BY roomid").fetch_all(&self.db_pool).await
+	}
+
+	async fn add_room(&self, room: &Room) -> sqlx::Result<i64> {
+		let result = query!("INSERT INTO room (roomid, admin_only_send, admin_only_view) VALUES (?,?,?)",
+			room.roomid, room.admin_only_send, room.admin_only_view)
+			.execute(&self.db_pool).await?;
+		Ok(result.last_insert_rowid())
+	}
+
+	async fn delete_room(&self, roomid: &str) -> sqlx::Result<()> {
+		query!("DELETE FROM room WHERE roomid = ?", roomid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn add_message(&self, message: &Message) -> sqlx::Result<i64> {
+		let (msg_type, msg_text, emoji, referencing_id) = message_columns(&message.data);
+		let result = query!("INSERT INTO message (timestamp, user, room, msg_type, msg_text, emoji, referencing_id) VALUES (?, ?, ?, ?, ?, ?, ?)",
+			message.timestamp, message.user.id, message.room.id, msg_type, msg_text, emoji, referencing_id)
+			.execute(&self.db_pool).await?;
+		Ok(result.last_insert_rowid())
+	}
+
+	async fn get_message(&self, id: i64) -> sqlx::Result<Message> {
+		let row = sqlx::query(&format!("{}{}", FETCH_MESSAGE, " WHERE message.id = ?"))
+			.bind(id)
+			.fetch_one(&self.db_pool).await?;
+		Message::from_row(&row)
+	}
+
+	async fn get_messages_since(&self, id: i64, limit: u32, admin_only_view: bool) -> sqlx::Result<Vec<Message>> {
+		let rows = sqlx::query(&format!("{}{}", FETCH_MESSAGE, " WHERE message.id > ? AND (? OR NOT room.admin_only_view) ORDER BY message.id LIMIT ?"))
+			.bind(id)
+			.bind(admin_only_view)
+			.bind(limit as i64)
+			.fetch_all(&self.db_pool).await?;
+		Message::from_rows(rows)
+	}
+
+	async fn get_direct_replies(&self, head: i64) -> sqlx::Result<Vec<Message>> {
+		let rows = sqlx::query(&format!("{}{}", FETCH_MESSAGE, " WHERE message.referencing_id = ? ORDER BY message.id"))
+			.bind(head)
+			.fetch_all(&self.db_pool).await?;
+		Message::from_rows(rows)
+	}
+
+	async fn is_banned(&self, userid: &str, now: DateTime<Utc>) -> sqlx::Result<bool> {
+		let record = query!("SELECT EXISTS (SELECT 1 FROM banned WHERE userid = ? AND (expires IS NULL OR expires > ?)) AS is_banned", userid, now)
+			.fetch_one(&self.db_pool).await?;
+		Ok(record.is_banned != 0)
+	}
+
+	async fn get_bans(&self, now: DateTime<Utc>) -> sqlx::Result<Vec<Ban>> {
+		query_as!(
+			Ban, r#"SELECT id, userid, reason, banned_by, created AS "created: DateTime<Utc>", expires AS "expires: DateTime<Utc>"
+			FROM banned WHERE expires IS NULL OR expires > ? ORDER BY id"#, now)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn add_ban(&self, ban: &Ban) -> sqlx::Result<i64> {
+		let result = query!("INSERT INTO banned (userid, reason, banned_by, created, expires) VALUES (?,?,?,?,?)",
+			ban.userid, ban.reason, ban.banned_by, ban.created, ban.expires)
+			.execute(&self.db_pool).await?;
+		Ok(result.last_insert_rowid())
+	}
+
+	async fn delete_ban(&self, userid: &str) -> sqlx::Result<bool> {
+		let result = query!("DELETE FROM banned WHERE userid = ?", userid).execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn delete_expired_bans(&self, now: DateTime<Utc>) -> sqlx::Result<u64> {
+		let result = query!("DELETE FROM banned WHERE expires IS NOT NULL AND expires <= ?", now)
+			.execute(&self.db_pool).await?;
+		Ok(result.rows_affected())
+	}
+
+	async fn get_timeout(&self, userid: &str, now: DateTime<Utc>) -> sqlx::Result<Option<Timeout>> {
+		query_as!(
+			Timeout, r#"SELECT id, userid, reason, issued_by, created AS "created: DateTime<Utc>", expires AS "expires: DateTime<Utc>"
+			FROM timeout WHERE userid = ? AND expires > ? ORDER BY expires DESC"#, userid, now)
+			.fetch_optional(&self.db_pool).await
+	}
+
+	async fn add_timeout(&self, timeout: &Timeout) -> sqlx::Result<i64> {
+		let result = query!("INSERT INTO timeout (userid, reason, issued_by, created, expires) VALUES (?,?,?,?,?)",
+			timeout.userid, timeout.reason, timeout.issued_by, timeout.created, timeout.expires)
+			.execute(&self.db_pool).await?;
+		Ok(result.last_insert_rowid())
+	}
+
+	async fn delete_timeout(&self, userid: &str) -> sqlx::Result<()> {
+		query!("DELETE FROM timeout WHERE userid = ?", userid).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn add_audit(&self, entry: &AuditLogEntry) -> sqlx::Result<i64> {
+		let result = query!("INSERT INTO audit_log (timestamp, actor, action, target, reason) VALUES (?,?,?,?,?)",
+			entry.timestamp, entry.actor, entry.action, entry.target, entry.reason)
+			.execute(&self.db_pool).await?;
+		Ok(result.last_insert_rowid())
+	}
+
+	async fn get_audit_log(&self, filter: &AuditLogFilter, before_id: i64, limit: u32) -> sqlx::Result<Vec<AuditLogEntry>> {
+		query_as!(
+			AuditLogEntry, r#"SELECT id, timestamp AS "timestamp: DateTime<Utc>", actor, action AS "action: AuditAction", target, reason
+			FROM audit_log
+			WHERE id < ? AND (? IS NULL OR actor = ?) AND (? IS NULL OR target = ?) AND (? IS NULL OR action = ?)
+			ORDER BY id DESC LIMIT ?"#,
+			before_id, filter.actor, filter.actor, filter.target, filter.target, filter.action, filter.action, limit)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn add_report(&self, report: &Report) -> sqlx::Result<i64> {
+		let result = query!("INSERT INTO report (created, reporter, target_userid, message_id, category, note, status, claimed_by, resolution, resolved_at)
+			VALUES (?,?,?,?,?,?,?,?,?,?)",
+			report.created, report.reporter, report.target_userid, report.message_id, report.category, report.note,
+			report.status, report.claimed_by, report.resolution, report.resolved_at)
+			.execute(&self.db_pool).await?;
+		Ok(result.last_insert_rowid())
+	}
+
+	async fn get_report(&self, id: i64) -> sqlx::Result<Report> {
+		query_as!(
+			Report, r#"SELECT id, created AS "created: DateTime<Utc>", reporter, target_userid, message_id,
+			category AS "category: ReportCategory", note, status AS "status: ReportStatus", claimed_by,
+			resolution AS "resolution: ReportResolution", resolved_at AS "resolved_at: DateTime<Utc>"
+			FROM report WHERE id = ?"#, id)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn get_reports(&self, status: Option<ReportStatus>) -> sqlx::Result<Vec<Report>> {
+		query_as!(
+			Report, r#"SELECT id, created AS "created: DateTime<Utc>", reporter, target_userid, message_id,
+			category AS "category: ReportCategory", note, status AS "status: ReportStatus", claimed_by,
+			resolution AS "resolution: ReportResolution", resolved_at AS "resolved_at: DateTime<Utc>"
+			FROM report WHERE (? IS NULL OR status = ?) ORDER BY id"#, status, status)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn claim_report(&self, id: i64, claimed_by: &str) -> sqlx::Result<bool> {
+		let result = query!("UPDATE report SET status = 'claimed', claimed_by = ?1 WHERE id = ?2 AND status <> 'resolved' AND (claimed_by IS NULL OR claimed_by = ?1)", claimed_by, id)
+			.execute(&self.db_pool).await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn resolve_report(&self, id: i64, resolved_by: &str, resolution: ReportResolution, resolved_at: DateTime<Utc>) -> sqlx::Result<()> {
+		query!("UPDATE report SET status = 'resolved', claimed_by = ?, resolution = ?, resolved_at = ? WHERE id = ?",
+			resolved_by, resolution, resolved_at, id)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn add_transfer(&self, transfer: &OwnershipTransfer) -> sqlx::Result<i64> {
+		let result = query!("INSERT INTO ownership_transfer (from_userid, to_userid, keep_ownership, created) VALUES (?,?,?,?)",
+			transfer.from_userid, transfer.to_userid, transfer.keep_ownership, transfer.created)
+			.execute(&self.db_pool).await?;
+		Ok(result.last_insert_rowid())
+	}
+
+	async fn get_transfer(&self, id: i64) -> sqlx::Result<OwnershipTransfer> {
+		query_as!(
+			OwnershipTransfer, r#"SELECT id, from_userid, to_userid, keep_ownership, created AS "created: DateTime<Utc>"
+			FROM ownership_transfer WHERE id = ?"#, id)
+			.fetch_one(&self.db_pool).await
+	}
+
+	async fn get_transfers(&self, userid: &str) -> sqlx::Result<Vec<OwnershipTransfer>> {
+		query_as!(
+			OwnershipTransfer, r#"SELECT id, from_userid, to_userid, keep_ownership, created AS "created: DateTime<Utc>"
+			FROM ownership_transfer WHERE from_userid = ? OR to_userid = ? ORDER BY id"#, userid, userid)
+			.fetch_all(&self.db_pool).await
+	}
+
+	async fn delete_transfer(&self, id: i64) -> sqlx::Result<()> {
+		query!("DELETE FROM ownership_transfer WHERE id = ?", id).execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn delete_transfers_between(&self, from_userid: &str, to_userid: &str) -> sqlx::Result<()> {
+		query!("DELETE FROM ownership_transfer WHERE from_userid = ? AND to_userid = ?", from_userid, to_userid)
+			.execute(&self.db_pool).await?;
+		Ok(())
+	}
+
+	async fn count_users(&self) -> sqlx::Result<i64> {
+		Ok(query!(r#"SELECT COUNT(*) AS "count!: i64" FROM user"#).fetch_one(&self.db_pool).await?.count)
+	}
+
+	async fn count_rooms(&self) -> sqlx::Result<i64> {
+		Ok(query!(r#"SELECT COUNT(*) AS "count!: i64" FROM room"#).fetch_one(&self.db_pool).await?.count)
+	}
+
+	async fn count_messages(&self, since: Option<DateTime<Utc>>) -> sqlx::Result<i64> {
+		Ok(query!(r#"SELECT COUNT(*) AS "count!: i64" FROM message WHERE ? IS NULL OR timestamp >= ?"#, since, since)
+			.fetch_one(&self.db_pool).await?.count)
+	}
+
+	async fn message_bytes_by_type(&self) -> sqlx::Result<Vec<(String, i64)>> {
+		Ok(query!(
+			r#"SELECT msg_type, COALESCE(SUM(LENGTH(CAST(msg_text AS BLOB))), 0) AS "bytes!: i64" FROM message GROUP BY msg_type ORDER BY msg_type"#)
+			.fetch_all(&self.db_pool).await?
+			.into_iter()
+			.map(|row| (row.msg_type, row.bytes))
+			.collect())
+	}
+
+	async fn message_bytes_by_room(&self) -> sqlx::Result<Vec<(String, i64)>> {
+		Ok(query!(
+			r#"SELECT room.roomid, COALESCE(SUM(LENGTH(CAST(message.msg_text AS BLOB))), 0) AS "bytes!: i64"
+			FROM room LEFT JOIN message ON message.room = room.id GROUP BY room.id ORDER BY 2 DESC"#)
+			.fetch_all(&self.db_pool).await?
+			.into_iter()
+			.map(|row| (row.roomid, row.bytes))
+			.collect())
+	}
+
+	async fn database_bytes(&self) -> sqlx::Result<i64> {
+		let page_count: i64 = query_scalar("PRAGMA page_count").fetch_one(&self.db_pool).await?;
+		let page_size: i64 = query_scalar("PRAGMA page_size").fetch_one(&self.db_pool).await?;
+		Ok(page_count * page_size)
+	}
+
+	async fn compact(&self) -> sqlx::Result<()> {
+		query("VACUUM").execute(&self.db_pool).await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
+use tokio::time::interval;
+use tracing::{error, info};
+use crate::presence::{self, PresenceTracker};
+use crate::store::ChatStore;
+
+/// Periodically removes bans whose expiry has passed, so `banned` only holds active bans.
+pub async fn lift_expired_bans(store: Arc<dyn ChatStore>, period: Duration) {
+	let mut ticker = interval(period);
+
+	loop {
+		ticker.tick().await;
+
+		match store.delete_expired_bans(Utc::now()).await {
+			Ok(lifted) => {
+				if lifted > 0 {
+					info!("Lifted {} expired ban(s)", lifted);
+				}
+			}
+			Err(e) => error!("Error lifting expired bans: {e:?}"),
+		}
+	}
+}
+
+/// Periodically moves users without recent heartbeats to idle, and then offline.
+pub async fn sweep_presence(store: Arc<dyn ChatStore>, tracker: PresenceTracker, period: Duration, idle_after: Duration, offline_after: Duration) {
+	let mut ticker = interval(period);
+
+	loop {
+		ticker.tick().await;
+
+		let changed = tracker.sweep(idle_after, offline_after).await;
+		presence::publish(store.as_ref(), changed).await;
+	}
+}
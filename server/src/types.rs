@@ -1,8 +1,9 @@
+use std::time::Duration;
 use chrono::{DateTime, Utc};
-use sqlx::{FromRow, Row};
-use sqlx::sqlite::SqliteRow;
+use sqlx::{ColumnIndex, Decode, FromRow, Row, Type};
 use tarpc::serde::{Deserialize, Serialize};
 
+use realm_shared::health::Health;
 use realm_shared::types::ErrorCode;
 use crate::events::Event;
 use crate::types::MessageData::*;
@@ -12,17 +13,25 @@ pub trait RealmChat {
 	async fn test(name: String) -> String;
 	
 	async fn get_info() -> ServerInfo;
+	async fn health() -> Health;
 	async fn is_user_admin(stoken: String) -> bool;
 	async fn is_user_owner(stoken: String) -> bool;
-	async fn poll_events_since(index: u32) -> Vec<(u32, Event)>;
+	async fn poll_events_since(stoken: String, userid: String, index: u32) -> Result<Vec<(u32, Event)>, ErrorCode>;
 	async fn join_server(stoken: String, userid: String) -> Result<User, ErrorCode>;
 	async fn leave_server(stoken: String, userid: String) -> Result<(), ErrorCode>;
+	async fn heartbeat(stoken: String, userid: String) -> Result<(), ErrorCode>;
+	async fn set_presence(stoken: String, userid: String, status: PresenceStatus, custom_status: Option<String>) -> Result<Presence, ErrorCode>;
+	async fn get_presences(stoken: String, userid: String) -> Result<Vec<Presence>, ErrorCode>;
+	async fn sync_block_list(stoken: String, userid: String) -> Result<Vec<String>, ErrorCode>; //NOTE: Call after changing blocks on the auth server
+	async fn set_nickname(stoken: String, userid: String, nickname: Option<String>) -> Result<User, ErrorCode>;
+	async fn refresh_display_name(stoken: String, userid: String) -> Result<User, ErrorCode>;
 
 	//NOTE: Any user authorized as themselves
 	async fn send_message(stoken: String, message: Message) -> Result<Message, ErrorCode>;
-	async fn start_typing(stoken: String, userid: String, roomid: String) -> ErrorCode;
-	async fn stop_typing(stoken: String, userid: String, roomid: String) -> ErrorCode;
-	async fn keep_typing(stoken: String, userid: String, roomid: String) -> ErrorCode; //NOTE: If a keep alive hasn't been received in 5 seconds, stop typing
+	async fn start_typing(stoken: String, userid: String, roomid: String) -> Result<(), ErrorCode>;
+	async fn stop_typing(stoken: String, userid: String, roomid: String) -> Result<(), ErrorCode>;
+	async fn keep_typing(stoken: String, userid: String, roomid: String) -> Result<(), ErrorCode>; //NOTE: If a keep alive hasn't been received in 5 seconds, stop typing
+	async fn get_timeout(stoken: String, userid: String) -> Result<Option<Timeout>, ErrorCode>;
 
 	//NOTE: Any user can call, if they are in the server
 	async fn get_message(stoken: String, userid: String, id: i64) -> Result<Message, ErrorCode>;
@@ -38,22 +47,78 @@ pub trait RealmChat {
 	async fn promote_user(stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode>;
 	async fn demote_user(stoken: String, owner_userid: String, userid: String) -> Result<(), ErrorCode>;
 	async fn kick_user(stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode>;
-	async fn ban_user(stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode>;
+	async fn ban_user(stoken: String, admin_userid: String, userid: String, reason: String, expires: Option<DateTime<Utc>>) -> Result<(), ErrorCode>;
 	async fn pardon_user(stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode>;
+	async fn transfer_ownership(stoken: String, owner_userid: String, userid: String, keep_ownership: bool) -> Result<OwnershipTransfer, ErrorCode>; //NOTE: keep_ownership makes them a co-owner
+	async fn get_ownership_transfers(stoken: String, userid: String) -> Result<Vec<OwnershipTransfer>, ErrorCode>;
+	async fn accept_ownership_transfer(stoken: String, userid: String, transfer_id: i64) -> Result<User, ErrorCode>;
+	async fn decline_ownership_transfer(stoken: String, userid: String, transfer_id: i64) -> Result<(), ErrorCode>;
+	async fn step_down_as_owner(stoken: String, owner_userid: String) -> Result<(), ErrorCode>;
+	async fn list_bans(stoken: String, admin_userid: String) -> Result<Vec<Ban>, ErrorCode>;
+	async fn timeout_user(stoken: String, admin_userid: String, userid: String, duration: Duration, reason: String) -> Result<Timeout, ErrorCode>;
+	async fn remove_timeout(stoken: String, admin_userid: String, userid: String) -> Result<(), ErrorCode>;
+	async fn report_message(stoken: String, userid: String, message_id: i64, category: ReportCategory, note: String) -> Result<Report, ErrorCode>;
+	async fn report_user(stoken: String, userid: String, target_userid: String, category: ReportCategory, note: String) -> Result<Report, ErrorCode>;
+	async fn list_reports(stoken: String, admin_userid: String, status: Option<ReportStatus>) -> Result<Vec<Report>, ErrorCode>;
+	async fn claim_report(stoken: String, admin_userid: String, report_id: i64) -> Result<Report, ErrorCode>;
+	async fn resolve_report(stoken: String, admin_userid: String, report_id: i64, action: ReportAction, reason: String) -> Result<Report, ErrorCode>;
+	async fn get_audit_log(stoken: String, admin_userid: String, filter: AuditLogFilter, before_id: Option<i64>, limit: u32) -> Result<Vec<AuditLogEntry>, ErrorCode>;
+	async fn get_stats(stoken: String, admin_userid: String) -> Result<ServerStats, ErrorCode>;
+	async fn compact_database(stoken: String, owner_userid: String) -> Result<(), ErrorCode>; //NOTE: Locks the database while it runs
 }
 
+/// Bumped whenever a change to `RealmChat` or the types it carries would break existing clients.
+/// 2: stokens are issued by the user's auth server (`get_server_token`), so version 1 clients' stokens never validate
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Optional parts of `RealmChat`, so clients can tell what a server supports before calling it
+pub const FEATURES: &[&str] = &["presence", "typing", "replies", "reports", "audit_log", "ownership_transfer", "timeouts", "stats", "health"];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerInfo {
 	pub server_id: String,
+	pub version: String, //NOTE: realm_server's crate version
+	pub protocol_version: u32,
+	pub features: Vec<String>,
+}
+
+impl ServerInfo {
+	pub fn supports(&self, feature: &str) -> bool {
+		self.features.iter().any(|f| f.eq(feature))
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStats {
+	pub users_online: Option<u32>, //NOTE: None when read from the database of a stopped server
+	pub users_total: i64,
+	pub rooms_total: i64,
+	pub messages_total: i64,
+	pub messages_per_minute: f64, //NOTE: Averaged over the last 15 minutes
+	pub storage: StorageStats,
+	pub process: Option<ProcessStats>, //NOTE: None offline, or where /proc isn't available
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageStats {
+	pub database_bytes: i64,
+	pub by_message_type: Vec<(String, i64)>, //NOTE: msg_type -> bytes of message text
+	pub by_room: Vec<(String, i64)>, //NOTE: room.roomid -> bytes of message text
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessStats {
+	pub uptime: Duration,
+	pub cpu_percent: f64, //NOTE: Of one core, sampled over half a second
+	pub memory_bytes: u64, //NOTE: Resident set size
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
 	pub id: i64,
 	pub timestamp: DateTime<Utc>,
 	pub user: User,
 	pub room: Room,
-	#[sqlx(flatten)]
 	pub data: MessageData,
 }
 
@@ -61,8 +126,8 @@ pub trait FromRows<R: Row>: Sized {
 	fn from_rows(rows: Vec<R>) -> Result<Vec<Self>, sqlx::Error>;
 }
 
-impl FromRows<SqliteRow> for Message {
-	fn from_rows(rows: Vec<SqliteRow>) -> sqlx::Result<Vec<Self>> {
+impl<R: Row> FromRows<R> for Message where Message: for<'r> FromRow<'r, R> {
+	fn from_rows(rows: Vec<R>) -> sqlx::Result<Vec<Self>> {
 		let mut messages = Vec::new();
 
 		for row in rows {
@@ -73,8 +138,15 @@ impl FromRows<SqliteRow> for Message {
 	}
 }
 
-impl FromRow<'_, SqliteRow> for Message {
-	fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+impl<'r, R: Row> FromRow<'r, R> for Message
+where
+	&'r str: ColumnIndex<R> + Decode<'r, R::Database> + Type<R::Database>,
+	i64: Decode<'r, R::Database> + Type<R::Database>,
+	bool: Decode<'r, R::Database> + Type<R::Database>,
+	String: Decode<'r, R::Database> + Type<R::Database>,
+	DateTime<Utc>: Decode<'r, R::Database> + Type<R::Database>,
+{
+	fn from_row(row: &'r R) -> sqlx::Result<Self> {
 		Ok(Self {
 			id: row.try_get("id")?,
 			timestamp: row.try_get("timestamp")?,
@@ -82,6 +154,7 @@ impl FromRow<'_, SqliteRow> for Message {
 				id: row.try_get("user_id")?,
 				userid: row.try_get("user_userid")?,
 				name: row.try_get("user_name")?,
+				nickname: row.try_get("user_nickname")?,
 				owner: row.try_get("user_owner")?,
 				admin: row.try_get("user_admin")?,
 			},
@@ -160,9 +233,32 @@ pub struct Redaction {
 pub struct User {
 	pub id: i64,
 	pub userid: String,
-	pub name: String,
+	pub name: String, //NOTE: Display name from the user's auth server
 	pub owner: bool,
 	pub admin: bool,
+	pub nickname: Option<String>, //NOTE: Per-server override of name
+}
+
+impl User {
+	pub fn display_name(&self) -> &str {
+		self.nickname.as_deref().unwrap_or(&self.name)
+	}
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum PresenceStatus {
+	Online,
+	Idle,
+	DoNotDisturb,
+	Offline,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Presence {
+	pub userid: String,
+	pub status: PresenceStatus,
+	pub custom_status: Option<String>,
+	pub last_seen: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
@@ -173,6 +269,128 @@ pub struct Room {
 	pub admin_only_view: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct Ban {
+	pub id: i64,
+	pub userid: String,
+	pub reason: String,
+	pub banned_by: String,
+	pub created: DateTime<Utc>,
+	pub expires: Option<DateTime<Utc>>, //NOTE: None is a permanent ban
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct Timeout {
+	pub id: i64,
+	pub userid: String,
+	pub reason: String,
+	pub issued_by: String,
+	pub created: DateTime<Utc>,
+	pub expires: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum AuditAction {
+	PromoteUser,
+	DemoteUser,
+	KickUser,
+	BanUser,
+	PardonUser,
+	TimeoutUser,
+	RemoveTimeout,
+	CreateRoom,
+	DeleteRoom,
+	DeleteMessage,
+	ResolveReport,
+	TransferOwnership,
+	AcceptOwnership,
+	StepDownAsOwner,
+	RecoverOwnership,
+	ClaimReport,
+	CompactDatabase,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct AuditLogEntry {
+	pub id: i64,
+	pub timestamp: DateTime<Utc>,
+	pub actor: String,
+	pub action: AuditAction,
+	pub target: String,
+	pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuditLogFilter {
+	pub actor: Option<String>,
+	pub target: Option<String>,
+	pub action: Option<AuditAction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct OwnershipTransfer {
+	pub id: i64,
+	pub from_userid: String,
+	pub to_userid: String,
+	pub keep_ownership: bool,
+	pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ReportCategory {
+	Spam,
+	Harassment,
+	HateSpeech,
+	Nsfw,
+	Impersonation,
+	Other,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ReportStatus {
+	Open,
+	Claimed,
+	Resolved,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ReportResolution {
+	Dismissed,
+	DeletedMessage,
+	TimedOut,
+	Kicked,
+	Banned,
+}
+
+/// What a moderator does to close a report
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ReportAction {
+	Dismiss,
+	DeleteMessage,
+	Timeout(Duration),
+	Kick,
+	Ban(Option<DateTime<Utc>>), //NOTE: None is a permanent ban
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct Report {
+	pub id: i64,
+	pub created: DateTime<Utc>,
+	pub reporter: String,
+	pub target_userid: String,
+	pub message_id: Option<i64>,
+	pub category: ReportCategory,
+	pub note: String,
+	pub status: ReportStatus,
+	pub claimed_by: Option<String>,
+	pub resolution: Option<ReportResolution>,
+	pub resolved_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReplyChain {
 	pub message: Message,
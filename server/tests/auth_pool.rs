@@ -0,0 +1,168 @@
+//! `AuthPool` against a real auth server on loopback, backed by its `MemoryStore`, that tests can take down.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use futures::StreamExt;
+use tarpc::client::RpcError;
+use tarpc::server::{BaseChannel, Channel};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use realm_auth::config::AuthConfig;
+use realm_auth::mailer::{Mail, Mailer, Templates};
+use realm_auth::metrics::AuthServerMetrics;
+use realm_auth::server::RealmAuthServer;
+use realm_auth::stoken::StokenKey;
+use realm_auth::store::MemoryStore;
+use realm_auth::types::RealmAuth;
+use realm_server::auth_pool::AuthPool;
+use realm_server::config::AuthSection;
+use realm_server::discovery::{AuthDiscovery, StaticResolver};
+use realm_shared::net::{self, Codec};
+
+const DOMAIN: &str = "example.com";
+
+struct NoMail;
+
+#[async_trait]
+impl Mailer for NoMail {
+	async fn send(&self, _: Mail) -> anyhow::Result<()> {
+		Ok(())
+	}
+}
+
+/// Serves an auth server on `addr` until the returned task is aborted, which drops every connection
+async fn auth_server(addr: SocketAddr) -> (SocketAddr, JoinHandle<()>) {
+	let templates = Templates::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../auth/templates"), "en").unwrap();
+	let server = RealmAuthServer::new(
+		&AuthConfig::default(), addr, Arc::new(MemoryStore::new()), Arc::new(NoMail), Arc::new(templates), StokenKey::generate(), Arc::new(AuthServerMetrics::default()));
+
+	let (addr, listener) = net::listen(addr, None, vec![Codec::Bincode], 1024 * 1024).await.unwrap();
+	let serving = tokio::spawn(listener
+		.map(BaseChannel::with_defaults)
+		.for_each_concurrent(None, move |channel| channel.execute(server.clone().serve()).for_each(|response| response)));
+
+	(addr, serving)
+}
+
+/// A free port on loopback, with nothing listening on it
+async fn closed_port() -> SocketAddr {
+	tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap()
+}
+
+fn pool(addr: SocketAddr) -> AuthPool {
+	let resolver = StaticResolver {
+		endpoints: HashMap::from([(DOMAIN.to_string(), addr.to_string().parse().unwrap())]),
+		next: None,
+	};
+	let config = AuthSection { call_timeout: 1, backoff_initial: 1, backoff_max: 60, ..AuthSection::default() };
+
+	AuthPool::new(AuthDiscovery::new(Arc::new(resolver), 5052, Duration::from_secs(60)), &config, None, Codec::Bincode, 1024 * 1024)
+}
+
+async fn ping(pool: &AuthPool) -> bool {
+	match pool.get(DOMAIN).await {
+		Some(client) => client.test(pool.context(), "ping".to_string()).await.is_ok(),
+		None => false,
+	}
+}
+
+#[tokio::test]
+async fn backoff_doubles_up_to_the_max() {
+	let pool = pool(closed_port().await);
+
+	let schedule: Vec<u64> = (1..=8).map(|failures| pool.backoff(failures).as_secs()).collect();
+	assert_eq!(schedule, [1, 2, 4, 8, 16, 32, 60, 60]);
+	assert_eq!(pool.backoff(u32::MAX), Duration::from_secs(60));
+}
+
+#[tokio::test]
+async fn no_dialing_while_backing_off() {
+	let pool = pool(closed_port().await);
+
+	assert!(pool.get(DOMAIN).await.is_none());
+	assert!(pool.get(DOMAIN).await.is_none());
+	assert_eq!((pool.metrics.connects.get(), pool.metrics.connect_failures.get()), (1, 1));
+
+	// The second failure backs off for 2s, not 1s
+	tokio::time::sleep(Duration::from_millis(1100)).await;
+	assert!(pool.get(DOMAIN).await.is_none());
+	assert_eq!(pool.metrics.connects.get(), 2);
+	tokio::time::sleep(Duration::from_millis(1100)).await;
+	assert!(pool.get(DOMAIN).await.is_none());
+	assert_eq!(pool.metrics.connects.get(), 2);
+}
+
+#[tokio::test]
+async fn clients_are_pooled_until_the_connection_fails() {
+	let (addr, _serving) = auth_server("127.0.0.1:0".parse().unwrap()).await;
+	let pool = pool(addr);
+
+	assert!(ping(&pool).await);
+	assert!(ping(&pool).await);
+	assert_eq!(pool.metrics.connects.get(), 1);
+
+	// Errors the server answered with leave the connection alone
+	pool.report_error(DOMAIN, &RpcError::DeadlineExceeded).await;
+	assert!(ping(&pool).await);
+	assert_eq!(pool.metrics.connects.get(), 1);
+
+	pool.report_error(DOMAIN, &RpcError::Shutdown).await;
+	assert!(ping(&pool).await);
+	assert_eq!(pool.metrics.connects.get(), 2);
+}
+
+#[tokio::test]
+async fn reconnects_after_the_auth_server_restarts() {
+	let (addr, serving) = auth_server("127.0.0.1:0".parse().unwrap()).await;
+	let pool = pool(addr);
+	assert!(ping(&pool).await);
+
+	serving.abort();
+	let _ = serving.await;
+	let client = pool.get(DOMAIN).await.unwrap();
+	let error = client.test(pool.context(), "ping".to_string()).await.unwrap_err();
+	pool.report_error(DOMAIN, &error).await;
+
+	let (_, _serving) = auth_server(addr).await;
+	assert!(ping(&pool).await);
+	assert_eq!(pool.metrics.connects.get(), 2);
+}
+
+#[tokio::test]
+async fn health_checks_drop_dead_connections() {
+	let (addr, serving) = auth_server("127.0.0.1:0".parse().unwrap()).await;
+	let pool = pool(addr);
+	assert!(ping(&pool).await);
+
+	serving.abort();
+	let _ = serving.await;
+	let health_check = tokio::spawn(pool.clone().health_check(Duration::from_millis(50)));
+	tokio::time::sleep(Duration::from_millis(200)).await;
+	health_check.abort();
+
+	let (_, _serving) = auth_server(addr).await;
+	assert!(ping(&pool).await);
+	assert_eq!(pool.metrics.connects.get(), 2);
+}
+
+#[tokio::test]
+async fn oversized_responses_drop_the_connection() {
+	// Answers anything with the header of a 1 GiB frame
+	let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let addr = listener.local_addr().unwrap();
+	tokio::spawn(async move {
+		let (mut stream, _) = listener.accept().await.unwrap();
+		let mut request = [0; 64];
+		let _ = stream.read(&mut request).await;
+		stream.write_all(&(1u32 << 30).to_be_bytes()).await.unwrap();
+		let _ = stream.read(&mut request).await;
+	});
+
+	let pool = pool(addr);
+	let client = pool.get(DOMAIN).await.unwrap();
+	let error = client.test(pool.context(), "ping".to_string()).await.unwrap_err();
+	assert!(!matches!(error, RpcError::DeadlineExceeded), "{error:?}");
+}
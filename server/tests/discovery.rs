@@ -0,0 +1,97 @@
+//! `AuthDiscovery` against a local resolver stub standing in for DNS.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use realm_server::config::{AuthSection, ResolverKind};
+use realm_server::discovery::{AuthDiscovery, AuthEndpoint, AuthResolver, StaticResolver};
+
+/// Answers like an SRV lookup would, counting every lookup that reaches it
+#[derive(Default)]
+struct StubResolver {
+	records: HashMap<String, AuthEndpoint>,
+	lookups: AtomicU32,
+}
+
+impl AuthResolver for StubResolver {
+	fn resolve<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Option<AuthEndpoint>> {
+		self.lookups.fetch_add(1, Ordering::Relaxed);
+		let endpoint = self.records.get(domain).cloned();
+		async move { endpoint }.boxed()
+	}
+}
+
+fn endpoint(s: &str) -> AuthEndpoint {
+	s.parse().unwrap()
+}
+
+fn stub(records: &[(&str, &str)]) -> Arc<StubResolver> {
+	Arc::new(StubResolver {
+		records: records.iter().map(|(domain, target)| (domain.to_string(), endpoint(target))).collect(),
+		..StubResolver::default()
+	})
+}
+
+#[tokio::test]
+async fn published_records_win_over_the_default_port() {
+	let resolver = stub(&[("example.com", "auth.example.com:7000")]);
+	let discovery = AuthDiscovery::new(resolver.clone(), 5052, Duration::from_secs(60));
+
+	assert_eq!(discovery.resolve("example.com").await, endpoint("auth.example.com:7000"));
+	assert_eq!(discovery.resolve("example.org").await, endpoint("example.org:5052"));
+	assert_eq!(resolver.lookups.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn endpoints_are_cached_until_they_expire() {
+	let resolver = stub(&[("example.com", "auth.example.com:7000")]);
+	let discovery = AuthDiscovery::new(resolver.clone(), 5052, Duration::from_millis(200));
+
+	discovery.resolve("example.com").await;
+	discovery.resolve("example.com").await;
+	discovery.resolve("example.org").await;
+	discovery.resolve("example.org").await;
+	assert_eq!(resolver.lookups.load(Ordering::Relaxed), 2);
+
+	tokio::time::sleep(Duration::from_millis(300)).await;
+	discovery.resolve("example.com").await;
+	assert_eq!(resolver.lookups.load(Ordering::Relaxed), 3);
+
+	discovery.forget("example.com").await;
+	discovery.resolve("example.com").await;
+	assert_eq!(resolver.lookups.load(Ordering::Relaxed), 4);
+}
+
+#[tokio::test]
+async fn overrides_win_over_the_resolver() {
+	let next = stub(&[("example.com", "auth.example.com:7000"), ("example.org", "auth.example.org:7000")]);
+	let overrides = StaticResolver {
+		endpoints: HashMap::from([("example.com".to_string(), endpoint("127.0.0.1:5052"))]),
+		next: Some(next.clone()),
+	};
+	let discovery = AuthDiscovery::new(Arc::new(overrides), 5052, Duration::from_secs(60));
+
+	assert_eq!(discovery.resolve("example.com").await, endpoint("127.0.0.1:5052"));
+	assert_eq!(discovery.resolve("example.org").await, endpoint("auth.example.org:7000"));
+	assert_eq!(next.lookups.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn overrides_from_config() {
+	let config = AuthSection {
+		port: 6000,
+		resolver: ResolverKind::Static,
+		endpoints: HashMap::from([("example.com".to_string(), "auth.example.com:7000".to_string())]),
+		..AuthSection::default()
+	};
+	let discovery = AuthDiscovery::from_config(&config).unwrap();
+
+	assert_eq!(discovery.resolve("example.com").await, endpoint("auth.example.com:7000"));
+	assert_eq!(discovery.resolve("example.org").await, endpoint("example.org:6000"));
+
+	let config = AuthSection { endpoints: HashMap::from([("example.com".to_string(), "auth.example.com".to_string())]), ..config };
+	assert!(config.endpoints().is_err());
+}
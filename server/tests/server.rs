@@ -0,0 +1,244 @@
+//! RPC logic run against `MemoryStore`, without a database or an auth server.
+//! Server tokens are seeded straight into the validation cache, and every auth domain resolves
+//! to a closed port, so anything that would ask an auth server gets its fallback.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use realm_server::blocks::BlockLists;
+use tarpc::context;
+use realm_server::auth_pool::AuthPool;
+use realm_server::config::ServerConfig;
+use realm_server::discovery::{AuthDiscovery, StaticResolver};
+use realm_server::limits::ConnectionLimiter;
+use realm_server::metrics::ChatMetrics;
+use realm_server::presence::PresenceTracker;
+use realm_server::server::{RealmChatServer, SharedState};
+use realm_server::store::{ChatStore, MemoryStore};
+use realm_server::types::{AuditAction, AuditLogFilter, Message, MessageData, RealmChat, Redaction, ReportAction, ReportCategory, ReportResolution, ReportStatus, Room};
+use realm_shared::net::Codec;
+use realm_shared::types::ErrorCode;
+
+const OWNER: &str = "@owner:localhost";
+const ADMIN: &str = "@admin:localhost";
+const ALICE: &str = "@alice:localhost";
+
+async fn server() -> (RealmChatServer, Arc<MemoryStore>) {
+	let mut config = ServerConfig::default();
+	config.server.id = "realm".to_string();
+	config.server.domain = "localhost".to_string();
+
+	let resolver = StaticResolver { endpoints: HashMap::new(), next: None };
+	let discovery = AuthDiscovery::new(Arc::new(resolver), 1, Duration::from_secs(60));
+	let auth_pool = AuthPool::new(discovery, &config.auth, None, Codec::Bincode, config.limits.max_frame_length);
+
+	let store = Arc::new(MemoryStore::new());
+	store.add_user(OWNER, "owner", true, true).await.unwrap();
+	store.add_user(ADMIN, "admin", false, true).await.unwrap();
+	store.add_user(ALICE, "alice", false, false).await.unwrap();
+
+	let shared = SharedState {
+		store: store.clone(),
+		presence: PresenceTracker::default(),
+		block_lists: BlockLists::new(&config.cache),
+		auth_pool,
+		connections: ConnectionLimiter::default(),
+		metrics: Arc::new(ChatMetrics::default()),
+	};
+	let server = RealmChatServer::new(&config, "127.0.0.1:5000".parse().unwrap(), shared);
+	(server, store)
+}
+
+async fn text(store: &MemoryStore, userid: &str, text: &str) -> Message {
+	if store.get_room("general").await.is_err() {
+		store.add_room(&Room { id: 0, roomid: "general".to_string(), admin_only_send: false, admin_only_view: false }).await.unwrap();
+	}
+	let room = store.get_room("general").await.unwrap();
+
+	Message { id: 0, timestamp: chrono::Utc::now(), user: store.get_user(userid).await.unwrap(), room, data: MessageData::Text(text.to_string()) }
+}
+
+/// Marks a server token as already validated for `userid`
+async fn stoken(server: &RealmChatServer, userid: &str) -> String {
+	let stoken = format!("stoken-for-{}", userid);
+	server.cache.insert(stoken.clone(), userid.to_string()).await;
+	stoken
+}
+
+#[tokio::test]
+async fn timeouts_reject_overflowing_durations() {
+	let (server, store) = server().await;
+	let admin = stoken(&server, ADMIN).await;
+	let timeout = |duration: Duration| server.clone().timeout_user(context::current(), admin.clone(), ADMIN.to_string(), ALICE.to_string(), duration, "spam".to_string());
+
+	assert_eq!(timeout(Duration::MAX).await, Err(ErrorCode::InvalidDuration));
+	assert_eq!(timeout(Duration::from_millis(i64::MAX as u64)).await, Err(ErrorCode::InvalidDuration));
+	assert_eq!(store.get_timeout(ALICE, chrono::Utc::now()).await.unwrap(), None);
+
+	let issued = timeout(Duration::from_secs(60)).await.unwrap();
+	assert_eq!(issued.expires - issued.created, chrono::Duration::seconds(60));
+}
+
+#[tokio::test]
+async fn claiming_reports_and_compacting_are_audited() {
+	let (server, _) = server().await;
+	let (alice, admin, owner) = (stoken(&server, ALICE).await, stoken(&server, ADMIN).await, stoken(&server, OWNER).await);
+
+	let report = server.clone().report_user(context::current(), alice, ALICE.to_string(), ADMIN.to_string(), ReportCategory::Spam, String::new()).await.unwrap();
+	server.clone().claim_report(context::current(), admin.clone(), ADMIN.to_string(), report.id).await.unwrap();
+	server.clone().compact_database(context::current(), owner, OWNER.to_string()).await.unwrap();
+
+	let log = server.clone().get_audit_log(context::current(), admin, ADMIN.to_string(), AuditLogFilter::default(), None, 10).await.unwrap();
+	let actions: Vec<_> = log.iter().map(|e| (e.actor.as_str(), e.action, e.target.as_str())).collect();
+	assert_eq!(actions, [(OWNER, AuditAction::CompactDatabase, "realm"), (ADMIN, AuditAction::ClaimReport, ADMIN)]);
+}
+
+#[tokio::test]
+async fn unknown_block_lists_fail_closed() {
+	let (server, store) = server().await;
+	let alice = stoken(&server, ALICE).await;
+	let send = |message: Message| server.clone().send_message(context::current(), alice.clone(), message);
+
+	// Signed in here, but their auth server is unreachable
+	let admin = stoken(&server, ADMIN).await;
+	server.clone().heartbeat(context::current(), admin, ADMIN.to_string()).await.unwrap();
+	assert_eq!(send(text(&store, ALICE, "hi @admin:localhost").await).await.unwrap().data, MessageData::Text("hi admin:localhost".to_string()));
+	assert_eq!(send(text(&store, ALICE, "hi @admin:localhost!").await).await.unwrap().data, MessageData::Text("hi admin:localhost!".to_string()));
+	assert_eq!(server.auth_pool.metrics.connects.get(), 1);
+
+	// Never signed in here, so nothing to ask their auth server with
+	assert_eq!(send(text(&store, ALICE, "hi @owner:localhost").await).await.unwrap().data, MessageData::Text("hi owner:localhost".to_string()));
+}
+
+#[tokio::test]
+async fn bans_lock_users_out_until_pardoned() {
+	let (server, store) = server().await;
+	let (alice, admin) = (stoken(&server, ALICE).await, stoken(&server, ADMIN).await);
+	let ban = |userid: &str| server.clone().ban_user(context::current(), admin.clone(), ADMIN.to_string(), userid.to_string(), "spam".to_string(), None);
+
+	assert_eq!(server.clone().ban_user(context::current(), alice.clone(), ALICE.to_string(), ADMIN.to_string(), String::new(), None).await, Err(ErrorCode::Unauthorized));
+	assert_eq!(ban(OWNER).await, Err(ErrorCode::Unauthorized));
+
+	ban(ALICE).await.unwrap();
+	assert_eq!(ban(ALICE).await, Err(ErrorCode::Banned));
+	assert!(store.get_user(ALICE).await.is_err());
+	assert_eq!(server.clone().get_rooms(context::current(), alice.clone(), ALICE.to_string()).await.unwrap_err(), ErrorCode::Banned);
+	let bans = server.clone().list_bans(context::current(), admin.clone(), ADMIN.to_string()).await.unwrap();
+	assert_eq!(bans.iter().map(|b| (b.userid.as_str(), b.banned_by.as_str())).collect::<Vec<_>>(), [(ALICE, ADMIN)]);
+
+	server.clone().pardon_user(context::current(), admin.clone(), ADMIN.to_string(), ALICE.to_string()).await.unwrap();
+	server.clone().get_rooms(context::current(), alice, ALICE.to_string()).await.unwrap();
+
+	let log = server.clone().get_audit_log(context::current(), admin, ADMIN.to_string(), AuditLogFilter::default(), None, 10).await.unwrap();
+	assert_eq!(log.iter().map(|e| e.action).collect::<Vec<_>>(), [AuditAction::PardonUser, AuditAction::BanUser]);
+}
+
+#[tokio::test]
+async fn timeouts_mute_users_until_removed() {
+	let (server, store) = server().await;
+	let (alice, admin) = (stoken(&server, ALICE).await, stoken(&server, ADMIN).await);
+	let timeout = |stoken: &str, admin: &str, userid: &str| server.clone().timeout_user(
+		context::current(), stoken.to_string(), admin.to_string(), userid.to_string(), Duration::from_secs(60), "spam".to_string());
+	let send = || async { server.clone().send_message(context::current(), alice.clone(), text(&store, ALICE, "hi").await).await };
+
+	assert_eq!(timeout(&alice, ALICE, ADMIN).await.unwrap_err(), ErrorCode::Unauthorized);
+	assert_eq!(timeout(&admin, ADMIN, OWNER).await.unwrap_err(), ErrorCode::Unauthorized);
+
+	let issued = timeout(&admin, ADMIN, ALICE).await.unwrap();
+	assert_eq!(send().await.unwrap_err(), ErrorCode::TimedOut);
+	assert_eq!(server.clone().get_timeout(context::current(), alice.clone(), ALICE.to_string()).await.unwrap(), Some(issued));
+
+	server.clone().remove_timeout(context::current(), admin, ADMIN.to_string(), ALICE.to_string()).await.unwrap();
+	send().await.unwrap();
+	assert_eq!(server.clone().get_timeout(context::current(), alice, ALICE.to_string()).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn reports_are_resolved_once_by_whoever_claimed_them() {
+	let (server, store) = server().await;
+	let (alice, admin, owner) = (stoken(&server, ALICE).await, stoken(&server, ADMIN).await, stoken(&server, OWNER).await);
+	let resolve = |stoken: &str, admin: &str, id: i64| server.clone().resolve_report(
+		context::current(), stoken.to_string(), admin.to_string(), id, ReportAction::DeleteMessage, "rude".to_string());
+
+	server.clone().send_message(context::current(), owner.clone(), text(&store, OWNER, "rude").await).await.unwrap();
+	let message = store.get_messages_since(0, 1, false).await.unwrap().remove(0);
+	let report = server.clone().report_message(context::current(), alice.clone(), ALICE.to_string(), message.id, ReportCategory::Harassment, String::new()).await.unwrap();
+	assert_eq!((report.target_userid.as_str(), report.message_id, report.status), (OWNER, Some(message.id), ReportStatus::Open));
+	assert_eq!(server.clone().list_reports(context::current(), alice, ALICE.to_string(), None).await.unwrap_err(), ErrorCode::Unauthorized);
+
+	let claimed = server.clone().claim_report(context::current(), admin.clone(), ADMIN.to_string(), report.id).await.unwrap();
+	assert_eq!((claimed.status, claimed.claimed_by.as_deref()), (ReportStatus::Claimed, Some(ADMIN)));
+	let claim = |stoken: &str, admin: &str| server.clone().claim_report(context::current(), stoken.to_string(), admin.to_string(), report.id);
+	assert_eq!(claim(&owner, OWNER).await.unwrap_err(), ErrorCode::ReportAlreadyClaimed);
+	assert_eq!(claim(&admin, ADMIN).await.unwrap().claimed_by.as_deref(), Some(ADMIN));
+	assert_eq!(resolve(&owner, OWNER, report.id).await.unwrap_err(), ErrorCode::Unauthorized);
+
+	let resolved = resolve(&admin, ADMIN, report.id).await.unwrap();
+	assert_eq!((resolved.status, resolved.resolution), (ReportStatus::Resolved, Some(ReportResolution::DeletedMessage)));
+	let since = server.clone().get_messages_since(context::current(), admin.clone(), ADMIN.to_string(), message.id).await.unwrap();
+	assert_eq!(since.iter().map(|m| &m.data).collect::<Vec<_>>(), [&MessageData::Redaction(Redaction { referencing_id: message.id })]);
+
+	assert_eq!(resolve(&admin, ADMIN, report.id).await.unwrap_err(), ErrorCode::ReportAlreadyResolved);
+	assert_eq!(server.clone().claim_report(context::current(), admin.clone(), ADMIN.to_string(), report.id).await.unwrap_err(), ErrorCode::ReportAlreadyResolved);
+	let open = server.clone().list_reports(context::current(), admin, ADMIN.to_string(), Some(ReportStatus::Open)).await.unwrap();
+	assert!(open.is_empty());
+}
+
+#[tokio::test]
+async fn ownership_moves_only_once_accepted() {
+	let (server, store) = server().await;
+	let (alice, admin, owner) = (stoken(&server, ALICE).await, stoken(&server, ADMIN).await, stoken(&server, OWNER).await);
+	let transfer = |stoken: &str, from: &str, to: &str| server.clone().transfer_ownership(context::current(), stoken.to_string(), from.to_string(), to.to_string(), false);
+
+	assert_eq!(transfer(&admin, ADMIN, ALICE).await.unwrap_err(), ErrorCode::Unauthorized);
+	assert_eq!(transfer(&owner, OWNER, OWNER).await.unwrap_err(), ErrorCode::AlreadyOwner);
+
+	// Declined offers change nothing
+	let offer = transfer(&owner, OWNER, ADMIN).await.unwrap();
+	server.clone().decline_ownership_transfer(context::current(), admin.clone(), ADMIN.to_string(), offer.id).await.unwrap();
+	assert!(server.clone().get_ownership_transfers(context::current(), admin.clone(), ADMIN.to_string()).await.unwrap().is_empty());
+	assert!(!store.get_user(ADMIN).await.unwrap().owner);
+
+	let offer = transfer(&owner, OWNER, ALICE).await.unwrap();
+	assert!(!store.get_user(ALICE).await.unwrap().owner);
+	let accept = |stoken: &str, userid: &str| server.clone().accept_ownership_transfer(context::current(), stoken.to_string(), userid.to_string(), offer.id);
+	assert_eq!(accept(&admin, ADMIN).await.unwrap_err(), ErrorCode::TransferNotFound);
+
+	let new_owner = accept(&alice, ALICE).await.unwrap();
+	assert!(new_owner.owner && new_owner.admin);
+	assert!(!store.get_user(OWNER).await.unwrap().owner);
+	assert_eq!(accept(&alice, ALICE).await.unwrap_err(), ErrorCode::TransferNotFound);
+	assert_eq!(server.clone().step_down_as_owner(context::current(), alice, ALICE.to_string()).await, Err(ErrorCode::LastOwner));
+}
+
+#[tokio::test]
+async fn blocked_users_mentions_dont_notify_their_blocker() {
+	let (server, store) = server().await;
+	let (alice, admin, owner) = (stoken(&server, ALICE).await, stoken(&server, ADMIN).await, stoken(&server, OWNER).await);
+
+	server.clone().heartbeat(context::current(), admin, ADMIN.to_string()).await.unwrap();
+	server.block_lists.insert(ADMIN, vec![ALICE.to_string()]).await;
+	server.clone().heartbeat(context::current(), owner.clone(), OWNER.to_string()).await.unwrap();
+	server.block_lists.insert(OWNER, Vec::new()).await;
+
+	// Sent like any other message, so Alice can't tell she's blocked
+	let blocked = server.clone().send_message(context::current(), alice.clone(), text(&store, ALICE, "hi @admin:localhost and\t@admin:localhost, @owner:localhost").await).await;
+	assert_eq!(blocked.unwrap().data, MessageData::Text("hi admin:localhost and\tadmin:localhost, @owner:localhost".to_string()));
+	let sent = server.clone().send_message(context::current(), owner, text(&store, OWNER, "hi @admin:localhost").await).await;
+	assert_eq!(sent.unwrap().data, MessageData::Text("hi @admin:localhost".to_string()));
+
+	// Syncing asks the auth server, which is unreachable here, and keeps the cached list
+	assert_eq!(server.clone().sync_block_list(context::current(), alice.clone(), ALICE.to_string()).await.unwrap_err(), ErrorCode::UnableToConnectToServer);
+	let blocked = server.clone().send_message(context::current(), alice, text(&store, ALICE, "@admin:localhost?").await).await;
+	assert_eq!(blocked.unwrap().data, MessageData::Text("admin:localhost?".to_string()));
+}
+
+#[tokio::test]
+async fn info_announces_issued_stokens() {
+	let (server, _) = server().await;
+
+	// Clients from before stokens were issued by the auth server must be told they're incompatible
+	let info = server.get_info(context::current()).await;
+	assert!(info.protocol_version >= 2);
+	assert_eq!(info.server_id, "realm");
+}
@@ -0,0 +1,338 @@
+//! The same checks run against every `ChatStore` backend.
+//!
+//! `MemoryStore` and SQLite run in memory. The Postgres tests need a local server and are ignored by default,
+//! run them with `cargo test --test store -- --ignored`. Each test creates, and then drops, its
+//! own database on the server at `REALM_TEST_POSTGRES_URL` (default `postgres://postgres@localhost/postgres`).
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use chrono::{Duration, Utc};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{query, PgPool};
+use realm_server::events::Event;
+use realm_server::store::{ChatStore, MemoryStore, PostgresStore, SqliteStore};
+use realm_server::types::*;
+
+async fn sqlite() -> SqliteStore {
+	// One connection, as every connection to `sqlite::memory:` is its own database
+	let db_pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+	let store = SqliteStore::new(db_pool);
+	store.migrate().await.unwrap();
+	store
+}
+
+/// A fresh database on the local Postgres server, dropped by `drop_postgres`
+async fn postgres() -> (PostgresStore, String) {
+	static NEXT: AtomicU32 = AtomicU32::new(0);
+	let name = format!("realm_server_test_{}_{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+
+	let admin = PgPool::connect(&postgres_url()).await.unwrap();
+	query(&format!("DROP DATABASE IF EXISTS {name} WITH (FORCE)")).execute(&admin).await.unwrap();
+	query(&format!("CREATE DATABASE {name}")).execute(&admin).await.unwrap();
+	admin.close().await;
+
+	let mut url = postgres_url();
+	url.truncate(url.rfind('/').unwrap() + 1);
+	url.push_str(&name);
+
+	let store = PostgresStore::new(PgPoolOptions::new().connect(&url).await.unwrap());
+	store.migrate().await.unwrap();
+	(store, name)
+}
+
+async fn drop_postgres(store: PostgresStore, name: String) {
+	store.close().await;
+	let admin = PgPool::connect(&postgres_url()).await.unwrap();
+	query(&format!("DROP DATABASE IF EXISTS {name} WITH (FORCE)")).execute(&admin).await.unwrap();
+}
+
+fn postgres_url() -> String {
+	std::env::var("REALM_TEST_POSTGRES_URL").unwrap_or("postgres://postgres@localhost/postgres".to_string())
+}
+
+/// Runs each check once per backend
+macro_rules! store_tests {
+	($($check:ident),* $(,)?) => {
+		mod memory {
+			$(
+				#[tokio::test]
+				async fn $check() {
+					super::$check(&super::MemoryStore::new()).await;
+				}
+			)*
+		}
+
+		mod sqlite {
+			$(
+				#[tokio::test]
+				async fn $check() {
+					super::$check(&super::sqlite().await).await;
+				}
+			)*
+		}
+
+		mod postgres {
+			$(
+				#[tokio::test]
+				#[ignore = "needs a local PostgreSQL server"]
+				async fn $check() {
+					let (store, name) = super::postgres().await;
+					super::$check(&store).await;
+					super::drop_postgres(store, name).await;
+				}
+			)*
+		}
+	};
+}
+
+store_tests!(health, users, rooms, messages, bans_and_timeouts, audit_log, reports, ownership_transfers, stats, events);
+
+async fn health(store: &dyn ChatStore) {
+	assert!(store.health().await.is_ready());
+}
+
+async fn users(store: &dyn ChatStore) {
+	store.add_user("@alice:localhost", "Alice", true, true).await.unwrap();
+	store.add_user("@bob:localhost", "Bob", false, false).await.unwrap();
+
+	let alice = store.get_user("@alice:localhost").await.unwrap();
+	assert_eq!(alice.name, "Alice");
+	assert!(alice.owner && alice.admin);
+	assert!(matches!(store.get_user("@carol:localhost").await, Err(sqlx::Error::RowNotFound)));
+
+	let users = store.get_users().await.unwrap();
+	assert_eq!(users.iter().map(|u| u.userid.as_str()).collect::<Vec<_>>(), ["@alice:localhost", "@bob:localhost"]);
+	assert_eq!(store.count_owners().await.unwrap(), 1);
+
+	store.set_name("@bob:localhost", "Robert").await.unwrap();
+	store.set_nickname("@bob:localhost", Some("Bobby")).await.unwrap();
+	assert!(store.set_admin("@bob:localhost", true).await.unwrap());
+	assert!(store.set_owner("@bob:localhost", true).await.unwrap());
+	assert!(!store.set_admin("@carol:localhost", true).await.unwrap());
+
+	let bob = store.get_user("@bob:localhost").await.unwrap();
+	assert_eq!(bob.display_name(), "Bobby");
+	assert_eq!(bob.name, "Robert");
+	assert!(bob.owner && bob.admin);
+	assert_eq!(store.count_owners().await.unwrap(), 2);
+
+	let seen = Utc::now();
+	store.set_custom_status("@bob:localhost", Some("Away")).await.unwrap();
+	store.set_last_seen("@bob:localhost", Some(seen)).await.unwrap();
+	let presences = store.get_presences().await.unwrap();
+	let bob = presences.iter().find(|p| p.userid == "@bob:localhost").unwrap();
+	assert_eq!(bob.status, PresenceStatus::Offline);
+	assert_eq!(bob.custom_status.as_deref(), Some("Away"));
+	assert_eq!(bob.last_seen.map(|t| t.timestamp_millis()), Some(seen.timestamp_millis()));
+
+	assert!(store.delete_user("@bob:localhost").await.unwrap());
+	assert!(!store.delete_user("@bob:localhost").await.unwrap());
+	assert_eq!(store.get_users().await.unwrap().len(), 1);
+}
+
+async fn rooms(store: &dyn ChatStore) {
+	let general = Room { id: 0, roomid: "general".to_string(), admin_only_send: false, admin_only_view: false };
+	let staff = Room { id: 0, roomid: "staff".to_string(), admin_only_send: true, admin_only_view: true };
+	let general_id = store.add_room(&general).await.unwrap();
+	store.add_room(&staff).await.unwrap();
+
+	let room = store.get_room("general").await.unwrap();
+	assert_eq!(room, Room { id: general_id, ..general });
+	assert!(store.get_room("staff").await.unwrap().admin_only_view);
+	assert_eq!(store.get_rooms().await.unwrap().len(), 2);
+
+	store.delete_room("staff").await.unwrap();
+	assert!(store.get_room("staff").await.is_err());
+	assert_eq!(store.get_rooms().await.unwrap().len(), 1);
+}
+
+async fn messages(store: &dyn ChatStore) {
+	store.add_user("@alice:localhost", "Alice", true, true).await.unwrap();
+	let user = store.get_user("@alice:localhost").await.unwrap();
+	let room = Room { id: 0, roomid: "general".to_string(), admin_only_send: false, admin_only_view: false };
+	let room = Room { id: store.add_room(&room).await.unwrap(), ..room };
+
+	let message = |data| Message { id: 0, timestamp: Utc::now(), user: user.clone(), room: room.clone(), data };
+
+	let head = store.add_message(&message(MessageData::Text("Hello".to_string()))).await.unwrap();
+	let reply = store.add_message(&message(MessageData::Reply(Reply { referencing_id: head, text: "Hi!".to_string() }))).await.unwrap();
+	store.add_message(&message(MessageData::Reaction(Reaction { referencing_id: head, emoji: "👋".to_string() }))).await.unwrap();
+	store.add_message(&message(MessageData::Edit(Edit { referencing_id: reply, text: "Hi there!".to_string() }))).await.unwrap();
+	store.add_message(&message(MessageData::Redaction(Redaction { referencing_id: reply }))).await.unwrap();
+
+	let fetched = store.get_message(head).await.unwrap();
+	assert_eq!(fetched.data, MessageData::Text("Hello".to_string()));
+	assert_eq!(fetched.user, user);
+	assert_eq!(fetched.room, room);
+	assert!(matches!(store.get_message(head + 100).await, Err(sqlx::Error::RowNotFound)));
+
+	let since = store.get_messages_since(0, 100, false).await.unwrap();
+	assert_eq!(since.len(), 5);
+	assert!(since.windows(2).all(|pair| pair[0].id < pair[1].id));
+	assert_eq!(since[1].data, MessageData::Reply(Reply { referencing_id: head, text: "Hi!".to_string() }));
+	assert_eq!(since[2].data, MessageData::Reaction(Reaction { referencing_id: head, emoji: "👋".to_string() }));
+	assert_eq!(since[4].data, MessageData::Redaction(Redaction { referencing_id: reply }));
+
+	assert_eq!(store.get_messages_since(head, 2, false).await.unwrap().iter().map(|m| m.id).collect::<Vec<_>>(), [reply, reply + 1]);
+
+	let replies = store.get_direct_replies(head).await.unwrap();
+	assert_eq!(replies.len(), 2);
+	assert!(replies.iter().all(|m| m.id != head));
+
+	let staff = Room { id: 0, roomid: "staff".to_string(), admin_only_send: false, admin_only_view: true };
+	let staff = Room { id: store.add_room(&staff).await.unwrap(), ..staff };
+	let hidden = store.add_message(&Message { room: staff, ..message(MessageData::Text("Admins only".to_string())) }).await.unwrap();
+	assert_eq!(store.get_messages_since(head, 100, false).await.unwrap().len(), 4);
+	assert_eq!(store.get_messages_since(head, 100, true).await.unwrap().last().unwrap().id, hidden);
+}
+
+async fn bans_and_timeouts(store: &dyn ChatStore) {
+	let now = Utc::now();
+	let ban = |userid: &str, expires| Ban {
+		id: 0, userid: userid.to_string(), reason: "Spam".to_string(), banned_by: "@alice:localhost".to_string(), created: now, expires,
+	};
+
+	store.add_ban(&ban("@bob:localhost", None)).await.unwrap();
+	store.add_ban(&ban("@carol:localhost", Some(now + Duration::hours(1)))).await.unwrap();
+	store.add_ban(&ban("@dave:localhost", Some(now - Duration::hours(1)))).await.unwrap();
+
+	assert!(store.is_banned("@bob:localhost", now).await.unwrap());
+	assert!(store.is_banned("@carol:localhost", now).await.unwrap());
+	assert!(!store.is_banned("@dave:localhost", now).await.unwrap());
+	assert!(!store.is_banned("@carol:localhost", now + Duration::hours(2)).await.unwrap());
+	assert_eq!(store.get_bans(now).await.unwrap().len(), 2);
+
+	assert_eq!(store.delete_expired_bans(now).await.unwrap(), 1);
+	assert!(store.delete_ban("@bob:localhost").await.unwrap());
+	assert!(!store.delete_ban("@bob:localhost").await.unwrap());
+	assert_eq!(store.get_bans(now).await.unwrap().iter().map(|b| b.userid.as_str()).collect::<Vec<_>>(), ["@carol:localhost"]);
+
+	let timeout = Timeout {
+		id: 0, userid: "@bob:localhost".to_string(), reason: "Cool off".to_string(), issued_by: "@alice:localhost".to_string(),
+		created: now, expires: now + Duration::minutes(10),
+	};
+	let id = store.add_timeout(&timeout).await.unwrap();
+	let fetched = store.get_timeout("@bob:localhost", now).await.unwrap().unwrap();
+	assert_eq!(fetched.id, id);
+	assert_eq!(fetched.reason, "Cool off");
+	assert!(store.get_timeout("@bob:localhost", now + Duration::minutes(11)).await.unwrap().is_none());
+
+	store.delete_timeout("@bob:localhost").await.unwrap();
+	assert!(store.get_timeout("@bob:localhost", now).await.unwrap().is_none());
+}
+
+async fn audit_log(store: &dyn ChatStore) {
+	let entry = |actor: &str, action, target: &str| AuditLogEntry {
+		id: 0, timestamp: Utc::now(), actor: actor.to_string(), action, target: target.to_string(), reason: String::new(),
+	};
+
+	store.add_audit(&entry("@alice:localhost", AuditAction::PromoteUser, "@bob:localhost")).await.unwrap();
+	store.add_audit(&entry("@alice:localhost", AuditAction::BanUser, "@carol:localhost")).await.unwrap();
+	let last = store.add_audit(&entry("@bob:localhost", AuditAction::CreateRoom, "general")).await.unwrap();
+
+	let all = store.get_audit_log(&AuditLogFilter::default(), i64::MAX, 10).await.unwrap();
+	assert_eq!(all.iter().map(|e| e.action).collect::<Vec<_>>(), [AuditAction::CreateRoom, AuditAction::BanUser, AuditAction::PromoteUser]);
+
+	let by_alice = AuditLogFilter { actor: Some("@alice:localhost".to_string()), ..Default::default() };
+	assert_eq!(store.get_audit_log(&by_alice, i64::MAX, 10).await.unwrap().len(), 2);
+
+	let bans = AuditLogFilter { action: Some(AuditAction::BanUser), ..Default::default() };
+	assert_eq!(store.get_audit_log(&bans, i64::MAX, 10).await.unwrap()[0].target, "@carol:localhost");
+
+	let page = store.get_audit_log(&AuditLogFilter::default(), last, 1).await.unwrap();
+	assert_eq!(page.len(), 1);
+	assert_eq!(page[0].action, AuditAction::BanUser);
+}
+
+async fn reports(store: &dyn ChatStore) {
+	let report = Report {
+		id: 0, created: Utc::now(), reporter: "@bob:localhost".to_string(), target_userid: "@carol:localhost".to_string(),
+		message_id: Some(7), category: ReportCategory::HateSpeech, note: "Rude".to_string(), status: ReportStatus::Open,
+		claimed_by: None, resolution: None, resolved_at: None,
+	};
+	let id = store.add_report(&report).await.unwrap();
+	store.add_report(&Report { message_id: None, category: ReportCategory::Spam, ..report.clone() }).await.unwrap();
+
+	let fetched = store.get_report(id).await.unwrap();
+	assert_eq!(fetched.category, ReportCategory::HateSpeech);
+	assert_eq!(fetched.message_id, Some(7));
+	assert!(store.get_report(id + 100).await.is_err());
+
+	assert!(store.claim_report(id, "@alice:localhost").await.unwrap());
+	assert!(store.claim_report(id, "@alice:localhost").await.unwrap());
+	assert!(!store.claim_report(id, "@bob:localhost").await.unwrap());
+	let claimed = store.get_report(id).await.unwrap();
+	assert_eq!(claimed.status, ReportStatus::Claimed);
+	assert_eq!(claimed.claimed_by.as_deref(), Some("@alice:localhost"));
+
+	store.resolve_report(id, "@alice:localhost", ReportResolution::DeletedMessage, Utc::now()).await.unwrap();
+	let resolved = store.get_report(id).await.unwrap();
+	assert_eq!(resolved.status, ReportStatus::Resolved);
+	assert_eq!(resolved.resolution, Some(ReportResolution::DeletedMessage));
+	assert!(resolved.resolved_at.is_some());
+	assert!(!store.claim_report(id, "@alice:localhost").await.unwrap());
+
+	assert_eq!(store.get_reports(None).await.unwrap().len(), 2);
+	assert_eq!(store.get_reports(Some(ReportStatus::Open)).await.unwrap().len(), 1);
+	assert_eq!(store.get_reports(Some(ReportStatus::Resolved)).await.unwrap()[0].id, id);
+}
+
+async fn ownership_transfers(store: &dyn ChatStore) {
+	let transfer = OwnershipTransfer {
+		id: 0, from_userid: "@alice:localhost".to_string(), to_userid: "@bob:localhost".to_string(), keep_ownership: true, created: Utc::now(),
+	};
+	let id = store.add_transfer(&transfer).await.unwrap();
+	store.add_transfer(&OwnershipTransfer { to_userid: "@carol:localhost".to_string(), ..transfer.clone() }).await.unwrap();
+
+	let fetched = store.get_transfer(id).await.unwrap();
+	assert_eq!(fetched.to_userid, "@bob:localhost");
+	assert!(fetched.keep_ownership);
+	assert_eq!(store.get_transfers("@alice:localhost").await.unwrap().len(), 2);
+	assert_eq!(store.get_transfers("@bob:localhost").await.unwrap().len(), 1);
+
+	store.delete_transfers_between("@alice:localhost", "@bob:localhost").await.unwrap();
+	assert!(store.get_transfer(id).await.is_err());
+
+	let remaining = store.get_transfers("@alice:localhost").await.unwrap();
+	store.delete_transfer(remaining[0].id).await.unwrap();
+	assert!(store.get_transfers("@alice:localhost").await.unwrap().is_empty());
+}
+
+async fn stats(store: &dyn ChatStore) {
+	store.add_user("@alice:localhost", "Alice", true, true).await.unwrap();
+	let user = store.get_user("@alice:localhost").await.unwrap();
+	let room = Room { id: 0, roomid: "general".to_string(), admin_only_send: false, admin_only_view: false };
+	let room = Room { id: store.add_room(&room).await.unwrap(), ..room };
+	store.add_room(&Room { roomid: "empty".to_string(), ..room.clone() }).await.unwrap();
+
+	let old = Utc::now() - Duration::hours(1);
+	for (timestamp, text) in [(old, "héllo"), (Utc::now(), "hi")] {
+		let message = Message { id: 0, timestamp, user: user.clone(), room: room.clone(), data: MessageData::Text(text.to_string()) };
+		store.add_message(&message).await.unwrap();
+	}
+
+	assert_eq!(store.count_users().await.unwrap(), 1);
+	assert_eq!(store.count_rooms().await.unwrap(), 2);
+	assert_eq!(store.count_messages(None).await.unwrap(), 2);
+	assert_eq!(store.count_messages(Some(Utc::now() - Duration::minutes(1))).await.unwrap(), 1);
+
+	assert_eq!(store.message_bytes_by_type().await.unwrap(), [("text".to_string(), 8)]);
+	assert_eq!(store.message_bytes_by_room().await.unwrap(), [("general".to_string(), 8), ("empty".to_string(), 0)]);
+
+	assert!(store.database_bytes().await.unwrap() > 0);
+	store.compact().await.unwrap();
+}
+
+async fn events(store: &dyn ChatStore) {
+	assert_eq!(store.count_events().await, 0);
+	assert_eq!(store.push_event(Event::NewRoom(Room { id: 1, roomid: "general".to_string(), admin_only_send: false, admin_only_view: false })).await, 1);
+	assert_eq!(store.push_event(Event::DeleteRoom("general".to_string())).await, 2);
+	assert_eq!(store.push_event(Event::ShuttingDown).await, 3);
+	assert_eq!(store.count_events().await, 3);
+
+	assert_eq!(store.get_events_since(0).await.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [1, 2, 3]);
+	let since = store.get_events_since(2).await;
+	assert_eq!(since.len(), 1);
+	assert!(matches!(since[0], (3, Event::ShuttingDown)));
+	assert!(store.get_events_since(3).await.is_empty());
+}
@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::{env, fs};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// A problem with the configuration, naming the offending key (e.g. `mail.port`) where there is one.
+#[derive(Debug)]
+pub enum ConfigError {
+	Io(String, std::io::Error),
+	Parse(String),
+	Invalid { key: String, reason: String },
+}
+
+impl ConfigError {
+	pub fn invalid(key: &str, reason: impl Into<String>) -> ConfigError {
+		ConfigError::Invalid { key: key.to_string(), reason: reason.into() }
+	}
+}
+
+impl Display for ConfigError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ConfigError::Io(path, error) => write!(f, "unable to read config file {}: {}", path, error),
+			ConfigError::Parse(message) => write!(f, "invalid config file: {}", message),
+			ConfigError::Invalid { key, reason } => write!(f, "invalid value for `{}`: {}", key, reason),
+		}
+	}
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads a TOML config from `CONFIG_PATH`, or from `default_path` if that isn't set.
+/// A missing file is only an error when `CONFIG_PATH` points at it, so env-only deployments keep working.
+pub fn load_file<T: DeserializeOwned + Default>(default_path: &str) -> Result<T, ConfigError> {
+	let (path, explicit) = match env::var("CONFIG_PATH") {
+		Ok(path) => (path, true),
+		Err(_) => (default_path.to_string(), false),
+	};
+
+	if !explicit && !Path::new(&path).exists() {
+		return Ok(T::default());
+	}
+
+	let contents = fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+	serde_path_to_error::deserialize(toml::Deserializer::new(&contents))
+		.map_err(|e| match e.path().to_string().as_str() {
+			"." => ConfigError::Parse(e.into_inner().to_string()),
+			key => ConfigError::invalid(key, e.into_inner().message()),
+		})
+}
+
+/// Replaces `target` with the value of the env var `var` if it is set, e.g. `PORT` for `server.port`.
+pub fn env_override<T: FromStr>(target: &mut T, var: &str, key: &str) -> Result<(), ConfigError> {
+	if let Ok(value) = env::var(var) {
+		*target = value.parse::<T>()
+			.map_err(|_| ConfigError::invalid(key, format!("unable to parse {} from env var {}", value, var)))?;
+	}
+
+	Ok(())
+}
+
+/// Which database a server keeps its data in, `database.backend` in its config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+	#[default]
+	Sqlite,
+	Postgres,
+}
+
+impl DatabaseBackend {
+	/// The backend a database url is for, going by its scheme
+	pub fn from_url(url: &str) -> Option<DatabaseBackend> {
+		if url.starts_with("sqlite:") {
+			Some(DatabaseBackend::Sqlite)
+		} else if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+			Some(DatabaseBackend::Postgres)
+		} else {
+			None
+		}
+	}
+}
+
+impl FromStr for DatabaseBackend {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"sqlite" => Ok(DatabaseBackend::Sqlite),
+			"postgres" => Ok(DatabaseBackend::Postgres),
+			_ => Err(()),
+		}
+	}
+}
@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::{Connection, Database, Pool};
+
+/// Whether a server can take traffic, as reported by its `health` RPC.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+	pub database: bool, //NOTE: A connection could be acquired and pinged
+	pub migrations: MigrationState,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MigrationState {
+	Current { version: i64 },
+	Pending { version: Option<i64>, pending: Vec<i64> }, //NOTE: version is the latest applied, if any
+	Dirty { version: i64 }, //NOTE: A migration failed partway through and needs fixing by hand
+	Ahead { version: i64 }, //NOTE: The database was migrated by a newer build
+	Unknown,
+}
+
+impl Health {
+	pub fn is_live(&self) -> bool {
+		self.database
+	}
+
+	pub fn is_ready(&self) -> bool {
+		self.database && matches!(self.migrations, MigrationState::Current { .. })
+	}
+}
+
+/// Checks that the database answers and compares its applied migrations against the ones built into `migrator`.
+pub async fn check<DB: Database>(pool: &Pool<DB>, migrator: &Migrator) -> Health
+where
+	DB::Connection: Migrate,
+{
+	let mut conn = match pool.acquire().await {
+		Ok(conn) => conn,
+		Err(_) => return Health { database: false, migrations: MigrationState::Unknown },
+	};
+	if conn.ping().await.is_err() {
+		return Health { database: false, migrations: MigrationState::Unknown };
+	}
+
+	Health {
+		database: true,
+		migrations: migration_state(&mut *conn, migrator).await,
+	}
+}
+
+async fn migration_state(conn: &mut impl Migrate, migrator: &Migrator) -> MigrationState {
+	if let Ok(Some(version)) = conn.dirty_version().await {
+		return MigrationState::Dirty { version };
+	}
+	let Ok(applied) = conn.list_applied_migrations().await else {
+		return MigrationState::Unknown;
+	};
+
+	let applied = applied.into_iter().map(|m| m.version).collect::<HashSet<i64>>();
+	let known = migrator.iter()
+		.filter(|m| !m.migration_type.is_down_migration())
+		.map(|m| m.version)
+		.collect::<HashSet<i64>>();
+	let version = applied.iter().max().copied();
+
+	if let Some(unknown) = applied.difference(&known).max() {
+		return MigrationState::Ahead { version: *unknown };
+	}
+
+	let mut pending = known.difference(&applied).copied().collect::<Vec<i64>>();
+	pending.sort();
+	match (version, pending.is_empty()) {
+		(Some(version), true) => MigrationState::Current { version },
+		(None, true) => MigrationState::Current { version: 0 },
+		(version, false) => MigrationState::Pending { version, pending },
+	}
+}
@@ -2,8 +2,19 @@ use sha3::digest::Update;
 use sha3::{Digest, Sha3_256};
 
 pub mod types;
+pub mod config;
+pub mod net;
+pub mod shutdown;
+pub mod metrics;
+pub mod health;
+pub mod redact;
 
-pub fn stoken(token: &str, serverid: &str, domain: &str, port: u16) -> String {
-	let hash = Sha3_256::new().chain(format!("{}{}{}{}", token, serverid, domain, port)).finalize();
-	hex::encode(hash)
+/// What the auth server keeps of a token, enough to recognize it but not to use it
+pub fn hash_token(token: &str) -> String {
+	hex::encode(Sha3_256::new().chain(token).finalize())
+}
+
+pub fn is_display_name_valid(display_name: &str) -> bool {
+	let trimmed = display_name.trim();
+	!trimmed.is_empty() && trimmed.chars().count() <= 64 && trimmed.len() == display_name.len()
 }
\ No newline at end of file
@@ -0,0 +1,483 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::ser::{self, Impossible, Serialize, Serializer};
+use tarpc::context;
+use tarpc::server::Serve;
+use tarpc::ServerError;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tracing::field::{Field, Visit};
+use tracing::{warn, Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// How long a scraper gets to send its request
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest request head read from a scraper
+const MAX_HTTP_REQUEST: usize = 8 * 1024;
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+	pub fn inc(&self) {
+		self.0.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn get(&self) -> u64 {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+	pub fn inc(&self) {
+		self.0.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn dec(&self) {
+		self.0.fetch_sub(1, Ordering::Relaxed);
+	}
+
+	pub fn get(&self) -> i64 {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+/// Latency histogram with fixed bucket upper bounds, in seconds.
+#[derive(Debug)]
+pub struct Histogram {
+	pub bounds: &'static [f64],
+	buckets: Vec<AtomicU64>,
+	count: AtomicU64,
+	sum_micros: AtomicU64,
+}
+
+pub const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+impl Default for Histogram {
+	fn default() -> Self {
+		Histogram::new(LATENCY_BUCKETS)
+	}
+}
+
+impl Histogram {
+	pub fn new(bounds: &'static [f64]) -> Histogram {
+		Histogram {
+			bounds,
+			buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+			count: AtomicU64::new(0),
+			sum_micros: AtomicU64::new(0),
+		}
+	}
+
+	pub fn observe(&self, elapsed: Duration) {
+		let seconds = elapsed.as_secs_f64();
+		if let Some(i) = self.bounds.iter().position(|bound| seconds <= *bound) {
+			self.buckets[i].fetch_add(1, Ordering::Relaxed);
+		}
+		self.count.fetch_add(1, Ordering::Relaxed);
+		self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+	}
+
+	pub fn count(&self) -> u64 {
+		self.count.load(Ordering::Relaxed)
+	}
+
+	pub fn sum(&self) -> Duration {
+		Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+	}
+
+	/// Cumulative counts per bucket, like Prometheus' `le` buckets
+	pub fn cumulative(&self) -> Vec<(f64, u64)> {
+		let mut total = 0;
+		self.bounds.iter().zip(&self.buckets)
+			.map(|(bound, bucket)| {
+				total += bucket.load(Ordering::Relaxed);
+				(*bound, total)
+			})
+			.collect()
+	}
+}
+
+/// One metric per combination of label values, e.g. calls per method and ErrorCode.
+#[derive(Debug)]
+pub struct Family<T> {
+	metrics: Mutex<BTreeMap<Vec<String>, Arc<T>>>,
+}
+
+impl<T> Default for Family<T> {
+	fn default() -> Self {
+		Family { metrics: Mutex::new(BTreeMap::new()) }
+	}
+}
+
+impl<T: Default> Family<T> {
+	pub fn get(&self, labels: &[&str]) -> Arc<T> {
+		let mut metrics = self.metrics.lock().unwrap();
+		let key: Vec<String> = labels.iter().map(|label| label.to_string()).collect();
+		metrics.entry(key).or_default().clone()
+	}
+
+	pub fn snapshot(&self) -> Vec<(Vec<String>, Arc<T>)> {
+		self.metrics.lock().unwrap().iter()
+			.map(|(labels, metric)| (labels.clone(), metric.clone()))
+			.collect()
+	}
+}
+
+/// Traffic of one tarpc server, recorded by [`Measured`].
+#[derive(Debug, Default)]
+pub struct RpcMetrics {
+	pub calls: Family<Counter>, //NOTE: method, code ("ok", an ErrorCode, or "server_error" when tarpc itself failed)
+	pub latency: Family<Histogram>, //NOTE: method
+	pub active_channels: Gauge,
+}
+
+impl RpcMetrics {
+	/// Wraps a tarpc `serve()` so every request it handles is counted and timed
+	pub fn measure<S>(self: &Arc<Self>, serve: S) -> Measured<S> {
+		Measured { serve, metrics: self.clone() }
+	}
+}
+
+#[derive(Clone)]
+pub struct Measured<S> {
+	serve: S,
+	metrics: Arc<RpcMetrics>,
+}
+
+impl<S> Serve for Measured<S>
+where
+	S: Serve,
+	S::Resp: Serialize,
+{
+	type Req = S::Req;
+	type Resp = S::Resp;
+
+	async fn serve(self, ctx: context::Context, req: Self::Req) -> Result<Self::Resp, ServerError> {
+		let method = self.serve.method(&req).unwrap_or("unknown");
+		let started = Instant::now();
+		let response = self.serve.serve(ctx, req).await;
+		self.metrics.latency.get(&[method]).observe(started.elapsed());
+
+		let code = match &response {
+			Ok(response) => error_code(response).unwrap_or("ok"),
+			Err(_) => "server_error",
+		};
+		self.metrics.calls.get(&[method, code]).inc();
+
+		response
+	}
+
+	fn method(&self, req: &Self::Req) -> Option<&'static str> {
+		self.serve.method(req)
+	}
+}
+
+/// The ErrorCode in a tarpc response like `Response::Method(Err(ErrorCode::Code))`, found by
+/// walking its serde representation and giving up at the first thing that isn't an `Err`.
+fn error_code<T: Serialize>(response: &T) -> Option<&'static str> {
+	response.serialize(Probe::Response).ok()
+}
+
+#[derive(Clone, Copy)]
+enum Probe {
+	Response,
+	Result,
+	ErrorCode,
+}
+
+/// Stops the walk; anything but the shape above isn't an error
+#[derive(Debug)]
+struct NotAnError;
+
+impl Display for NotAnError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("not an error")
+	}
+}
+
+impl std::error::Error for NotAnError {}
+
+impl ser::Error for NotAnError {
+	fn custom<M: Display>(_: M) -> Self {
+		NotAnError
+	}
+}
+
+macro_rules! not_an_error {
+	($($method:ident($($arg:ty),*) -> $ok:ty;)*) => {
+		$(fn $method(self, $(_: $arg),*) -> Result<$ok, NotAnError> { Err(NotAnError) })*
+	};
+}
+
+impl Serializer for Probe {
+	type Ok = &'static str;
+	type Error = NotAnError;
+	type SerializeSeq = Impossible<&'static str, NotAnError>;
+	type SerializeTuple = Impossible<&'static str, NotAnError>;
+	type SerializeTupleStruct = Impossible<&'static str, NotAnError>;
+	type SerializeTupleVariant = Impossible<&'static str, NotAnError>;
+	type SerializeMap = Impossible<&'static str, NotAnError>;
+	type SerializeStruct = Impossible<&'static str, NotAnError>;
+	type SerializeStructVariant = Impossible<&'static str, NotAnError>;
+
+	fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, variant: &'static str, value: &T) -> Result<Self::Ok, NotAnError> {
+		match self {
+			Probe::Response => value.serialize(Probe::Result),
+			Probe::Result if variant == "Err" => value.serialize(Probe::ErrorCode),
+			_ => Err(NotAnError),
+		}
+	}
+
+	fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<Self::Ok, NotAnError> {
+		match self {
+			Probe::ErrorCode => Ok(variant),
+			_ => Err(NotAnError),
+		}
+	}
+
+	fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<Self::Ok, NotAnError> {
+		Err(NotAnError)
+	}
+
+	fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, _: &T) -> Result<Self::Ok, NotAnError> {
+		Err(NotAnError)
+	}
+
+	not_an_error! {
+		serialize_bool(bool) -> Self::Ok;
+		serialize_i8(i8) -> Self::Ok;
+		serialize_i16(i16) -> Self::Ok;
+		serialize_i32(i32) -> Self::Ok;
+		serialize_i64(i64) -> Self::Ok;
+		serialize_u8(u8) -> Self::Ok;
+		serialize_u16(u16) -> Self::Ok;
+		serialize_u32(u32) -> Self::Ok;
+		serialize_u64(u64) -> Self::Ok;
+		serialize_f32(f32) -> Self::Ok;
+		serialize_f64(f64) -> Self::Ok;
+		serialize_char(char) -> Self::Ok;
+		serialize_str(&str) -> Self::Ok;
+		serialize_bytes(&[u8]) -> Self::Ok;
+		serialize_none() -> Self::Ok;
+		serialize_unit() -> Self::Ok;
+		serialize_unit_struct(&'static str) -> Self::Ok;
+		serialize_seq(Option<usize>) -> Self::SerializeSeq;
+		serialize_tuple(usize) -> Self::SerializeTuple;
+		serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+		serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+		serialize_map(Option<usize>) -> Self::SerializeMap;
+		serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+		serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+	}
+}
+
+/// Query timings, taken from the events sqlx emits for every statement.
+#[derive(Debug, Default)]
+pub struct DbMetrics {
+	pub queries: Family<Histogram>, //NOTE: statement kind, e.g. "select"
+}
+
+impl DbMetrics {
+	/// A tracing layer feeding these metrics. Give it a filter for the `sqlx::query` target so that
+	/// other layers' filters still decide what gets logged.
+	pub fn layer(self: &Arc<Self>) -> DbMetricsLayer {
+		DbMetricsLayer(self.clone())
+	}
+}
+
+pub struct DbMetricsLayer(Arc<DbMetrics>);
+
+impl DbMetricsLayer {
+	pub fn is_query(metadata: &tracing::Metadata<'_>) -> bool {
+		metadata.target() == "sqlx::query"
+	}
+}
+
+impl<S: Subscriber> Layer<S> for DbMetricsLayer {
+	fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+		if !Self::is_query(event.metadata()) {
+			return;
+		}
+
+		let mut query = QueryVisitor::default();
+		event.record(&mut query);
+
+		if let Some(elapsed) = query.elapsed_secs {
+			self.0.queries.get(&[&query.kind]).observe(Duration::from_secs_f64(elapsed.max(0.0)));
+		}
+	}
+}
+
+#[derive(Default)]
+struct QueryVisitor {
+	kind: String,
+	elapsed_secs: Option<f64>,
+}
+
+impl Visit for QueryVisitor {
+	fn record_f64(&mut self, field: &Field, value: f64) {
+		if field.name() == "elapsed_secs" {
+			self.elapsed_secs = Some(value);
+		}
+	}
+
+	fn record_str(&mut self, field: &Field, value: &str) {
+		if field.name() == "summary" {
+			self.kind = value.split_whitespace().next().unwrap_or("other").to_lowercase();
+		}
+	}
+
+	fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+/// Builds a page in the Prometheus text exposition format.
+#[derive(Default)]
+pub struct Exposition {
+	out: String,
+}
+
+impl Exposition {
+	pub fn counter(&mut self, name: &str, help: &str, value: u64) {
+		self.header(name, help, "counter");
+		let _ = writeln!(self.out, "{} {}", name, value);
+	}
+
+	pub fn gauge(&mut self, name: &str, help: &str, value: impl Display) {
+		self.header(name, help, "gauge");
+		let _ = writeln!(self.out, "{} {}", name, value);
+	}
+
+	pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) {
+		self.header(name, help, "histogram");
+		self.histogram_series(name, &[], histogram);
+	}
+
+	pub fn counter_family(&mut self, name: &str, help: &str, label_names: &[&str], family: &Family<Counter>) {
+		self.header(name, help, "counter");
+		for (labels, counter) in family.snapshot() {
+			let _ = writeln!(self.out, "{}{{{}}} {}", name, label_pairs(label_names, &labels), counter.get());
+		}
+	}
+
+	pub fn histogram_family(&mut self, name: &str, help: &str, label_names: &[&str], family: &Family<Histogram>) {
+		self.header(name, help, "histogram");
+		for (labels, histogram) in family.snapshot() {
+			let pairs: Vec<(&str, &str)> = label_names.iter().copied().zip(labels.iter().map(String::as_str)).collect();
+			self.histogram_series(name, &pairs, &histogram);
+		}
+	}
+
+	/// Everything in [`RpcMetrics`], under `realm_rpc_*`
+	pub fn rpc(&mut self, rpc: &RpcMetrics) {
+		self.counter_family("realm_rpc_calls_total", "RPC calls by method and result", &["method", "code"], &rpc.calls);
+		self.histogram_family("realm_rpc_duration_seconds", "Time spent handling RPC calls", &["method"], &rpc.latency);
+		self.gauge("realm_active_channels", "Open client channels", rpc.active_channels.get());
+	}
+
+	/// Everything in [`DbMetrics`], under `realm_db_*`
+	pub fn db(&mut self, db: &DbMetrics) {
+		self.histogram_family("realm_db_query_duration_seconds", "Time spent running database statements", &["statement"], &db.queries);
+	}
+
+	pub fn finish(self) -> String {
+		self.out
+	}
+
+	fn header(&mut self, name: &str, help: &str, kind: &str) {
+		let _ = writeln!(self.out, "# HELP {} {}", name, help);
+		let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+	}
+
+	fn histogram_series(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+		let base: Vec<String> = labels.iter().map(|(key, value)| format!("{}=\"{}\"", key, escape(value))).collect();
+		let with = |extra: Option<String>| {
+			let mut all = base.clone();
+			all.extend(extra);
+			match all.is_empty() {
+				true => String::new(),
+				false => format!("{{{}}}", all.join(",")),
+			}
+		};
+
+		for (bound, count) in histogram.cumulative() {
+			let _ = writeln!(self.out, "{}_bucket{} {}", name, with(Some(format!("le=\"{}\"", bound))), count);
+		}
+		let _ = writeln!(self.out, "{}_bucket{} {}", name, with(Some("le=\"+Inf\"".to_string())), histogram.count());
+		let _ = writeln!(self.out, "{}_sum{} {}", name, with(None), histogram.sum().as_secs_f64());
+		let _ = writeln!(self.out, "{}_count{} {}", name, with(None), histogram.count());
+	}
+}
+
+fn label_pairs(names: &[&str], values: &[String]) -> String {
+	names.iter().zip(values)
+		.map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+		.collect::<Vec<_>>()
+		.join(",")
+}
+
+fn escape(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serves `GET /metrics` with whatever `render` produces. Runs until the listener fails.
+pub async fn serve_http<F, Fut>(addr: SocketAddr, render: F) -> io::Result<()>
+where
+	F: Fn() -> Fut + Clone + Send + 'static,
+	Fut: Future<Output = String> + Send,
+{
+	if !addr.ip().is_loopback() {
+		warn!("Metrics are exposed on {}, which is reachable from other hosts", addr);
+	}
+
+	let listener = TcpListener::bind(addr).await?;
+	tracing::info!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+
+	loop {
+		let (stream, _) = listener.accept().await?;
+		let render = render.clone();
+		tokio::spawn(async move {
+			if let Err(e) = respond(stream, render).await {
+				warn!("Error answering metrics request: {e}");
+			}
+		});
+	}
+}
+
+async fn respond<F, Fut>(mut stream: TcpStream, render: F) -> io::Result<()>
+where
+	F: Fn() -> Fut,
+	Fut: Future<Output = String>,
+{
+	let mut head = Vec::new();
+	let mut buffer = [0; 1024];
+	while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+		let read = timeout(HTTP_TIMEOUT, stream.read(&mut buffer)).await
+			.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request took too long"))??;
+		if read == 0 || head.len() + read > MAX_HTTP_REQUEST {
+			return Ok(());
+		}
+		head.extend_from_slice(&buffer[..read]);
+	}
+
+	let response = match head.starts_with(b"GET /metrics ") || head.starts_with(b"GET /metrics?") {
+		true => {
+			let body = render().await;
+			format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+		}
+		false => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+	};
+
+	stream.write_all(response.as_bytes()).await?;
+	stream.shutdown().await
+}
@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader};
+use std::marker::PhantomData;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use futures::Stream;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use bincode::Options;
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use tarpc::serde_transport::Transport;
+use tarpc::tokio_serde;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_rustls::{client, server};
+pub use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_util::codec::length_delimited::LengthDelimitedCodec;
+use tracing::{info, warn};
+use crate::config::ConfigError;
+
+/// How long a peer gets to finish the TLS handshake before it is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshakes in progress at once on a listener, connections beyond it are dropped
+const MAX_PENDING_HANDSHAKES: usize = 1024;
+/// Handshakes in progress at once from one IP, so a few idle peers can't use up the rest
+const MAX_PENDING_HANDSHAKES_PER_IP: usize = 8;
+
+/// The `[tls]` config section shared by the auth and chat servers.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+	pub enabled: bool,
+	pub cert: String, //NOTE: PEM certificate chain
+	pub key: String, //NOTE: PEM private key
+	pub ca: String, //NOTE: Extra PEM CA trusted when connecting out, on top of the public roots
+	pub self_signed: bool, //NOTE: Dev mode: generate `cert` and `key` for the domain if they don't exist
+}
+
+impl TlsConfig {
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		if !self.enabled {
+			return Ok(());
+		}
+		if self.cert.is_empty() {
+			return Err(ConfigError::invalid("tls.cert", "must be set when tls is enabled"));
+		}
+		if self.key.is_empty() {
+			return Err(ConfigError::invalid("tls.key", "must be set when tls is enabled"));
+		}
+		if !self.self_signed && !Path::new(&self.cert).exists() {
+			return Err(ConfigError::invalid("tls.cert", format!("{} does not exist", self.cert)));
+		}
+		if !self.self_signed && !Path::new(&self.key).exists() {
+			return Err(ConfigError::invalid("tls.key", format!("{} does not exist", self.key)));
+		}
+		if !self.ca.is_empty() && !Path::new(&self.ca).exists() {
+			return Err(ConfigError::invalid("tls.ca", format!("{} does not exist", self.ca)));
+		}
+
+		Ok(())
+	}
+
+	/// Builds the listener side, generating a self-signed certificate for `domain` first in dev mode
+	pub fn acceptor(&self, domain: &str) -> Result<Option<TlsAcceptor>, ConfigError> {
+		if !self.enabled {
+			return Ok(None);
+		}
+
+		if self.self_signed && !(Path::new(&self.cert).exists() && Path::new(&self.key).exists()) {
+			generate_self_signed(domain, &self.cert, &self.key)?;
+		}
+
+		let certs = read_certs(&self.cert).map_err(|e| ConfigError::invalid("tls.cert", e.to_string()))?;
+		let key = read_key(&self.key).map_err(|e| ConfigError::invalid("tls.key", e.to_string()))?;
+
+		let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+			.with_safe_default_protocol_versions()
+			.and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+			.map_err(|e| ConfigError::invalid("tls.cert", e.to_string()))?;
+
+		Ok(Some(TlsAcceptor::from(Arc::new(config))))
+	}
+
+	/// Builds the connecting side, verifying peers against the public roots plus `ca`
+	pub fn connector(&self) -> Result<Option<TlsConnector>, ConfigError> {
+		if !self.enabled {
+			return Ok(None);
+		}
+
+		client_connector(Some(&self.ca).filter(|ca| !ca.is_empty()).map(String::as_str))
+			.map(Some)
+			.map_err(|e| ConfigError::invalid("tls.ca", e.to_string()))
+	}
+}
+
+/// A TLS connector trusting the public web roots, plus the PEM CA at `ca` if given
+pub fn client_connector(ca: Option<&str>) -> io::Result<TlsConnector> {
+	let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+	if let Some(ca) = ca {
+		for cert in read_certs(ca)? {
+			roots.add(cert).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		}
+	}
+
+	let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+		.with_safe_default_protocol_versions()
+		.map_err(io::Error::other)?
+		.with_root_certificates(roots)
+		.with_no_client_auth();
+
+	Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Writes a self-signed certificate and key for `domain`; clients trust it by pointing their CA at `cert_path`
+pub fn generate_self_signed(domain: &str, cert_path: &str, key_path: &str) -> Result<(), ConfigError> {
+	warn!("Generating a self-signed certificate for {}, only use this for development", domain);
+
+	let generated = rcgen::generate_simple_self_signed(vec![domain.to_string()])
+		.map_err(|e| ConfigError::invalid("tls.self_signed", e.to_string()))?;
+
+	fs::write(cert_path, generated.cert.pem()).map_err(|e| ConfigError::invalid("tls.cert", e.to_string()))?;
+	fs::write(key_path, generated.key_pair.serialize_pem()).map_err(|e| ConfigError::invalid("tls.key", e.to_string()))?;
+	Ok(())
+}
+
+fn read_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+	let mut reader = BufReader::new(fs::File::open(path)?);
+	rustls_pemfile::certs(&mut reader).collect()
+}
+
+fn read_key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
+	let mut reader = BufReader::new(fs::File::open(path)?);
+	rustls_pemfile::private_key(&mut reader)?
+		.ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("no private key found in {}", path)))
+}
+
+/// A TCP connection, optionally wrapped in TLS.
+pub enum NetStream {
+	Plain(TcpStream),
+	Client(Box<client::TlsStream<TcpStream>>),
+	Server(Box<server::TlsStream<TcpStream>>),
+}
+
+impl NetStream {
+	pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+		match self {
+			NetStream::Plain(stream) => stream.peer_addr(),
+			NetStream::Client(stream) => stream.get_ref().0.peer_addr(),
+			NetStream::Server(stream) => stream.get_ref().0.peer_addr(),
+		}
+	}
+
+	pub fn is_tls(&self) -> bool {
+		!matches!(self, NetStream::Plain(_))
+	}
+}
+
+impl AsyncRead for NetStream {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			NetStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+			NetStream::Client(stream) => Pin::new(stream).poll_read(cx, buf),
+			NetStream::Server(stream) => Pin::new(stream).poll_read(cx, buf),
+		}
+	}
+}
+
+impl AsyncWrite for NetStream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		match self.get_mut() {
+			NetStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+			NetStream::Client(stream) => Pin::new(stream).poll_write(cx, buf),
+			NetStream::Server(stream) => Pin::new(stream).poll_write(cx, buf),
+		}
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			NetStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+			NetStream::Client(stream) => Pin::new(stream).poll_flush(cx),
+			NetStream::Server(stream) => Pin::new(stream).poll_flush(cx),
+		}
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			NetStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+			NetStream::Client(stream) => Pin::new(stream).poll_shutdown(cx),
+			NetStream::Server(stream) => Pin::new(stream).poll_shutdown(cx),
+		}
+	}
+}
+
+/// Wire format for tarpc messages. The connecting side picks one by sending its id as the first byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+	Json, //NOTE: Readable, for debugging
+	Bincode, //NOTE: Compact, for production
+}
+
+impl Codec {
+	fn id(self) -> u8 {
+		match self {
+			Codec::Json => b'j',
+			Codec::Bincode => b'b',
+		}
+	}
+
+	fn from_id(id: u8) -> Option<Codec> {
+		match id {
+			b'j' => Some(Codec::Json),
+			b'b' => Some(Codec::Bincode),
+			_ => None,
+		}
+	}
+
+	fn bincode() -> impl Options {
+		bincode::DefaultOptions::new()
+	}
+
+	pub fn encode<T: Serialize>(self, item: &T) -> io::Result<Vec<u8>> {
+		match self {
+			Codec::Json => Ok(serde_json::to_vec(item)?),
+			Codec::Bincode => Codec::bincode().serialize(item).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+		}
+	}
+
+	pub fn decode<T: for<'de> Deserialize<'de>>(self, bytes: &[u8]) -> io::Result<T> {
+		match self {
+			Codec::Json => Ok(serde_json::from_slice(bytes)?),
+			Codec::Bincode => Codec::bincode().deserialize(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+		}
+	}
+}
+
+impl std::str::FromStr for Codec {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"json" => Ok(Codec::Json),
+			"bincode" => Ok(Codec::Bincode),
+			_ => Err(()),
+		}
+	}
+}
+
+/// The `[codec]` config section: what a listener accepts and what outgoing connections use.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodecConfig {
+	pub accept: Vec<Codec>,
+	pub connect: Codec,
+}
+
+impl Default for CodecConfig {
+	fn default() -> Self {
+		Self {
+			accept: vec![Codec::Json, Codec::Bincode],
+			connect: Codec::Bincode,
+		}
+	}
+}
+
+impl CodecConfig {
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		if self.accept.is_empty() {
+			return Err(ConfigError::invalid("codec.accept", "must list at least one codec"));
+		}
+
+		Ok(())
+	}
+}
+
+/// A tokio-serde codec dispatching on the negotiated `Codec`.
+pub struct WireCodec<Item, SinkItem> {
+	codec: Codec,
+	ghost: PhantomData<fn(SinkItem) -> Item>,
+}
+
+impl<Item, SinkItem> WireCodec<Item, SinkItem> {
+	pub fn new(codec: Codec) -> Self {
+		WireCodec { codec, ghost: PhantomData }
+	}
+}
+
+impl<Item, SinkItem: Serialize> tokio_serde::Serializer<SinkItem> for WireCodec<Item, SinkItem> {
+	type Error = io::Error;
+
+	fn serialize(self: Pin<&mut Self>, item: &SinkItem) -> Result<Bytes, Self::Error> {
+		self.codec.encode(item).map(Bytes::from)
+	}
+}
+
+impl<Item: for<'de> Deserialize<'de>, SinkItem> tokio_serde::Deserializer<Item> for WireCodec<Item, SinkItem> {
+	type Error = io::Error;
+
+	fn deserialize(self: Pin<&mut Self>, src: &BytesMut) -> Result<Item, Self::Error> {
+		self.codec.decode(src)
+	}
+}
+
+pub type NetTransport<Item, SinkItem> = Transport<NetStream, Item, SinkItem, WireCodec<Item, SinkItem>>;
+
+fn transport<Item, SinkItem>(stream: NetStream, codec: Codec, max_frame_length: usize) -> NetTransport<Item, SinkItem>
+where
+	Item: for<'de> Deserialize<'de>,
+	SinkItem: Serialize,
+{
+	let framed = LengthDelimitedCodec::builder()
+		.max_frame_length(max_frame_length)
+		.new_framed(stream);
+	tarpc::serde_transport::new(framed, WireCodec::new(codec))
+}
+
+/// Connects to `host:port`, over TLS when a connector is given. The certificate must be valid for `host`.
+pub async fn connect<Item, SinkItem>(host: &str, port: u16, tls: Option<&TlsConnector>, codec: Codec, max_frame_length: usize) -> io::Result<NetTransport<Item, SinkItem>>
+where
+	Item: for<'de> Deserialize<'de>,
+	SinkItem: Serialize,
+{
+	let tcp = TcpStream::connect((host, port)).await?;
+
+	let mut stream = match tls {
+		Some(connector) => {
+			let server_name = ServerName::try_from(host.to_string())
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+			NetStream::Client(Box::new(connector.connect(server_name, tcp).await?))
+		}
+		None => NetStream::Plain(tcp),
+	};
+
+	stream.write_all(&[codec.id()]).await?;
+	Ok(transport(stream, codec, max_frame_length))
+}
+
+/// Handshakes in progress on a listener, in total and per peer IP
+#[derive(Clone)]
+struct PendingHandshakes {
+	total: Arc<Semaphore>,
+	per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+/// One admitted handshake, given back when dropped
+struct PendingHandshake {
+	ip: IpAddr,
+	per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+	_permit: OwnedSemaphorePermit,
+}
+
+impl PendingHandshakes {
+	fn new() -> PendingHandshakes {
+		PendingHandshakes {
+			total: Arc::new(Semaphore::new(MAX_PENDING_HANDSHAKES)),
+			per_ip: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// `None` if the listener, or `ip`, already has as many handshakes going as it's allowed
+	fn admit(&self, ip: IpAddr) -> Option<PendingHandshake> {
+		let mut per_ip = self.per_ip.lock().unwrap();
+		let pending = per_ip.entry(ip).or_default();
+		if *pending >= MAX_PENDING_HANDSHAKES_PER_IP {
+			return None;
+		}
+
+		let permit = self.total.clone().try_acquire_owned().ok()?;
+		*pending += 1;
+		Some(PendingHandshake { ip, per_ip: self.per_ip.clone(), _permit: permit })
+	}
+}
+
+impl Drop for PendingHandshake {
+	fn drop(&mut self) {
+		let mut per_ip = self.per_ip.lock().unwrap();
+		if let Some(pending) = per_ip.get_mut(&self.ip) {
+			*pending -= 1;
+			if *pending == 0 {
+				per_ip.remove(&self.ip);
+			}
+		}
+	}
+}
+
+/// Finishes the TLS handshake, if any, and reads the codec the peer picked
+async fn handshake(tcp: TcpStream, peer: SocketAddr, tls: Option<TlsAcceptor>, codecs: &[Codec]) -> Option<(NetStream, Codec)> {
+	let handshake = async {
+		let mut stream = match tls {
+			Some(acceptor) => NetStream::Server(Box::new(acceptor.accept(tcp).await?)),
+			None => NetStream::Plain(tcp),
+		};
+
+		let id = stream.read_u8().await?;
+		match Codec::from_id(id).filter(|codec| codecs.contains(codec)) {
+			Some(codec) => Ok((stream, codec)),
+			None => Err(io::Error::new(io::ErrorKind::Unsupported, format!("codec {:?} not accepted", id as char))),
+		}
+	};
+
+	match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+		Ok(Ok(negotiated)) => Some(negotiated),
+		Ok(Err(e)) => {
+			warn!("Handshake with {} failed: {}", peer, e);
+			None
+		}
+		Err(_) => {
+			warn!("Handshake with {} timed out", peer);
+			None
+		}
+	}
+}
+
+/// Binds `addr` and yields a transport per accepted connection. Each TLS and codec handshake runs on its own task,
+/// so the accept loop never waits on a slow peer; peers with too many handshakes pending are dropped straight away.
+/// Failed handshakes are logged and skipped. The listener closes once the returned stream is dropped.
+pub async fn listen<A, Item, SinkItem>(addr: A, tls: Option<TlsAcceptor>, codecs: Vec<Codec>, max_frame_length: usize) -> io::Result<(SocketAddr, impl Stream<Item = NetTransport<Item, SinkItem>>)>
+where
+	A: ToSocketAddrs,
+	Item: for<'de> Deserialize<'de> + Send + 'static,
+	SinkItem: Serialize + Send + 'static,
+{
+	let listener = TcpListener::bind(addr).await?;
+	let local_addr = listener.local_addr()?;
+	info!("Listening on {} ({})", local_addr, if tls.is_some() { "tls" } else { "plaintext" });
+
+	let (sender, receiver) = mpsc::channel(MAX_PENDING_HANDSHAKES);
+	let pending = PendingHandshakes::new();
+
+	tokio::spawn(async move {
+		loop {
+			let (tcp, peer) = tokio::select! {
+				_ = sender.closed() => break,
+				accepted = listener.accept() => match accepted {
+					Ok(accepted) => accepted,
+					Err(_) => continue,
+				},
+			};
+
+			let Some(admitted) = pending.admit(peer.ip()) else {
+				warn!("Too many pending handshakes, dropping {}", peer);
+				continue;
+			};
+
+			let (tls, codecs, sender) = (tls.clone(), codecs.clone(), sender.clone());
+			tokio::spawn(async move {
+				let negotiated = handshake(tcp, peer, tls, &codecs).await;
+				drop(admitted);
+
+				if let Some((stream, codec)) = negotiated {
+					let _ = sender.send(transport(stream, codec, max_frame_length)).await;
+				}
+			});
+		}
+	});
+
+	let transports = futures::stream::unfold(receiver, |mut receiver| async move {
+		receiver.recv().await.map(|transport| (transport, receiver))
+	});
+
+	Ok((local_addr, transports))
+}
@@ -0,0 +1,70 @@
+//! Log output with secrets taken out, so a leaked log can't be used to sign in as anyone.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::LazyLock;
+use regex::Regex;
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Tokens, stokens and token hashes, which are all 64 hex digits
+static HEX_SECRET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b[0-9a-fA-F]{64}\b").unwrap());
+//NOTE: Needs something before the @, so usernames like @alice:example.com are left alone
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*").unwrap());
+static LOGIN_CODE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)(\b(?:login_)?code\b\W{0,4}(?:Some\()?)\d{6}\b").unwrap());
+
+/// `text` with any tokens, stokens, emails and login codes in it replaced
+pub fn redact(text: &str) -> Cow<'_, str> {
+	let mut text = Cow::Borrowed(text);
+	for (pattern, replacement) in [(&*HEX_SECRET, "[token]"), (&*EMAIL, "[email]"), (&*LOGIN_CODE, "${1}[code]")] {
+		if let Cow::Owned(replaced) = pattern.replace_all(&text, replacement) {
+			text = Cow::Owned(replaced);
+		}
+	}
+	text
+}
+
+/// Wraps a `MakeWriter`, like `std::io::stdout`, redacting everything written through it
+pub struct Redacting<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+	type Writer = RedactingWriter<M::Writer>;
+
+	fn make_writer(&'a self) -> Self::Writer {
+		RedactingWriter(self.0.make_writer())
+	}
+
+	fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+		RedactingWriter(self.0.make_writer_for(meta))
+	}
+}
+
+pub struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+	//NOTE: The fmt layer writes each event in one go, so a secret is never split across writes
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.write_all(redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.0.flush()
+	}
+}
+
+/// The servers' log output: compact lines on stdout, redacted
+pub fn layer<S>() -> impl Layer<S>
+where
+	S: Subscriber + for<'a> LookupSpan<'a>,
+{
+	tracing_subscriber::fmt::layer()
+		.compact()
+		.with_file(true)
+		.with_line_number(true)
+		.with_thread_ids(true)
+		.with_target(false)
+		.with_writer(Redacting(io::stdout))
+}
@@ -0,0 +1,150 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures::{Sink, Stream};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tracing::{error, info};
+
+/// A one-shot shutdown trigger shared by a server's listener, channels and background tasks.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+	token: CancellationToken,
+}
+
+impl Shutdown {
+	/// A trigger that fires on the first SIGTERM or SIGINT
+	pub fn on_signal() -> Shutdown {
+		let shutdown = Shutdown::default();
+		let trigger = shutdown.clone();
+		tokio::spawn(async move {
+			signal().await;
+			trigger.trigger();
+		});
+		shutdown
+	}
+
+	pub fn trigger(&self) {
+		self.token.cancel();
+	}
+
+	pub fn is_triggered(&self) -> bool {
+		self.token.is_cancelled()
+	}
+
+	/// Resolves once triggered
+	pub fn triggered(&self) -> WaitForCancellationFutureOwned {
+		self.token.clone().cancelled_owned()
+	}
+
+	/// Ends `incoming` once triggered and drops it, so a listener stops accepting right away
+	pub fn until<S: Stream>(&self, incoming: S) -> Until<S> {
+		Until {
+			inner: Some(Box::pin(incoming)),
+			triggered: Box::pin(self.triggered()),
+		}
+	}
+
+	/// Wraps a transport so it reads as closed once triggered. tarpc then stops taking new requests
+	/// on the channel, but still answers the ones in flight before closing it.
+	pub fn drain<T>(&self, transport: T) -> Draining<T> {
+		Draining {
+			inner: transport,
+			triggered: Box::pin(self.triggered()),
+			closed: false,
+		}
+	}
+}
+
+async fn signal() {
+	#[cfg(unix)]
+	{
+		use tokio::signal::unix::{signal, SignalKind};
+
+		let mut terminate = match signal(SignalKind::terminate()) {
+			Ok(terminate) => terminate,
+			Err(e) => {
+				error!("Unable to listen for SIGTERM: {e}");
+				let _ = tokio::signal::ctrl_c().await;
+				return;
+			}
+		};
+
+		tokio::select! {
+			_ = terminate.recv() => info!("Received SIGTERM"),
+			_ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+		}
+	}
+
+	#[cfg(not(unix))]
+	{
+		let _ = tokio::signal::ctrl_c().await;
+		info!("Received Ctrl-C");
+	}
+}
+
+/// A stream that ends, and is dropped, when its [`Shutdown`] is triggered, see [`Shutdown::until`].
+pub struct Until<S> {
+	inner: Option<Pin<Box<S>>>,
+	triggered: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+impl<S: Stream> Stream for Until<S> {
+	type Item = S::Item;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		if self.inner.is_some() && self.triggered.as_mut().poll(cx).is_ready() {
+			self.inner = None;
+		}
+		match self.inner.as_mut() {
+			Some(inner) => inner.as_mut().poll_next(cx),
+			None => Poll::Ready(None),
+		}
+	}
+}
+
+/// A transport whose read half ends when its [`Shutdown`] is triggered, see [`Shutdown::drain`].
+pub struct Draining<T> {
+	inner: T,
+	triggered: Pin<Box<WaitForCancellationFutureOwned>>,
+	closed: bool,
+}
+
+impl<T> Draining<T> {
+	pub fn get_ref(&self) -> &T {
+		&self.inner
+	}
+}
+
+impl<T: Stream + Unpin> Stream for Draining<T> {
+	type Item = T::Item;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		if !self.closed && self.triggered.as_mut().poll(cx).is_ready() {
+			self.closed = true;
+		}
+		if self.closed {
+			return Poll::Ready(None);
+		}
+		Pin::new(&mut self.inner).poll_next(cx)
+	}
+}
+
+impl<T: Sink<I> + Unpin, I> Sink<I> for Draining<T> {
+	type Error = T::Error;
+
+	fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Pin::new(&mut self.inner).poll_ready(cx)
+	}
+
+	fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+		Pin::new(&mut self.inner).start_send(item)
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Pin::new(&mut self.inner).poll_close(cx)
+	}
+}
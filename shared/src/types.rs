@@ -24,4 +24,23 @@ pub enum ErrorCode {
     
     RPCError,
     UnableToConnectToServer,
+    
+    //NOTE: Bincode encodes the variant index, so new codes only ever go at the end
+    Banned,
+    TimedOut,
+    ReportNotFound,
+    ReportAlreadyResolved,
+    InvalidDisplayName,
+    InvalidCustomStatus,
+    LastOwner,
+    AlreadyOwner,
+    TransferNotFound,
+    Blocked,
+    TooManyConnections,
+    MessageTooLong,
+    InputTooLong,
+    IncompatibleServer,
+    SessionNotFound,
+    InvalidDuration,
+    ReportAlreadyClaimed,
 }
\ No newline at end of file
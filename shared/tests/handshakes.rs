@@ -0,0 +1,42 @@
+use std::time::Duration;
+use futures::StreamExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpSocket, TcpStream};
+use realm_shared::net::{self, Codec};
+
+#[tokio::test]
+async fn idle_peers_dont_block_accepts() {
+	let (addr, listener) = net::listen::<_, (), ()>(("127.0.0.1", 0), None, vec![Codec::Json], 1024).await.unwrap();
+	let mut listener = Box::pin(listener);
+
+	// Connect and never send the codec byte
+	let mut idle = Vec::new();
+	for _ in 0..100 {
+		idle.push(TcpStream::connect(addr).await.unwrap());
+	}
+
+	// Everything on loopback can connect from 127.0.0.0/8, so this is another peer
+	let socket = TcpSocket::new_v4().unwrap();
+	socket.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+	let mut stream = socket.connect(addr).await.unwrap();
+	stream.write_all(b"j").await.unwrap();
+
+	let accepted = tokio::time::timeout(Duration::from_secs(2), listener.next()).await.unwrap().unwrap();
+	assert_eq!(accepted.get_ref().peer_addr().unwrap().ip().to_string(), "127.0.0.2");
+
+	// The same address is only dropped while it has too many handshakes pending
+	drop(idle);
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	let mut stream = TcpStream::connect(addr).await.unwrap();
+	stream.write_all(b"j").await.unwrap();
+	tokio::time::timeout(Duration::from_secs(2), listener.next()).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn listener_closes_with_its_stream() {
+	let (addr, listener) = net::listen::<_, (), ()>(("127.0.0.1", 0), None, vec![Codec::Json], 1024).await.unwrap();
+	drop(listener);
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	assert!(TcpStream::connect(addr).await.is_err());
+}
@@ -0,0 +1,48 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::MakeWriter;
+use realm_shared::redact::{redact, Redacting};
+use realm_shared::hash_token;
+
+const TOKEN: &str = "3f9a0c2b7d1e4f5a6b8c9d0e1f2a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c";
+
+#[test]
+fn secrets_are_redacted() {
+	let stoken = hash_token("stoken");
+	let line = format!("API Request: change_email_flow( username -> @alice:localhost, new_email -> alice@example.org, token -> {TOKEN} )");
+	assert_eq!(redact(&line), "API Request: change_email_flow( username -> @alice:localhost, new_email -> [email], token -> [token] )");
+	assert_eq!(redact(&format!("stoken {stoken}, hash {}", hash_token(TOKEN))), "stoken [token], hash [token]");
+
+	assert_eq!(redact("finish_login_flow( username -> @alice:localhost, login_code -> 123456 )"), "finish_login_flow( username -> @alice:localhost, login_code -> [code] )");
+	assert_eq!(redact("AuthUser { id: 123456, login_code: Some(987654) }"), "AuthUser { id: 123456, login_code: Some([code]) }");
+	assert_eq!(redact("Listening on 0.0.0.0:5051, 123456 messages"), "Listening on 0.0.0.0:5051, 123456 messages");
+}
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0.lock().unwrap().write(buf)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+#[test]
+fn writer_redacts() {
+	let buffer = Buffer::default();
+	let sink = buffer.clone();
+	let redacting = Redacting(move || sink.clone());
+	redacting.make_writer().write_all(format!("Signed in with {TOKEN}\n").as_bytes()).unwrap();
+
+	assert_eq!(String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(), "Signed in with [token]\n");
+}
+
+#[test]
+fn hashes_differ_from_the_token() {
+	assert_ne!(hash_token(TOKEN), TOKEN);
+	assert_eq!(hash_token(TOKEN), hash_token(TOKEN));
+}
@@ -0,0 +1,76 @@
+use futures::StreamExt;
+use tarpc::context;
+use tarpc::server::{BaseChannel, Channel};
+use realm_shared::net::{self, Codec, TlsConfig};
+
+#[tarpc::service]
+trait Echo {
+	async fn echo(text: String) -> String;
+}
+
+#[derive(Clone)]
+struct EchoServer;
+
+impl Echo for EchoServer {
+	async fn echo(self, _: context::Context, text: String) -> String {
+		text
+	}
+}
+
+fn self_signed_config(dir: &std::path::Path) -> TlsConfig {
+	TlsConfig {
+		enabled: true,
+		cert: dir.join("cert.pem").to_string_lossy().to_string(),
+		key: dir.join("key.pem").to_string_lossy().to_string(),
+		ca: String::new(),
+		self_signed: true,
+	}
+}
+
+#[tokio::test]
+async fn tarpc_over_tls_on_loopback() {
+	let dir = std::env::temp_dir().join(format!("realm_tls_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let server_tls = self_signed_config(&dir);
+
+	let acceptor = server_tls.acceptor("localhost").unwrap();
+	let (addr, listener) = net::listen(("127.0.0.1", 0), acceptor, vec![Codec::Json, Codec::Bincode], 1024 * 1024).await.unwrap();
+	tokio::spawn(async move {
+		listener
+			.map(BaseChannel::with_defaults)
+			.for_each(|channel| async move {
+				tokio::spawn(channel.execute(EchoServer.serve()).for_each(|f| async { tokio::spawn(f); }));
+			})
+			.await;
+	});
+
+	// The client trusts the generated certificate as its CA
+	let client_tls = TlsConfig { ca: server_tls.cert.clone(), self_signed: false, ..server_tls.clone() };
+	let connector = client_tls.connector().unwrap().unwrap();
+
+	let transport = net::connect("localhost", addr.port(), Some(&connector), Codec::Bincode, 1024 * 1024).await.unwrap();
+	assert!(transport.get_ref().is_tls());
+
+	let client = EchoClient::new(tarpc::client::Config::default(), transport).spawn();
+	let reply = client.echo(context::current(), "over tls".to_string()).await.unwrap();
+	assert_eq!(reply, "over tls");
+
+	std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn tls_rejects_untrusted_certificate() {
+	let dir = std::env::temp_dir().join(format!("realm_tls_untrusted_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+
+	let acceptor = self_signed_config(&dir).acceptor("localhost").unwrap();
+	let (addr, listener) = net::listen::<_, (), ()>(("127.0.0.1", 0), acceptor, vec![Codec::Json], 1024).await.unwrap();
+	tokio::spawn(listener.for_each(|_| async {}));
+
+	// Only the public roots are trusted, so the self-signed certificate must fail verification
+	let connector = net::client_connector(None).unwrap();
+	let result = net::connect::<(), ()>("localhost", addr.port(), Some(&connector), Codec::Json, 1024).await;
+	assert!(result.is_err());
+
+	std::fs::remove_dir_all(&dir).ok();
+}